@@ -161,4 +161,54 @@ TwoFAButton.addEventListener("click", (e) => {
             });
         }
     });
-});
\ No newline at end of file
+});
+
+// -----------------------------------------------------
+
+// Opened from the "this wasn't me" link of a login alert
+const reportLoginSection = document.getElementById("report-login-section");
+const reportLoginForm = document.getElementById("report-login-form");
+const reportLoginButton = document.getElementById("report-login-form-submit");
+const reportLoginErrAlter = document.getElementById("report-login-err-alert");
+const reportLoginToken = new URLSearchParams(window.location.search).get("report_login");
+
+if (reportLoginToken) {
+    loginSection.style.display = "none";
+    twoFASection.style.display = "none";
+    signupSection.style.display = "none";
+    reportLoginSection.style.display = "block";
+}
+
+reportLoginButton.addEventListener("click", (e) => {
+    e.preventDefault();
+
+    const password = reportLoginForm.password.value;
+
+    fetch('/report-login', {
+        method: 'POST',
+        headers: {
+            'Content-Type': 'application/json',
+        },
+        body: JSON.stringify({ token: reportLoginToken, password }),
+    }).then(response => {
+        if (response.ok) {
+            reportLoginForm.password.value = "";
+            reportLoginErrAlter.style.display = "none";
+            alert("Your account is secured, log in with your new password.");
+            // Drop the used token from the address bar
+            window.history.replaceState(null, "", window.location.pathname);
+            loginSection.style.display = "block";
+            reportLoginSection.style.display = "none";
+        } else {
+            response.json().then(data => {
+                let error_msg = data.error;
+                if (error_msg !== undefined && error_msg !== null && error_msg !== "") {
+                    reportLoginErrAlter.innerHTML = `<span><strong>Error: </strong>${error_msg}</span>`;
+                    reportLoginErrAlter.style.display = "block";
+                } else {
+                    reportLoginErrAlter.style.display = "none";
+                }
+            });
+        }
+    });
+});
@@ -0,0 +1,202 @@
+//! Throughput of concurrent signups and logins against the PostgreSQL user store and
+//! the Redis 2FA code and banned token stores, shared as the app does now versus each
+//! behind the `RwLock` it used to need.
+//!
+//! Needs the same PostgreSQL and Redis as the API tests. Run with `cargo bench --bench store_contention`.
+
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use auth_service::{
+    app_state::{BannedTokenStoreType, TwoFACodeStoreType, UserStoreType},
+    domain::{
+        BannedTokenStore, Email, LoginAttemptId, Password, TwoFACode, TwoFACodeHash, TwoFACodeStore,
+        User, UserStore,
+    },
+    get_redis_connection,
+    services::data_stores::{PostgresUserStore, RedisBannedTokenStore, RedisTwoFACodeStore},
+    utils::constants::{DATABASE_URL, REDIS_HOST_NAME},
+};
+use secrecy::{ExposeSecret, Secret};
+use sqlx::{postgres::PgPoolOptions, Connection, Executor, PgConnection, PgPool};
+use tokio::{sync::RwLock, task::JoinSet};
+use uuid::Uuid;
+
+const CLIENTS: usize = 16;
+// Each client signs up once, then logs in this many times
+const LOGINS_PER_CLIENT: usize = 4;
+// Token checks, as made by `/verify-token`, after each login
+const TOKEN_CHECKS_PER_LOGIN: usize = 10;
+const PASSWORD: &str = "Sup3r-Secret-Pass!";
+
+#[derive(Clone)]
+struct SharedStores {
+    users: UserStoreType,
+    two_fa_codes: TwoFACodeStoreType,
+    banned_tokens: BannedTokenStoreType,
+}
+
+#[derive(Clone)]
+struct LockedStores {
+    users: Arc<RwLock<dyn UserStore + Send + Sync>>,
+    two_fa_codes: Arc<RwLock<dyn TwoFACodeStore + Send + Sync>>,
+    banned_tokens: Arc<RwLock<dyn BannedTokenStore + Send + Sync>>,
+}
+
+#[tokio::main]
+async fn main() {
+    let db_name = format!("bench-{}", Uuid::new_v4());
+    let pool = create_database(&db_name).await;
+    let redis_conn = get_redis_connection(REDIS_HOST_NAME.to_owned())
+        .await
+        .expect("Failed to get Redis connection");
+
+    let shared = SharedStores {
+        users: Arc::new(PostgresUserStore::new(pool.clone())),
+        two_fa_codes: Arc::new(RedisTwoFACodeStore::new(redis_conn.clone())),
+        banned_tokens: Arc::new(RedisBannedTokenStore::new(redis_conn.clone())),
+    };
+    let shared_time = run_clients(move |email, password| shared_client(shared.clone(), email, password)).await;
+
+    let locked = LockedStores {
+        users: Arc::new(RwLock::new(PostgresUserStore::new(pool))),
+        two_fa_codes: Arc::new(RwLock::new(RedisTwoFACodeStore::new(redis_conn.clone()))),
+        banned_tokens: Arc::new(RwLock::new(RedisBannedTokenStore::new(redis_conn))),
+    };
+    let locked_time = run_clients(move |email, password| locked_client(locked.clone(), email, password)).await;
+
+    let operations = (CLIENTS * (1 + LOGINS_PER_CLIENT * (1 + 3 + TOKEN_CHECKS_PER_LOGIN))) as f64;
+    println!(
+        "{} clients, 1 signup and {} logins each, a login being a password check, \
+         a 2FA code added, read and removed, and {} token checks",
+        CLIENTS, LOGINS_PER_CLIENT, TOKEN_CHECKS_PER_LOGIN
+    );
+    report("shared stores", operations, shared_time);
+    report("stores behind RwLock", operations, locked_time);
+    println!(
+        "speedup: {:.1}x",
+        locked_time.as_secs_f64() / shared_time.as_secs_f64()
+    );
+
+    drop_database(&db_name).await;
+}
+
+async fn shared_client(stores: SharedStores, email: Email, password: Password) {
+    let user = User::new(email.clone(), password.clone(), false);
+    let user_id = user.id;
+    stores.users.add_user(user).await.unwrap();
+
+    for _ in 0..LOGINS_PER_CLIENT {
+        stores.users.validate_user(email.clone(), password.clone()).await.unwrap();
+
+        let (login_attempt_id, code_hash) = two_fa_code();
+        stores.two_fa_codes.add_code(user_id, login_attempt_id.clone(), code_hash).await.unwrap();
+        stores.two_fa_codes.get_code(&login_attempt_id).await.unwrap();
+        stores.two_fa_codes.remove_code(&login_attempt_id).await.unwrap();
+
+        let token = Secret::new(Uuid::new_v4().to_string());
+        for _ in 0..TOKEN_CHECKS_PER_LOGIN {
+            stores.banned_tokens.contains_token(&token).await.unwrap();
+            stores.banned_tokens.tokens_revoked_before(&user_id.to_string()).await.unwrap();
+        }
+    }
+}
+
+// Writes hold their lock for the whole call, password hash and round trips included
+async fn locked_client(stores: LockedStores, email: Email, password: Password) {
+    let user = User::new(email.clone(), password.clone(), false);
+    let user_id = user.id;
+    stores.users.write().await.add_user(user).await.unwrap();
+
+    for _ in 0..LOGINS_PER_CLIENT {
+        stores.users.read().await.validate_user(email.clone(), password.clone()).await.unwrap();
+
+        let (login_attempt_id, code_hash) = two_fa_code();
+        stores
+            .two_fa_codes
+            .write()
+            .await
+            .add_code(user_id, login_attempt_id.clone(), code_hash)
+            .await
+            .unwrap();
+        stores.two_fa_codes.read().await.get_code(&login_attempt_id).await.unwrap();
+        stores.two_fa_codes.write().await.remove_code(&login_attempt_id).await.unwrap();
+
+        let token = Secret::new(Uuid::new_v4().to_string());
+        for _ in 0..TOKEN_CHECKS_PER_LOGIN {
+            let banned_tokens = stores.banned_tokens.read().await;
+            banned_tokens.contains_token(&token).await.unwrap();
+            banned_tokens.tokens_revoked_before(&user_id.to_string()).await.unwrap();
+        }
+    }
+}
+
+fn two_fa_code() -> (LoginAttemptId, TwoFACodeHash) {
+    let login_attempt_id = LoginAttemptId::default();
+    let code_hash = TwoFACodeHash::new(&login_attempt_id, &TwoFACode::default());
+    (login_attempt_id, code_hash)
+}
+
+// Runs one task per client at once, returns how long they took altogether
+async fn run_clients<F, Fut>(client: F) -> Duration
+where
+    F: Fn(Email, Password) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let mut clients = JoinSet::new();
+    let start = Instant::now();
+    for _ in 0..CLIENTS {
+        let email = Email::parse(Secret::new(format!("{}@example.com", Uuid::new_v4()))).unwrap();
+        let password = Password::parse(Secret::new(PASSWORD.to_owned())).unwrap();
+        clients.spawn(client(email, password));
+    }
+    while let Some(result) = clients.join_next().await {
+        result.unwrap();
+    }
+    start.elapsed()
+}
+
+fn report(name: &str, operations: f64, elapsed: Duration) {
+    println!(
+        "{:<20} {:>8.0} ms {:>8.1} ops/s",
+        name,
+        elapsed.as_secs_f64() * 1000.0,
+        operations / elapsed.as_secs_f64()
+    );
+}
+
+async fn create_database(db_name: &str) -> PgPool {
+    let mut connection = PgConnection::connect(DATABASE_URL.expose_secret())
+        .await
+        .expect("Failed to connect to Postgres");
+    connection
+        .execute(format!(r#"CREATE DATABASE "{}";"#, db_name).as_str())
+        .await
+        .expect("Failed to create database.");
+
+    // More connections than clients, so that none of them waits for the pool
+    let url = format!("{}/{}", DATABASE_URL.expose_secret(), db_name);
+    let pool = PgPoolOptions::new()
+        .max_connections(CLIENTS as u32 + 4)
+        .connect(&url)
+        .await
+        .expect("Failed to create Postgres connection pool!");
+    sqlx::migrate!()
+        .run(&pool)
+        .await
+        .expect("Failed to migrate the database");
+    pool
+}
+
+async fn drop_database(db_name: &str) {
+    let mut connection = PgConnection::connect(DATABASE_URL.expose_secret())
+        .await
+        .expect("Failed to connect to Postgres");
+    connection
+        .execute(format!(r#"DROP DATABASE "{}" WITH (FORCE);"#, db_name).as_str())
+        .await
+        .expect("Failed to drop the database.");
+}
@@ -0,0 +1,41 @@
+//! Prints the events the auth service publishes to its Redis Stream.
+//!
+//! Run with `cargo run --example event_stream_consumer [LAST_ENTRY_ID]`. Without an
+//! entry id the stream is read from the start. Events can be seen more than once, a
+//! real consumer would skip ids it has handled already.
+
+use std::time::Duration;
+
+use auth_service::{
+    get_redis_client,
+    services::redis_event_stream::read_stream_events,
+    utils::constants::{EVENT_STREAM_NAME, REDIS_HOST_NAME},
+};
+
+#[tokio::main]
+async fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+    let mut conn = get_redis_client(REDIS_HOST_NAME.to_owned())?
+        .get_multiplexed_async_connection()
+        .await?;
+    let mut last_entry_id = std::env::args().nth(1).unwrap_or_else(|| "0".to_owned());
+
+    loop {
+        let events = read_stream_events(
+            &mut conn,
+            &EVENT_STREAM_NAME,
+            &last_entry_id,
+            100,
+            Some(Duration::from_secs(5)),
+        )
+        .await?;
+        for stream_event in events {
+            let event = &stream_event.event;
+            println!(
+                "{}\t{}\t{}\t{}\t{}",
+                stream_event.entry_id, event.occurred_at, event.event_type, event.id, event.data
+            );
+            last_entry_id = stream_event.entry_id;
+        }
+    }
+}
@@ -0,0 +1,2 @@
+-- Version of the pepper applied to the password before hashing, 0 means no pepper
+ALTER TABLE users ADD COLUMN IF NOT EXISTS password_pepper_version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,8 @@
+-- Users are identified by a random id instead of their email, which becomes a
+-- regular unique column. The default only serves rows inserted outside the service.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS id UUID DEFAULT gen_random_uuid();
+UPDATE users SET id = gen_random_uuid() WHERE id IS NULL;
+ALTER TABLE users ALTER COLUMN id SET NOT NULL;
+ALTER TABLE users DROP CONSTRAINT users_pkey;
+ALTER TABLE users ADD CONSTRAINT users_pkey PRIMARY KEY (id);
+ALTER TABLE users ADD CONSTRAINT users_email_key UNIQUE (email);
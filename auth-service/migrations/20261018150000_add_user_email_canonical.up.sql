@@ -0,0 +1,20 @@
+-- Canonical form of each email (see `Email::canonical`), used for lookups
+ALTER TABLE users ADD COLUMN email_canonical TEXT;
+
+-- Approximates the default canonicalization for ASCII addresses, run
+-- `auth-service-admin canonicalize-emails` to apply the exact rules. Addresses sharing a
+-- canonical form are left NULL, keep matching on their exact spelling and are
+-- reported by the same command.
+WITH candidates AS (
+    SELECT id,
+           lower(btrim(email)) AS email_canonical,
+           count(*) OVER (PARTITION BY lower(btrim(email))) AS users_sharing_it
+    FROM users
+    WHERE email ~ '^[[:ascii:]]*$'
+)
+UPDATE users
+SET email_canonical = candidates.email_canonical
+FROM candidates
+WHERE users.id = candidates.id AND candidates.users_sharing_it = 1;
+
+ALTER TABLE users ADD CONSTRAINT users_email_canonical_key UNIQUE (email_canonical);
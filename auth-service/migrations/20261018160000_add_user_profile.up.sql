@@ -0,0 +1,6 @@
+-- Optional profile details, see `Profile`
+ALTER TABLE users
+    ADD COLUMN display_name TEXT,
+    ADD COLUMN locale TEXT,
+    ADD COLUMN timezone TEXT,
+    ADD COLUMN avatar_url TEXT;
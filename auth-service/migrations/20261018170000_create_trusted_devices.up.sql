@@ -0,0 +1,10 @@
+-- Browsers on which a user chose to skip 2FA, see `TrustedDeviceStore`
+CREATE TABLE IF NOT EXISTS trusted_devices(
+   id UUID NOT NULL PRIMARY KEY,
+   user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+   name TEXT,
+   created_at TIMESTAMPTZ NOT NULL,
+   expires_at TIMESTAMPTZ NOT NULL,
+   last_used_at TIMESTAMPTZ
+);
+CREATE INDEX IF NOT EXISTS trusted_devices_user_id_idx ON trusted_devices (user_id);
@@ -0,0 +1,10 @@
+-- Devices and networks users logged in from, see `LoginHistoryStore`. Unknown IP
+-- addresses and user agents are stored as empty strings so they can be compared.
+CREATE TABLE IF NOT EXISTS login_history(
+   user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+   ip TEXT NOT NULL,
+   user_agent TEXT NOT NULL,
+   first_seen_at TIMESTAMPTZ NOT NULL,
+   last_seen_at TIMESTAMPTZ NOT NULL,
+   PRIMARY KEY (user_id, ip, user_agent)
+);
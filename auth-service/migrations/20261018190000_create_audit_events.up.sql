@@ -0,0 +1,15 @@
+-- Requests made to the service, see `AuditLog`. Events are kept when their user is
+-- deleted, so `actor` is not a foreign key.
+CREATE TABLE IF NOT EXISTS audit_events(
+   id UUID NOT NULL PRIMARY KEY,
+   occurred_at TIMESTAMPTZ NOT NULL,
+   action TEXT NOT NULL,
+   outcome TEXT NOT NULL,
+   actor UUID,
+   ip TEXT,
+   user_agent TEXT,
+   request_id UUID,
+   detail TEXT
+);
+CREATE INDEX IF NOT EXISTS audit_events_actor_idx ON audit_events (actor, occurred_at DESC);
+CREATE INDEX IF NOT EXISTS audit_events_occurred_at_idx ON audit_events (occurred_at DESC);
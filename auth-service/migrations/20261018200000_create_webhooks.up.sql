@@ -0,0 +1,34 @@
+-- Events written in the same transaction as the change they describe, see `DomainEvent`.
+-- The webhook dispatcher turns them into deliveries and sets `dispatched_at`.
+CREATE TABLE IF NOT EXISTS event_outbox(
+   id UUID NOT NULL PRIMARY KEY,
+   event_type TEXT NOT NULL,
+   payload JSONB NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL,
+   dispatched_at TIMESTAMPTZ
+);
+CREATE INDEX IF NOT EXISTS event_outbox_pending_idx ON event_outbox (created_at) WHERE dispatched_at IS NULL;
+
+-- Endpoints of downstream systems, see `WebhookStore`
+CREATE TABLE IF NOT EXISTS webhook_subscriptions(
+   id UUID NOT NULL PRIMARY KEY,
+   url TEXT NOT NULL,
+   secret TEXT NOT NULL,
+   event_types TEXT[] NOT NULL,
+   created_at TIMESTAMPTZ NOT NULL
+);
+
+-- One event to send to one subscription. Status is pending, delivered or dead.
+CREATE TABLE IF NOT EXISTS webhook_deliveries(
+   id UUID NOT NULL PRIMARY KEY,
+   subscription_id UUID NOT NULL REFERENCES webhook_subscriptions(id) ON DELETE CASCADE,
+   event_id UUID NOT NULL REFERENCES event_outbox(id) ON DELETE CASCADE,
+   status TEXT NOT NULL DEFAULT 'pending',
+   attempts INTEGER NOT NULL DEFAULT 0,
+   next_attempt_at TIMESTAMPTZ NOT NULL,
+   last_error TEXT,
+   updated_at TIMESTAMPTZ NOT NULL,
+   UNIQUE (subscription_id, event_id)
+);
+CREATE INDEX IF NOT EXISTS webhook_deliveries_due_idx ON webhook_deliveries (next_attempt_at) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS webhook_deliveries_dead_idx ON webhook_deliveries (updated_at) WHERE status = 'dead';
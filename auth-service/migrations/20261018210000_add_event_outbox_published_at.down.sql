@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS event_outbox_unpublished_idx;
+ALTER TABLE event_outbox DROP COLUMN IF EXISTS published_at;
@@ -0,0 +1,5 @@
+-- Set once the event stream publisher has appended the event to the stream
+ALTER TABLE event_outbox ADD COLUMN IF NOT EXISTS published_at TIMESTAMPTZ;
+-- The stream starts at this deploy, events from before it are not published
+UPDATE event_outbox SET published_at = COALESCE(dispatched_at, now()) WHERE published_at IS NULL;
+CREATE INDEX IF NOT EXISTS event_outbox_unpublished_idx ON event_outbox (created_at) WHERE published_at IS NULL;
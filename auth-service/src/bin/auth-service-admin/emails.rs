@@ -0,0 +1,170 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use auth_service::{
+    domain::{Email, UserId},
+    services::data_stores::{PostgresUserStore, StoredEmail},
+    utils::constants::EMAIL_FOLD_LOCAL_PART_CASE,
+};
+use clap::Args;
+use color_eyre::eyre::{Context, Result};
+use secrecy::{ExposeSecret, Secret};
+use serde::Serialize;
+
+#[derive(Args)]
+pub struct CanonicalizeEmailsArgs {
+    /// Report collisions and pending changes without writing anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Write the collisions as JSONL to this file instead of stdout
+    #[arg(long)]
+    report: Option<PathBuf>,
+}
+
+// Users whose emails only differ by case, whitespace or Unicode form. They keep no
+// canonical email, and so keep logging in with their exact spelling, until an
+// operator merges, renames or deletes all but one of them.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Collision {
+    pub canonical: String,
+    pub users: Vec<CollidingUser>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct CollidingUser {
+    pub id: String,
+    pub email: String,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct CanonicalizationPlan {
+    // Only rows whose stored canonical email differs from the expected one
+    pub changes: Vec<(UserId, Option<String>)>,
+    pub collisions: Vec<Collision>,
+    // Stored emails that no longer parse, left untouched
+    pub invalid: Vec<String>,
+}
+
+pub async fn run(args: CanonicalizeEmailsArgs, user_store: &PostgresUserStore) -> Result<()> {
+    let stored = user_store.stored_emails().await?;
+    let plan = plan(&stored, *EMAIL_FOLD_LOCAL_PART_CASE);
+
+    let mut report: Box<dyn Write> = match &args.report {
+        Some(path) => Box::new(
+            File::create(path).wrap_err_with(|| format!("failed to create {}", path.display()))?,
+        ),
+        None => Box::new(io::stdout()),
+    };
+    for collision in plan.collisions.iter() {
+        serde_json::to_writer(&mut report, collision).wrap_err("failed to write report")?;
+        writeln!(report).wrap_err("failed to write report")?;
+    }
+    for email in plan.invalid.iter() {
+        eprintln!("Skipped invalid stored email {}", email);
+    }
+
+    if !args.dry_run {
+        user_store.set_canonical_emails(&plan.changes).await?;
+    }
+    eprintln!(
+        "{} users, {} canonical emails {}, {} collisions",
+        stored.len(),
+        plan.changes.len(),
+        if args.dry_run { "to update" } else { "updated" },
+        plan.collisions.len()
+    );
+    Ok(())
+}
+
+// Canonicalizes every stored email with the configured rules. Emails sharing a
+// canonical form are reported and get none, so the unique constraint holds.
+pub fn plan(stored: &[StoredEmail], fold_local_part_case: bool) -> CanonicalizationPlan {
+    let mut plan = CanonicalizationPlan::default();
+    let mut groups: BTreeMap<String, Vec<&StoredEmail>> = BTreeMap::new();
+
+    for row in stored {
+        match Email::parse_with(Secret::new(row.email.clone()), fold_local_part_case) {
+            Ok(email) => groups
+                .entry(email.canonical().expose_secret().to_owned())
+                .or_default()
+                .push(row),
+            Err(_) => plan.invalid.push(row.email.clone()),
+        }
+    }
+
+    for (canonical, rows) in groups {
+        let expected = (rows.len() == 1).then(|| canonical.clone());
+        for row in rows.iter() {
+            if row.email_canonical != expected {
+                plan.changes.push((row.id.into(), expected.clone()));
+            }
+        }
+        if rows.len() > 1 {
+            plan.collisions.push(Collision {
+                canonical,
+                users: rows
+                    .iter()
+                    .map(|row| CollidingUser { id: row.id.to_string(), email: row.email.clone() })
+                    .collect(),
+            });
+        }
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn stored(email: &str, email_canonical: Option<&str>) -> StoredEmail {
+        StoredEmail {
+            id: Uuid::new_v4(),
+            email: email.to_owned(),
+            email_canonical: email_canonical.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn reports_collisions_and_fixes_the_rest() {
+        let rows = vec![
+            stored("Bob@Example.com", Some("bob@example.com")),
+            stored("bob@example.com", None),
+            stored("alice@example.com", Some("alice@example.com")),
+            stored("carol@Bücher.example", None),
+        ];
+
+        let plan = plan(&rows, true);
+
+        assert_eq!(
+            plan.changes,
+            vec![
+                (rows[0].id.into(), None),
+                (rows[3].id.into(), Some("carol@xn--bcher-kva.example".to_owned())),
+            ]
+        );
+        assert_eq!(plan.collisions.len(), 1);
+        assert_eq!(plan.collisions[0].canonical, "bob@example.com");
+        assert_eq!(plan.collisions[0].users.len(), 2);
+        assert!(plan.invalid.is_empty());
+    }
+
+    #[test]
+    fn follows_the_local_part_case_setting() {
+        let rows = vec![
+            stored("Bob@Example.com", Some("bob@example.com")),
+            stored("not-an-email", None),
+        ];
+
+        let plan = plan(&rows, false);
+
+        assert_eq!(plan.changes, vec![(rows[0].id.into(), Some("Bob@example.com".to_owned()))]);
+        assert!(plan.collisions.is_empty());
+        assert_eq!(plan.invalid, vec!["not-an-email".to_owned()]);
+    }
+}
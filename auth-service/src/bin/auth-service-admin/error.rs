@@ -0,0 +1,73 @@
+use std::process::ExitCode;
+
+use auth_service::domain::{BannedTokenStoreError, UserStoreError, WebhookStoreError};
+use color_eyre::eyre::Report;
+use thiserror::Error;
+
+// Failure of a subcommand. Each variant maps to its own exit code so that
+// scripts can tell expected failures apart from broken infrastructure.
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error("User not found")]
+    UserNotFound,
+    #[error("User already exists")]
+    UserAlreadyExists,
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+    #[error("Token is invalid")]
+    InvalidToken,
+    // A webhook subscription or delivery
+    #[error("{0}")]
+    NotFound(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] Report),
+}
+
+// Exit code 2 is used by clap for usage errors
+pub const EXIT_UNEXPECTED_ERROR: u8 = 1;
+pub const EXIT_USER_NOT_FOUND: u8 = 3;
+pub const EXIT_USER_ALREADY_EXISTS: u8 = 4;
+pub const EXIT_INVALID_INPUT: u8 = 5;
+pub const EXIT_INVALID_TOKEN: u8 = 6;
+pub const EXIT_NOT_FOUND: u8 = 7;
+
+impl CommandError {
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            Self::UserNotFound => EXIT_USER_NOT_FOUND,
+            Self::UserAlreadyExists => EXIT_USER_ALREADY_EXISTS,
+            Self::InvalidInput(_) => EXIT_INVALID_INPUT,
+            Self::InvalidToken => EXIT_INVALID_TOKEN,
+            Self::NotFound(_) => EXIT_NOT_FOUND,
+            Self::UnexpectedError(_) => EXIT_UNEXPECTED_ERROR,
+        })
+    }
+}
+
+impl From<UserStoreError> for CommandError {
+    fn from(error: UserStoreError) -> Self {
+        match error {
+            UserStoreError::UserNotFound => Self::UserNotFound,
+            UserStoreError::UserAlreadyExists => Self::UserAlreadyExists,
+            UserStoreError::UnexpectedError(e) => Self::UnexpectedError(e),
+            e => Self::UnexpectedError(e.into()),
+        }
+    }
+}
+
+impl From<BannedTokenStoreError> for CommandError {
+    fn from(error: BannedTokenStoreError) -> Self {
+        match error {
+            BannedTokenStoreError::UnexpectedError(e) => Self::UnexpectedError(e),
+        }
+    }
+}
+
+impl From<WebhookStoreError> for CommandError {
+    fn from(error: WebhookStoreError) -> Self {
+        match error {
+            WebhookStoreError::UnexpectedError(e) => Self::UnexpectedError(e),
+            e => Self::NotFound(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+};
+
+use auth_service::domain::{UserRecord, UserStore};
+use clap::Args;
+use color_eyre::eyre::{Context, Result};
+use secrecy::ExposeSecret;
+
+use crate::{Format, UserRow};
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// File to write, stdout by default
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// Output format, detected from the file extension by default
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+    /// Number of users read per query
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+    batch_size: u32,
+}
+
+pub async fn run(args: ExportArgs, user_store: &dyn UserStore) -> Result<()> {
+    let format = args
+        .format
+        .unwrap_or_else(|| Format::from_path(args.output.as_deref()));
+    let output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(
+            File::create(path).wrap_err_with(|| format!("failed to create {}", path.display()))?,
+        ),
+        None => Box::new(io::stdout()),
+    };
+
+    let exported = export(
+        user_store,
+        BufWriter::new(output),
+        format,
+        args.batch_size as usize,
+        &mut io::stderr(),
+    )
+    .await?;
+    eprintln!("Exported {} users", exported);
+
+    Ok(())
+}
+
+pub async fn export(
+    user_store: &dyn UserStore,
+    output: impl Write,
+    format: Format,
+    batch_size: usize,
+    progress: &mut dyn Write,
+) -> Result<usize> {
+    let mut writer = RowWriter::new(output, format);
+    let mut after = None;
+    let mut exported = 0;
+
+    loop {
+        let users = user_store
+            .export_users(after.take(), batch_size)
+            .await
+            .wrap_err("failed to read batch of users")?;
+        let Some(last) = users.last() else {
+            break;
+        };
+        after = Some(last.email.clone());
+
+        for user in users.iter() {
+            writer.write(user)?;
+        }
+        exported += users.len();
+        writeln!(progress, "exported {} users", exported).wrap_err("failed to report progress")?;
+    }
+
+    writer.finish()?;
+    Ok(exported)
+}
+
+enum RowWriter<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Jsonl(W),
+}
+
+impl<W: Write> RowWriter<W> {
+    fn new(output: W, format: Format) -> Self {
+        match format {
+            Format::Csv => Self::Csv(Box::new(csv::Writer::from_writer(output))),
+            Format::Jsonl => Self::Jsonl(output),
+        }
+    }
+
+    fn write(&mut self, user: &UserRecord) -> Result<()> {
+        let row = UserRow {
+            email: user.email.expose_secret().to_owned(),
+            password_hash: user.password_hash.expose_secret().to_owned(),
+            requires_2fa: user.requires_2fa,
+            password_pepper_version: user.password_pepper_version,
+            id: Some(user.id.to_string()),
+        };
+        match self {
+            Self::Csv(writer) => writer.serialize(row).wrap_err("failed to write CSV row"),
+            Self::Jsonl(writer) => {
+                serde_json::to_writer(&mut *writer, &row).wrap_err("failed to write JSON row")?;
+                writeln!(writer).wrap_err("failed to write JSON row")
+            }
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Self::Csv(mut writer) => writer.flush(),
+            Self::Jsonl(mut writer) => writer.flush(),
+        }
+        .wrap_err("failed to flush output")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use auth_service::{domain::{Email, UserId}, services::data_stores::HashmapUserStore};
+    use secrecy::Secret;
+
+    use super::*;
+    use crate::import::{import, read_rows};
+
+    const ARGON2_HASH: &str = "$argon2id$v=19$m=15000,t=2,p=1$c2FsdHNhbHRzYWx0$UDLy8dyQWxxA3RYTcAhIjLCTMqEIFdBo8onDSvWXThI";
+
+    #[tokio::test]
+    async fn export_can_be_imported_again() {
+        let source = HashmapUserStore::default();
+        let records = ["carol@example.com", "alice@example.com", "bob@example.com"]
+            .into_iter()
+            .map(|email| UserRecord {
+                id: UserId::default(),
+                email: Email::parse(Secret::new(email.to_owned())).unwrap(),
+                password_hash: Secret::new(ARGON2_HASH.to_owned()),
+                requires_2fa: email.starts_with('b'),
+                password_pepper_version: 0,
+            })
+            .collect();
+        source.import_users(records).await.unwrap();
+
+        for format in [Format::Csv, Format::Jsonl] {
+            let mut output = Vec::new();
+            let exported = export(&source, &mut output, format, 2, &mut io::sink()).await.unwrap();
+            assert_eq!(exported, 3);
+
+            let target = HashmapUserStore::default();
+            let rows = read_rows(output.as_slice(), format).unwrap();
+            let summary = import(rows, &target, 10, false, &mut io::sink()).await.unwrap();
+            assert_eq!(summary.imported, 3);
+            assert!(summary.skipped.is_empty());
+
+            let bob = target
+                .get_user(Email::parse(Secret::new("bob@example.com".to_owned())).unwrap())
+                .await
+                .unwrap();
+            assert!(bob.requires_2fa);
+            // Ids are kept so that issued tokens still identify the same users
+            let source_bob = source.get_user(bob.email.clone()).await.unwrap();
+            assert_eq!(bob.id, source_bob.id);
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::PathBuf,
+};
+
+use auth_service::{
+    domain::{Email, UserId, UserRecord, UserStore, UserStoreError},
+    services::data_stores::is_supported_password_hash,
+    utils::constants::{NO_PASSWORD_PEPPER_VERSION, PASSWORD_PEPPERS},
+};
+use clap::Args;
+use color_eyre::eyre::{eyre, Context, Result};
+use secrecy::{ExposeSecret, Secret};
+use serde::Serialize;
+
+use crate::{Format, UserRow};
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// File to import, `-` reads from stdin
+    input: PathBuf,
+    /// Input format, detected from the file extension by default
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+    /// Number of users inserted per statement
+    #[arg(long, default_value_t = 500, value_parser = clap::value_parser!(u32).range(1..))]
+    batch_size: u32,
+    /// Validate the file and report what would be imported without writing anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Write the skipped rows as JSONL to this file
+    #[arg(long)]
+    report: Option<PathBuf>,
+}
+
+// A row that was not imported, with its line in the input file
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SkippedRow {
+    pub line: u64,
+    pub email: String,
+    #[serde(flatten)]
+    pub reason: SkipReason,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum SkipReason {
+    Invalid { detail: String },
+    DuplicateInFile { first_line: u64 },
+    AlreadyExists,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub total: usize,
+    pub imported: usize,
+    pub skipped: Vec<SkippedRow>,
+}
+
+pub async fn run(args: ImportArgs, user_store: &dyn UserStore) -> Result<()> {
+    let is_stdin = args.input.as_os_str() == "-";
+    let format = args
+        .format
+        .unwrap_or_else(|| Format::from_path((!is_stdin).then_some(args.input.as_path())));
+    let input: Box<dyn Read> = if is_stdin {
+        Box::new(io::stdin())
+    } else {
+        Box::new(
+            File::open(&args.input)
+                .wrap_err_with(|| format!("failed to open {}", args.input.display()))?,
+        )
+    };
+
+    let rows = read_rows(input, format)?;
+    let summary = import(
+        rows,
+        user_store,
+        args.batch_size as usize,
+        args.dry_run,
+        &mut io::stderr(),
+    )
+    .await?;
+
+    match &args.report {
+        Some(path) => {
+            let mut report = File::create(path)
+                .wrap_err_with(|| format!("failed to create {}", path.display()))?;
+            write_report(&summary.skipped, &mut report)?;
+        }
+        None => write_report(&summary.skipped, &mut io::stderr())?,
+    }
+
+    eprintln!(
+        "{}{} of {} users, skipped {} ({} invalid, {} duplicated in file, {} already registered)",
+        if args.dry_run { "[dry run] would import " } else { "Imported " },
+        summary.imported,
+        summary.total,
+        summary.skipped.len(),
+        summary.count(|r| matches!(r, SkipReason::Invalid { .. })),
+        summary.count(|r| matches!(r, SkipReason::DuplicateInFile { .. })),
+        summary.count(|r| matches!(r, SkipReason::AlreadyExists)),
+    );
+
+    Ok(())
+}
+
+impl ImportSummary {
+    fn count(&self, predicate: impl Fn(&SkipReason) -> bool) -> usize {
+        self.skipped.iter().filter(|row| predicate(&row.reason)).count()
+    }
+}
+
+// Parses the whole input up front so that a malformed file is reported before
+// anything is written. Rows that cannot be deserialized are kept as errors.
+pub fn read_rows(input: impl Read, format: Format) -> Result<Vec<(u64, Result<UserRow, String>)>> {
+    let mut rows = Vec::new();
+    match format {
+        Format::Csv => {
+            // A row with the wrong number of fields is reported like any other invalid row
+            let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(input);
+            let headers = reader.headers().wrap_err("failed to read CSV header")?.clone();
+            for record in reader.records() {
+                let record = record.wrap_err("failed to read CSV record")?;
+                let line = record.position().map(|p| p.line()).unwrap_or_default();
+                let row = record
+                    .deserialize::<UserRow>(Some(&headers))
+                    .map_err(|e| e.to_string());
+                rows.push((line, row));
+            }
+        }
+        Format::Jsonl => {
+            for (index, line) in BufReader::new(input).lines().enumerate() {
+                let line = line.wrap_err("failed to read input")?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let row = serde_json::from_str::<UserRow>(&line).map_err(|e| e.to_string());
+                rows.push((index as u64 + 1, row));
+            }
+        }
+    }
+    Ok(rows)
+}
+
+pub async fn import(
+    rows: Vec<(u64, Result<UserRow, String>)>,
+    user_store: &dyn UserStore,
+    batch_size: usize,
+    dry_run: bool,
+    progress: &mut dyn Write,
+) -> Result<ImportSummary> {
+    let mut summary = ImportSummary {
+        total: rows.len(),
+        ..Default::default()
+    };
+    let mut first_lines: HashMap<String, u64> = HashMap::new();
+    let mut batch: Vec<(u64, UserRecord)> = Vec::with_capacity(batch_size);
+    let mut processed = 0;
+
+    for (line, row) in rows {
+        processed += 1;
+        match validate_row(row) {
+            Ok(record) => {
+                let email = record.email.expose_secret().to_owned();
+                if let Some(first_line) = first_lines.get(&email) {
+                    summary.skipped.push(SkippedRow {
+                        line,
+                        email,
+                        reason: SkipReason::DuplicateInFile { first_line: *first_line },
+                    });
+                } else {
+                    first_lines.insert(email, line);
+                    batch.push((line, record));
+                }
+            }
+            Err((email, detail)) => summary.skipped.push(SkippedRow {
+                line,
+                email,
+                reason: SkipReason::Invalid { detail },
+            }),
+        }
+
+        if batch.len() == batch_size {
+            flush(&mut batch, user_store, dry_run, &mut summary).await?;
+            report_progress(progress, processed, &summary)?;
+        }
+    }
+    if !batch.is_empty() {
+        flush(&mut batch, user_store, dry_run, &mut summary).await?;
+    }
+    report_progress(progress, processed, &summary)?;
+
+    summary.skipped.sort_by_key(|row| row.line);
+    Ok(summary)
+}
+
+// Returns the email as given alongside the error, for the report
+fn validate_row(row: Result<UserRow, String>) -> Result<UserRecord, (String, String)> {
+    let row = row.map_err(|e| (String::new(), e))?;
+    let email = Email::parse(Secret::new(row.email.trim().to_owned()))
+        .map_err(|e| (row.email.clone(), e.to_string()))?;
+    let id = match row.id.as_deref().map(str::trim).filter(|id| !id.is_empty()) {
+        Some(id) => UserId::parse(id).map_err(|_| (row.email.clone(), format!("invalid user id {id}")))?,
+        None => UserId::default(),
+    };
+    if !is_supported_password_hash(&row.password_hash) {
+        return Err((row.email, "unsupported or malformed password hash".to_owned()));
+    }
+    // Users whose pepper is unknown to the service would never be able to log in
+    if row.password_pepper_version != NO_PASSWORD_PEPPER_VERSION
+        && !PASSWORD_PEPPERS.contains_key(&row.password_pepper_version)
+    {
+        return Err((
+            row.email,
+            format!("password pepper version {} is not configured", row.password_pepper_version),
+        ));
+    }
+
+    Ok(UserRecord {
+        id,
+        email,
+        password_hash: Secret::new(row.password_hash),
+        requires_2fa: row.requires_2fa,
+        password_pepper_version: row.password_pepper_version,
+    })
+}
+
+async fn flush(
+    batch: &mut Vec<(u64, UserRecord)>,
+    user_store: &dyn UserStore,
+    dry_run: bool,
+    summary: &mut ImportSummary,
+) -> Result<()> {
+    let lines: HashMap<String, u64> = batch
+        .iter()
+        .map(|(line, record)| (record.email.expose_secret().to_owned(), *line))
+        .collect();
+    let records: Vec<UserRecord> = batch.drain(..).map(|(_, record)| record).collect();
+    let batch_len = records.len();
+
+    let existing = if dry_run {
+        let mut existing = Vec::new();
+        for record in records {
+            match user_store.get_user(record.email.clone()).await {
+                Ok(_) => existing.push(record.email),
+                Err(UserStoreError::UserNotFound) => {}
+                Err(e) => return Err(eyre!(e)).wrap_err("failed to look up existing user"),
+            }
+        }
+        existing
+    } else {
+        user_store
+            .import_users(records)
+            .await
+            .wrap_err("failed to import batch of users")?
+    };
+
+    summary.imported += batch_len - existing.len();
+    for email in existing {
+        let email = email.expose_secret().to_owned();
+        summary.skipped.push(SkippedRow {
+            line: lines[&email],
+            email,
+            reason: SkipReason::AlreadyExists,
+        });
+    }
+    Ok(())
+}
+
+fn report_progress(progress: &mut dyn Write, processed: usize, summary: &ImportSummary) -> Result<()> {
+    writeln!(
+        progress,
+        "processed {}/{} rows: {} imported, {} skipped",
+        processed,
+        summary.total,
+        summary.imported,
+        summary.skipped.len()
+    )
+    .wrap_err("failed to report progress")
+}
+
+fn write_report(skipped: &[SkippedRow], output: &mut dyn Write) -> Result<()> {
+    for row in skipped {
+        let line = serde_json::to_string(row).wrap_err("failed to serialize report row")?;
+        writeln!(output, "{}", line).wrap_err("failed to write report")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use auth_service::services::data_stores::HashmapUserStore;
+
+    use super::*;
+
+    const ARGON2_HASH: &str = "$argon2id$v=19$m=15000,t=2,p=1$c2FsdHNhbHRzYWx0$UDLy8dyQWxxA3RYTcAhIjLCTMqEIFdBo8onDSvWXThI";
+    const BCRYPT_HASH: &str = "$2b$04$0TUqUhKK5vDaoE7rjORxAeDMPZjS8yKCrOb6jqmGJe9XYTChDLwEO";
+
+    fn csv_input() -> String {
+        format!(
+            "email,password_hash,requires_2fa\n\
+             alice@example.com,\"{ARGON2_HASH}\",true\n\
+             not-an-email,\"{ARGON2_HASH}\",false\n\
+             bob@example.com,{BCRYPT_HASH},false\n\
+             alice@example.com,{BCRYPT_HASH},false\n\
+             carol@example.com,plaintext-password,false\n"
+        )
+    }
+
+    async fn store_with(email: &str) -> HashmapUserStore {
+        let store = HashmapUserStore::default();
+        let record = UserRecord {
+            id: UserId::default(),
+            email: Email::parse(Secret::new(email.to_owned())).unwrap(),
+            password_hash: Secret::new(ARGON2_HASH.to_owned()),
+            requires_2fa: false,
+            password_pepper_version: 0,
+        };
+        store.import_users(vec![record]).await.unwrap();
+        store
+    }
+
+    #[tokio::test]
+    async fn imports_valid_rows_and_reports_the_rest() {
+        let store = store_with("bob@example.com").await;
+        let rows = read_rows(csv_input().as_bytes(), Format::Csv).unwrap();
+        let mut progress = Vec::new();
+
+        let summary = import(rows, &store, 2, false, &mut progress).await.unwrap();
+
+        assert_eq!(summary.total, 5);
+        assert_eq!(summary.imported, 1);
+        let reasons: Vec<(u64, &SkipReason)> =
+            summary.skipped.iter().map(|row| (row.line, &row.reason)).collect();
+        assert!(matches!(reasons[0], (3, SkipReason::Invalid { .. })));
+        assert_eq!(reasons[1], (4, &SkipReason::AlreadyExists));
+        assert_eq!(reasons[2], (5, &SkipReason::DuplicateInFile { first_line: 2 }));
+        assert!(matches!(reasons[3], (6, SkipReason::Invalid { .. })));
+
+        let alice = store
+            .get_user(Email::parse(Secret::new("alice@example.com".to_owned())).unwrap())
+            .await
+            .unwrap();
+        assert!(alice.requires_2fa);
+        assert!(String::from_utf8(progress).unwrap().contains("processed 5/5 rows"));
+    }
+
+    #[tokio::test]
+    async fn dry_run_does_not_write() {
+        let store = store_with("bob@example.com").await;
+        let rows = read_rows(csv_input().as_bytes(), Format::Csv).unwrap();
+
+        let summary = import(rows, &store, 10, true, &mut io::sink()).await.unwrap();
+
+        assert_eq!(summary.imported, 1);
+        assert_eq!(summary.skipped.len(), 4);
+        assert_eq!(store.export_users(None, 10).await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn reads_jsonl_with_line_numbers() {
+        let input = format!(
+            "{{\"email\":\"alice@example.com\",\"password_hash\":\"{ARGON2_HASH}\"}}\n\n{{\"email\":1}}\n"
+        );
+        let rows = read_rows(input.as_bytes(), Format::Jsonl).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].0, 1);
+        assert!(rows[0].1.as_ref().is_ok_and(|row| !row.requires_2fa));
+        assert_eq!(rows[1].0, 3);
+        assert!(rows[1].1.is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use rand::RngCore;
+use secrecy::{ExposeSecret, Secret};
+
+// Secrets the service reads from its environment. Rotating one means generating a
+// new value with this command and redeploying with it.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum KeyKind {
+    /// JWT_SECRET, outstanding auth tokens become invalid
+    Jwt,
+    /// COOKIE_SECRET, pending 2FA logins have to be restarted
+    Cookie,
+    /// TWO_FA_CODE_SECRET, pending 2FA codes become invalid
+    TwoFa,
+    /// PASSWORD_PEPPERS, adds a new version and keeps the current ones
+    Pepper,
+}
+
+const GENERATED_SECRET_BYTES: usize = 32;
+
+pub fn generate_secret() -> Secret<String> {
+    let mut bytes = [0u8; GENERATED_SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    Secret::new(hex::encode(bytes))
+}
+
+// Returns the environment variable to update, its new value and what deploying it implies
+pub fn rotate(
+    kind: KeyKind,
+    current_peppers: &HashMap<i32, Secret<String>>,
+) -> (&'static str, Secret<String>, &'static str) {
+    match kind {
+        KeyKind::Jwt => (
+            "JWT_SECRET",
+            generate_secret(),
+            "Users will have to log in again.",
+        ),
+        KeyKind::Cookie => (
+            "COOKIE_SECRET",
+            generate_secret(),
+            "Logins waiting for a 2FA code will have to be restarted.",
+        ),
+        KeyKind::TwoFa => (
+            "TWO_FA_CODE_SECRET",
+            generate_secret(),
+            "2FA codes already sent will be rejected.",
+        ),
+        KeyKind::Pepper => (
+            "PASSWORD_PEPPERS",
+            rotate_peppers(current_peppers),
+            "Password hashes move to the new pepper as users log in. Keep the old versions until no user relies on them.",
+        ),
+    }
+}
+
+fn rotate_peppers(current_peppers: &HashMap<i32, Secret<String>>) -> Secret<String> {
+    let mut versions: Vec<&i32> = current_peppers.keys().collect();
+    versions.sort();
+    let next_version = versions.last().map_or(1, |version| **version + 1);
+
+    let mut peppers: Vec<String> = versions
+        .into_iter()
+        .map(|version| format!("{}:{}", version, current_peppers[version].expose_secret()))
+        .collect();
+    peppers.push(format!("{}:{}", next_version, generate_secret().expose_secret()));
+    Secret::new(peppers.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_secrets_are_long_enough_and_distinct() {
+        let first = generate_secret();
+        assert_eq!(first.expose_secret().len(), 2 * GENERATED_SECRET_BYTES);
+        assert_ne!(first.expose_secret(), generate_secret().expose_secret());
+    }
+
+    #[test]
+    fn pepper_rotation_appends_a_version() {
+        let (_, peppers, _) = rotate(KeyKind::Pepper, &HashMap::new());
+        assert!(peppers.expose_secret().starts_with("1:"));
+
+        let current = HashMap::from([
+            (2, Secret::new("second".to_owned())),
+            (1, Secret::new("first".to_owned())),
+        ]);
+        let (name, peppers, _) = rotate(KeyKind::Pepper, &current);
+        assert_eq!(name, "PASSWORD_PEPPERS");
+        let peppers: Vec<&str> = peppers.expose_secret().split(',').collect();
+        assert_eq!(&peppers[..2], &["1:first", "2:second"]);
+        assert!(peppers[2].starts_with("3:"));
+    }
+}
@@ -0,0 +1,225 @@
+use std::{path::Path, process::ExitCode, sync::Arc};
+
+use auth_service::{
+    get_postgres_pool, get_redis_connection,
+    services::data_stores::{PostgresUserStore, PostgresWebhookStore, RedisBannedTokenStore},
+    utils::auth::inspect_token,
+    utils::constants::{DATABASE_URL, PASSWORD_PEPPERS, PASSWORD_POLICY, REDIS_HOST_NAME},
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use color_eyre::eyre::{Context, Result};
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use error::CommandError;
+
+mod emails;
+mod error;
+mod export;
+mod import;
+mod keys;
+mod tokens;
+mod users;
+mod webhooks;
+
+// Administrative tasks run against the production stores, outside the HTTP service.
+// Exit codes: 0 success, 1 unexpected error, 2 usage error, 3 user not found,
+// 4 user already exists, 5 invalid input, 6 invalid token, 7 webhook or delivery not found.
+#[derive(Parser)]
+#[command(name = "auth-service-admin", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Import users with pre-hashed passwords from a CSV or JSONL file
+    Import(import::ImportArgs),
+    /// Export users and their password hashes as CSV or JSONL
+    Export(export::ExportArgs),
+    /// Apply pending database migrations
+    Migrate,
+    /// Recompute canonical emails with the configured rules and report collisions
+    CanonicalizeEmails(emails::CanonicalizeEmailsArgs),
+    /// Create an admin user. The password is read from ADMIN_PASSWORD or stdin
+    CreateAdmin {
+        email: String,
+        /// Let the admin log in without a 2FA code
+        #[arg(long)]
+        no_2fa: bool,
+    },
+    /// Prevent a user from logging in and revoke their tokens
+    DisableUser { email: String },
+    /// Allow a disabled user to log in again
+    EnableUser { email: String },
+    /// Delete a user and revoke their tokens, announced to webhooks as user.deleted
+    DeleteUser { email: String },
+    /// Revoke every token issued to a user so far
+    RevokeTokens { email: String },
+    /// Print a valid auth token for a user, for testing
+    IssueToken { email: String },
+    /// Explain why a JWT is accepted or rejected by the service
+    InspectToken {
+        /// Token to inspect, read from stdin when omitted or `-`
+        token: Option<String>,
+    },
+    /// Manage the webhooks that receive user events
+    Webhooks {
+        #[command(subcommand)]
+        command: webhooks::WebhooksCommand,
+    },
+    /// Generate a new value for one of the service's secrets
+    RotateKeys {
+        #[arg(value_enum)]
+        key: keys::KeyKind,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Format {
+    Csv,
+    Jsonl,
+}
+
+impl Format {
+    // Picks the format from the file extension, defaulting to JSONL
+    pub fn from_path(path: Option<&Path>) -> Self {
+        match path.and_then(Path::extension).and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => Format::Csv,
+            _ => Format::Jsonl,
+        }
+    }
+}
+
+// One user in an import or export file. Exports use the same shape so that
+// they can be imported again.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserRow {
+    pub email: String,
+    pub password_hash: String,
+    #[serde(default)]
+    pub requires_2fa: bool,
+    #[serde(default)]
+    pub password_pepper_version: i32,
+    // Keeps user ids, and so token subjects, stable across a migration
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    if let Err(e) = color_eyre::install() {
+        eprintln!("Failed to install color_eyre: {}", e);
+    }
+    let cli = Cli::parse();
+
+    match run(cli.command).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            match &e {
+                CommandError::UnexpectedError(report) => eprintln!("Error: {:?}", report),
+                e => eprintln!("Error: {}", e),
+            }
+            e.exit_code()
+        }
+    }
+}
+
+async fn run(command: Command) -> Result<(), CommandError> {
+    match command {
+        Command::Import(args) => {
+            let user_store = PostgresUserStore::new(configure_postgresql().await?);
+            import::run(args, &user_store).await?;
+        }
+        Command::Export(args) => {
+            let user_store = PostgresUserStore::new(configure_postgresql().await?);
+            export::run(args, &user_store).await?;
+        }
+        Command::Migrate => {
+            let pg_pool = configure_postgresql().await?;
+            sqlx::migrate!()
+                .run(&pg_pool)
+                .await
+                .wrap_err("failed to run migrations")?;
+            eprintln!("Migrations applied");
+        }
+        Command::CanonicalizeEmails(args) => {
+            let user_store = PostgresUserStore::new(configure_postgresql().await?);
+            emails::run(args, &user_store).await?;
+        }
+        Command::CreateAdmin { email, no_2fa } => {
+            let email = users::parse_email(email)?;
+            let password = users::read_admin_password()?;
+            let user_store = PostgresUserStore::new(configure_postgresql().await?);
+            users::create_admin(&user_store, &PASSWORD_POLICY, email, password, !no_2fa).await?;
+            eprintln!("Admin created");
+        }
+        Command::DisableUser { email } => {
+            let email = users::parse_email(email)?;
+            let user_store = PostgresUserStore::new(configure_postgresql().await?);
+            let banned_token_store = configure_banned_token_store().await?;
+            users::disable_user(&user_store, &banned_token_store, &email).await?;
+            eprintln!("User disabled and tokens revoked");
+        }
+        Command::EnableUser { email } => {
+            let email = users::parse_email(email)?;
+            let user_store = PostgresUserStore::new(configure_postgresql().await?);
+            users::enable_user(&user_store, &email).await?;
+            eprintln!("User enabled");
+        }
+        Command::DeleteUser { email } => {
+            let email = users::parse_email(email)?;
+            let user_store = PostgresUserStore::new(configure_postgresql().await?);
+            let banned_token_store = configure_banned_token_store().await?;
+            users::delete_user(&user_store, &banned_token_store, &email).await?;
+            eprintln!("User deleted and tokens revoked");
+        }
+        Command::RevokeTokens { email } => {
+            let email = users::parse_email(email)?;
+            let user_store = PostgresUserStore::new(configure_postgresql().await?);
+            let banned_token_store = configure_banned_token_store().await?;
+            users::revoke_tokens(&user_store, &banned_token_store, &email).await?;
+            eprintln!("Tokens revoked");
+        }
+        Command::IssueToken { email } => {
+            let email = users::parse_email(email)?;
+            let user_store = PostgresUserStore::new(configure_postgresql().await?);
+            let token = users::issue_token(&user_store, &email).await?;
+            println!("{}", token.expose_secret());
+        }
+        Command::InspectToken { token } => {
+            let token = tokens::read_token(token)?;
+            let banned_token_store = Arc::new(configure_banned_token_store().await?);
+            let inspection = inspect_token(&token, banned_token_store).await?;
+            print!("{}", tokens::describe(&inspection));
+            if !inspection.is_valid() {
+                return Err(CommandError::InvalidToken);
+            }
+        }
+        Command::Webhooks { command } => {
+            let webhook_store = PostgresWebhookStore::new(configure_postgresql().await?);
+            webhooks::run(command, &webhook_store).await?;
+        }
+        Command::RotateKeys { key } => {
+            let (name, value, note) = keys::rotate(key, &PASSWORD_PEPPERS);
+            println!("{}={}", name, value.expose_secret());
+            eprintln!("{}", note);
+        }
+    }
+    Ok(())
+}
+
+async fn configure_postgresql() -> Result<PgPool> {
+    get_postgres_pool(&DATABASE_URL)
+        .await
+        .wrap_err("failed to connect to PostgreSQL")
+}
+
+async fn configure_banned_token_store() -> Result<RedisBannedTokenStore> {
+    let conn = get_redis_connection(REDIS_HOST_NAME.to_owned())
+        .await
+        .wrap_err("failed to connect to Redis")?;
+    Ok(RedisBannedTokenStore::new(conn))
+}
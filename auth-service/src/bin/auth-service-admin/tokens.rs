@@ -0,0 +1,183 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Read},
+};
+
+use auth_service::utils::auth::TokenInspection;
+use chrono::DateTime;
+use color_eyre::eyre::Context;
+use secrecy::Secret;
+
+use crate::error::CommandError;
+
+// From the argument, or from stdin so that tokens stay out of the shell history
+pub fn read_token(token: Option<String>) -> Result<Secret<String>, CommandError> {
+    let token = match token {
+        Some(token) if token != "-" => token,
+        _ => {
+            let mut token = String::new();
+            io::stdin()
+                .read_to_string(&mut token)
+                .wrap_err("failed to read token from stdin")?;
+            token
+        }
+    };
+
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(CommandError::InvalidInput("no token given".to_owned()));
+    }
+    Ok(Secret::new(token.to_owned()))
+}
+
+// Human readable report, one line per check
+pub fn describe(inspection: &TokenInspection) -> String {
+    let mut report = String::new();
+
+    if let Some(error) = &inspection.decode_error {
+        let _ = writeln!(report, "Format:    INVALID, the token cannot be decoded ({})", error);
+        let _ = writeln!(report, "Banned:    {}", yes_no(inspection.banned, "logged out"));
+        let _ = writeln!(report, "Result:    invalid");
+        return report;
+    }
+
+    if let Some(header) = &inspection.header {
+        let _ = writeln!(report, "Algorithm: {:?}", header.alg);
+    }
+    if let Some(claims) = &inspection.claims {
+        let _ = writeln!(
+            report,
+            "Claims:    {}",
+            serde_json::to_string(claims).unwrap_or_default()
+        );
+        let _ = writeln!(report, "Issued at: {}", format_timestamp(claims.iat as i64));
+    }
+
+    let _ = writeln!(
+        report,
+        "Signature: {}",
+        if inspection.signature_valid {
+            "valid"
+        } else {
+            "INVALID, not signed with the configured JWT_SECRET"
+        }
+    );
+    let _ = match &inspection.audience {
+        Some(audience) => writeln!(
+            report,
+            "Audience:  INVALID, token is for {}, not an auth token",
+            audience
+        ),
+        None => writeln!(report, "Audience:  valid"),
+    };
+
+    if let (Some(expires_in), Some(claims)) = (inspection.expires_in, &inspection.claims) {
+        let expiry = format_timestamp(claims.exp as i64);
+        let _ = match (expires_in, inspection.expired) {
+            (expires_in, _) if expires_in >= 0 => {
+                writeln!(report, "Expiry:    {}, expires in {}s", expiry, expires_in)
+            }
+            (expires_in, false) => writeln!(
+                report,
+                "Expiry:    {}, expired {}s ago but still within the leeway",
+                expiry, -expires_in
+            ),
+            (expires_in, true) => {
+                writeln!(report, "Expiry:    EXPIRED at {}, {}s ago", expiry, -expires_in)
+            }
+        };
+    }
+
+    let _ = writeln!(report, "Banned:    {}", yes_no(inspection.banned, "logged out"));
+    let _ = match (inspection.revoked, inspection.revoked_before) {
+        (true, Some(before)) => writeln!(
+            report,
+            "Revoked:   YES, tokens issued before {} were revoked",
+            format_timestamp(before)
+        ),
+        _ => writeln!(report, "Revoked:   no"),
+    };
+
+    let _ = writeln!(
+        report,
+        "Result:    {}",
+        if inspection.is_valid() { "valid" } else { "invalid" }
+    );
+    report
+}
+
+fn yes_no(value: bool, reason: &str) -> String {
+    if value {
+        format!("YES, {}", reason)
+    } else {
+        "no".to_owned()
+    }
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|time| time.to_rfc3339())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use auth_service::{
+        domain::{BannedTokenStore, UserId},
+        services::data_stores::HashsetBannedTokenStore,
+        utils::auth::{generate_auth_token, generate_login_report_token, inspect_token},
+    };
+    use chrono::Utc;
+    use secrecy::ExposeSecret;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn explains_which_check_failed() {
+        let user_id = UserId::default();
+        let token = generate_auth_token(&user_id).unwrap();
+        let store = HashsetBannedTokenStore::default();
+
+        let inspection = inspect_token(&token, Arc::new(HashsetBannedTokenStore::default()))
+            .await
+            .unwrap();
+        let report = describe(&inspection);
+        assert!(report.contains("Signature: valid"));
+        assert!(report.contains("Result:    valid"));
+
+        store
+            .revoke_tokens_issued_before(&user_id.to_string(), Utc::now().timestamp() + 1)
+            .await
+            .unwrap();
+        let inspection = inspect_token(&token, Arc::new(store)).await.unwrap();
+        let report = describe(&inspection);
+        assert!(report.contains("Revoked:   YES"));
+        assert!(report.contains("Banned:    no"));
+        assert!(report.contains("Result:    invalid"));
+
+        let inspection = inspect_token(
+            &Secret::new("not-a-jwt".to_owned()),
+            Arc::new(HashsetBannedTokenStore::default()),
+        )
+        .await
+        .unwrap();
+        assert!(describe(&inspection).contains("Format:    INVALID"));
+
+        let report_token = generate_login_report_token(&user_id).unwrap();
+        let inspection = inspect_token(&report_token, Arc::new(HashsetBannedTokenStore::default()))
+            .await
+            .unwrap();
+        let report = describe(&inspection);
+        assert!(report.contains("Signature: valid"));
+        assert!(report.contains("Audience:  INVALID, token is for login-report, not an auth token"));
+        assert!(report.contains("Result:    invalid"));
+    }
+
+    #[test]
+    fn token_argument_is_trimmed() {
+        let token = read_token(Some(" header.claims.signature\n".to_owned())).unwrap();
+        assert_eq!(token.expose_secret(), "header.claims.signature");
+    }
+}
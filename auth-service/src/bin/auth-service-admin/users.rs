@@ -0,0 +1,197 @@
+use std::io::{self, BufRead};
+
+use auth_service::{
+    domain::{BannedTokenStore, Email, Password, PasswordPolicy, User, UserId, UserStore},
+    utils::auth::generate_auth_token,
+};
+use chrono::Utc;
+use color_eyre::eyre::Context;
+use secrecy::Secret;
+
+use crate::error::CommandError;
+
+// Password of a new admin, from the environment for scripts or from stdin
+pub const ADMIN_PASSWORD_ENV_VAR: &str = "ADMIN_PASSWORD";
+
+pub fn parse_email(email: String) -> Result<Email, CommandError> {
+    Email::parse(Secret::new(email)).map_err(|e| CommandError::InvalidInput(e.to_string()))
+}
+
+pub fn read_admin_password() -> Result<Secret<String>, CommandError> {
+    if let Ok(password) = std::env::var(ADMIN_PASSWORD_ENV_VAR) {
+        return Ok(Secret::new(password));
+    }
+
+    eprintln!("Password for the new admin (read from stdin):");
+    let mut password = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .wrap_err("failed to read password from stdin")?;
+    Ok(Secret::new(password.trim_end_matches(['\r', '\n']).to_owned()))
+}
+
+pub async fn create_admin(
+    user_store: &dyn UserStore,
+    password_policy: &PasswordPolicy,
+    email: Email,
+    password: Secret<String>,
+    requires_2fa: bool,
+) -> Result<(), CommandError> {
+    if let Err(violations) = password_policy.check(&password, &email) {
+        let reasons: Vec<String> = violations.iter().map(ToString::to_string).collect();
+        return Err(CommandError::InvalidInput(reasons.join(", ")));
+    }
+    let password = Password::parse(password).map_err(|e| CommandError::InvalidInput(e.to_string()))?;
+
+    user_store
+        .add_admin(User::new(email, password, requires_2fa))
+        .await?;
+    Ok(())
+}
+
+// Disabling also revokes the user's tokens, otherwise open sessions would stay valid
+pub async fn disable_user(
+    user_store: &dyn UserStore,
+    banned_token_store: &dyn BannedTokenStore,
+    email: &Email,
+) -> Result<(), CommandError> {
+    user_store.set_disabled(email, true).await?;
+    revoke_tokens(user_store, banned_token_store, email).await
+}
+
+// Tokens are revoked too, they would otherwise name a user that no longer exists
+pub async fn delete_user(
+    user_store: &dyn UserStore,
+    banned_token_store: &dyn BannedTokenStore,
+    email: &Email,
+) -> Result<(), CommandError> {
+    let user = user_store.get_user(email.clone()).await?;
+    user_store.delete_user(&user.id).await?;
+    revoke_tokens_of(banned_token_store, &user.id).await
+}
+
+pub async fn enable_user(user_store: &dyn UserStore, email: &Email) -> Result<(), CommandError> {
+    user_store.set_disabled(email, false).await?;
+    Ok(())
+}
+
+pub async fn revoke_tokens(
+    user_store: &dyn UserStore,
+    banned_token_store: &dyn BannedTokenStore,
+    email: &Email,
+) -> Result<(), CommandError> {
+    let user = user_store.get_user(email.clone()).await?;
+    revoke_tokens_of(banned_token_store, &user.id).await
+}
+
+// Token timestamps are in seconds, so the cutoff is the next second to also
+// revoke tokens issued in the second the command runs
+async fn revoke_tokens_of(
+    banned_token_store: &dyn BannedTokenStore,
+    user_id: &UserId,
+) -> Result<(), CommandError> {
+    banned_token_store
+        .revoke_tokens_issued_before(&user_id.to_string(), Utc::now().timestamp() + 1)
+        .await?;
+    Ok(())
+}
+
+pub async fn issue_token(user_store: &dyn UserStore, email: &Email) -> Result<Secret<String>, CommandError> {
+    let user = user_store.get_user(email.clone()).await?;
+    Ok(generate_auth_token(&user.id)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use auth_service::{
+        domain::UserStoreError,
+        services::data_stores::{HashmapUserStore, HashsetBannedTokenStore},
+        utils::auth::validate_token,
+    };
+
+    use super::*;
+
+    fn email() -> Email {
+        parse_email("admin@example.com".to_owned()).unwrap()
+    }
+
+    fn password(password: &str) -> Secret<String> {
+        Secret::new(password.to_owned())
+    }
+
+    #[tokio::test]
+    async fn creates_admin_with_policy_compliant_password() {
+        let store = HashmapUserStore::default();
+        let policy = PasswordPolicy::default();
+
+        let result = create_admin(&store, &policy, email(), password("aaaaaaaaaaaa"), true).await;
+        assert!(matches!(result, Err(CommandError::InvalidInput(_))));
+
+        create_admin(&store, &policy, email(), password("Sup3r-Secret-Pass!"), true)
+            .await
+            .unwrap();
+        let admin = store.get_user(email()).await.unwrap();
+        assert_eq!(store.is_admin(&admin.id).await, Ok(true));
+        assert!(admin.requires_2fa);
+
+        let result = create_admin(&store, &policy, email(), password("Sup3r-Secret-Pass!"), true).await;
+        assert!(matches!(result, Err(CommandError::UserAlreadyExists)));
+    }
+
+    #[tokio::test]
+    async fn disabling_revokes_tokens() {
+        let store = HashmapUserStore::default();
+        let banned_token_store = Arc::new(HashsetBannedTokenStore::default());
+        let password = Password::parse(password("Sup3r-Secret-Pass!")).unwrap();
+        let user = User::new(email(), password.clone(), false);
+        store.add_user(user.clone()).await.unwrap();
+        // Issued in the same second as the command runs
+        let token = generate_auth_token(&user.id).unwrap();
+
+        disable_user(&store, banned_token_store.as_ref(), &email()).await.unwrap();
+        assert_eq!(
+            store.validate_user(email(), password.clone()).await,
+            Err(UserStoreError::UserDisabled)
+        );
+        assert!(validate_token(&token, banned_token_store).await.is_err());
+
+        enable_user(&store, &email()).await.unwrap();
+        assert_eq!(store.validate_user(email(), password).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn deleting_removes_the_user_and_revokes_tokens() {
+        let store = HashmapUserStore::default();
+        let banned_token_store = Arc::new(HashsetBannedTokenStore::default());
+        let password = Password::parse(password("Sup3r-Secret-Pass!")).unwrap();
+        let user = User::new(email(), password, false);
+        store.add_user(user.clone()).await.unwrap();
+        let token = generate_auth_token(&user.id).unwrap();
+
+        delete_user(&store, banned_token_store.as_ref(), &email()).await.unwrap();
+        assert_eq!(store.get_user_by_id(&user.id).await, Err(UserStoreError::UserNotFound));
+        assert!(validate_token(&token, banned_token_store.clone()).await.is_err());
+        assert!(matches!(
+            delete_user(&store, banned_token_store.as_ref(), &email()).await,
+            Err(CommandError::UserNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn unknown_users_are_reported() {
+        let store = HashmapUserStore::default();
+        let banned_token_store = HashsetBannedTokenStore::default();
+        assert!(matches!(
+            revoke_tokens(&store, &banned_token_store, &email()).await,
+            Err(CommandError::UserNotFound)
+        ));
+        assert!(matches!(enable_user(&store, &email()).await, Err(CommandError::UserNotFound)));
+        assert_eq!(
+            CommandError::UserNotFound.exit_code(),
+            std::process::ExitCode::from(crate::error::EXIT_USER_NOT_FOUND)
+        );
+    }
+}
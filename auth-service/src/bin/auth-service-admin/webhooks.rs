@@ -0,0 +1,118 @@
+use auth_service::domain::{DomainEventType, WebhookStore, WebhookSubscription};
+use clap::Subcommand;
+use secrecy::ExposeSecret;
+use uuid::Uuid;
+
+use crate::error::CommandError;
+
+#[derive(Subcommand)]
+pub enum WebhooksCommand {
+    /// Subscribe an endpoint to events and print its signing secret
+    Add {
+        url: String,
+        /// Event types to send, e.g. user.created,user.deleted
+        #[arg(long, required = true, value_delimiter = ',')]
+        events: Vec<String>,
+    },
+    /// List the subscribed endpoints
+    List,
+    /// Unsubscribe an endpoint, its pending deliveries are dropped
+    Remove { id: String },
+    /// List deliveries that failed every attempt
+    DeadLetters {
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+    },
+    /// Queue a dead letter for delivery again
+    Redeliver { id: String },
+}
+
+pub async fn run(command: WebhooksCommand, store: &dyn WebhookStore) -> Result<(), CommandError> {
+    match command {
+        WebhooksCommand::Add { url, events } => {
+            let subscription = add(store, &url, &events).await?;
+            println!("{}", subscription.id);
+            eprintln!(
+                "Signing secret, shown only once: {}",
+                subscription.secret.expose_secret()
+            );
+        }
+        WebhooksCommand::List => {
+            for subscription in store.get_subscriptions().await? {
+                let events: Vec<&str> = subscription.event_types.iter().map(|t| t.as_str()).collect();
+                println!("{}\t{}\t{}", subscription.id, subscription.url, events.join(","));
+            }
+        }
+        WebhooksCommand::Remove { id } => {
+            store.remove_subscription(&parse_id(&id)?).await?;
+            eprintln!("Webhook removed");
+        }
+        WebhooksCommand::DeadLetters { limit } => {
+            for delivery in store.get_dead_letters(limit).await? {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}",
+                    delivery.id,
+                    delivery.event_type,
+                    delivery.url,
+                    delivery.attempts,
+                    delivery.last_error.unwrap_or_default()
+                );
+            }
+        }
+        WebhooksCommand::Redeliver { id } => {
+            store.redeliver(&parse_id(&id)?).await?;
+            eprintln!("Delivery queued");
+        }
+    }
+    Ok(())
+}
+
+pub async fn add(
+    store: &dyn WebhookStore,
+    url: &str,
+    events: &[String],
+) -> Result<WebhookSubscription, CommandError> {
+    let event_types = events
+        .iter()
+        .map(|event| event.trim().parse::<DomainEventType>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| CommandError::InvalidInput(e.to_string()))?;
+    let subscription =
+        WebhookSubscription::new(url, event_types).map_err(|e| CommandError::InvalidInput(e.to_string()))?;
+
+    store.add_subscription(subscription.clone()).await?;
+    Ok(subscription)
+}
+
+fn parse_id(id: &str) -> Result<Uuid, CommandError> {
+    Uuid::parse_str(id).map_err(|_| CommandError::InvalidInput(format!("Invalid id: {}", id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use auth_service::services::data_stores::HashmapWebhookStore;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn adds_subscriptions_for_known_events_only() {
+        let store = HashmapWebhookStore::default();
+        let events = vec!["user.created".to_owned(), " user.deleted".to_owned()];
+
+        let subscription = add(&store, "https://crm.example.com/hooks", &events).await.unwrap();
+        assert_eq!(
+            subscription.event_types,
+            vec![DomainEventType::UserCreated, DomainEventType::UserDeleted]
+        );
+        assert_eq!(store.get_subscriptions().await.unwrap().len(), 1);
+
+        let result = add(&store, "https://crm.example.com/hooks", &["user.renamed".to_owned()]).await;
+        assert!(matches!(result, Err(CommandError::InvalidInput(_))));
+        let result = add(&store, "not a url", &events).await;
+        assert!(matches!(result, Err(CommandError::InvalidInput(_))));
+        assert!(matches!(
+            run(WebhooksCommand::Remove { id: Uuid::new_v4().to_string() }, &store).await,
+            Err(CommandError::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,193 @@
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Report, Result};
+use uuid::Uuid;
+
+use super::UserId;
+
+// What a request to the service tried to do, one per route
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AuditAction {
+    Signup,
+    Login,
+    Logout,
+    Verify2FA,
+    Resend2FA,
+    VerifyToken,
+    StepUp,
+    ReportLogin,
+    ViewProfile,
+    UpdateProfile,
+    Enable2FA,
+    Disable2FA,
+    ListTrustedDevices,
+    RevokeTrustedDevice,
+    ViewActivity,
+    QueryAuditLog,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 16] = [
+        Self::Signup,
+        Self::Login,
+        Self::Logout,
+        Self::Verify2FA,
+        Self::Resend2FA,
+        Self::VerifyToken,
+        Self::StepUp,
+        Self::ReportLogin,
+        Self::ViewProfile,
+        Self::UpdateProfile,
+        Self::Enable2FA,
+        Self::Disable2FA,
+        Self::ListTrustedDevices,
+        Self::RevokeTrustedDevice,
+        Self::ViewActivity,
+        Self::QueryAuditLog,
+    ];
+
+    // Stored and shown under this name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Signup => "signup",
+            Self::Login => "login",
+            Self::Logout => "logout",
+            Self::Verify2FA => "verify_2fa",
+            Self::Resend2FA => "resend_2fa",
+            Self::VerifyToken => "verify_token",
+            Self::StepUp => "step_up",
+            Self::ReportLogin => "report_login",
+            Self::ViewProfile => "view_profile",
+            Self::UpdateProfile => "update_profile",
+            Self::Enable2FA => "enable_2fa",
+            Self::Disable2FA => "disable_2fa",
+            Self::ListTrustedDevices => "list_trusted_devices",
+            Self::RevokeTrustedDevice => "revoke_trusted_device",
+            Self::ViewActivity => "view_activity",
+            Self::QueryAuditLog => "query_audit_log",
+        }
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|action| action.as_str() == s)
+            .ok_or_else(|| eyre!("Unknown audit action: {}", s))
+    }
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+impl AuditOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+        }
+    }
+}
+
+impl FromStr for AuditOutcome {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "success" => Ok(Self::Success),
+            "failure" => Ok(Self::Failure),
+            _ => Err(eyre!("Unknown audit outcome: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for AuditOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// One request to the service, as kept in the audit log
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditEvent {
+    pub id: Uuid,
+    pub occurred_at: DateTime<Utc>,
+    pub action: AuditAction,
+    pub outcome: AuditOutcome,
+    // The user who acted, or whose account was acted on, when known
+    pub actor: Option<UserId>,
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    // Same id as in the tracing output of the request
+    pub request_id: Option<Uuid>,
+    // The error of a failure, or how a success went, e.g. "2fa_required"
+    pub detail: Option<String>,
+}
+
+// Filters of an audit log query, unset ones match every event
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuditQuery {
+    pub actor: Option<UserId>,
+    pub action: Option<AuditAction>,
+    pub outcome: Option<AuditOutcome>,
+    pub ip: Option<IpAddr>,
+    // Inclusive
+    pub since: Option<DateTime<Utc>>,
+    // Exclusive, pass the time of the last event seen to get the next page
+    pub until: Option<DateTime<Utc>>,
+    pub limit: usize,
+}
+
+impl AuditQuery {
+    pub const DEFAULT_LIMIT: usize = 50;
+    pub const MAX_LIMIT: usize = 500;
+
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        self.actor.is_none_or(|actor| event.actor == Some(actor))
+            && self.action.is_none_or(|action| event.action == action)
+            && self.outcome.is_none_or(|outcome| event.outcome == outcome)
+            && self.ip.is_none_or(|ip| event.ip == Some(ip))
+            && self.since.is_none_or(|since| event.occurred_at >= since)
+            && self.until.is_none_or(|until| event.occurred_at < until)
+    }
+}
+
+impl Default for AuditQuery {
+    fn default() -> Self {
+        Self {
+            actor: None,
+            action: None,
+            outcome: None,
+            ip: None,
+            since: None,
+            until: None,
+            limit: Self::DEFAULT_LIMIT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_actions_round_trip_through_their_names() {
+        for action in AuditAction::ALL {
+            assert_eq!(action.as_str().parse::<AuditAction>().unwrap(), action);
+        }
+        assert!("drop_tables".parse::<AuditAction>().is_err());
+    }
+}
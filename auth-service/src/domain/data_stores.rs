@@ -1,17 +1,60 @@
-use super::{Email, Password, User};
+use super::{
+    AuditEvent, AuditQuery, DeliveryOutcome, DomainEvent, Email, Login, LoginNovelty, Password, Profile, ProfileUpdate, TrustedDevice,
+    TrustedDeviceId, User, UserId, UserRecord, WebhookDelivery, WebhookSubscription,
+};
+use crate::utils::constants::{MAX_TWO_FA_RESENDS, TWO_FA_CODE_SECRET, TWO_FA_RESEND_COOLDOWN_SECONDS};
+use chrono::{Duration, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::hash::Hash;
 use secrecy::{Secret, ExposeSecret};
 use rand::Rng;
 use color_eyre::eyre::{eyre, Report, Result};
 use thiserror::Error;
+use uuid::Uuid;
 
 
+// The user, banned token and 2FA code stores are shared by every request without a
+// lock around them, so implementations synchronize internally where they need to.
 #[async_trait::async_trait]
 pub trait UserStore: {
     // Add the `add_user`, `get_user`, and `validate_user` methods.
     // Make sure all methods are async so we can use async user stores in the future
-    async fn add_user(&mut self, user: User) -> Result<(), UserStoreError>;
+    async fn add_user(&self, user: User) -> Result<(), UserStoreError>;
+    // Adds the user with the admin flag already set, so there is never a
+    // non-admin account left behind if creating an admin fails half way
+    async fn add_admin(&self, user: User) -> Result<(), UserStoreError>;
     async fn get_user(&self, email: Email) -> Result<User, UserStoreError>;
+    async fn get_user_by_id(&self, id: &UserId) -> Result<User, UserStoreError>;
     async fn validate_user(&self, email: Email, password: Password) -> Result<(), UserStoreError>;
+    // Adds users that keep their existing password hash. Users whose email is already
+    // registered are left untouched, their emails are returned.
+    async fn import_users(&self, users: Vec<UserRecord>) -> Result<Vec<Email>, UserStoreError>;
+    // Returns up to `limit` users ordered by email, starting after `after`
+    async fn export_users(
+        &self,
+        after: Option<Email>,
+        limit: usize,
+    ) -> Result<Vec<UserRecord>, UserStoreError>;
+    async fn set_admin(&self, email: &Email, is_admin: bool) -> Result<(), UserStoreError>;
+    async fn is_admin(&self, id: &UserId) -> Result<bool, UserStoreError>;
+    // Disabled users keep their data but `validate_user` rejects them
+    async fn set_disabled(&self, email: &Email, disabled: bool) -> Result<(), UserStoreError>;
+    // For logins that complete without `validate_user`, such as a 2FA attempt
+    // started before the user was disabled
+    async fn is_disabled(&self, id: &UserId) -> Result<bool, UserStoreError>;
+    async fn set_requires_2fa(&self, id: &UserId, requires_2fa: bool) -> Result<(), UserStoreError>;
+    // Hashes the new password with the current pepper
+    async fn update_password(&self, id: &UserId, password: Password) -> Result<(), UserStoreError>;
+    // Removes the user along with their devices and login history
+    async fn delete_user(&self, id: &UserId) -> Result<(), UserStoreError>;
+    async fn get_profile(&self, id: &UserId) -> Result<Profile, UserStoreError>;
+    // Applies an update checked by `ProfileUpdate::parse`, returns the new profile
+    async fn update_profile(
+        &self,
+        id: &UserId,
+        update: ProfileUpdate,
+    ) -> Result<Profile, UserStoreError>;
 }
 
 // Add a BannedTokenStore trait
@@ -20,8 +63,16 @@ pub trait UserStore: {
 // exact API (input parameters & return values).
 #[async_trait::async_trait]
 pub trait BannedTokenStore {
-    async fn add_token(&mut self, token: Secret<String>) -> Result<(), BannedTokenStoreError>;
+    async fn add_token(&self, token: Secret<String>) -> Result<(), BannedTokenStoreError>;
     async fn contains_token(&self, token: &Secret<String>) -> Result<bool, BannedTokenStoreError>;
+    // Revokes every token of the subject (the `sub` claim) issued before the given
+    // timestamp, without having to know the tokens themselves.
+    async fn revoke_tokens_issued_before(
+        &self,
+        subject: &str,
+        issued_before: i64,
+    ) -> Result<(), BannedTokenStoreError>;
+    async fn tokens_revoked_before(&self, subject: &str) -> Result<Option<i64>, BannedTokenStoreError>;
 }
 
 #[derive(Debug, Error)]
@@ -48,6 +99,8 @@ pub enum UserStoreError {
     UserNotFound,
     #[error("Invalid credentials")]
     InvalidCredentials,
+    #[error("User is disabled")]
+    UserDisabled,
     #[error("Unexpected error")]
     UnexpectedError(#[source] Report),
 }
@@ -58,26 +111,41 @@ impl PartialEq for UserStoreError {
             (Self::UserAlreadyExists, Self::UserAlreadyExists)
                 | (Self::UserNotFound, Self::UserNotFound)
                 | (Self::InvalidCredentials, Self::InvalidCredentials)
+                | (Self::UserDisabled, Self::UserDisabled)
                 | (Self::UnexpectedError(_), Self::UnexpectedError(_))
         )
     }
 }
 
 
-// This trait represents the interface all concrete 2FA code stores should implement
+// This trait represents the interface all concrete 2FA code stores should implement.
+// Codes are keyed by login attempt so that a user can have several pending logins
+// (e.g. from different devices) at once. Stores keep at most
+// MAX_PENDING_TWO_FA_ATTEMPTS attempts per user and evict the oldest one beyond that.
+// Stores only ever see a keyed hash of the code, never the code itself.
 #[async_trait::async_trait]
 pub trait TwoFACodeStore {
     async fn add_code(
-        &mut self,
-        email: Email,
+        &self,
+        user_id: UserId,
         login_attempt_id: LoginAttemptId,
-        code: TwoFACode,
+        code_hash: TwoFACodeHash,
+    ) -> Result<(), TwoFACodeStoreError>;
+    async fn remove_code(
+        &self,
+        login_attempt_id: &LoginAttemptId,
     ) -> Result<(), TwoFACodeStoreError>;
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError>;
     async fn get_code(
         &self,
-        email: &Email,
-    ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError>;
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(UserId, TwoFACodeHash), TwoFACodeStoreError>;
+    // Replaces the code of a pending login attempt, enforcing the resend cooldown and limit
+    async fn resend_code(
+        &self,
+        user_id: &UserId,
+        login_attempt_id: &LoginAttemptId,
+        code_hash: TwoFACodeHash,
+    ) -> Result<(), TwoFACodeStoreError>;
 }
 
 // Updated!
@@ -85,6 +153,10 @@ pub trait TwoFACodeStore {
 pub enum TwoFACodeStoreError {
     #[error("Login Attempt ID not found")]
     LoginAttemptIdNotFound,
+    #[error("2FA code resend requested too soon")]
+    ResendTooSoon,
+    #[error("2FA code resend limit reached")]
+    ResendLimitReached,
     #[error("Unexpected error")]
     UnexpectedError(#[source] Report),
 }
@@ -94,11 +166,207 @@ impl PartialEq for TwoFACodeStoreError {
         matches!(
             (self, other),
             (Self::LoginAttemptIdNotFound, Self::LoginAttemptIdNotFound)
+                | (Self::ResendTooSoon, Self::ResendTooSoon)
+                | (Self::ResendLimitReached, Self::ResendLimitReached)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+// Browsers on which users skip 2FA. Expired devices are never returned. Like the
+// stores above, it is shared without a lock, as every login may use a device.
+#[async_trait::async_trait]
+pub trait TrustedDeviceStore {
+    async fn add_device(&self, device: TrustedDevice) -> Result<(), TrustedDeviceStoreError>;
+    // Finds a device trusted by this user, and records that it was used to log in
+    async fn use_device(
+        &self,
+        user_id: &UserId,
+        id: &TrustedDeviceId,
+    ) -> Result<TrustedDevice, TrustedDeviceStoreError>;
+    // Most recently trusted first
+    async fn list_devices(&self, user_id: &UserId) -> Result<Vec<TrustedDevice>, TrustedDeviceStoreError>;
+    async fn remove_device(
+        &self,
+        user_id: &UserId,
+        id: &TrustedDeviceId,
+    ) -> Result<(), TrustedDeviceStoreError>;
+    async fn remove_all_devices(&self, user_id: &UserId) -> Result<(), TrustedDeviceStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum TrustedDeviceStoreError {
+    #[error("Trusted device not found")]
+    DeviceNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for TrustedDeviceStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::DeviceNotFound, Self::DeviceNotFound)
+                | (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+// Devices and networks users logged in from, to notice logins from new ones. Shared
+// without a lock, so implementations record logins of the same user one at a time.
+#[async_trait::async_trait]
+pub trait LoginHistoryStore {
+    // Records a successful login and tells how it compares with the earlier ones
+    async fn record_login(&self, login: Login) -> Result<LoginNovelty, LoginHistoryStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum LoginHistoryStoreError {
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for LoginHistoryStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+// Durable record of the requests made to the service, for users and admins to review.
+// Every request records an event, so the log is shared without a lock around it.
+#[async_trait::async_trait]
+pub trait AuditLog {
+    async fn record(&self, event: AuditEvent) -> Result<(), AuditLogError>;
+    // Matching events, most recent first
+    async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>, AuditLogError>;
+}
+
+#[derive(Debug, Error)]
+pub enum AuditLogError {
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for AuditLogError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+// Subscriptions of downstream systems, and the deliveries of outbox events to them.
+// Shared without a lock: claiming deliveries, not the caller, keeps them from being
+// sent twice.
+#[async_trait::async_trait]
+pub trait WebhookStore {
+    async fn add_subscription(&self, subscription: WebhookSubscription) -> Result<(), WebhookStoreError>;
+    async fn get_subscriptions(&self) -> Result<Vec<WebhookSubscription>, WebhookStoreError>;
+    // Pending deliveries of the subscription are dropped with it
+    async fn remove_subscription(&self, id: &Uuid) -> Result<(), WebhookStoreError>;
+    // Queues a delivery of up to `limit` outbox events to each subscription that wants
+    // them, and takes them out of the outbox. Returns the number of events.
+    async fn fan_out_events(&self, limit: usize) -> Result<usize, WebhookStoreError>;
+    // Up to `limit` deliveries due for an attempt. They are not handed out again for
+    // `lease`, so that several instances of the service do not send them twice.
+    async fn claim_deliveries(
+        &self,
+        limit: usize,
+        lease: Duration,
+    ) -> Result<Vec<WebhookDelivery>, WebhookStoreError>;
+    async fn record_outcome(&self, id: &Uuid, outcome: DeliveryOutcome) -> Result<(), WebhookStoreError>;
+    // Deliveries given up on, most recent first
+    async fn get_dead_letters(&self, limit: usize) -> Result<Vec<WebhookDelivery>, WebhookStoreError>;
+    // Queues a dead letter again, with a fresh set of attempts
+    async fn redeliver(&self, id: &Uuid) -> Result<(), WebhookStoreError>;
+}
+
+#[derive(Debug, Error)]
+pub enum WebhookStoreError {
+    #[error("Webhook subscription not found")]
+    SubscriptionNotFound,
+    #[error("Dead letter not found")]
+    DeadLetterNotFound,
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for WebhookStoreError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::SubscriptionNotFound, Self::SubscriptionNotFound)
+                | (Self::DeadLetterNotFound, Self::DeadLetterNotFound)
                 | (Self::UnexpectedError(_), Self::UnexpectedError(_))
         )
     }
 }
 
+// Outbox events waiting to be published to the event stream. Shared without a lock
+// like the other stores.
+#[async_trait::async_trait]
+pub trait EventOutbox {
+    // Oldest first
+    async fn get_unpublished_events(&self, limit: usize) -> Result<Vec<DomainEvent>, EventOutboxError>;
+    async fn mark_published(&self, ids: &[Uuid]) -> Result<(), EventOutboxError>;
+}
+
+#[derive(Debug, Error)]
+pub enum EventOutboxError {
+    #[error("Unexpected error")]
+    UnexpectedError(#[source] Report),
+}
+
+impl PartialEq for EventOutboxError {
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::UnexpectedError(_), Self::UnexpectedError(_))
+        )
+    }
+}
+
+// Stored alongside a 2FA code to track how often it has been re-sent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TwoFAResendState {
+    pub resend_count: u32,
+    pub last_sent_at: i64,
+}
+
+impl TwoFAResendState {
+    pub fn new(resend_count: u32, last_sent_at: i64) -> Self {
+        Self {
+            resend_count,
+            last_sent_at,
+        }
+    }
+
+    // Returns the state after one more resend, or an error if the cooldown
+    // has not elapsed yet or the maximum number of resends was reached.
+    pub fn next(&self) -> Result<Self, TwoFACodeStoreError> {
+        if self.resend_count >= MAX_TWO_FA_RESENDS {
+            return Err(TwoFACodeStoreError::ResendLimitReached);
+        }
+
+        let now = Utc::now().timestamp();
+        if now - self.last_sent_at < TWO_FA_RESEND_COOLDOWN_SECONDS {
+            return Err(TwoFACodeStoreError::ResendTooSoon);
+        }
+
+        Ok(Self::new(self.resend_count + 1, now))
+    }
+}
+
+impl Default for TwoFAResendState {
+    fn default() -> Self {
+        Self::new(0, Utc::now().timestamp())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LoginAttemptId(Secret<String>);
 
@@ -108,6 +376,14 @@ impl PartialEq for LoginAttemptId {
     }
 }
 
+impl Hash for LoginAttemptId {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.expose_secret().hash(state);
+    }
+}
+
+impl Eq for LoginAttemptId {}
+
 impl LoginAttemptId {
     pub fn parse(id: Secret<String>) -> Result<Self> {
         let id = uuid::Uuid::parse_str(id.expose_secret())
@@ -165,4 +441,109 @@ impl AsRef<Secret<String>> for TwoFACode {
     fn as_ref(&self) -> &Secret<String> {
         &self.0
     }
-}
\ No newline at end of file
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Hex encoded HMAC-SHA256 of a 2FA code, keyed with TWO_FA_CODE_SECRET and bound
+// to the login attempt the code was issued for.
+#[derive(Clone, Debug)]
+pub struct TwoFACodeHash(Secret<String>);
+
+impl PartialEq for TwoFACodeHash {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.expose_secret() == other.0.expose_secret()
+    }
+}
+
+impl TwoFACodeHash {
+    pub fn new(login_attempt_id: &LoginAttemptId, code: &TwoFACode) -> Self {
+        let mac = two_fa_code_mac(login_attempt_id, code);
+        Self(Secret::new(hex::encode(mac.finalize().into_bytes())))
+    }
+
+    pub fn parse(hash: Secret<String>) -> Result<Self> {
+        let bytes = hex::decode(hash.expose_secret()).map_err(|_| eyre!("Invalid 2FA code hash"))?;
+        if bytes.len() != <Sha256 as sha2::Digest>::output_size() {
+            return Err(eyre!("Invalid 2FA code hash"));
+        }
+        Ok(Self(hash))
+    }
+
+    // Compares in constant time so that response timing reveals nothing about the code
+    pub fn verify(&self, login_attempt_id: &LoginAttemptId, code: &TwoFACode) -> bool {
+        let Ok(expected) = hex::decode(self.0.expose_secret()) else {
+            return false;
+        };
+        two_fa_code_mac(login_attempt_id, code)
+            .verify_slice(&expected)
+            .is_ok()
+    }
+}
+
+impl AsRef<Secret<String>> for TwoFACodeHash {
+    fn as_ref(&self) -> &Secret<String> {
+        &self.0
+    }
+}
+
+fn two_fa_code_mac(login_attempt_id: &LoginAttemptId, code: &TwoFACode) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(TWO_FA_CODE_SECRET.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(login_attempt_id.expose_secret().as_bytes());
+    mac.update(b":");
+    mac.update(code.as_ref().expose_secret().as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resend_is_rejected_during_cooldown() {
+        let state = TwoFAResendState::default();
+        assert_eq!(state.next(), Err(TwoFACodeStoreError::ResendTooSoon));
+    }
+
+    #[test]
+    fn resend_is_allowed_after_cooldown() {
+        let last_sent_at = Utc::now().timestamp() - TWO_FA_RESEND_COOLDOWN_SECONDS;
+        let state = TwoFAResendState::new(0, last_sent_at).next().unwrap();
+        assert_eq!(state.resend_count, 1);
+        assert!(state.last_sent_at > last_sent_at);
+    }
+
+    #[test]
+    fn code_hash_verifies_only_the_hashed_code() {
+        let login_attempt_id = LoginAttemptId::default();
+        let code = TwoFACode::parse(Secret::new("123456".to_owned())).unwrap();
+        let other_code = TwoFACode::parse(Secret::new("654321".to_owned())).unwrap();
+        let hash = TwoFACodeHash::new(&login_attempt_id, &code);
+        assert_ne!(hash.as_ref().expose_secret(), code.as_ref().expose_secret());
+        assert!(hash.verify(&login_attempt_id, &code));
+        assert!(!hash.verify(&login_attempt_id, &other_code));
+    }
+
+    #[test]
+    fn code_hash_is_bound_to_login_attempt() {
+        let code = TwoFACode::default();
+        let hash = TwoFACodeHash::new(&LoginAttemptId::default(), &code);
+        assert!(!hash.verify(&LoginAttemptId::default(), &code));
+    }
+
+    #[test]
+    fn code_hash_parse_rejects_malformed_input() {
+        assert!(TwoFACodeHash::parse(Secret::new("123456".to_owned())).is_err());
+        assert!(TwoFACodeHash::parse(Secret::new("zz".repeat(32))).is_err());
+        let hash = TwoFACodeHash::new(&LoginAttemptId::default(), &TwoFACode::default());
+        assert_eq!(TwoFACodeHash::parse(hash.as_ref().clone()).unwrap(), hash);
+    }
+
+    #[test]
+    fn resend_is_rejected_after_limit() {
+        let last_sent_at = Utc::now().timestamp() - TWO_FA_RESEND_COOLDOWN_SECONDS;
+        let state = TwoFAResendState::new(MAX_TWO_FA_RESENDS, last_sent_at);
+        assert_eq!(state.next(), Err(TwoFACodeStoreError::ResendLimitReached));
+    }
+}
@@ -4,18 +4,26 @@ use color_eyre::eyre::{eyre, Result};
 use secrecy::{ExposeSecret, Secret};
 use validator::validate_email;
 
+use crate::utils::constants::EMAIL_FOLD_LOCAL_PART_CASE;
+
+// Keeps the address as the user typed it, for display and for sending emails, and
+// a canonical form that identifies the account. Two emails are equal when their
+// canonical forms are, so `Bob@Example.com` and `bob@example.com` are the same user.
 #[derive(Debug, Clone)]
-pub struct Email(Secret<String>);
+pub struct Email {
+    display: Secret<String>,
+    canonical: Secret<String>,
+}
 
 impl PartialEq for Email {
     fn eq(&self, other: &Self) -> bool {
-        self.0.expose_secret() == other.0.expose_secret()
+        self.canonical.expose_secret() == other.canonical.expose_secret()
     }
 }
 
 impl Hash for Email {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.0.expose_secret().hash(state);
+        self.canonical.expose_secret().hash(state);
     }
 }
 
@@ -23,26 +31,57 @@ impl Eq for Email {}
 
 impl Email {
     pub fn parse(s: Secret<String>) -> Result<Email> {
-        if validate_email(s.expose_secret()) {
-            Ok(Self(s))
-        } else {
-            Err(eyre!(format!(
-                "{} is not a valid email.",
-                s.expose_secret()
-            )))
+        Self::parse_with(s, *EMAIL_FOLD_LOCAL_PART_CASE)
+    }
+
+    // `fold_local_part_case` overrides EMAIL_FOLD_LOCAL_PART_CASE
+    pub fn parse_with(s: Secret<String>, fold_local_part_case: bool) -> Result<Email> {
+        let display = s.expose_secret().trim();
+        if !validate_email(display) {
+            return Err(eyre!(format!("{} is not a valid email.", display)));
         }
+        let canonical = canonicalize(display, fold_local_part_case)?;
+        Ok(Self {
+            display: Secret::new(display.to_owned()),
+            canonical: Secret::new(canonical),
+        })
+    }
+
+    // The form stored alongside the display form and used for lookups
+    pub fn canonical(&self) -> &Secret<String> {
+        &self.canonical
     }
 }
 
+// The local part, ASCII only per `validate_email`, is lowercased unless disabled. The
+// domain goes through IDNA processing, which also normalizes its Unicode form, and
+// ends up as lowercase ASCII (punycode) so that IDNs have a single spelling.
+fn canonicalize(email: &str, fold_local_part_case: bool) -> Result<String> {
+    let (local_part, domain) = email
+        .rsplit_once('@')
+        .ok_or_else(|| eyre!(format!("{} is not a valid email.", email)))?;
+
+    let local_part = if fold_local_part_case {
+        local_part.to_lowercase()
+    } else {
+        local_part.to_owned()
+    };
+
+    let domain = idna::domain_to_ascii(domain.trim_end_matches('.'))
+        .map_err(|_| eyre!(format!("{} is not a valid email domain.", domain)))?;
+
+    Ok(format!("{}@{}", local_part, domain))
+}
+
 impl AsRef<Secret<String>> for Email {
     fn as_ref(&self) -> &Secret<String> {
-        &self.0
+        &self.display
     }
 }
 
 impl ExposeSecret<String> for Email {
     fn expose_secret(&self) -> &String {
-        self.0.expose_secret()
+        self.display.expose_secret()
     }
 }
 
@@ -52,7 +91,7 @@ mod tests {
 
     use fake::faker::internet::en::SafeEmail;
     use fake::Fake;
-    use secrecy::Secret;
+    use secrecy::{ExposeSecret, Secret};
 
     #[test]
     fn empty_string_is_rejected() {
@@ -70,6 +109,39 @@ mod tests {
         assert!(Email::parse(email).is_err());
     }
 
+    fn parse(email: &str) -> Email {
+        Email::parse_with(Secret::new(email.to_owned()), true).unwrap()
+    }
+
+    #[test]
+    fn case_and_whitespace_variants_are_the_same_email() {
+        let email = parse(" Bob@Example.COM ");
+        assert_eq!(email, parse("bob@example.com"));
+        assert_eq!(email.expose_secret(), "Bob@Example.COM");
+        assert_eq!(email.canonical().expose_secret(), "bob@example.com");
+    }
+
+    #[test]
+    fn local_part_case_folding_can_be_disabled() {
+        let email = Email::parse_with(Secret::new("Bob@Example.com".to_owned()), false).unwrap();
+        assert_eq!(email.canonical().expose_secret(), "Bob@example.com");
+        assert_ne!(email, parse("bob@example.com"));
+    }
+
+    #[test]
+    fn internationalized_domains_are_converted_to_ascii() {
+        let email = parse("user@Bücher.example");
+        assert_eq!(email.canonical().expose_secret(), "user@xn--bcher-kva.example");
+        assert_eq!(email, parse("user@xn--bcher-kva.example"));
+        assert_eq!(email.expose_secret(), "user@Bücher.example");
+    }
+
+    #[test]
+    fn domain_is_unicode_normalized() {
+        // "ü" precomposed and as "u" followed by a combining diaeresis
+        assert_eq!(parse("user@b\u{fc}cher.example"), parse("user@bu\u{308}cher.example"));
+    }
+
     #[derive(Debug, Clone)]
     struct ValidEmailFixture(pub String);
 
@@ -1,4 +1,7 @@
-use crate::domain::data_stores::UserStoreError;
+use crate::domain::{
+    data_stores::{TrustedDeviceStoreError, TwoFACodeStoreError, UserStoreError},
+    PasswordViolation, ProfileViolation, SignupPolicyViolation,
+};
 use color_eyre::eyre::Report;
 use thiserror::Error;
 
@@ -10,10 +13,28 @@ pub enum AuthAPIError {
     InvalidCredentials,
     #[error("Incorrect credentials")]
     IncorrectCredentials,
+    #[error("User is disabled")]
+    UserDisabled,
     #[error("Missing token")]
     MissingToken,
     #[error("Invalid token")]
     InvalidToken,
+    #[error("2FA code resend requested too soon")]
+    TwoFAResendTooSoon,
+    #[error("2FA code resend limit reached")]
+    TwoFAResendLimitReached,
+    #[error("Password does not meet the password policy")]
+    PasswordPolicyViolation(Vec<PasswordViolation>),
+    #[error("Profile update is invalid")]
+    InvalidProfile(Vec<ProfileViolation>),
+    #[error("Trusted device not found")]
+    TrustedDeviceNotFound,
+    #[error("User is not an admin")]
+    NotAdmin,
+    #[error("Invalid audit log query")]
+    InvalidAuditQuery,
+    #[error("Email domain is not allowed to sign up")]
+    SignupPolicyViolation(SignupPolicyViolation),
     #[error("Unexpected error")]
     UnexpectedError(#[source] Report),
 }
@@ -23,8 +44,29 @@ impl From<UserStoreError> for AuthAPIError {
         match error {
             UserStoreError::UserNotFound => AuthAPIError::IncorrectCredentials,
             UserStoreError::InvalidCredentials => AuthAPIError::InvalidCredentials,
+            UserStoreError::UserDisabled => AuthAPIError::UserDisabled,
             UserStoreError::UserAlreadyExists => AuthAPIError::UserAlreadyExists,
             UserStoreError::UnexpectedError(e) => AuthAPIError::UnexpectedError(e),
         }
     }
-}
\ No newline at end of file
+}
+
+impl From<TrustedDeviceStoreError> for AuthAPIError {
+    fn from(error: TrustedDeviceStoreError) -> Self {
+        match error {
+            TrustedDeviceStoreError::DeviceNotFound => AuthAPIError::TrustedDeviceNotFound,
+            TrustedDeviceStoreError::UnexpectedError(e) => AuthAPIError::UnexpectedError(e),
+        }
+    }
+}
+
+impl From<TwoFACodeStoreError> for AuthAPIError {
+    fn from(error: TwoFACodeStoreError) -> Self {
+        match error {
+            TwoFACodeStoreError::LoginAttemptIdNotFound => AuthAPIError::IncorrectCredentials,
+            TwoFACodeStoreError::ResendTooSoon => AuthAPIError::TwoFAResendTooSoon,
+            TwoFACodeStoreError::ResendLimitReached => AuthAPIError::TwoFAResendLimitReached,
+            TwoFACodeStoreError::UnexpectedError(e) => AuthAPIError::UnexpectedError(e),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Context, Result};
+use uuid::Uuid;
+
+use super::{DomainEvent, DomainEventType};
+
+// Version of the entry fields below, raised on incompatible changes
+pub const EVENT_STREAM_SCHEMA_VERSION: &str = "1";
+
+// A log that internal services read events from, e.g. a Redis Stream. Each event is
+// one entry with the fields `version`, `id`, `type`, `occurredAt` and `data`, the last
+// one holding the JSON object sent as `data` to webhooks.
+#[async_trait::async_trait]
+pub trait EventStream {
+    // Appends the events in the given order
+    async fn publish(&self, events: &[DomainEvent]) -> Result<()>;
+}
+
+// The fields of a stream entry, in the order they are written
+pub fn event_stream_fields(event: &DomainEvent) -> Vec<(&'static str, String)> {
+    vec![
+        ("version", EVENT_STREAM_SCHEMA_VERSION.to_owned()),
+        ("id", event.id.to_string()),
+        ("type", event.event_type.as_str().to_owned()),
+        ("occurredAt", event.occurred_at.to_rfc3339()),
+        ("data", event.data.to_string()),
+    ]
+}
+
+// An event as read back by a consumer of the stream
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamEvent {
+    // Position in the stream, to resume reading after
+    pub entry_id: String,
+    pub event: DomainEvent,
+}
+
+impl StreamEvent {
+    pub fn parse(entry_id: String, mut fields: HashMap<String, String>) -> Result<Self> {
+        let mut field = |name: &str| fields.remove(name).ok_or_else(|| eyre!("Missing field {}", name));
+        let version = field("version")?;
+        if version != EVENT_STREAM_SCHEMA_VERSION {
+            return Err(eyre!("Unsupported event schema version {}", version));
+        }
+
+        let event = DomainEvent {
+            id: Uuid::parse_str(&field("id")?).wrap_err("Invalid event id")?,
+            event_type: field("type")?.parse::<DomainEventType>()?,
+            occurred_at: DateTime::parse_from_rfc3339(&field("occurredAt")?)
+                .wrap_err("Invalid event time")?
+                .with_timezone(&Utc),
+            data: serde_json::from_str(&field("data")?).wrap_err("Invalid event data")?,
+        };
+        Ok(Self { entry_id, event })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::UserId;
+
+    #[test]
+    fn test_fields_parse_back_into_the_event() {
+        let event = DomainEvent::user_login(&UserId::default());
+        let fields: HashMap<String, String> = event_stream_fields(&event)
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value))
+            .collect();
+
+        let parsed = StreamEvent::parse("1-0".to_owned(), fields.clone()).unwrap();
+        assert_eq!(parsed.event, event);
+
+        let mut future = fields;
+        future.insert("version".to_owned(), "2".to_owned());
+        assert!(StreamEvent::parse("1-0".to_owned(), future).is_err());
+    }
+}
@@ -0,0 +1,44 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+
+use super::UserId;
+
+// Where and when a user logged in. The IP address and user agent are missing when
+// the request did not tell them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Login {
+    pub user_id: UserId,
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    pub logged_in_at: DateTime<Utc>,
+}
+
+impl Login {
+    pub fn new(user_id: UserId, ip: Option<IpAddr>, user_agent: Option<String>) -> Self {
+        Self {
+            user_id,
+            ip,
+            user_agent,
+            logged_in_at: Utc::now(),
+        }
+    }
+
+    // Logins from the same device and network share this key
+    pub fn device_key(&self) -> (String, String) {
+        (
+            self.ip.map(|ip| ip.to_string()).unwrap_or_default(),
+            self.user_agent.clone().unwrap_or_default(),
+        )
+    }
+}
+
+// How a login compares with the earlier logins of the user
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoginNovelty {
+    // The user never logged in before, e.g. right after signing up
+    FirstLogin,
+    KnownDevice,
+    // Another device or IP address than all earlier logins
+    NewDevice,
+}
@@ -0,0 +1,22 @@
+use std::sync::Mutex;
+
+use color_eyre::eyre::Result;
+
+use crate::domain::{DomainEvent, EventStream};
+
+// Keeps the published events in memory, for tests
+#[derive(Default)]
+pub struct MockEventStream {
+    pub events: Mutex<Vec<DomainEvent>>,
+}
+
+#[async_trait::async_trait]
+impl EventStream for MockEventStream {
+    async fn publish(&self, events: &[DomainEvent]) -> Result<()> {
+        self.events
+            .lock()
+            .expect("Mock event stream lock poisoned")
+            .extend_from_slice(events);
+        Ok(())
+    }
+}
@@ -1,14 +1,31 @@
 mod user;
 mod error;
 mod data_stores;
+pub mod audit;
 pub mod email_client;
 pub mod mock_email_client;
+pub mod mock_event_stream;
 pub mod email;
+pub mod event_stream;
+pub mod login_history;
 pub mod password;
+pub mod password_policy;
+pub mod profile;
+pub mod signup_policy;
+pub mod trusted_device;
+pub mod webhook;
 
 pub use user::*;
 pub use error::*;
 pub use data_stores::*;
+pub use audit::*;
 pub use email_client::*;
 pub use email::*;
-pub use password::*;
\ No newline at end of file
+pub use event_stream::*;
+pub use login_history::*;
+pub use password::*;
+pub use password_policy::*;
+pub use profile::*;
+pub use signup_policy::*;
+pub use trusted_device::*;
+pub use webhook::*;
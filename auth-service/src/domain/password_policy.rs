@@ -0,0 +1,362 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::Arc,
+};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use secrecy::{ExposeSecret, Secret};
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+
+use super::Email;
+
+// Rules a new password must satisfy. `Password::parse` only enforces the minimum
+// needed to handle a password at all, this policy is applied when a password is chosen.
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    // Reject passwords containing the local part of the user's email
+    pub reject_email_local_part: bool,
+    // Minimum score returned by `estimate_strength`, from 0 (weakest) to 4
+    pub min_strength: u8,
+    pub breached_passwords: Arc<BreachedPasswords>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            max_length: 128,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            reject_email_local_part: true,
+            min_strength: 2,
+            breached_passwords: Arc::new(BreachedPasswords::default()),
+        }
+    }
+}
+
+impl PasswordPolicy {
+    // Returns every rule the password breaks, so the user can fix them all at once
+    pub fn check(&self, password: &Secret<String>, email: &Email) -> Result<(), Vec<PasswordViolation>> {
+        let password = password.expose_secret();
+        let length = password.chars().count();
+        let mut violations = Vec::new();
+
+        if length < self.min_length {
+            violations.push(PasswordViolation::TooShort { min_length: self.min_length });
+        }
+        if length > self.max_length {
+            violations.push(PasswordViolation::TooLong { max_length: self.max_length });
+        }
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            violations.push(PasswordViolation::MissingLowercase);
+        }
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            violations.push(PasswordViolation::MissingUppercase);
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            violations.push(PasswordViolation::MissingDigit);
+        }
+        if self.require_symbol && !password.chars().any(is_symbol) {
+            violations.push(PasswordViolation::MissingSymbol);
+        }
+        if self.reject_email_local_part && contains_email_local_part(password, email) {
+            violations.push(PasswordViolation::ContainsEmail);
+        }
+        if estimate_strength(password) < self.min_strength {
+            violations.push(PasswordViolation::TooWeak);
+        }
+        if self.breached_passwords.contains(password) {
+            violations.push(PasswordViolation::Breached);
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum PasswordViolation {
+    #[error("Password must be at least {min_length} characters long")]
+    TooShort { min_length: usize },
+    #[error("Password must be at most {max_length} characters long")]
+    TooLong { max_length: usize },
+    #[error("Password must contain a lowercase letter")]
+    MissingLowercase,
+    #[error("Password must contain an uppercase letter")]
+    MissingUppercase,
+    #[error("Password must contain a digit")]
+    MissingDigit,
+    #[error("Password must contain a symbol")]
+    MissingSymbol,
+    #[error("Password must not contain your email address")]
+    ContainsEmail,
+    #[error("Password is too easy to guess")]
+    TooWeak,
+    #[error("Password has appeared in a data breach")]
+    Breached,
+}
+
+impl PasswordViolation {
+    // Stable identifier clients can match on, unlike the message
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::TooShort { .. } => "too_short",
+            Self::TooLong { .. } => "too_long",
+            Self::MissingLowercase => "missing_lowercase",
+            Self::MissingUppercase => "missing_uppercase",
+            Self::MissingDigit => "missing_digit",
+            Self::MissingSymbol => "missing_symbol",
+            Self::ContainsEmail => "contains_email",
+            Self::TooWeak => "too_weak",
+            Self::Breached => "breached",
+        }
+    }
+}
+
+fn is_symbol(c: char) -> bool {
+    !c.is_alphanumeric() && !c.is_whitespace()
+}
+
+fn contains_email_local_part(password: &str, email: &Email) -> bool {
+    let local_part = match email.as_ref().expose_secret().split('@').next() {
+        Some(local_part) => local_part.to_lowercase(),
+        None => return false,
+    };
+    // Very short local parts would reject too many unrelated passwords
+    local_part.chars().count() >= 3 && password.to_lowercase().contains(&local_part)
+}
+
+// Rough strength score from 0 to 4 based on the entropy of the character classes
+// used. Repeated characters and runs like "abc" or "321" add no entropy.
+pub fn estimate_strength(password: &str) -> u8 {
+    let mut pool_size = 0;
+    if password.chars().any(|c| c.is_ascii_lowercase()) {
+        pool_size += 26;
+    }
+    if password.chars().any(|c| c.is_ascii_uppercase()) {
+        pool_size += 26;
+    }
+    if password.chars().any(|c| c.is_ascii_digit()) {
+        pool_size += 10;
+    }
+    if password.chars().any(|c| c.is_ascii() && is_symbol(c)) {
+        pool_size += 33;
+    }
+    if !password.is_ascii() {
+        pool_size += 100;
+    }
+    if pool_size == 0 {
+        return 0;
+    }
+
+    let mut effective_length = 0;
+    let mut previous: Option<char> = None;
+    for c in password.chars() {
+        let predictable = previous.is_some_and(|p| (c as i64 - p as i64).abs() <= 1);
+        if !predictable {
+            effective_length += 1;
+        }
+        previous = Some(c);
+    }
+
+    let bits = effective_length as f64 * (pool_size as f64).log2();
+    match bits {
+        b if b < 25.0 => 0,
+        b if b < 35.0 => 1,
+        b if b < 50.0 => 2,
+        b if b < 70.0 => 3,
+        _ => 4,
+    }
+}
+
+// Offline corpus of breached passwords, stored as upper case hex SHA-1 hashes
+// (one `HASH[:COUNT]` per line, the format of the Have I Been Pwned downloads).
+// Hashes are grouped by their first five characters like the k-anonymity range
+// API, so a lookup only ever compares against the hashes sharing its prefix.
+// The corpus is held in memory, so it should be a top-N extract of the download
+// (e.g. the most common few hundred thousand hashes), never the full file.
+#[derive(Debug, Default)]
+pub struct BreachedPasswords {
+    ranges: HashMap<String, HashSet<String>>,
+}
+
+const HASH_PREFIX_LENGTH: usize = 5;
+const SHA1_HEX_LENGTH: usize = 40;
+// About 700,000 `HASH:COUNT` lines, which take roughly 60 MB once loaded
+const MAX_CORPUS_FILE_BYTES: u64 = 32 * 1024 * 1024;
+
+impl BreachedPasswords {
+    pub fn parse(corpus: &str) -> Result<Self> {
+        let mut ranges: HashMap<String, HashSet<String>> = HashMap::new();
+
+        for (index, line) in corpus.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let hash = line.split(':').next().unwrap_or_default().to_uppercase();
+            if hash.len() != SHA1_HEX_LENGTH || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(eyre!("Invalid SHA-1 hash on line {}", index + 1));
+            }
+
+            let (prefix, suffix) = hash.split_at(HASH_PREFIX_LENGTH);
+            ranges
+                .entry(prefix.to_owned())
+                .or_default()
+                .insert(suffix.to_owned());
+        }
+
+        Ok(Self { ranges })
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let size = std::fs::metadata(path)
+            .wrap_err_with(|| format!("failed to read breached password file {}", path.display()))?
+            .len();
+        if size > MAX_CORPUS_FILE_BYTES {
+            return Err(eyre!(
+                "breached password file {} is {} bytes, over the {} byte limit: \
+                 use a top-N extract of the Have I Been Pwned download, not the full file",
+                path.display(),
+                size,
+                MAX_CORPUS_FILE_BYTES
+            ));
+        }
+
+        let corpus = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read breached password file {}", path.display()))?;
+        Self::parse(&corpus)
+    }
+
+    pub fn contains(&self, password: &str) -> bool {
+        let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(HASH_PREFIX_LENGTH);
+        self.ranges
+            .get(prefix)
+            .is_some_and(|suffixes| suffixes.contains(suffix))
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.values().map(HashSet::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email() -> Email {
+        Email::parse(Secret::new("jane.doe@example.com".to_owned())).unwrap()
+    }
+
+    fn check(policy: &PasswordPolicy, password: &str) -> Result<(), Vec<PasswordViolation>> {
+        policy.check(&Secret::new(password.to_owned()), &email())
+    }
+
+    #[test]
+    fn default_policy_accepts_strong_password() {
+        assert_eq!(check(&PasswordPolicy::default(), "Tr0ub4dor&Horse"), Ok(()));
+    }
+
+    #[test]
+    fn length_limits_are_enforced() {
+        let policy = PasswordPolicy {
+            max_length: 16,
+            ..Default::default()
+        };
+        assert!(check(&policy, "Xk3!").unwrap_err().contains(&PasswordViolation::TooShort { min_length: 8 }));
+        assert!(check(&policy, "Xk3!pQ9#mW2$zR7&vL").unwrap_err().contains(&PasswordViolation::TooLong { max_length: 16 }));
+    }
+
+    #[test]
+    fn character_classes_are_enforced_when_configured() {
+        let policy = PasswordPolicy {
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            check(&policy, "qwmzkxnvbt"),
+            Err(vec![
+                PasswordViolation::MissingUppercase,
+                PasswordViolation::MissingDigit,
+                PasswordViolation::MissingSymbol,
+            ])
+        );
+        assert_eq!(check(&policy, "Qwmzk7xn!b"), Ok(()));
+    }
+
+    #[test]
+    fn password_containing_email_local_part_is_rejected() {
+        assert_eq!(
+            check(&PasswordPolicy::default(), "My-JANE.DOE-pw7"),
+            Err(vec![PasswordViolation::ContainsEmail])
+        );
+    }
+
+    #[test]
+    fn predictable_passwords_are_too_weak() {
+        assert_eq!(estimate_strength("aaaaaaaaaaaa"), 0);
+        assert_eq!(estimate_strength("123456789"), 0);
+        assert_eq!(estimate_strength("abcdefghij"), 0);
+        assert!(estimate_strength("Tr0ub4dor&Horse") >= 3);
+        assert_eq!(
+            check(&PasswordPolicy::default(), "aaaaaaaaaaaa"),
+            Err(vec![PasswordViolation::TooWeak])
+        );
+    }
+
+    #[test]
+    fn breached_passwords_are_rejected() {
+        // SHA-1 of "Tr0ub4dor&Horse", with a count like the HIBP files
+        let hash = hex::encode_upper(Sha1::digest(b"Tr0ub4dor&Horse"));
+        let corpus = BreachedPasswords::parse(&format!("# comment\n\n{}:42\n", hash.to_lowercase())).unwrap();
+        assert_eq!(corpus.len(), 1);
+        assert!(corpus.contains("Tr0ub4dor&Horse"));
+        assert!(!corpus.contains("Tr0ub4dor&Horse2"));
+
+        let policy = PasswordPolicy {
+            breached_passwords: Arc::new(corpus),
+            ..Default::default()
+        };
+        assert_eq!(check(&policy, "Tr0ub4dor&Horse"), Err(vec![PasswordViolation::Breached]));
+    }
+
+    #[test]
+    fn malformed_corpus_is_rejected() {
+        assert!(BreachedPasswords::parse("not-a-hash\n").is_err());
+    }
+
+    #[test]
+    fn oversized_corpus_file_is_rejected() {
+        let path = std::env::temp_dir().join(format!("breached-{}.txt", uuid::Uuid::new_v4()));
+        let file = std::fs::File::create(&path).unwrap();
+        file.set_len(MAX_CORPUS_FILE_BYTES + 1).unwrap();
+
+        let error = BreachedPasswords::from_file(&path).unwrap_err();
+        assert!(error.to_string().contains("over the"));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+use std::str::FromStr;
+
+use color_eyre::eyre::{eyre, Report};
+use serde::{Deserialize, Deserializer, Serialize};
+use thiserror::Error;
+
+// Optional details shared by every app using the service, so that they do not
+// each keep their own copy.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub display_name: Option<String>,
+    // BCP 47 language tag, e.g. `en-US`
+    pub locale: Option<String>,
+    // IANA time zone, e.g. `Europe/Paris`
+    pub timezone: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+// Body of `PATCH /me`. A missing field is left unchanged, `null` clears it.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ProfileUpdate {
+    #[serde(default, deserialize_with = "present")]
+    pub display_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub locale: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub timezone: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub avatar_url: Option<Option<String>>,
+}
+
+// Tells a field set to `null` apart from a missing one
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Option<String>>, D::Error> {
+    Option::<String>::deserialize(deserializer).map(Some)
+}
+
+const MAX_DISPLAY_NAME_LENGTH: usize = 100;
+const MAX_AVATAR_URL_LENGTH: usize = 2048;
+
+impl ProfileUpdate {
+    // Trims every value, blank values clear the field. Returns every invalid field
+    // so the user can fix them all at once.
+    pub fn parse(self) -> Result<Self, Vec<ProfileViolation>> {
+        let mut violations = Vec::new();
+        let mut check = |value: Option<Option<String>>, valid: fn(&str) -> bool, violation| {
+            let value = value.map(|value| {
+                value
+                    .map(|value| value.trim().to_owned())
+                    .filter(|value| !value.is_empty())
+            });
+            if let Some(Some(value)) = &value {
+                if !valid(value) {
+                    violations.push(violation);
+                }
+            }
+            value
+        };
+
+        let update = Self {
+            display_name: check(self.display_name, is_valid_display_name, ProfileViolation::InvalidDisplayName),
+            locale: check(self.locale, is_valid_locale, ProfileViolation::InvalidLocale),
+            timezone: check(self.timezone, is_valid_timezone, ProfileViolation::InvalidTimezone),
+            avatar_url: check(self.avatar_url, is_valid_avatar_url, ProfileViolation::InvalidAvatarUrl),
+        };
+
+        if violations.is_empty() {
+            Ok(update)
+        } else {
+            Err(violations)
+        }
+    }
+
+    pub fn apply(self, profile: &mut Profile) {
+        let fields = [
+            (self.display_name, &mut profile.display_name),
+            (self.locale, &mut profile.locale),
+            (self.timezone, &mut profile.timezone),
+            (self.avatar_url, &mut profile.avatar_url),
+        ];
+        for (update, field) in fields {
+            if let Some(value) = update {
+                *field = value;
+            }
+        }
+    }
+}
+
+fn is_valid_display_name(name: &str) -> bool {
+    name.chars().count() <= MAX_DISPLAY_NAME_LENGTH && !name.chars().any(char::is_control)
+}
+
+// Language, then optional script, region and variant subtags, e.g. `zh-Hant-TW`
+fn is_valid_locale(locale: &str) -> bool {
+    let mut subtags = locale.split('-');
+    let language = subtags.next().unwrap_or_default();
+    (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|subtag| {
+            (2..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+fn is_valid_timezone(timezone: &str) -> bool {
+    timezone.parse::<chrono_tz::Tz>().is_ok()
+}
+
+fn is_valid_avatar_url(url: &str) -> bool {
+    url.len() <= MAX_AVATAR_URL_LENGTH
+        && url::Url::parse(url).is_ok_and(|url| url.scheme() == "https" && url.host().is_some())
+}
+
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum ProfileViolation {
+    #[error("Display name must be at most 100 characters, without control characters")]
+    InvalidDisplayName,
+    #[error("Locale must be a language tag such as en-US")]
+    InvalidLocale,
+    #[error("Timezone must be an IANA time zone such as Europe/Paris")]
+    InvalidTimezone,
+    #[error("Avatar URL must be an https URL")]
+    InvalidAvatarUrl,
+}
+
+impl ProfileViolation {
+    // Stable identifier clients can match on, unlike the message
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidDisplayName => "invalid_display_name",
+            Self::InvalidLocale => "invalid_locale",
+            Self::InvalidTimezone => "invalid_timezone",
+            Self::InvalidAvatarUrl => "invalid_avatar_url",
+        }
+    }
+}
+
+// Profile fields that can be copied into auth tokens, named after the matching
+// OpenID Connect standard claims.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProfileField {
+    Name,
+    Locale,
+    Zoneinfo,
+    Picture,
+}
+
+impl FromStr for ProfileField {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "name" => Ok(Self::Name),
+            "locale" => Ok(Self::Locale),
+            "zoneinfo" => Ok(Self::Zoneinfo),
+            "picture" => Ok(Self::Picture),
+            other => Err(eyre!("Unknown profile claim {}", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(json: serde_json::Value) -> ProfileUpdate {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn missing_fields_are_kept_and_null_fields_cleared() {
+        let mut profile = Profile {
+            display_name: Some("Jane".to_owned()),
+            locale: Some("en".to_owned()),
+            ..Default::default()
+        };
+
+        update(serde_json::json!({ "locale": null, "timezone": " Europe/Paris " }))
+            .parse()
+            .unwrap()
+            .apply(&mut profile);
+
+        assert_eq!(
+            profile,
+            Profile {
+                display_name: Some("Jane".to_owned()),
+                locale: None,
+                timezone: Some("Europe/Paris".to_owned()),
+                avatar_url: None,
+            }
+        );
+    }
+
+    #[test]
+    fn every_invalid_field_is_reported() {
+        let result = update(serde_json::json!({
+            "displayName": "Jane\u{7}",
+            "locale": "english",
+            "timezone": "Mars/Olympus_Mons",
+            "avatarUrl": "http://example.com/jane.png",
+        }))
+        .parse();
+
+        assert_eq!(
+            result,
+            Err(vec![
+                ProfileViolation::InvalidDisplayName,
+                ProfileViolation::InvalidLocale,
+                ProfileViolation::InvalidTimezone,
+                ProfileViolation::InvalidAvatarUrl,
+            ])
+        );
+    }
+
+    #[test]
+    fn valid_fields_are_accepted() {
+        let result = update(serde_json::json!({
+            "displayName": "Jane Doe",
+            "locale": "zh-Hant-TW",
+            "timezone": "America/New_York",
+            "avatarUrl": "https://cdn.example.com/avatars/jane.png",
+        }))
+        .parse();
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(serde_json::from_value::<ProfileUpdate>(serde_json::json!({ "email": "x" })).is_err());
+    }
+
+    #[test]
+    fn profile_fields_are_parsed_from_claim_names() {
+        assert_eq!("zoneinfo".parse::<ProfileField>().unwrap(), ProfileField::Zoneinfo);
+        assert!("timezone".parse::<ProfileField>().is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::SystemTime,
+};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use secrecy::ExposeSecret;
+use thiserror::Error;
+
+use super::Email;
+
+// Which email domains may sign up. A domain also covers its subdomains, so blocking
+// `example.com` blocks `mail.example.com` too. Existing users are never affected.
+#[derive(Debug, Clone, Default)]
+pub struct SignupPolicy {
+    // When not empty, only these domains may sign up
+    pub allowed_domains: HashSet<String>,
+    pub blocked_domains: HashSet<String>,
+    pub reject_disposable: bool,
+    pub disposable_domains: Arc<DisposableDomains>,
+}
+
+impl SignupPolicy {
+    // Domains are compared in their canonical form, see `Email::canonical`
+    pub fn check(&self, email: &Email) -> Result<(), SignupPolicyViolation> {
+        let domain = match email.canonical().expose_secret().rsplit_once('@') {
+            Some((_, domain)) => domain,
+            None => return Err(SignupPolicyViolation::DomainNotAllowed),
+        };
+
+        if !self.allowed_domains.is_empty() && !matches_any(domain, &self.allowed_domains) {
+            return Err(SignupPolicyViolation::DomainNotAllowed);
+        }
+        if matches_any(domain, &self.blocked_domains) {
+            return Err(SignupPolicyViolation::DomainBlocked);
+        }
+        if self.reject_disposable && self.disposable_domains.contains(domain) {
+            return Err(SignupPolicyViolation::DisposableDomain);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Error, PartialEq)]
+pub enum SignupPolicyViolation {
+    #[error("Signups are restricted to specific email domains")]
+    DomainNotAllowed,
+    #[error("Signups from this email domain are not allowed")]
+    DomainBlocked,
+    #[error("Disposable email addresses are not allowed")]
+    DisposableDomain,
+}
+
+impl SignupPolicyViolation {
+    // Stable identifier clients can match on, unlike the message
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::DomainNotAllowed => "domain_not_allowed",
+            Self::DomainBlocked => "domain_blocked",
+            Self::DisposableDomain => "disposable_domain",
+        }
+    }
+}
+
+// Parses a configured domain into the form used by canonical emails
+pub fn parse_domain(domain: &str) -> Result<String> {
+    let domain = domain.trim().trim_start_matches('@').trim_end_matches('.');
+    if domain.is_empty() {
+        return Err(eyre!("Empty email domain"));
+    }
+    let ascii = idna::domain_to_ascii(domain).map_err(|_| eyre!("Invalid email domain {}", domain))?;
+    let valid_label = |label: &str| {
+        !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    if !ascii.split('.').all(valid_label) {
+        return Err(eyre!("Invalid email domain {}", domain));
+    }
+    Ok(ascii)
+}
+
+fn matches_any(domain: &str, domains: &HashSet<String>) -> bool {
+    // Walks up the parents of the domain: `a.b.example.com`, `b.example.com`, ...
+    let mut candidate = domain;
+    loop {
+        if domains.contains(candidate) {
+            return true;
+        }
+        match candidate.split_once('.') {
+            Some((_, parent)) => candidate = parent,
+            None => return false,
+        }
+    }
+}
+
+// Throwaway email providers, one domain per line. Loaded from a file that can be
+// replaced while the service runs, `refresh` picks up the new version.
+#[derive(Debug, Default)]
+pub struct DisposableDomains {
+    domains: RwLock<HashSet<String>>,
+    source: Option<PathBuf>,
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl DisposableDomains {
+    pub fn parse(list: &str) -> Result<HashSet<String>> {
+        list.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .enumerate()
+            .map(|(index, line)| {
+                parse_domain(line).wrap_err_with(|| format!("Invalid domain on entry {}", index + 1))
+            })
+            .collect()
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let list = Self {
+            source: Some(path.as_ref().to_owned()),
+            ..Default::default()
+        };
+        list.refresh()?;
+        Ok(list)
+    }
+
+    // Reloads the file if it changed since it was last read. On error the current
+    // list is kept, so a half-written file cannot disable the check.
+    pub fn refresh(&self) -> Result<bool> {
+        let Some(path) = &self.source else {
+            return Ok(false);
+        };
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .wrap_err_with(|| format!("failed to read disposable domain file {}", path.display()))?;
+        if *self.modified.lock().expect("lock poisoned") == Some(modified) {
+            return Ok(false);
+        }
+
+        let list = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read disposable domain file {}", path.display()))?;
+        let domains = Self::parse(&list)?;
+        *self.domains.write().expect("lock poisoned") = domains;
+        *self.modified.lock().expect("lock poisoned") = Some(modified);
+        Ok(true)
+    }
+
+    pub fn contains(&self, domain: &str) -> bool {
+        matches_any(domain, &self.domains.read().expect("lock poisoned"))
+    }
+
+    pub fn len(&self) -> usize {
+        self.domains.read().expect("lock poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl From<HashSet<String>> for DisposableDomains {
+    fn from(domains: HashSet<String>) -> Self {
+        Self {
+            domains: RwLock::new(domains),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+
+    use super::*;
+
+    fn email(email: &str) -> Email {
+        Email::parse_with(Secret::new(email.to_owned()), true).unwrap()
+    }
+
+    fn domains(domains: &[&str]) -> HashSet<String> {
+        domains.iter().map(|domain| parse_domain(domain).unwrap()).collect()
+    }
+
+    #[test]
+    fn default_policy_accepts_any_domain() {
+        assert_eq!(SignupPolicy::default().check(&email("jane@example.com")), Ok(()));
+    }
+
+    #[test]
+    fn only_allowed_domains_and_their_subdomains_can_sign_up() {
+        let policy = SignupPolicy {
+            allowed_domains: domains(&["Corp.example", "bücher.example"]),
+            ..Default::default()
+        };
+        assert_eq!(policy.check(&email("jane@corp.example")), Ok(()));
+        assert_eq!(policy.check(&email("jane@eu.CORP.example")), Ok(()));
+        assert_eq!(policy.check(&email("jane@xn--bcher-kva.example")), Ok(()));
+        assert_eq!(
+            policy.check(&email("jane@notcorp.example")),
+            Err(SignupPolicyViolation::DomainNotAllowed)
+        );
+    }
+
+    #[test]
+    fn blocked_and_disposable_domains_are_rejected() {
+        let policy = SignupPolicy {
+            blocked_domains: domains(&["spam.example"]),
+            reject_disposable: true,
+            disposable_domains: Arc::new(DisposableDomains::from(domains(&["throwaway.example"]))),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.check(&email("jane@mx.spam.example")),
+            Err(SignupPolicyViolation::DomainBlocked)
+        );
+        assert_eq!(
+            policy.check(&email("jane@Throwaway.example")),
+            Err(SignupPolicyViolation::DisposableDomain)
+        );
+        assert_eq!(policy.check(&email("jane@example.com")), Ok(()));
+
+        let policy = SignupPolicy { reject_disposable: false, ..policy };
+        assert_eq!(policy.check(&email("jane@throwaway.example")), Ok(()));
+    }
+
+    #[test]
+    fn disposable_domains_are_refreshed_from_their_file() {
+        let path = std::env::temp_dir().join(format!("disposable-{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "# comment\n\nthrowaway.example\n").unwrap();
+        let list = DisposableDomains::from_file(&path).unwrap();
+        assert_eq!(list.len(), 1);
+        assert!(list.contains("throwaway.example"));
+        assert!(!list.refresh().unwrap());
+
+        std::fs::write(&path, "other.example\nthrowaway.example\n").unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(1)).unwrap();
+        assert!(list.refresh().unwrap());
+        assert!(list.contains("other.example"));
+
+        // A broken file leaves the current list in place
+        std::fs::write(&path, "not a domain!\n").unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(2)).unwrap();
+        assert!(list.refresh().is_err());
+        assert_eq!(list.len(), 2);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
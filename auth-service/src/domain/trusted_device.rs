@@ -0,0 +1,71 @@
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+use color_eyre::eyre::{eyre, Result};
+use uuid::Uuid;
+
+use super::UserId;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TrustedDeviceId(Uuid);
+
+impl TrustedDeviceId {
+    pub fn parse(id: &str) -> Result<Self> {
+        Uuid::parse_str(id)
+            .map(Self)
+            .map_err(|_| eyre!("Invalid trusted device id"))
+    }
+
+    pub fn as_uuid(&self) -> Uuid {
+        self.0
+    }
+}
+
+impl Default for TrustedDeviceId {
+    fn default() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl From<Uuid> for TrustedDeviceId {
+    fn from(id: Uuid) -> Self {
+        Self(id)
+    }
+}
+
+impl fmt::Display for TrustedDeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// A browser on which the user chose to skip 2FA until `expires_at`. The browser
+// proves it with a signed cookie holding the id.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrustedDevice {
+    pub id: TrustedDeviceId,
+    pub user_id: UserId,
+    // User agent of the browser, so the user can tell their devices apart
+    pub name: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl TrustedDevice {
+    pub fn new(user_id: UserId, name: Option<String>, ttl: Duration) -> Self {
+        let created_at = Utc::now();
+        Self {
+            id: TrustedDeviceId::default(),
+            user_id,
+            name,
+            created_at,
+            expires_at: created_at + ttl,
+            last_used_at: None,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+}
@@ -1,18 +1,87 @@
+use std::fmt;
+
+use color_eyre::eyre::{eyre, Result};
+use secrecy::Secret;
+use uuid::Uuid;
+
 use super::{Email, Password};
 
+// Stable identifier of a user. Tokens and stores refer to users by id so that
+// no personal data ends up in them and the email can change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UserId(Uuid);
+
+impl UserId {
+    pub fn parse(id: &str) -> Result<Self> {
+        Uuid::parse_str(id)
+            .map(Self)
+            .map_err(|_| eyre!("Invalid user id"))
+    }
+
+    pub fn as_uuid(&self) -> Uuid {
+        self.0
+    }
+}
+
+impl Default for UserId {
+    fn default() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl From<Uuid> for UserId {
+    fn from(id: Uuid) -> Self {
+        Self(id)
+    }
+}
+
+impl fmt::Display for UserId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct User {
+    pub id: UserId,
     pub email: Email,
     pub password: Password,
     pub requires_2fa: bool,
 }
 
 impl User {
+    // A new user, with a fresh id
     pub fn new(email: Email, password: Password, requires_2fa: bool) -> Self {
         Self {
+            id: UserId::default(),
             email,
             password,
             requires_2fa,
         }
     }
-}
\ No newline at end of file
+}
+
+// A user as persisted, with the password hash instead of the password. Used to move
+// users in and out of a store in bulk without re-hashing their passwords.
+#[derive(Clone, Debug)]
+pub struct UserRecord {
+    pub id: UserId,
+    pub email: Email,
+    // PHC string (argon2, scrypt) or bcrypt hash
+    pub password_hash: Secret<String>,
+    pub requires_2fa: bool,
+    pub password_pepper_version: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_id_round_trips_through_its_string_form() {
+        let id = UserId::default();
+        assert_eq!(UserId::parse(&id.to_string()).unwrap(), id);
+        assert!(UserId::parse("test@example.com").is_err());
+        assert_ne!(UserId::default(), id);
+    }
+}
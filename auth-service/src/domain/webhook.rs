@@ -0,0 +1,306 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Duration, Utc};
+use color_eyre::eyre::{eyre, Report, Result};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use url::Url;
+use uuid::Uuid;
+
+use super::{Email, UserId};
+
+// Header holding the signature of a webhook payload, as `t=<unix time>,v1=<hex HMAC>`
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "Webhook-Signature";
+// Id of the event, receivers use it to ignore deliveries they have seen already
+pub const WEBHOOK_ID_HEADER: &str = "Webhook-Id";
+pub const WEBHOOK_EVENT_HEADER: &str = "Webhook-Event";
+
+// Changes to users that downstream systems subscribe to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DomainEventType {
+    UserCreated,
+    UserLogin,
+    UserPasswordChanged,
+    UserDeleted,
+}
+
+impl DomainEventType {
+    pub const ALL: [DomainEventType; 4] = [
+        Self::UserCreated,
+        Self::UserLogin,
+        Self::UserPasswordChanged,
+        Self::UserDeleted,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UserCreated => "user.created",
+            Self::UserLogin => "user.login",
+            Self::UserPasswordChanged => "user.password_changed",
+            Self::UserDeleted => "user.deleted",
+        }
+    }
+}
+
+impl FromStr for DomainEventType {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|event_type| event_type.as_str() == s)
+            .ok_or_else(|| eyre!("Unknown event type: {}", s))
+    }
+}
+
+impl fmt::Display for DomainEventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// An event as written to the outbox, in the same transaction as the change it describes
+#[derive(Clone, Debug, PartialEq)]
+pub struct DomainEvent {
+    pub id: Uuid,
+    pub event_type: DomainEventType,
+    pub occurred_at: DateTime<Utc>,
+    // Always holds `userId`, and event specific fields
+    pub data: serde_json::Value,
+}
+
+impl DomainEvent {
+    fn new(event_type: DomainEventType, user_id: &UserId, mut data: serde_json::Value) -> Self {
+        data["userId"] = user_id.to_string().into();
+        Self {
+            id: Uuid::new_v4(),
+            event_type,
+            occurred_at: Utc::now(),
+            data,
+        }
+    }
+
+    pub fn user_created(user_id: &UserId, email: &Email) -> Self {
+        let data = serde_json::json!({ "email": email.expose_secret() });
+        Self::new(DomainEventType::UserCreated, user_id, data)
+    }
+
+    pub fn user_login(user_id: &UserId) -> Self {
+        Self::new(DomainEventType::UserLogin, user_id, serde_json::json!({}))
+    }
+
+    pub fn user_password_changed(user_id: &UserId) -> Self {
+        Self::new(DomainEventType::UserPasswordChanged, user_id, serde_json::json!({}))
+    }
+
+    pub fn user_deleted(user_id: &UserId) -> Self {
+        Self::new(DomainEventType::UserDeleted, user_id, serde_json::json!({}))
+    }
+
+    // Body of the webhook requests, see the webhooks section of the API schema
+    pub fn payload(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "type": self.event_type.as_str(),
+            "occurredAt": self.occurred_at.to_rfc3339(),
+            "data": self.data,
+        })
+    }
+}
+
+// An endpoint of a downstream system and the events it receives
+#[derive(Clone, Debug)]
+pub struct WebhookSubscription {
+    pub id: Uuid,
+    pub url: Url,
+    // Key of the payload signatures, shared with the receiver once when subscribing
+    pub secret: Secret<String>,
+    pub event_types: Vec<DomainEventType>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl WebhookSubscription {
+    pub fn new(url: &str, event_types: Vec<DomainEventType>) -> Result<Self> {
+        let url = Url::parse(url).map_err(|_| eyre!("Invalid webhook URL"))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(eyre!("Webhook URL must use http or https"));
+        }
+        if event_types.is_empty() {
+            return Err(eyre!("A webhook must subscribe to at least one event type"));
+        }
+
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        Ok(Self {
+            id: Uuid::new_v4(),
+            url,
+            secret: Secret::new(format!("whsec_{}", hex::encode(key))),
+            event_types,
+            created_at: Utc::now(),
+        })
+    }
+
+    pub fn wants(&self, event_type: DomainEventType) -> bool {
+        self.event_types.contains(&event_type)
+    }
+}
+
+// One event to send to one subscription, with what is needed to send it
+#[derive(Clone, Debug)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub subscription_id: Uuid,
+    pub url: String,
+    pub secret: Secret<String>,
+    pub event_id: Uuid,
+    pub event_type: DomainEventType,
+    pub payload: String,
+    // Attempts made before this one
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+// What became of an attempt to send a delivery
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeliveryOutcome {
+    Delivered,
+    Retry { error: String, at: DateTime<Utc> },
+    // Given up on, the delivery is kept as a dead letter until redelivered by hand
+    Dead { error: String },
+}
+
+// Failed deliveries are retried with exponential back-off until `max_attempts`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    // The outcome of a failed attempt, `attempts` counting the failed one
+    pub fn after_failure(&self, attempts: u32, error: String) -> DeliveryOutcome {
+        if attempts >= self.max_attempts {
+            return DeliveryOutcome::Dead { error };
+        }
+        let factor = 2i32.saturating_pow(attempts.saturating_sub(1));
+        let delay = self
+            .base_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        DeliveryOutcome::Retry { error, at: Utc::now() + delay }
+    }
+}
+
+// Signs the payload along with the time, so that a captured request cannot be replayed
+// later. Receivers compute the same HMAC-SHA256 of `<t>.<body>` with their secret.
+pub fn sign_webhook_payload(secret: &Secret<String>, timestamp: i64, payload: &str) -> String {
+    let mut mac = hmac(secret);
+    mac.update(format!("{}.{}", timestamp, payload).as_bytes());
+    format!("t={},v1={}", timestamp, hex::encode(mac.finalize().into_bytes()))
+}
+
+// What a receiver does with a `Webhook-Signature` header. The signature must match
+// and be at most `tolerance` old.
+pub fn verify_webhook_signature(
+    secret: &Secret<String>,
+    header: &str,
+    payload: &str,
+    tolerance: Duration,
+) -> bool {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => timestamp = value.parse::<i64>().ok(),
+            Some(("v1", value)) => signatures.extend(hex::decode(value).ok()),
+            _ => {}
+        }
+    }
+    let Some(timestamp) = timestamp else {
+        return false;
+    };
+    if (Utc::now().timestamp() - timestamp).abs() > tolerance.num_seconds() {
+        return false;
+    }
+
+    signatures.iter().any(|candidate| {
+        let mut mac = hmac(secret);
+        mac.update(format!("{}.{}", timestamp, payload).as_bytes());
+        mac.verify_slice(candidate).is_ok()
+    })
+}
+
+fn hmac(secret: &Secret<String>) -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret() -> Secret<String> {
+        Secret::new("whsec_test".to_owned())
+    }
+
+    #[test]
+    fn test_signatures_are_checked_with_the_secret_and_time() {
+        let now = Utc::now().timestamp();
+        let header = sign_webhook_payload(&secret(), now, "{}");
+        let tolerance = Duration::minutes(5);
+
+        assert!(verify_webhook_signature(&secret(), &header, "{}", tolerance));
+        assert!(!verify_webhook_signature(&secret(), &header, "{\"a\":1}", tolerance));
+        assert!(!verify_webhook_signature(&Secret::new("other".to_owned()), &header, "{}", tolerance));
+        assert!(!verify_webhook_signature(&secret(), "v1=00", "{}", tolerance));
+
+        let old = sign_webhook_payload(&secret(), now - 600, "{}");
+        assert!(!verify_webhook_signature(&secret(), &old, "{}", tolerance));
+    }
+
+    #[test]
+    fn test_retries_back_off_then_give_up() {
+        let policy = RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::seconds(10),
+            max_delay: Duration::seconds(30),
+        };
+        let delay = |attempts| match policy.after_failure(attempts, "500".to_owned()) {
+            DeliveryOutcome::Retry { at, .. } => (at - Utc::now()).num_seconds() + 1,
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        };
+
+        assert_eq!(delay(1), 10);
+        assert_eq!(delay(2), 20);
+        assert_eq!(delay(3), 30);
+        assert_eq!(
+            policy.after_failure(4, "500".to_owned()),
+            DeliveryOutcome::Dead { error: "500".to_owned() }
+        );
+    }
+
+    #[test]
+    fn test_subscriptions_need_an_http_url_and_events() {
+        let subscription =
+            WebhookSubscription::new("https://crm.example.com/hooks", vec![DomainEventType::UserCreated])
+                .unwrap();
+        assert!(subscription.wants(DomainEventType::UserCreated));
+        assert!(!subscription.wants(DomainEventType::UserDeleted));
+        assert!(subscription.secret.expose_secret().starts_with("whsec_"));
+
+        assert!(WebhookSubscription::new("ftp://crm.example.com", vec![DomainEventType::UserCreated]).is_err());
+        assert!(WebhookSubscription::new("https://crm.example.com", vec![]).is_err());
+    }
+
+    #[test]
+    fn test_payload_names_the_user() {
+        let user_id = UserId::default();
+        let payload = DomainEvent::user_password_changed(&user_id).payload();
+        assert_eq!(payload["type"], "user.password_changed");
+        assert_eq!(payload["data"]["userId"], user_id.to_string());
+        assert_eq!("user.deleted".parse::<DomainEventType>().unwrap(), DomainEventType::UserDeleted);
+    }
+}
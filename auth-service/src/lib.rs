@@ -1,21 +1,26 @@
-use std::error::Error;
+use std::{error::Error, net::SocketAddr};
 
 use axum::{
     http::{HeaderValue, Method, StatusCode},
     response::{IntoResponse, Response},
-    routing::post,
+    extract::connect_info::{ConnectInfo, IntoMakeServiceWithConnectInfo},
+    middleware::{self, AddExtension},
+    routing::{delete, get, post},
     serve::Serve,
     Json, Router,
 };
 use domain::AuthAPIError;
-use redis::{Client, RedisResult};
+use redis::{aio::ConnectionManager, Client, RedisResult};
 use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
 
 use sqlx::{postgres::PgPoolOptions, PgPool};
 use tower_http::{cors::CorsLayer, services::ServeDir, trace::TraceLayer};
 use app_state::AppState;
-use utils::tracing::{make_span_with_request_id, on_request, on_response};
+use utils::{
+    audit::{record_audit_event, AuditFailure},
+    tracing::{assign_request_id, make_span_with_request_id, on_request, on_response},
+};
 
 
 pub mod routes;
@@ -26,7 +31,11 @@ pub mod utils;
 
 // This struct encapsulates our application-related logic.
 pub struct Application {
-    server: Serve<Router, Router>,
+    // Serves with the peer address, the IP of logins is recorded
+    server: Serve<
+        IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+        AddExtension<Router, ConnectInfo<SocketAddr>>,
+    >,
     // address is exposed as a public field
     // so we have access to it in tests.
     pub address: String,
@@ -41,8 +50,8 @@ impl Application {
         ];
 
         let cors = CorsLayer::new()
-            // Allow GET and POST requests
-            .allow_methods([Method::GET, Method::POST])
+            // Allow GET, POST, PATCH and DELETE requests
+            .allow_methods([Method::GET, Method::POST, Method::PATCH, Method::DELETE])
             // Allow cookies to be included in requests
             .allow_credentials(true)
             .allow_origin(allowed_origins);
@@ -53,7 +62,18 @@ impl Application {
             .route("/login", post(routes::login))
             .route("/logout", post(routes::logout))
             .route("/verify-2fa", post(routes::verify_2fa))
+            .route("/resend-2fa", post(routes::resend_2fa))
             .route("/verify-token", post(routes::verify_token))
+            .route("/step-up", post(routes::step_up))
+            .route("/report-login", post(routes::report_login))
+            .route("/me", get(routes::get_me).patch(routes::update_me))
+            .route("/me/2fa/enable", post(routes::enable_2fa))
+            .route("/me/2fa/disable", post(routes::disable_2fa))
+            .route("/me/devices", get(routes::list_trusted_devices))
+            .route("/me/devices/:id", delete(routes::revoke_trusted_device))
+            .route("/me/activity", get(routes::get_activity))
+            .route("/admin/audit-events", get(routes::query_audit_events))
+            .route_layer(middleware::from_fn_with_state(app_state.clone(), record_audit_event))
             .with_state(app_state)
             .layer(cors)
             .layer( // New!
@@ -64,11 +84,15 @@ impl Application {
                 .make_span_with(make_span_with_request_id)
                     .on_request(on_request)
                     .on_response(on_response),
-            ); // Add CORS config to our Axum router
+            ) // Add CORS config to our Axum router
+            .layer(middleware::from_fn(assign_request_id));
 
         let listener = tokio::net::TcpListener::bind(address).await?;
         let address = listener.local_addr()?.to_string();
-        let server = axum::serve(listener, router);
+        let server = axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        );
 
         // Create a new Application instance and return it
         Ok(Self { server, address })
@@ -90,15 +114,34 @@ pub fn get_redis_client(redis_hostname: String) -> RedisResult<Client> {
     redis::Client::open(redis_url)
 }
 
+// Multiplexed connection for the Redis stores. Clones are cheap and pipeline their
+// commands over the same socket, which is re-established after a failure.
+pub async fn get_redis_connection(redis_hostname: String) -> RedisResult<ConnectionManager> {
+    ConnectionManager::new(get_redis_client(redis_hostname)?).await
+}
+
 pub mod app_state {
     use std::sync::Arc;
-    use tokio::sync::RwLock;
-    use crate::domain::{BannedTokenStore, EmailClient, TwoFACodeStore, UserStore};
+    use axum::extract::FromRef;
+    use axum_extra::extract::cookie::Key;
+    use secrecy::ExposeSecret;
+    use crate::{
+        domain::{
+            AuditLog, BannedTokenStore, EmailClient, LoginHistoryStore, PasswordPolicy, ProfileField,
+            SignupPolicy, TrustedDeviceStore, TwoFACodeStore, UserStore,
+        },
+        utils::constants::{
+            COOKIE_SECRET, ENUMERATION_PROTECTION, PASSWORD_POLICY, SIGNUP_POLICY, TOKEN_PROFILE_CLAIMS,
+        },
+    };
 
     // Using a type alias to improve readability!
-    pub type UserStoreType = Arc<RwLock<dyn UserStore + Send + Sync>>;
-    pub type BannedTokenStoreType = Arc<RwLock<dyn BannedTokenStore + Send + Sync>>;
-    pub type TwoFACodeStoreType = Arc<RwLock<dyn TwoFACodeStore + Send + Sync>>;
+    pub type UserStoreType = Arc<dyn UserStore + Send + Sync>;
+    pub type BannedTokenStoreType = Arc<dyn BannedTokenStore + Send + Sync>;
+    pub type TwoFACodeStoreType = Arc<dyn TwoFACodeStore + Send + Sync>;
+    pub type TrustedDeviceStoreType = Arc<dyn TrustedDeviceStore + Send + Sync>;
+    pub type LoginHistoryStoreType = Arc<dyn LoginHistoryStore + Send + Sync>;
+    pub type AuditLogType = Arc<dyn AuditLog + Send + Sync>;
     pub type EmailClientType = Arc<dyn EmailClient + Send + Sync>;
 
 
@@ -107,7 +150,23 @@ pub mod app_state {
         pub user_store: UserStoreType,
         pub banned_token_store: BannedTokenStoreType,
         pub two_factor_code_store: TwoFACodeStoreType,
+        // Browsers on which users skip 2FA
+        pub trusted_device_store: TrustedDeviceStoreType,
+        // Devices and networks users logged in from, to alert them of new ones
+        pub login_history_store: LoginHistoryStoreType,
+        // One event per request, see `record_audit_event`
+        pub audit_log: AuditLogType,
         pub email_client: EmailClientType,
+        // Key used to sign cookies, e.g. the login attempt cookie set during 2FA
+        pub cookie_key: Key,
+        // Hide whether an email is registered from login and signup responses
+        pub enumeration_protection: bool,
+        // Rules applied to newly chosen passwords
+        pub password_policy: Arc<PasswordPolicy>,
+        // Email domains allowed to sign up
+        pub signup_policy: Arc<SignupPolicy>,
+        // Profile fields copied into the auth tokens issued at login
+        pub token_profile_claims: Vec<ProfileField>,
     }
 
     impl AppState {
@@ -115,41 +174,110 @@ pub mod app_state {
             user_store: UserStoreType, 
             banned_token_store: BannedTokenStoreType,
             two_factor_code_store: TwoFACodeStoreType,
+            trusted_device_store: TrustedDeviceStoreType,
+            login_history_store: LoginHistoryStoreType,
+            audit_log: AuditLogType,
             email_client: EmailClientType,
         ) -> Self {
             Self { 
                 user_store,
                 banned_token_store,
                 two_factor_code_store,
+                trusted_device_store,
+                login_history_store,
+                audit_log,
                 email_client,
+                cookie_key: Key::derive_from(COOKIE_SECRET.expose_secret().as_bytes()),
+                enumeration_protection: *ENUMERATION_PROTECTION,
+                password_policy: Arc::new(PASSWORD_POLICY.clone()),
+                signup_policy: Arc::new(SIGNUP_POLICY.clone()),
+                token_profile_claims: TOKEN_PROFILE_CLAIMS.clone(),
             }
         }
     }
+
+    // Allows handlers to extract a `SignedCookieJar`
+    impl FromRef<AppState> for Key {
+        fn from_ref(state: &AppState) -> Self {
+            state.cookie_key.clone()
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ErrorResponse {
     pub error: String,
+    // Details of what was wrong with the request, e.g. every broken password rule
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reasons: Vec<ErrorReason>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ErrorReason {
+    pub code: String,
+    pub message: String,
 }
 
 impl IntoResponse for AuthAPIError {
     fn into_response(self) -> Response {
         log_error_chain(&self); // New!
 
+        let reasons = match &self {
+            AuthAPIError::PasswordPolicyViolation(violations) => violations
+                .iter()
+                .map(|violation| ErrorReason {
+                    code: violation.code().to_owned(),
+                    message: violation.to_string(),
+                })
+                .collect(),
+            AuthAPIError::InvalidProfile(violations) => violations
+                .iter()
+                .map(|violation| ErrorReason {
+                    code: violation.code().to_owned(),
+                    message: violation.to_string(),
+                })
+                .collect(),
+            AuthAPIError::SignupPolicyViolation(violation) => vec![ErrorReason {
+                code: violation.code().to_owned(),
+                message: violation.to_string(),
+            }],
+            _ => Vec::new(),
+        };
+
         let (status, error_message) = match self {
             AuthAPIError::UserAlreadyExists => (StatusCode::CONFLICT, "User already exists"),
             AuthAPIError::InvalidCredentials => (StatusCode::BAD_REQUEST, "Invalid credentials"),
             AuthAPIError::IncorrectCredentials => (StatusCode::UNAUTHORIZED, "Incorrect credentials"),
+            AuthAPIError::UserDisabled => (StatusCode::FORBIDDEN, "User account is disabled"),
             AuthAPIError::UnexpectedError(_) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Unexpected error")
             },
             AuthAPIError::MissingToken => (StatusCode::BAD_REQUEST, "Missing token"),
             AuthAPIError::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid token"),
+            AuthAPIError::TwoFAResendTooSoon => {
+                (StatusCode::TOO_MANY_REQUESTS, "Please wait before requesting a new 2FA code")
+            }
+            AuthAPIError::TwoFAResendLimitReached => {
+                (StatusCode::TOO_MANY_REQUESTS, "Too many 2FA code requests")
+            }
+            AuthAPIError::PasswordPolicyViolation(_) => {
+                (StatusCode::BAD_REQUEST, "Password does not meet the requirements")
+            }
+            AuthAPIError::InvalidProfile(_) => (StatusCode::BAD_REQUEST, "Invalid profile"),
+            AuthAPIError::TrustedDeviceNotFound => (StatusCode::NOT_FOUND, "Trusted device not found"),
+            AuthAPIError::NotAdmin => (StatusCode::FORBIDDEN, "Admin rights required"),
+            AuthAPIError::InvalidAuditQuery => (StatusCode::BAD_REQUEST, "Invalid audit log query"),
+            AuthAPIError::SignupPolicyViolation(_) => {
+                (StatusCode::BAD_REQUEST, "Email address is not allowed to sign up")
+            }
         };
         let body = Json(ErrorResponse {
             error: error_message.to_string(),
+            reasons,
         });
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        response.extensions_mut().insert(AuditFailure(error_message));
+        response
     }
 }
 
@@ -1,28 +1,54 @@
 use std::sync::Arc;
 use auth_service::{
     app_state::{AppState, TwoFACodeStoreType, UserStoreType}, 
-    domain::Email, get_postgres_pool, get_redis_client, 
-    services::{data_stores::{PostgresUserStore, RedisBannedTokenStore, RedisTwoFACodeStore}, postmark_email_client::PostmarkEmailClient}, 
-    utils::{constants::{prod, DATABASE_URL, POSTMARK_AUTH_TOKEN, REDIS_HOST_NAME}, tracing::init_tracing}, Application
+    domain::{DisposableDomains, Email}, get_postgres_pool, get_redis_connection, 
+    services::{data_stores::{PostgresAuditLog, PostgresEventOutbox, PostgresLoginHistoryStore, PostgresTrustedDeviceStore, PostgresUserStore, PostgresWebhookStore, RedisBannedTokenStore, RedisTwoFACodeStore}, event_stream_publisher::EventStreamPublisher, postmark_email_client::PostmarkEmailClient, redis_event_stream::RedisEventStream, webhook_dispatcher::WebhookDispatcher}, 
+    utils::{constants::{prod, DATABASE_URL, DISPOSABLE_DOMAINS_REFRESH_INTERVAL, EVENT_STREAM_MAX_LEN, EVENT_STREAM_NAME, EVENT_STREAM_POLL_INTERVAL, PASSWORD_PEPPERS, POSTMARK_AUTH_TOKEN, REDIS_HOST_NAME, WEBHOOK_POLL_INTERVAL, WEBHOOK_RETRY_POLICY}, tracing::init_tracing}, Application
 };
+use redis::aio::ConnectionManager;
 use reqwest::Client;
 use secrecy::Secret;
 use sqlx::PgPool;
-use tokio::sync::RwLock;
 
 #[tokio::main]
 async fn main() {
     color_eyre::install().expect("Failed to install color_eyre"); // New!
     init_tracing().expect("Failed to initialize tracing"); // Updated!
+    if PASSWORD_PEPPERS.is_empty() {
+        // Likely a missing or misspelled PASSWORD_PEPPERS, new hashes would have no pepper
+        tracing::warn!("No password pepper is configured, new password hashes will not be peppered.");
+    }
     let pg_pool = configure_postgresql().await;
-    let user_store: UserStoreType = Arc::new(RwLock::new(PostgresUserStore::new(pg_pool)));
-    let redis_client = Arc::new(RwLock::new(configure_redis()));
-    let banned_token_store = Arc::new(RwLock::new(RedisBannedTokenStore::new(redis_client.clone())));
-    let two_fa_code_store: TwoFACodeStoreType  = Arc::new(RwLock::new(RedisTwoFACodeStore::new(redis_client))); 
+    let user_store: UserStoreType = Arc::new(PostgresUserStore::new(pg_pool.clone()));
+    let trusted_device_store = Arc::new(PostgresTrustedDeviceStore::new(pg_pool.clone()));
+    let login_history_store = Arc::new(PostgresLoginHistoryStore::new(pg_pool.clone()));
+    let audit_log = Arc::new(PostgresAuditLog::new(pg_pool.clone()));
+    let webhook_store = Arc::new(PostgresWebhookStore::new(pg_pool.clone()));
+    let event_outbox = Arc::new(PostgresEventOutbox::new(pg_pool));
+    let redis_conn = configure_redis().await;
+    let event_stream = Arc::new(RedisEventStream::new(
+        redis_conn.clone(),
+        EVENT_STREAM_NAME.to_owned(),
+        *EVENT_STREAM_MAX_LEN,
+    ));
+    let banned_token_store = Arc::new(RedisBannedTokenStore::new(redis_conn.clone()));
+    let two_fa_code_store: TwoFACodeStoreType  = Arc::new(RedisTwoFACodeStore::new(redis_conn)); 
 
     //let email_client: EmailClientType = Arc::new(RwLock::new(MockEmailClient));
     let email_client = Arc::new(configure_postmark_email_client()); // Updated!
-    let app_state = AppState::new(user_store, banned_token_store, two_fa_code_store, email_client);
+    let app_state = AppState::new(
+        user_store,
+        banned_token_store,
+        two_fa_code_store,
+        trusted_device_store,
+        login_history_store,
+        audit_log,
+        email_client,
+    );
+    spawn_disposable_domains_refresh(app_state.signup_policy.disposable_domains.clone());
+    WebhookDispatcher::new(webhook_store, configure_webhook_http_client(), WEBHOOK_RETRY_POLICY.clone())
+        .spawn(WEBHOOK_POLL_INTERVAL);
+    EventStreamPublisher::new(event_outbox, event_stream).spawn(EVENT_STREAM_POLL_INTERVAL);
 
     let app = Application::build(app_state, prod::APP_ADDRESS)
         .await
@@ -46,13 +72,34 @@ async fn configure_postgresql() -> PgPool {
     pg_pool
 }
 
-fn configure_redis() -> redis::Connection {
-    get_redis_client(REDIS_HOST_NAME.to_owned())
-        .expect("Failed to get Redis client")
-        .get_connection()
+// Picks up a replaced disposable domain file without a restart
+fn spawn_disposable_domains_refresh(disposable_domains: Arc<DisposableDomains>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(DISPOSABLE_DOMAINS_REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            match disposable_domains.refresh() {
+                Ok(true) => tracing::info!("Reloaded {} disposable domains", disposable_domains.len()),
+                Ok(false) => {}
+                Err(e) => tracing::warn!("Failed to reload disposable domains: {:?}", e),
+            }
+        }
+    });
+}
+
+async fn configure_redis() -> ConnectionManager {
+    get_redis_connection(REDIS_HOST_NAME.to_owned())
+        .await
         .expect("Failed to get Redis connection")
 }
 
+fn configure_webhook_http_client() -> Client {
+    Client::builder()
+        .timeout(prod::webhooks::TIMEOUT)
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
 // New!
 fn configure_postmark_email_client() -> PostmarkEmailClient {
     let http_client = Client::builder()
@@ -0,0 +1,146 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuditEvent, AuditQuery, AuthAPIError, UserId},
+};
+
+use super::me::{authenticated_user, user_gone};
+
+// The audit events of the current user, most recent first
+#[tracing::instrument(name = "Get activity", skip_all)]
+pub async fn get_activity(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Query(params): Query<ActivityParams>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let user_id = authenticated_user(&state, &jar).await?;
+    let query = AuditQuery {
+        actor: Some(user_id),
+        until: parse_time(params.before.as_deref())?,
+        limit: limit(params.limit),
+        ..Default::default()
+    };
+    query_events(&state, &query).await
+}
+
+// Every audit event matching the filters, for admins
+#[tracing::instrument(name = "Query audit events", skip_all)]
+pub async fn query_audit_events(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Query(params): Query<AuditQueryParams>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let user_id = authenticated_user(&state, &jar).await?;
+    if !state.user_store.is_admin(&user_id).await.map_err(user_gone)? {
+        return Err(AuthAPIError::NotAdmin);
+    }
+
+    let query = AuditQuery {
+        actor: parse(params.actor.as_deref(), |actor| UserId::parse(actor).ok())?,
+        action: parse(params.action.as_deref(), |action| action.parse().ok())?,
+        outcome: parse(params.outcome.as_deref(), |outcome| outcome.parse().ok())?,
+        ip: parse(params.ip.as_deref(), |ip| ip.parse().ok())?,
+        since: parse_time(params.since.as_deref())?,
+        until: parse_time(params.until.as_deref())?,
+        limit: limit(params.limit),
+    };
+    query_events(&state, &query).await
+}
+
+async fn query_events(
+    state: &AppState,
+    query: &AuditQuery,
+) -> Result<(StatusCode, Json<AuditEventsResponse>), AuthAPIError> {
+    let events = state
+        .audit_log
+        .query(query)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+
+    let response = AuditEventsResponse {
+        events: events.into_iter().map(AuditEventResponse::from).collect(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+fn parse<T>(value: Option<&str>, parse: impl Fn(&str) -> Option<T>) -> Result<Option<T>, AuthAPIError> {
+    value
+        .map(|value| parse(value).ok_or(AuthAPIError::InvalidAuditQuery))
+        .transpose()
+}
+
+// RFC 3339, as the times in responses
+fn parse_time(value: Option<&str>) -> Result<Option<DateTime<Utc>>, AuthAPIError> {
+    parse(value, |value| {
+        DateTime::parse_from_rfc3339(value)
+            .ok()
+            .map(|time| time.with_timezone(&Utc))
+    })
+}
+
+fn limit(limit: Option<usize>) -> usize {
+    limit.unwrap_or(AuditQuery::DEFAULT_LIMIT).clamp(1, AuditQuery::MAX_LIMIT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ActivityParams {
+    pub limit: Option<usize>,
+    // Only events older than this time, the `occurredAt` of the last event of a page
+    pub before: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditQueryParams {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub outcome: Option<String>,
+    pub ip: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuditEventsResponse {
+    pub events: Vec<AuditEventResponse>,
+}
+
+// Timestamps are RFC 3339
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEventResponse {
+    pub id: String,
+    pub occurred_at: String,
+    pub action: String,
+    pub outcome: String,
+    pub actor: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+    pub detail: Option<String>,
+}
+
+impl From<AuditEvent> for AuditEventResponse {
+    fn from(event: AuditEvent) -> Self {
+        Self {
+            id: event.id.to_string(),
+            occurred_at: event.occurred_at.to_rfc3339(),
+            action: event.action.to_string(),
+            outcome: event.outcome.to_string(),
+            actor: event.actor.map(|actor| actor.to_string()),
+            ip: event.ip.map(|ip| ip.to_string()),
+            user_agent: event.user_agent,
+            request_id: event.request_id.map(|id| id.to_string()),
+            detail: event.detail,
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
-use axum_extra::extract::{cookie::Cookie, CookieJar};
+use axum_extra::extract::{CookieJar, SignedCookieJar};
 
 use secrecy::{Secret, ExposeSecret};
 use serde::{Deserialize, Serialize};
@@ -7,46 +7,98 @@ use color_eyre::eyre::Result;
 
 use crate::{
     app_state::AppState,
-    domain::{AuthAPIError, Email, LoginAttemptId, Password, TwoFACode},
-    utils::auth::generate_auth_cookie,
+    domain::{
+        AuthAPIError, Email, LoginAttemptId, Password, TrustedDeviceId, TrustedDeviceStoreError, TwoFACode,
+        TwoFACodeHash, User, UserId, UserStoreError,
+    },
+    services::data_stores::verify_dummy_password_hash,
+    utils::auth::{
+        create_login_attempt_cookie, generate_auth_cookie, AuthMethod, AuthenticationClaims,
+        ProfileClaims,
+    },
+    utils::{audit::Audit, constants::TRUSTED_DEVICE_COOKIE_NAME, request_metadata::RequestMetadata},
 };
 
+use super::{login_alerts::record_login, me::profile_claims};
+
 #[tracing::instrument(name = "Login", skip_all)]
 pub async fn login(
     State(state): State<AppState>,
     jar: CookieJar, // New!
+    signed_jar: SignedCookieJar,
+    metadata: RequestMetadata,
+    audit: Audit,
     Json(request): Json<LoginRequest>,
-) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+) -> (CookieJar, SignedCookieJar, Result<impl IntoResponse, AuthAPIError>) {
 
     // match email, if there is a parsing error, return AuthAPIError::InvalidCredentials
     let email = match Email::parse(request.email) {
         Ok(email) => email,
-        Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
+        Err(_) => return (jar, signed_jar, Err(AuthAPIError::InvalidCredentials)),
     };
 
 
     let password = match Password::parse(request.password) {
         Ok(password) => password,
-        Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
+        Err(_) => return (jar, signed_jar, Err(AuthAPIError::InvalidCredentials)),
     };
 
-    let user_store = &state.user_store.read().await;
+    let user_store = &state.user_store;
 
     // call `user_store.validate_user` and return
     // `AuthAPIError::IncorrectCredentials` if validation fails.
-    if user_store.validate_user(email.clone(), password.clone()).await.is_err() {
-        return (jar, Err(AuthAPIError::IncorrectCredentials));
+    if let Err(e) = user_store.validate_user(email.clone(), password.clone()).await {
+        // Only reported once the password is known to be correct
+        if e == UserStoreError::UserDisabled {
+            return (jar, signed_jar, Err(AuthAPIError::UserDisabled));
+        }
+        // E.g. several users matching the email, which must not pass for a wrong password
+        if let UserStoreError::UnexpectedError(e) = e {
+            return (jar, signed_jar, Err(AuthAPIError::UnexpectedError(e)));
+        }
+        // Unknown users are rejected without hashing anything, so pay for a
+        // password check anyway to keep registered emails indistinguishable
+        if state.enumeration_protection && e == UserStoreError::UserNotFound {
+            verify_dummy_password_hash(password.as_ref().to_owned()).await;
+        }
+        // Failed guesses show in the activity of the account they target
+        if let Ok(user) = user_store.get_user(email).await {
+            audit.actor(user.id);
+        }
+        return (jar, signed_jar, Err(AuthAPIError::IncorrectCredentials));
     };
 
     let user = match user_store.get_user(email).await {
         Ok(user) => user,
-        Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
+        Err(_) => return (jar, signed_jar, Err(AuthAPIError::IncorrectCredentials)),
     };
+    audit.actor(user.id);
+
+    // Browsers the user trusted when verifying a 2FA code skip it
+    let trusted_device = user.requires_2fa && is_trusted_device(&state, &user.id, &signed_jar).await;
+    let requires_2fa = user.requires_2fa && !trusted_device;
 
     // Handle request based on user's 2FA configuration
-    match user.requires_2fa {
-        true => handle_2fa(&user.email, &state, jar).await,
-        false => handle_no_2fa(&user.email, jar).await,
+    match requires_2fa {
+        true => {
+            audit.detail("2fa_required");
+            let (signed_jar, result) = handle_2fa(&user, &state, signed_jar).await;
+            (jar, signed_jar, result)
+        }
+        false => {
+            if trusted_device {
+                audit.detail("trusted_device");
+            }
+            let profile = match profile_claims(&**user_store, &state.token_profile_claims, &user.id).await {
+                Ok(profile) => profile,
+                Err(e) => return (jar, signed_jar, Err(e)),
+            };
+            let (jar, result) = handle_no_2fa(&user.id, profile, jar).await;
+            if result.is_ok() {
+                record_login(&state, &user, metadata).await;
+            }
+            (jar, signed_jar, result)
+        }
     }
 }
 
@@ -76,58 +128,87 @@ pub struct TwoFactorAuthResponse {
 
 #[tracing::instrument(name = "Handle 2FA", skip_all)]
 async fn handle_2fa(
-    email: &Email, // New!
-    state: &AppState, // New!
-    jar: CookieJar,
+    user: &User,
+    state: &AppState,
+    signed_jar: SignedCookieJar,
 ) -> (
-    CookieJar,
+    SignedCookieJar,
     Result<(StatusCode, Json<LoginResponse>), AuthAPIError>,
 ) {
-    // First, we must generate a new random login attempt ID and 2FA code
-    let login_attempt_id: LoginAttemptId = LoginAttemptId::default();
-    let two_fa_code: TwoFACode = TwoFACode::default(); // New!
-
-    if let Err(e) = state
-        .two_factor_code_store
-        .write()
-        .await
-        .add_code(email.clone(), login_attempt_id.clone(), two_fa_code.clone())
-        .await
-    {
-        return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
-    }
-
-    // send 2FA code via the email client. Return `AuthAPIError::UnexpectedError` if the operation fails.
-    if let Err(e) = state
-        .email_client
-        .send_email(email, "2FA Code", two_fa_code.as_ref().expose_secret())
-        .await
-    {
-        return (jar, Err(AuthAPIError::UnexpectedError(e)));
-    }
+    let login_attempt_id = match send_2fa_code(user, state).await {
+        Ok(login_attempt_id) => login_attempt_id,
+        Err(e) => return (signed_jar, Err(e)),
+    };
     // Return a TwoFactorAuthResponse. The message should be "2FA required".
-    // The login attempt ID should be "123456". We will replace this hard-coded login attempt ID soon!
     let two_factor_auth_response = TwoFactorAuthResponse {
         message: "2FA required".to_string(),
         login_attempt_id: login_attempt_id.as_ref().expose_secret().to_owned(),
     };
 
-    let updated_jar = jar.add(Cookie::new("login_attempt_id", "123456"));
+    // Bind the login attempt to this browser, `verify_2fa` only accepts
+    // the attempt if the same signed cookie is sent back.
+    let updated_jar = signed_jar.add(create_login_attempt_cookie(&login_attempt_id));
 
     (updated_jar, Ok((StatusCode::PARTIAL_CONTENT, Json(LoginResponse::TwoFactorAuth(two_factor_auth_response)))))
 }
 
+// Whether the signed trusted device cookie names a device the user trusted. Store
+// failures count as untrusted, so that the user is asked for a code.
+async fn is_trusted_device(state: &AppState, user_id: &UserId, signed_jar: &SignedCookieJar) -> bool {
+    let Some(device_id) = signed_jar
+        .get(TRUSTED_DEVICE_COOKIE_NAME)
+        .and_then(|cookie| TrustedDeviceId::parse(cookie.value()).ok())
+    else {
+        return false;
+    };
+
+    match state.trusted_device_store.use_device(user_id, &device_id).await {
+        Ok(_) => true,
+        Err(TrustedDeviceStoreError::DeviceNotFound) => false,
+        Err(e) => {
+            tracing::warn!("Failed to check trusted device: {:?}", e);
+            false
+        }
+    }
+}
+
+// Stores the hash of a fresh code under a new login attempt and emails the code
+// to the user
+pub(crate) async fn send_2fa_code(
+    user: &User,
+    state: &AppState,
+) -> Result<LoginAttemptId, AuthAPIError> {
+    let login_attempt_id = LoginAttemptId::default();
+    let two_fa_code = TwoFACode::default();
+    let two_fa_code_hash = TwoFACodeHash::new(&login_attempt_id, &two_fa_code);
+
+    state
+        .two_factor_code_store
+        .add_code(user.id, login_attempt_id.clone(), two_fa_code_hash)
+        .await?;
+
+    state
+        .email_client
+        .send_email(&user.email, "2FA Code", two_fa_code.as_ref().expose_secret())
+        .await
+        .map_err(AuthAPIError::UnexpectedError)?;
+
+    Ok(login_attempt_id)
+}
+
 #[tracing::instrument(name = "Handle no 2FA", skip_all)]
 async fn handle_no_2fa(
-    email: &Email,
+    user_id: &UserId,
+    profile: ProfileClaims,
     jar: CookieJar,
 ) -> (
     CookieJar,
     Result<(StatusCode, Json<LoginResponse>), AuthAPIError>,
 ) {
-    let auth_cookie = match generate_auth_cookie(email) {
+    let authentication = AuthenticationClaims::now(vec![AuthMethod::Pwd]);
+    let auth_cookie = match generate_auth_cookie(user_id, authentication, profile) {
         Ok(cookie) => cookie,
-        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e.into()))),
+        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
     };
     let updated_jar = jar.add(auth_cookie);
     (updated_jar, Ok((StatusCode::OK, Json(LoginResponse::RegularAuth))))
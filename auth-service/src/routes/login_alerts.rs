@@ -0,0 +1,155 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use chrono::Utc;
+use color_eyre::eyre::Result;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Login, LoginNovelty, Password, User},
+    utils::{
+        auth::{generate_login_report_token, validate_login_report_token},
+        constants::PUBLIC_URL,
+        audit::Audit,
+        request_metadata::RequestMetadata,
+    },
+};
+
+use super::me::user_gone;
+
+pub const LOGIN_ALERT_SUBJECT: &str = "New login to your account";
+
+// Records a completed login and alerts the user by email when it comes from a device
+// or network they never logged in from. Failures are only logged, the user has
+// already proven who they are and must not be locked out by a notification.
+pub(crate) async fn record_login(state: &AppState, user: &User, metadata: RequestMetadata) {
+    let login = Login::new(user.id, metadata.ip, metadata.user_agent);
+    let novelty = match state.login_history_store.record_login(login.clone()).await {
+        Ok(novelty) => novelty,
+        Err(e) => {
+            tracing::error!("Failed to record login: {:?}", e);
+            return;
+        }
+    };
+
+    if novelty == LoginNovelty::NewDevice {
+        if let Err(e) = send_login_alert(state, user, &login).await {
+            tracing::error!("Failed to send login alert: {:?}", e);
+        }
+    }
+}
+
+async fn send_login_alert(state: &AppState, user: &User, login: &Login) -> Result<()> {
+    let token = generate_login_report_token(&user.id)?;
+    let report_link = format!("{}/?report_login={}", *PUBLIC_URL, token.expose_secret());
+    state
+        .email_client
+        .send_email(&user.email, LOGIN_ALERT_SUBJECT, &login_alert_content(login, &report_link))
+        .await
+}
+
+fn login_alert_content(login: &Login, report_link: &str) -> String {
+    format!(
+        "Your account was just accessed from a device or network it was not used from before.\n\n\
+         Time: {}\n\
+         IP address: {}\n\
+         Browser: {}\n\n\
+         If this was you, you can ignore this email. If it wasn't, open the link below. It signs \
+         out every session, forgets your trusted devices and asks you for a new password.\n\n\
+         This wasn't me: {}",
+        login.logged_in_at.format("%Y-%m-%d %H:%M:%S UTC"),
+        login.ip.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown".to_owned()),
+        login.user_agent.as_deref().unwrap_or("unknown"),
+        report_link,
+    )
+}
+
+// The "this wasn't me" link of a login alert. Whoever logged in may know the password,
+// so a new one is required, and every session and trusted device is dropped.
+#[tracing::instrument(name = "Report login", skip_all)]
+pub async fn report_login(
+    State(state): State<AppState>,
+    audit: Audit,
+    Json(request): Json<ReportLoginRequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let user_id = validate_login_report_token(&request.token, state.banned_token_store.clone())
+        .await
+        .map_err(|_| AuthAPIError::InvalidToken)?;
+    audit.actor(user_id);
+    let user = state
+        .user_store
+        .get_user_by_id(&user_id)
+        .await
+        .map_err(user_gone)?;
+
+    state
+        .password_policy
+        .check(&request.password, &user.email)
+        .map_err(AuthAPIError::PasswordPolicyViolation)?;
+    let password = Password::parse(request.password).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    state
+        .user_store
+        .update_password(&user.id, password)
+        .await
+        .map_err(user_gone)?;
+
+    // Also revokes the link itself. Done after the password change, so that the link
+    // still works if that failed.
+    state
+        .banned_token_store
+        .revoke_tokens_issued_before(&user.id.to_string(), Utc::now().timestamp() + 1)
+        .await
+        .map_err(|e| AuthAPIError::UnexpectedError(e.into()))?;
+    state.trusted_device_store.remove_all_devices(&user.id).await?;
+
+    if let Err(e) = state
+        .email_client
+        .send_email(
+            &user.email,
+            "Your account was secured",
+            "As you reported a login you did not make, your password was changed, every session \
+             was signed out and your trusted devices were forgotten. Log in again with your new \
+             password.",
+        )
+        .await
+    {
+        tracing::error!("Failed to confirm login report: {:?}", e);
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(ReportLoginResponse {
+            message: "Account secured, log in with the new password".to_owned(),
+        }),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReportLoginRequest {
+    pub token: Secret<String>,
+    // The new password
+    pub password: Secret<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ReportLoginResponse {
+    pub message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::UserId;
+
+    use super::*;
+
+    #[test]
+    fn test_login_alert_tells_where_the_login_came_from() {
+        let login = Login::new(UserId::default(), Some("203.0.113.7".parse().unwrap()), None);
+        let content = login_alert_content(&login, "https://auth.example.com/?report_login=abc");
+
+        assert!(content.contains("IP address: 203.0.113.7"));
+        assert!(content.contains("Browser: unknown"));
+        assert!(content.ends_with("This wasn't me: https://auth.example.com/?report_login=abc"));
+    }
+}
@@ -28,8 +28,6 @@ pub async fn logout(
     }
 
     state.banned_token_store
-        .write()
-        .await
         .add_token(token)
         .await
         .unwrap();
@@ -0,0 +1,104 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum_extra::extract::CookieJar;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{
+        AuthAPIError, Profile, ProfileField, ProfileUpdate, User, UserId, UserStore, UserStoreError,
+    },
+    utils::{
+        auth::{validate_token, ProfileClaims},
+        constants::JWT_COOKIE_NAME,
+    },
+};
+
+#[tracing::instrument(name = "Get current user", skip_all)]
+pub async fn get_me(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let user_id = authenticated_user(&state, &jar).await?;
+
+    let user_store = &state.user_store;
+    let user = user_store.get_user_by_id(&user_id).await.map_err(user_gone)?;
+    let profile = user_store.get_profile(&user_id).await.map_err(user_gone)?;
+
+    Ok((StatusCode::OK, Json(MeResponse::new(user, profile))))
+}
+
+#[tracing::instrument(name = "Update current user", skip_all)]
+pub async fn update_me(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Json(request): Json<ProfileUpdate>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let user_id = authenticated_user(&state, &jar).await?;
+    let update = request.parse().map_err(AuthAPIError::InvalidProfile)?;
+
+    let user_store = &state.user_store;
+    let user = user_store.get_user_by_id(&user_id).await.map_err(user_gone)?;
+    let profile = user_store.update_profile(&user_id, update).await.map_err(user_gone)?;
+
+    Ok((StatusCode::OK, Json(MeResponse::new(user, profile))))
+}
+
+// The user the `jwt` cookie was issued to
+pub(crate) async fn authenticated_user(
+    state: &AppState,
+    jar: &CookieJar,
+) -> Result<UserId, AuthAPIError> {
+    let cookie = jar.get(JWT_COOKIE_NAME).ok_or(AuthAPIError::MissingToken)?;
+    let token = Secret::new(cookie.value().to_owned());
+
+    let claims = validate_token(&token, state.banned_token_store.clone())
+        .await
+        .map_err(|_| AuthAPIError::InvalidToken)?;
+
+    UserId::parse(&claims.sub).map_err(|_| AuthAPIError::InvalidToken)
+}
+
+// Profile claims for a token about to be issued, the profile is only read when
+// TOKEN_PROFILE_CLAIMS selects some fields
+pub(crate) async fn profile_claims(
+    user_store: &(dyn UserStore + Send + Sync),
+    fields: &[ProfileField],
+    user_id: &UserId,
+) -> Result<ProfileClaims, AuthAPIError> {
+    if fields.is_empty() {
+        return Ok(ProfileClaims::default());
+    }
+    let profile = user_store.get_profile(user_id).await?;
+    Ok(ProfileClaims::new(profile, fields))
+}
+
+// A valid token whose user no longer exists
+pub(crate) fn user_gone(e: UserStoreError) -> AuthAPIError {
+    match e {
+        UserStoreError::UserNotFound => AuthAPIError::InvalidToken,
+        e => e.into(),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MeResponse {
+    pub id: String,
+    pub email: String,
+    #[serde(rename = "requires2FA")]
+    pub requires_2fa: bool,
+    #[serde(flatten)]
+    pub profile: Profile,
+}
+
+impl MeResponse {
+    fn new(user: User, profile: Profile) -> Self {
+        Self {
+            id: user.id.to_string(),
+            email: user.email.expose_secret().to_owned(),
+            requires_2fa: user.requires_2fa,
+            profile,
+        }
+    }
+}
@@ -1,12 +1,26 @@
+mod activity;
 mod login;
+mod login_alerts;
 mod logout;
+mod me;
+mod resend_2fa;
 mod signup;
+mod step_up;
+mod trusted_devices;
+mod two_fa_settings;
 mod verify_2fa;
 mod verify_token;
 
 // re-export items from sub-modules
+pub use activity::*;
 pub use login::*;
+pub use login_alerts::*;
 pub use logout::*;
+pub use me::*;
+pub use resend_2fa::*;
 pub use signup::*;
+pub use step_up::*;
+pub use trusted_devices::*;
+pub use two_fa_settings::*;
 pub use verify_2fa::*;
 pub use verify_token::*;
\ No newline at end of file
@@ -0,0 +1,62 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Email, LoginAttemptId, TwoFACode, TwoFACodeHash},
+    utils::audit::Audit,
+};
+
+#[tracing::instrument(name = "Resend 2FA", skip_all)]
+pub async fn resend_2fa(
+    State(state): State<AppState>,
+    audit: Audit,
+    Json(request): Json<Resend2FARequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let email = Email::parse(request.email).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    let login_attempt_id = LoginAttemptId::parse(request.login_attempt_id)
+        .map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    let user = state
+        .user_store
+        .get_user(email)
+        .await
+        .map_err(|_| AuthAPIError::IncorrectCredentials)?;
+    audit.actor(user.id);
+
+    // Generate a fresh code for the same login attempt. The store rejects the
+    // request if the cooldown has not elapsed or the resend limit was reached.
+    let two_fa_code = TwoFACode::default();
+    let two_fa_code_hash = TwoFACodeHash::new(&login_attempt_id, &two_fa_code);
+
+    state
+        .two_factor_code_store
+        .resend_code(&user.id, &login_attempt_id, two_fa_code_hash)
+        .await?;
+
+    state
+        .email_client
+        .send_email(&user.email, "2FA Code", two_fa_code.as_ref().expose_secret())
+        .await
+        .map_err(AuthAPIError::UnexpectedError)?;
+
+    let response = Json(Resend2FAResponse {
+        message: "2FA code sent".to_string(),
+    });
+
+    Ok((StatusCode::OK, response))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Resend2FARequest {
+    pub email: Secret<String>,
+    #[serde(rename = "loginAttemptId")]
+    pub login_attempt_id: Secret<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Resend2FAResponse {
+    pub message: String,
+}
@@ -5,40 +5,80 @@ use std::result::Result;
 
 use crate::{
     app_state::AppState,
-    domain::{AuthAPIError, Email, Password, User},
+    domain::{AuthAPIError, Email, Password, User, UserStoreError},
+    utils::audit::Audit,
 };
 
 #[tracing::instrument(name = "Signup", skip_all)] // New!
 pub async fn signup(
     State(state): State<AppState>,
+    audit: Audit,
     Json(request): Json<SignupRequest>,
     ) -> Result<impl IntoResponse, AuthAPIError> {
 
     let email =
         Email::parse(request.email.clone()).map_err(|_| AuthAPIError::InvalidCredentials)?;
-    
+
+    state
+        .signup_policy
+        .check(&email)
+        .map_err(AuthAPIError::SignupPolicyViolation)?;
+
+    state
+        .password_policy
+        .check(&request.password, &email)
+        .map_err(AuthAPIError::PasswordPolicyViolation)?;
+
     let password =
         Password::parse(request.password.clone()).map_err(|_| AuthAPIError::InvalidCredentials)?;
 
     let user = User::new(email.clone(), password, request.requires_2fa);
+    let user_id = user.id;
 
-    let mut user_store = state.user_store.write().await;
-
-    // early return AuthAPIError::UserAlreadyExists if email exists in user_store.
-    if user_store.get_user(email).await.is_ok() {
-        return Err(AuthAPIError::UserAlreadyExists);
+    // A single insert, so that of two signups racing for one email only one succeeds
+    match state.user_store.add_user(user).await {
+        Ok(()) => {}
+        Err(UserStoreError::UserAlreadyExists) if state.enumeration_protection => {
+            return Ok(signup_existing_user(&state, email));
+        }
+        Err(e) => return Err(e.into()),
     }
+    audit.actor(user_id);
 
-    // instead of using unwrap, early return AuthAPIError::UnexpectedError if add_user() fails.
-    if let Err(e) = user_store.add_user(user).await {
-        return Err(AuthAPIError::UnexpectedError(e.into())); // Updated!
-    }
+    Ok(signup_response())
+}
+
+// Answers a signup for an already registered email exactly like a successful
+// one and lets the owner know instead, so the response reveals nothing.
+// The store hashes the password before finding the email taken, so this takes as
+// long as a successful signup. The email is sent in the background, as waiting
+// for the email provider would make this path the slower one.
+fn signup_existing_user(state: &AppState, email: Email) -> (StatusCode, Json<SignupResponse>) {
+    let email_client = state.email_client.clone();
+    tokio::spawn(async move {
+        if let Err(e) = email_client
+            .send_email(
+                &email,
+                "Sign up attempt",
+                "Someone tried to create an account with this email address, which already has one. \
+                 If this was you, log in or reset your password instead. Otherwise you can ignore this email.",
+            )
+            .await
+        {
+            // The caller must not learn that the account exists, so only log the failure
+            tracing::error!("Failed to notify existing user of signup attempt: {:?}", e);
+        }
+    });
+
+    signup_response()
+}
 
+fn signup_response() -> (StatusCode, Json<SignupResponse>) {
     let response = Json(SignupResponse {
         message: "User created successfully!".to_string(),
     });
 
-    Ok((StatusCode::CREATED, response))
+    (StatusCode::CREATED, response)
 }
 
 #[derive(Deserialize)]
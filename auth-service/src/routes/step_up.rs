@@ -0,0 +1,145 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, LoginAttemptId, Password, TwoFACode, User, UserId, UserStoreError},
+    utils::auth::{generate_step_up_token, AuthMethod, AuthenticationClaims, STEP_UP_TOKEN_TTL_SECONDS},
+};
+
+use super::{
+    login::send_2fa_code,
+    me::{authenticated_user, profile_claims, user_gone},
+    LoginResponse, TwoFactorAuthResponse,
+};
+
+// Issues a short-lived token proving a recent strong login, for sensitive operations
+// such as payment changes. The token is returned rather than set as the `jwt` cookie,
+// so the session itself keeps its lifetime.
+#[tracing::instrument(name = "Step up", skip_all)]
+pub async fn step_up(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Json(request): Json<ReauthenticationRequest>,
+) -> Result<Response, AuthAPIError> {
+    let user_id = authenticated_user(&state, &jar).await?;
+    let (user, amr) = match reauthenticate(&state, &user_id, request).await? {
+        Reauthentication::Verified { user, amr } => (user, amr),
+        Reauthentication::CodeSent(login_attempt_id) => {
+            return Ok(two_fa_required(&login_attempt_id))
+        }
+    };
+
+    let profile = profile_claims(
+        &*state.user_store,
+        &state.token_profile_claims,
+        &user.id,
+    )
+    .await?;
+    let token = generate_step_up_token(&user.id, AuthenticationClaims::now(amr), profile)
+        .map_err(AuthAPIError::UnexpectedError)?;
+
+    let response = StepUpResponse {
+        token: token.expose_secret().to_owned(),
+        expires_in: STEP_UP_TOKEN_TTL_SECONDS,
+    };
+    Ok((StatusCode::OK, Json(response)).into_response())
+}
+
+pub(crate) enum Reauthentication {
+    Verified { user: User, amr: Vec<AuthMethod> },
+    // The user requires 2FA and sent no code, one was emailed
+    CodeSent(LoginAttemptId),
+}
+
+// Checks the password of the current user again, and a fresh 2FA code if the user
+// requires 2FA. Like logging in this takes two calls, the first one without a code
+// gets one emailed.
+pub(crate) async fn reauthenticate(
+    state: &AppState,
+    user_id: &UserId,
+    request: ReauthenticationRequest,
+) -> Result<Reauthentication, AuthAPIError> {
+    let user = verify_password(state, user_id, request.password).await?;
+    if !user.requires_2fa {
+        return Ok(Reauthentication::Verified { user, amr: vec![AuthMethod::Pwd] });
+    }
+
+    match (request.login_attempt_id, request.two_fa_code) {
+        (Some(login_attempt_id), Some(two_fa_code)) => {
+            verify_2fa_code(state, &user, login_attempt_id, two_fa_code).await?;
+            Ok(Reauthentication::Verified { user, amr: vec![AuthMethod::Pwd, AuthMethod::Email] })
+        }
+        _ => Ok(Reauthentication::CodeSent(send_2fa_code(&user, state).await?)),
+    }
+}
+
+// Asks for the password again, so that a stolen session alone is not enough
+pub(crate) async fn verify_password(
+    state: &AppState,
+    user_id: &UserId,
+    password: Secret<String>,
+) -> Result<User, AuthAPIError> {
+    let password = Password::parse(password).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    let user_store = &state.user_store;
+    let user = user_store.get_user_by_id(user_id).await.map_err(user_gone)?;
+
+    match user_store.validate_user(user.email.clone(), password).await {
+        Ok(()) => Ok(user),
+        Err(UserStoreError::UserDisabled) => Err(AuthAPIError::UserDisabled),
+        Err(UserStoreError::UnexpectedError(e)) => Err(AuthAPIError::UnexpectedError(e)),
+        Err(_) => Err(AuthAPIError::IncorrectCredentials),
+    }
+}
+
+async fn verify_2fa_code(
+    state: &AppState,
+    user: &User,
+    login_attempt_id: Secret<String>,
+    two_fa_code: Secret<String>,
+) -> Result<(), AuthAPIError> {
+    let login_attempt_id =
+        LoginAttemptId::parse(login_attempt_id).map_err(|_| AuthAPIError::InvalidCredentials)?;
+    let two_fa_code = TwoFACode::parse(two_fa_code).map_err(|_| AuthAPIError::InvalidCredentials)?;
+
+    let two_fa_code_store = &state.two_factor_code_store;
+    let (code_user_id, code_hash) = two_fa_code_store.get_code(&login_attempt_id).await?;
+    if code_user_id != user.id || !code_hash.verify(&login_attempt_id, &two_fa_code) {
+        return Err(AuthAPIError::IncorrectCredentials);
+    }
+    two_fa_code_store.remove_code(&login_attempt_id).await?;
+    Ok(())
+}
+
+// Same answer as `login` gives, the request is sent again with the emailed code
+pub(crate) fn two_fa_required(login_attempt_id: &LoginAttemptId) -> Response {
+    let response = LoginResponse::TwoFactorAuth(TwoFactorAuthResponse {
+        message: "2FA required".to_string(),
+        login_attempt_id: login_attempt_id.as_ref().expose_secret().to_owned(),
+    });
+    (StatusCode::PARTIAL_CONTENT, Json(response)).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReauthenticationRequest {
+    pub password: Secret<String>,
+    #[serde(default, rename = "loginAttemptId")]
+    pub login_attempt_id: Option<Secret<String>>,
+    #[serde(default, rename = "2FACode")]
+    pub two_fa_code: Option<Secret<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StepUpResponse {
+    pub token: String,
+    #[serde(rename = "expiresIn")]
+    pub expires_in: i64,
+}
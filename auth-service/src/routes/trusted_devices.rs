@@ -0,0 +1,107 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use axum_extra::extract::{cookie::Cookie, CookieJar, SignedCookieJar};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, TrustedDevice, TrustedDeviceId},
+    utils::constants::TRUSTED_DEVICE_COOKIE_NAME,
+};
+
+use super::me::authenticated_user;
+
+#[tracing::instrument(name = "List trusted devices", skip_all)]
+pub async fn list_trusted_devices(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    signed_jar: SignedCookieJar,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let user_id = authenticated_user(&state, &jar).await?;
+    let current = current_device(&signed_jar);
+
+    let devices = state.trusted_device_store.list_devices(&user_id).await?;
+
+    let response = TrustedDevicesResponse {
+        devices: devices
+            .into_iter()
+            .map(|device| TrustedDeviceResponse::new(device, current))
+            .collect(),
+    };
+    Ok((StatusCode::OK, Json(response)))
+}
+
+// The device asks for 2FA again on its next login
+#[tracing::instrument(name = "Revoke trusted device", skip_all)]
+pub async fn revoke_trusted_device(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    signed_jar: SignedCookieJar,
+    Path(device_id): Path<String>,
+) -> (SignedCookieJar, Result<impl IntoResponse, AuthAPIError>) {
+    let user_id = match authenticated_user(&state, &jar).await {
+        Ok(user_id) => user_id,
+        Err(e) => return (signed_jar, Err(e)),
+    };
+    let device_id = match TrustedDeviceId::parse(&device_id) {
+        Ok(device_id) => device_id,
+        Err(_) => return (signed_jar, Err(AuthAPIError::TrustedDeviceNotFound)),
+    };
+
+    if let Err(e) = state
+        .trusted_device_store
+        .remove_device(&user_id, &device_id)
+        .await
+    {
+        return (signed_jar, Err(e.into()));
+    }
+
+    // Revoking this very browser, its cookie is now useless
+    let signed_jar = if current_device(&signed_jar) == Some(device_id) {
+        signed_jar.remove(Cookie::build(TRUSTED_DEVICE_COOKIE_NAME).path("/"))
+    } else {
+        signed_jar
+    };
+    (signed_jar, Ok(StatusCode::NO_CONTENT))
+}
+
+fn current_device(signed_jar: &SignedCookieJar) -> Option<TrustedDeviceId> {
+    signed_jar
+        .get(TRUSTED_DEVICE_COOKIE_NAME)
+        .and_then(|cookie| TrustedDeviceId::parse(cookie.value()).ok())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrustedDevicesResponse {
+    pub devices: Vec<TrustedDeviceResponse>,
+}
+
+// Timestamps are RFC 3339
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrustedDeviceResponse {
+    pub id: String,
+    pub name: Option<String>,
+    pub created_at: String,
+    pub expires_at: String,
+    pub last_used_at: Option<String>,
+    // The device making the request
+    pub current: bool,
+}
+
+impl TrustedDeviceResponse {
+    fn new(device: TrustedDevice, current: Option<TrustedDeviceId>) -> Self {
+        Self {
+            id: device.id.to_string(),
+            current: current == Some(device.id),
+            name: device.name,
+            created_at: device.created_at.to_rfc3339(),
+            expires_at: device.expires_at.to_rfc3339(),
+            last_used_at: device.last_used_at.map(|time| time.to_rfc3339()),
+        }
+    }
+}
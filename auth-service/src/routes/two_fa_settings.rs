@@ -0,0 +1,103 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use secrecy::Secret;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, User},
+};
+
+use super::{
+    me::{authenticated_user, user_gone},
+    step_up::{reauthenticate, two_fa_required, verify_password, Reauthentication},
+    ReauthenticationRequest,
+};
+
+#[tracing::instrument(name = "Enable 2FA", skip_all)]
+pub async fn enable_2fa(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Json(request): Json<Enable2FARequest>,
+) -> Result<impl IntoResponse, AuthAPIError> {
+    let user_id = authenticated_user(&state, &jar).await?;
+    let user = verify_password(&state, &user_id, request.password).await?;
+
+    if !user.requires_2fa {
+        set_requires_2fa(&state, &user, true).await?;
+    }
+
+    Ok((StatusCode::OK, Json(TwoFASettingResponse { requires_2fa: true })))
+}
+
+// Takes two calls, like logging in. With the password only, a code is emailed and
+// a login attempt id returned. The change is made once the code is sent back.
+#[tracing::instrument(name = "Disable 2FA", skip_all)]
+pub async fn disable_2fa(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Json(request): Json<ReauthenticationRequest>,
+) -> Result<Response, AuthAPIError> {
+    let user_id = authenticated_user(&state, &jar).await?;
+    let user = match reauthenticate(&state, &user_id, request).await? {
+        Reauthentication::Verified { user, .. } => user,
+        Reauthentication::CodeSent(login_attempt_id) => {
+            return Ok(two_fa_required(&login_attempt_id))
+        }
+    };
+
+    if user.requires_2fa {
+        set_requires_2fa(&state, &user, false).await?;
+    }
+
+    Ok((StatusCode::OK, Json(TwoFASettingResponse { requires_2fa: false })).into_response())
+}
+
+async fn set_requires_2fa(
+    state: &AppState,
+    user: &User,
+    requires_2fa: bool,
+) -> Result<(), AuthAPIError> {
+    state
+        .user_store
+        .set_requires_2fa(&user.id, requires_2fa)
+        .await
+        .map_err(user_gone)?;
+
+    let (subject, content) = if requires_2fa {
+        (
+            "Two-factor authentication enabled",
+            "Two-factor authentication was turned on for your account. Logging in now also \
+             requires a code sent to this email address. If you did not make this change, \
+             reset your password right away.",
+        )
+    } else {
+        (
+            "Two-factor authentication disabled",
+            "Two-factor authentication was turned off for your account. Logging in now only \
+             requires your password. If you did not make this change, reset your password \
+             right away and turn two-factor authentication back on.",
+        )
+    };
+    if let Err(e) = state.email_client.send_email(&user.email, subject, content).await {
+        // The setting has changed already, failing the request would hide that
+        tracing::error!("Failed to notify user of 2FA setting change: {:?}", e);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Enable2FARequest {
+    pub password: Secret<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct TwoFASettingResponse {
+    #[serde(rename = "requires2FA")]
+    pub requires_2fa: bool,
+}
@@ -1,54 +1,118 @@
 use axum::{extract::State, response::IntoResponse, Json};
-use axum_extra::extract::CookieJar;
-use secrecy::Secret;
+use axum_extra::extract::{cookie::Cookie, CookieJar, SignedCookieJar};
+use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
 
-use crate::{app_state::AppState, domain::{AuthAPIError, Email, LoginAttemptId, TwoFACode}, utils::auth::generate_auth_cookie};
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, Email, LoginAttemptId, TrustedDevice, TwoFACode},
+    utils::{
+        auth::{
+            create_trusted_device_cookie, generate_auth_cookie, AuthMethod, AuthenticationClaims,
+        },
+        constants::{LOGIN_ATTEMPT_COOKIE_NAME, TRUSTED_DEVICE_TTL_DAYS},
+        audit::Audit,
+        request_metadata::RequestMetadata,
+    },
+};
+
+use super::{login_alerts::record_login, me::profile_claims};
 
 #[tracing::instrument(name = "Verify 2FA", skip_all)]
 pub async fn verify_2fa(
     State(state): State<AppState>,
     jar: CookieJar,
+    signed_jar: SignedCookieJar,
+    metadata: RequestMetadata,
+    audit: Audit,
     Json(request): Json<Verify2FARequest>,
-) -> (CookieJar, Result<impl IntoResponse, AuthAPIError>) {
+) -> (CookieJar, SignedCookieJar, Result<impl IntoResponse, AuthAPIError>) {
     let email = match Email::parse(request.email.clone()) {
         Ok(email) => email,
-        Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
+        Err(_) => return (jar, signed_jar, Err(AuthAPIError::InvalidCredentials)),
     };
 
     let login_attempt_id = match LoginAttemptId::parse(request.login_attempt_id) {
         Ok(login_attempt_id) => login_attempt_id,
-        Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
+        Err(_) => return (jar, signed_jar, Err(AuthAPIError::InvalidCredentials)),
     };
 
     let two_fa_code = match TwoFACode::parse(request.two_fa_code) {
         Ok(two_fa_code) => two_fa_code,
-        Err(_) => return (jar, Err(AuthAPIError::InvalidCredentials)),
+        Err(_) => return (jar, signed_jar, Err(AuthAPIError::InvalidCredentials)),
+    };
+
+    // The login attempt must have been started by this browser. The signed jar
+    // only yields the cookie if its signature is valid.
+    let cookie_matches = signed_jar
+        .get(LOGIN_ATTEMPT_COOKIE_NAME)
+        .is_some_and(|cookie| cookie.value() == login_attempt_id.expose_secret());
+    if !cookie_matches {
+        return (jar, signed_jar, Err(AuthAPIError::IncorrectCredentials));
+    }
+
+    // Codes are bound to the user id, an unknown email can never match one
+    let user_store = &state.user_store;
+    let user = match user_store.get_user(email).await {
+        Ok(user) => user,
+        Err(_) => return (jar, signed_jar, Err(AuthAPIError::IncorrectCredentials)),
     };
+    audit.actor(user.id);
 
-    let mut two_fa_code_store = state.two_factor_code_store.write().await;
+    let two_fa_code_store = &state.two_factor_code_store;
 
-    let code_tuple = match two_fa_code_store.get_code(&email).await {
+    let code_tuple = match two_fa_code_store.get_code(&login_attempt_id).await {
         Ok(code_tuple) => code_tuple,
-        Err(_) => return (jar, Err(AuthAPIError::IncorrectCredentials)),
+        Err(_) => return (jar, signed_jar, Err(AuthAPIError::IncorrectCredentials)),
     };
 
-    if !code_tuple.0.eq(&login_attempt_id) || !code_tuple.1.eq(&two_fa_code) {
-        return (jar, Err(AuthAPIError::IncorrectCredentials));
+    if code_tuple.0 != user.id || !code_tuple.1.verify(&login_attempt_id, &two_fa_code) {
+        return (jar, signed_jar, Err(AuthAPIError::IncorrectCredentials));
+    }
+
+    if let Err(e) = two_fa_code_store.remove_code(&login_attempt_id).await {
+        return (jar, signed_jar, Err(AuthAPIError::UnexpectedError(e.into())));
     }
 
-    if let Err(e) = two_fa_code_store.remove_code(&email).await {
-        return (jar, Err(AuthAPIError::UnexpectedError(e.into())));
+    // The password was checked before the code was sent, the user may have been disabled since
+    match user_store.is_disabled(&user.id).await {
+        Ok(false) => {}
+        Ok(true) => return (jar, signed_jar, Err(AuthAPIError::UserDisabled)),
+        Err(e) => return (jar, signed_jar, Err(e.into())),
     }
 
-    let cookie = match generate_auth_cookie(&email) {
+    let profile = match profile_claims(&**user_store, &state.token_profile_claims, &user.id).await {
+        Ok(profile) => profile,
+        Err(e) => return (jar, signed_jar, Err(e)),
+    };
+
+    let authentication = AuthenticationClaims::now(vec![AuthMethod::Pwd, AuthMethod::Email]);
+    let cookie = match generate_auth_cookie(&user.id, authentication, profile) {
         Ok(cookie) => cookie,
-        Err(e) => return (jar, Err(AuthAPIError::UnexpectedError(e))),
+        Err(e) => return (jar, signed_jar, Err(AuthAPIError::UnexpectedError(e))),
     };
 
+    // The login attempt is complete, its cookie is no longer needed
+    let mut updated_signed_jar =
+        signed_jar.remove(Cookie::build(LOGIN_ATTEMPT_COOKIE_NAME).path("/"));
+
+    if request.trust_device {
+        let device = TrustedDevice::new(
+            user.id,
+            metadata.user_agent.clone(),
+            chrono::Duration::days(*TRUSTED_DEVICE_TTL_DAYS),
+        );
+        if let Err(e) = state.trusted_device_store.add_device(device.clone()).await {
+            return (jar, updated_signed_jar, Err(e.into()));
+        }
+        updated_signed_jar = updated_signed_jar.add(create_trusted_device_cookie(&device));
+        audit.detail("device_trusted");
+    }
+
     let updated_jar = jar.add(cookie);
+    record_login(&state, &user, metadata).await;
 
-    (updated_jar, Ok(()))
+    (updated_jar, updated_signed_jar, Ok(()))
 }
 
 // implement the Verify2FARequest struct. See the verify-2fa route contract in step 1 for the expected JSON body.
@@ -59,6 +123,9 @@ pub struct Verify2FARequest {
     pub login_attempt_id: Secret<String>,
     #[serde(rename = "2FACode")]
     pub two_fa_code: Secret<String>,
+    // Skip 2FA on this browser for TRUSTED_DEVICE_TTL_DAYS
+    #[serde(default, rename = "trustDevice")]
+    pub trust_device: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -1,21 +1,50 @@
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
 use secrecy::Secret;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::{app_state::AppState, domain::AuthAPIError, utils::auth::validate_token};
+use crate::{
+    app_state::AppState,
+    domain::{AuthAPIError, UserId},
+    utils::{
+        audit::Audit,
+        auth::{validate_token, Acr, AuthMethod},
+    },
+};
 
 #[tracing::instrument(name = "Verify Token", skip_all)]
 pub async fn verify_token(
     State(state): State<AppState>,
+    audit: Audit,
     Json(request): Json<VerifyRequest>
 ) -> Result<impl IntoResponse, AuthAPIError> {
     
-    validate_token(&request.token, state.banned_token_store).await.map_err(|_| AuthAPIError::InvalidToken)?;
+    let claims = validate_token(&request.token, state.banned_token_store).await.map_err(|_| AuthAPIError::InvalidToken)?;
+    if let Ok(user_id) = UserId::parse(&claims.sub) {
+        audit.actor(user_id);
+    }
 
-    Ok((StatusCode::OK, "Token verified successfully!".to_string()  ))
+    // Callers guarding sensitive operations check how recent and how strong the login was
+    let response = VerifyTokenResponse {
+        sub: claims.sub,
+        exp: claims.exp,
+        auth_time: claims.authentication.auth_time,
+        amr: claims.authentication.amr,
+        acr: claims.authentication.acr,
+    };
+    Ok((StatusCode::OK, Json(response)))
 }
 
 #[derive(Deserialize)]
 pub struct VerifyRequest {
     pub token: Secret<String>
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyTokenResponse {
+    pub sub: String,
+    pub exp: usize,
+    pub auth_time: Option<usize>,
+    pub amr: Vec<AuthMethod>,
+    pub acr: Option<Acr>,
+}
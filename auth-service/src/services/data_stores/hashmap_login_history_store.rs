@@ -0,0 +1,52 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use crate::domain::{Login, LoginHistoryStore, LoginHistoryStoreError, LoginNovelty, UserId};
+
+#[derive(Default)]
+pub struct HashmapLoginHistoryStore {
+    // Device keys each user logged in from. The lock is never held across an await.
+    devices: Mutex<HashMap<UserId, HashSet<(String, String)>>>,
+}
+
+#[async_trait::async_trait]
+impl LoginHistoryStore for HashmapLoginHistoryStore {
+    async fn record_login(&self, login: Login) -> Result<LoginNovelty, LoginHistoryStoreError> {
+        let mut devices = self.devices.lock().expect("Login history store lock poisoned");
+        let devices = devices.entry(login.user_id).or_default();
+        let first_login = devices.is_empty();
+
+        Ok(match (devices.insert(login.device_key()), first_login) {
+            (_, true) => LoginNovelty::FirstLogin,
+            (true, false) => LoginNovelty::NewDevice,
+            (false, false) => LoginNovelty::KnownDevice,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn login(user_id: UserId, ip: &str, user_agent: &str) -> Login {
+        Login::new(user_id, Some(ip.parse().unwrap()), Some(user_agent.to_owned()))
+    }
+
+    #[tokio::test]
+    async fn test_logins_from_new_devices_are_noticed() {
+        let store = HashmapLoginHistoryStore::default();
+        let user_id = UserId::default();
+
+        assert_eq!(store.record_login(login(user_id, "10.0.0.1", "Firefox")).await, Ok(LoginNovelty::FirstLogin));
+        assert_eq!(store.record_login(login(user_id, "10.0.0.1", "Firefox")).await, Ok(LoginNovelty::KnownDevice));
+        assert_eq!(store.record_login(login(user_id, "10.0.0.2", "Firefox")).await, Ok(LoginNovelty::NewDevice));
+        assert_eq!(store.record_login(login(user_id, "10.0.0.1", "Chrome")).await, Ok(LoginNovelty::NewDevice));
+        assert_eq!(store.record_login(login(user_id, "10.0.0.2", "Firefox")).await, Ok(LoginNovelty::KnownDevice));
+
+        // Each user has their own history
+        let other = UserId::default();
+        assert_eq!(store.record_login(login(other, "10.0.0.1", "Firefox")).await, Ok(LoginNovelty::FirstLogin));
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use chrono::Utc;
+
+use crate::domain::{
+    TrustedDevice, TrustedDeviceId, TrustedDeviceStore, TrustedDeviceStoreError, UserId,
+};
+
+#[derive(Default)]
+pub struct HashmapTrustedDeviceStore {
+    // The lock is never held across an await
+    devices: Mutex<Devices>,
+}
+
+type Devices = HashMap<TrustedDeviceId, TrustedDevice>;
+
+impl HashmapTrustedDeviceStore {
+    fn lock(&self) -> MutexGuard<'_, Devices> {
+        self.devices.lock().expect("Trusted device store lock poisoned")
+    }
+}
+
+fn find_mut<'a>(
+    devices: &'a mut Devices,
+    user_id: &UserId,
+    id: &TrustedDeviceId,
+) -> Option<&'a mut TrustedDevice> {
+    devices
+        .get_mut(id)
+        .filter(|device| device.user_id == *user_id && !device.is_expired())
+}
+
+#[async_trait::async_trait]
+impl TrustedDeviceStore for HashmapTrustedDeviceStore {
+    async fn add_device(&self, device: TrustedDevice) -> Result<(), TrustedDeviceStoreError> {
+        let mut devices = self.lock();
+        devices.retain(|_, device| !device.is_expired());
+        devices.insert(device.id, device);
+        Ok(())
+    }
+
+    async fn use_device(
+        &self,
+        user_id: &UserId,
+        id: &TrustedDeviceId,
+    ) -> Result<TrustedDevice, TrustedDeviceStoreError> {
+        let mut devices = self.lock();
+        let device = find_mut(&mut devices, user_id, id)
+            .ok_or(TrustedDeviceStoreError::DeviceNotFound)?;
+        device.last_used_at = Some(Utc::now());
+        Ok(device.clone())
+    }
+
+    async fn list_devices(&self, user_id: &UserId) -> Result<Vec<TrustedDevice>, TrustedDeviceStoreError> {
+        let mut devices: Vec<TrustedDevice> = self
+            .lock()
+            .values()
+            .filter(|device| device.user_id == *user_id && !device.is_expired())
+            .cloned()
+            .collect();
+        devices.sort_by_key(|device| std::cmp::Reverse(device.created_at));
+        Ok(devices)
+    }
+
+    async fn remove_device(
+        &self,
+        user_id: &UserId,
+        id: &TrustedDeviceId,
+    ) -> Result<(), TrustedDeviceStoreError> {
+        let mut devices = self.lock();
+        find_mut(&mut devices, user_id, id).ok_or(TrustedDeviceStoreError::DeviceNotFound)?;
+        devices.remove(id);
+        Ok(())
+    }
+
+    async fn remove_all_devices(&self, user_id: &UserId) -> Result<(), TrustedDeviceStoreError> {
+        self.lock().retain(|_, device| device.user_id != *user_id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_devices_are_only_found_for_their_user() {
+        let store = HashmapTrustedDeviceStore::default();
+        let user_id = UserId::default();
+        let device = TrustedDevice::new(user_id, Some("Firefox".to_owned()), Duration::days(30));
+        store.add_device(device.clone()).await.unwrap();
+
+        let used = store.use_device(&user_id, &device.id).await.unwrap();
+        assert!(used.last_used_at.is_some());
+        assert_eq!(
+            store.use_device(&UserId::default(), &device.id).await,
+            Err(TrustedDeviceStoreError::DeviceNotFound)
+        );
+        assert_eq!(store.list_devices(&user_id).await.unwrap().len(), 1);
+        assert!(store.list_devices(&UserId::default()).await.unwrap().is_empty());
+
+        assert_eq!(
+            store.remove_device(&UserId::default(), &device.id).await,
+            Err(TrustedDeviceStoreError::DeviceNotFound)
+        );
+        store.remove_device(&user_id, &device.id).await.unwrap();
+        assert_eq!(
+            store.use_device(&user_id, &device.id).await,
+            Err(TrustedDeviceStoreError::DeviceNotFound)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remove_all_devices_keeps_other_users_devices() {
+        let store = HashmapTrustedDeviceStore::default();
+        let user_id = UserId::default();
+        let other = TrustedDevice::new(UserId::default(), None, Duration::days(30));
+        store.add_device(TrustedDevice::new(user_id, None, Duration::days(30))).await.unwrap();
+        store.add_device(TrustedDevice::new(user_id, None, Duration::days(30))).await.unwrap();
+        store.add_device(other.clone()).await.unwrap();
+
+        store.remove_all_devices(&user_id).await.unwrap();
+        assert!(store.list_devices(&user_id).await.unwrap().is_empty());
+        assert_eq!(store.list_devices(&other.user_id).await.unwrap(), vec![other]);
+    }
+
+    #[tokio::test]
+    async fn test_expired_devices_are_ignored() {
+        let store = HashmapTrustedDeviceStore::default();
+        let user_id = UserId::default();
+        let device = TrustedDevice::new(user_id, None, Duration::seconds(-1));
+        store.lock().insert(device.id, device.clone());
+
+        assert_eq!(
+            store.use_device(&user_id, &device.id).await,
+            Err(TrustedDeviceStoreError::DeviceNotFound)
+        );
+        assert!(store.list_devices(&user_id).await.unwrap().is_empty());
+
+        // Adding a device clears the expired ones
+        store.add_device(TrustedDevice::new(user_id, None, Duration::days(1))).await.unwrap();
+        assert_eq!(store.lock().len(), 1);
+    }
+}
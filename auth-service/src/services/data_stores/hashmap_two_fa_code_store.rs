@@ -1,77 +1,196 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+};
 
-use crate::domain::{
-    {LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError},
-    Email,
+use crate::{
+    domain::{
+        {LoginAttemptId, TwoFACodeHash, TwoFACodeStore, TwoFACodeStoreError, TwoFAResendState},
+        UserId,
+    },
+    utils::constants::MAX_PENDING_TWO_FA_ATTEMPTS,
 };
 
 
 #[derive(Default)]
 pub struct HashmapTwoFACodeStore {
-    codes: HashMap<Email, (LoginAttemptId, TwoFACode)>,
+    codes: Mutex<Codes>,
+}
+
+#[derive(Default)]
+struct Codes {
+    codes: HashMap<LoginAttemptId, (UserId, TwoFACodeHash, TwoFAResendState)>,
+    // Pending login attempts of each user, oldest first
+    attempts: HashMap<UserId, Vec<LoginAttemptId>>,
+}
+
+impl HashmapTwoFACodeStore {
+    fn lock(&self) -> MutexGuard<'_, Codes> {
+        self.codes.lock().expect("2FA code store lock poisoned")
+    }
 }
 
 // implement TwoFACodeStore for HashmapTwoFACodeStore
 #[async_trait::async_trait]
 impl TwoFACodeStore for HashmapTwoFACodeStore {
     async fn add_code(
-        &mut self,
-        email: Email,
+        &self,
+        user_id: UserId,
         login_attempt_id: LoginAttemptId,
-        code: TwoFACode,
+        code_hash: TwoFACodeHash,
     ) -> Result<(), TwoFACodeStoreError> {
-        self.codes.insert(email, (login_attempt_id, code));
+        let store = &mut *self.lock();
+        let attempts = store.attempts.entry(user_id).or_default();
+        while attempts.len() >= MAX_PENDING_TWO_FA_ATTEMPTS {
+            let oldest = attempts.remove(0);
+            store.codes.remove(&oldest);
+        }
+        attempts.push(login_attempt_id.clone());
+
+        store
+            .codes
+            .insert(login_attempt_id, (user_id, code_hash, TwoFAResendState::default()));
         Ok(())
     }
 
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError> {
-        self.codes.remove(email);
+    async fn remove_code(
+        &self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(), TwoFACodeStoreError> {
+        let mut store = self.lock();
+        if let Some((user_id, _, _)) = store.codes.remove(login_attempt_id) {
+            if let Some(attempts) = store.attempts.get_mut(&user_id) {
+                attempts.retain(|id| id != login_attempt_id);
+                if attempts.is_empty() {
+                    store.attempts.remove(&user_id);
+                }
+            }
+        }
         Ok(())
     }
+
     async fn get_code(
         &self,
-        email: &Email,
-    ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError> {
-        self.codes.get(email).cloned().ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(UserId, TwoFACodeHash), TwoFACodeStoreError> {
+        self.lock()
+            .codes
+            .get(login_attempt_id)
+            .map(|(user_id, code_hash, _)| (*user_id, code_hash.clone()))
+            .ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)
+    }
+
+    async fn resend_code(
+        &self,
+        user_id: &UserId,
+        login_attempt_id: &LoginAttemptId,
+        code_hash: TwoFACodeHash,
+    ) -> Result<(), TwoFACodeStoreError> {
+        let mut store = self.lock();
+        let entry = match store.codes.get_mut(login_attempt_id) {
+            Some(entry) if entry.0 == *user_id => entry,
+            _ => return Err(TwoFACodeStoreError::LoginAttemptIdNotFound),
+        };
+
+        entry.2 = entry.2.next()?;
+        entry.1 = code_hash;
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use secrecy::Secret;
-
     use super::*;
-    use crate::domain::Email;
+    use crate::domain::TwoFACode;
+
+    fn code_hash() -> TwoFACodeHash {
+        TwoFACodeHash::new(&LoginAttemptId::default(), &TwoFACode::default())
+    }
 
     #[tokio::test]
     async fn test_add_code() {
-        let mut store = HashmapTwoFACodeStore::default();
-        let code = TwoFACode::default();
-        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
-        
+        let store = HashmapTwoFACodeStore::default();
+        let code = code_hash();
+        let user_id = UserId::default();
+
         let login_attempt_id = LoginAttemptId::default();
-        store.add_code(email.clone(), login_attempt_id.clone(), code.clone()).await.unwrap();
-        assert_eq!(store.get_code(&email).await.unwrap(), (login_attempt_id, code));
+        store.add_code(user_id, login_attempt_id.clone(), code.clone()).await.unwrap();
+        assert_eq!(store.get_code(&login_attempt_id).await.unwrap(), (user_id, code));
     }
 
     #[tokio::test]
     async fn test_remove_code() {
-        let mut store = HashmapTwoFACodeStore::default();
-        let code = TwoFACode::default();
-        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
+        let store = HashmapTwoFACodeStore::default();
+        let code = code_hash();
+        let user_id = UserId::default();
         let login_attempt_id = LoginAttemptId::default();
-        store.add_code(email.clone(), login_attempt_id, code.clone()).await.unwrap();
-        store.remove_code(&email).await.unwrap();
-        assert_eq!(store.get_code(&email).await, Err(TwoFACodeStoreError::LoginAttemptIdNotFound));
+        store.add_code(user_id, login_attempt_id.clone(), code.clone()).await.unwrap();
+        store.remove_code(&login_attempt_id).await.unwrap();
+        assert_eq!(store.get_code(&login_attempt_id).await, Err(TwoFACodeStoreError::LoginAttemptIdNotFound));
+        assert!(!store.lock().attempts.contains_key(&user_id));
     }
 
     #[tokio::test]
     async fn test_get_code() {
-        let mut store = HashmapTwoFACodeStore::default();
-        let code = TwoFACode::default();
-        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
+        let store = HashmapTwoFACodeStore::default();
+        let code = code_hash();
+        let user_id = UserId::default();
+        let login_attempt_id = LoginAttemptId::default();
+        store.add_code(user_id, login_attempt_id.clone(), code.clone()).await.unwrap();
+        assert_eq!(store.get_code(&login_attempt_id).await.unwrap(), (user_id, code));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_attempts_are_kept_apart() {
+        let store = HashmapTwoFACodeStore::default();
+        let user_id = UserId::default();
+        let first = (LoginAttemptId::default(), code_hash());
+        let second = (LoginAttemptId::default(), code_hash());
+        store.add_code(user_id, first.0.clone(), first.1.clone()).await.unwrap();
+        store.add_code(user_id, second.0.clone(), second.1.clone()).await.unwrap();
+        assert_eq!(store.get_code(&first.0).await.unwrap(), (user_id, first.1));
+        assert_eq!(store.get_code(&second.0).await.unwrap(), (user_id, second.1));
+    }
+
+    #[tokio::test]
+    async fn test_oldest_attempt_is_evicted_beyond_limit() {
+        let store = HashmapTwoFACodeStore::default();
+        let user_id = UserId::default();
+        let ids: Vec<LoginAttemptId> = (0..=MAX_PENDING_TWO_FA_ATTEMPTS)
+            .map(|_| LoginAttemptId::default())
+            .collect();
+        for id in ids.iter() {
+            store.add_code(user_id, id.clone(), code_hash()).await.unwrap();
+        }
+        assert_eq!(store.get_code(&ids[0]).await, Err(TwoFACodeStoreError::LoginAttemptIdNotFound));
+        for id in ids[1..].iter() {
+            assert!(store.get_code(id).await.is_ok());
+        }
+        assert_eq!(store.lock().attempts[&user_id].len(), MAX_PENDING_TWO_FA_ATTEMPTS);
+    }
+
+    #[tokio::test]
+    async fn test_resend_code_during_cooldown() {
+        let store = HashmapTwoFACodeStore::default();
+        let code = code_hash();
+        let user_id = UserId::default();
         let login_attempt_id = LoginAttemptId::default();
-        store.add_code(email.clone(), login_attempt_id.clone(), code.clone()).await.unwrap();
-        assert_eq!(store.get_code(&email).await.unwrap(), (login_attempt_id, code));
+        store.add_code(user_id, login_attempt_id.clone(), code.clone()).await.unwrap();
+        assert_eq!(
+            store.resend_code(&user_id, &login_attempt_id, code_hash()).await,
+            Err(TwoFACodeStoreError::ResendTooSoon)
+        );
+        assert_eq!(store.get_code(&login_attempt_id).await.unwrap(), (user_id, code));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_resend_code_with_unknown_login_attempt_id() {
+        let store = HashmapTwoFACodeStore::default();
+        let user_id = UserId::default();
+        store.add_code(user_id, LoginAttemptId::default(), code_hash()).await.unwrap();
+        assert_eq!(
+            store.resend_code(&user_id, &LoginAttemptId::default(), code_hash()).await,
+            Err(TwoFACodeStoreError::LoginAttemptIdNotFound)
+        );
+    }
+}
@@ -1,22 +1,76 @@
-use std::collections::HashMap;
-use crate::domain::{Email, Password, User, UserStore, UserStoreError};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+use secrecy::ExposeSecret;
+use crate::{
+    domain::{Email, Password, Profile, ProfileUpdate, User, UserId, UserRecord, UserStore, UserStoreError},
+    utils::constants::NO_PASSWORD_PEPPER_VERSION,
+};
 
 // Create a new struct called `HashmapUserStore` containing a `users` field
 // which stores a `HashMap`` of email `String`s mapped to `User` objects.
 // Derive the `Default` trait for `HashmapUserStore`.
 #[derive(Default)]
 pub struct HashmapUserStore {
-    pub users: HashMap<Email, User>,
+    // The lock is never held across an await
+    users: RwLock<Users>,
 }
+
+#[derive(Default)]
+struct Users {
+    users: HashMap<Email, User>,
+    admins: HashSet<Email>,
+    disabled: HashSet<Email>,
+    profiles: HashMap<UserId, Profile>,
+}
+
+impl HashmapUserStore {
+    fn read(&self) -> RwLockReadGuard<'_, Users> {
+        self.users.read().expect("User store lock poisoned")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Users> {
+        self.users.write().expect("User store lock poisoned")
+    }
+}
+
+impl Users {
+    fn find_by_id(&self, id: &UserId) -> Result<&User, UserStoreError> {
+        self.users
+            .values()
+            .find(|user| user.id == *id)
+            .ok_or(UserStoreError::UserNotFound)
+    }
+
+    fn find_by_id_mut(&mut self, id: &UserId) -> Result<&mut User, UserStoreError> {
+        self.users
+            .values_mut()
+            .find(|user| user.id == *id)
+            .ok_or(UserStoreError::UserNotFound)
+    }
+}
+
 #[async_trait::async_trait]
 impl UserStore for HashmapUserStore {
-    async fn add_user(&mut self, user: User) -> Result<(), UserStoreError> {
+    async fn add_user(&self, user: User) -> Result<(), UserStoreError> {
         // Return `UserStoreError::UserAlreadyExists` if the user already exists,
         // otherwise insert the user into the hashmap and return `Ok(())`.
-        if self.users.contains_key(&user.email) {
+        let mut users = self.write();
+        if users.users.contains_key(&user.email) {
             return Err(UserStoreError::UserAlreadyExists);
         }
-        self.users.insert(user.email.clone(), user);
+        users.users.insert(user.email.clone(), user);
+        Ok(())
+    }
+
+    async fn add_admin(&self, user: User) -> Result<(), UserStoreError> {
+        let mut users = self.write();
+        if users.users.contains_key(&user.email) {
+            return Err(UserStoreError::UserAlreadyExists);
+        }
+        users.admins.insert(user.email.clone());
+        users.users.insert(user.email.clone(), user);
         Ok(())
     }
 
@@ -26,12 +80,16 @@ impl UserStore for HashmapUserStore {
     // `User` object or a `UserStoreError`.
     // Return `UserStoreError::UserNotFound` if the user can not be found.
     async fn get_user(&self, email: Email) -> Result<User, UserStoreError> {
-        match self.users.get(&email) {
+        match self.read().users.get(&email) {
             Some(u) => Ok(u.to_owned()),
             None => Err(UserStoreError::UserNotFound)
         }
     }
 
+    async fn get_user_by_id(&self, id: &UserId) -> Result<User, UserStoreError> {
+        self.read().find_by_id(id).cloned()
+    }
+
     // Implement a public method called `validate_user`, which takes an
     // immutable reference to self, an email string slice, and a password string slice
     // as arguments. `validate_user` should return a `Result` type containing either a
@@ -39,17 +97,147 @@ impl UserStore for HashmapUserStore {
     // Return `UserStoreError::UserNotFound` if the user can not be found.
     // Return `UserStoreError::InvalidCredentials` if the password is incorrect.
     async fn validate_user(&self, email: Email, password: Password) -> Result<(), UserStoreError> {
-        if let Some(user) = self.users.get(&email) {
-            if user.password == password {
-                Ok(())
-            } else {
+        let users = self.read();
+        if let Some(user) = users.users.get(&email) {
+            if user.password != password {
                 Err(UserStoreError::InvalidCredentials)
+            } else if users.disabled.contains(&email) {
+                Err(UserStoreError::UserDisabled)
+            } else {
+                Ok(())
             }
         } else {
             Err(UserStoreError::UserNotFound)
         }
     }
 
+    // This store keeps passwords as given, so imported users hold their hash as password
+    async fn import_users(&self, users: Vec<UserRecord>) -> Result<Vec<Email>, UserStoreError> {
+        let mut store = self.write();
+        let mut existing = Vec::new();
+        for record in users {
+            if store.users.contains_key(&record.email) {
+                existing.push(record.email);
+                continue;
+            }
+            let password = Password::parse(record.password_hash)
+                .map_err(UserStoreError::UnexpectedError)?;
+            let user = User {
+                id: record.id,
+                email: record.email.clone(),
+                password,
+                requires_2fa: record.requires_2fa,
+            };
+            store.users.insert(record.email, user);
+        }
+        Ok(existing)
+    }
+
+    async fn export_users(
+        &self,
+        after: Option<Email>,
+        limit: usize,
+    ) -> Result<Vec<UserRecord>, UserStoreError> {
+        let store = self.read();
+        let mut users: Vec<&User> = store
+            .users
+            .values()
+            .filter(|user| match &after {
+                Some(after) => user.email.expose_secret() > after.expose_secret(),
+                None => true,
+            })
+            .collect();
+        users.sort_by(|a, b| a.email.expose_secret().cmp(b.email.expose_secret()));
+
+        Ok(users
+            .into_iter()
+            .take(limit)
+            .map(|user| UserRecord {
+                id: user.id,
+                email: user.email.clone(),
+                password_hash: user.password.as_ref().clone(),
+                requires_2fa: user.requires_2fa,
+                password_pepper_version: NO_PASSWORD_PEPPER_VERSION,
+            })
+            .collect())
+    }
+
+    async fn set_admin(&self, email: &Email, is_admin: bool) -> Result<(), UserStoreError> {
+        let store = &mut *self.write();
+        set_flag(&store.users, &mut store.admins, email, is_admin)
+    }
+
+    async fn is_admin(&self, id: &UserId) -> Result<bool, UserStoreError> {
+        let store = self.read();
+        let user = store.find_by_id(id)?;
+        Ok(store.admins.contains(&user.email))
+    }
+
+    async fn set_disabled(&self, email: &Email, disabled: bool) -> Result<(), UserStoreError> {
+        let store = &mut *self.write();
+        set_flag(&store.users, &mut store.disabled, email, disabled)
+    }
+
+    async fn is_disabled(&self, id: &UserId) -> Result<bool, UserStoreError> {
+        let store = self.read();
+        let user = store.find_by_id(id)?;
+        Ok(store.disabled.contains(&user.email))
+    }
+
+    async fn set_requires_2fa(&self, id: &UserId, requires_2fa: bool) -> Result<(), UserStoreError> {
+        self.write().find_by_id_mut(id)?.requires_2fa = requires_2fa;
+        Ok(())
+    }
+
+    async fn update_password(&self, id: &UserId, password: Password) -> Result<(), UserStoreError> {
+        self.write().find_by_id_mut(id)?.password = password;
+        Ok(())
+    }
+
+    async fn delete_user(&self, id: &UserId) -> Result<(), UserStoreError> {
+        let mut store = self.write();
+        let email = store.find_by_id(id)?.email.clone();
+        store.users.remove(&email);
+        store.admins.remove(&email);
+        store.disabled.remove(&email);
+        store.profiles.remove(id);
+        Ok(())
+    }
+
+    async fn get_profile(&self, id: &UserId) -> Result<Profile, UserStoreError> {
+        let store = self.read();
+        store.find_by_id(id)?;
+        Ok(store.profiles.get(id).cloned().unwrap_or_default())
+    }
+
+    async fn update_profile(
+        &self,
+        id: &UserId,
+        update: ProfileUpdate,
+    ) -> Result<Profile, UserStoreError> {
+        let mut store = self.write();
+        store.find_by_id(id)?;
+        let profile = store.profiles.entry(*id).or_default();
+        update.apply(profile);
+        Ok(profile.clone())
+    }
+}
+
+fn set_flag(
+    users: &HashMap<Email, User>,
+    flagged: &mut HashSet<Email>,
+    email: &Email,
+    value: bool,
+) -> Result<(), UserStoreError> {
+    if !users.contains_key(email) {
+        return Err(UserStoreError::UserNotFound);
+    }
+    if value {
+        flagged.insert(email.clone());
+    } else {
+        flagged.remove(email);
+    }
+    Ok(())
 }
 
 // Add unit tests for your `HashmapUserStore` implementation
@@ -61,31 +249,127 @@ mod tests {
 
     #[tokio::test]
     async fn test_add_user() {
-        let mut store = HashmapUserStore::default();
+        let store = HashmapUserStore::default();
         let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
         let password = Password::parse(Secret::new("password123".to_string())).unwrap();
         let user = User::new(email.clone(), password, true);
         assert_eq!(store.add_user(user).await, Ok(()));
-        assert!(store.users.contains_key(&email));
+        assert!(store.read().users.contains_key(&email));
     }
 
     #[tokio::test]
     async fn test_get_user() {
-        let mut store = HashmapUserStore::default();
+        let store = HashmapUserStore::default();
         let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
         let password = Password::parse(Secret::new("password123".to_string())).unwrap();
         let user = User::new(email.clone(), password, true);
         store.add_user(user.clone()).await.unwrap();
-        assert_eq!(store.get_user(email.clone()).await, Ok(user));
+        assert_eq!(store.get_user(email.clone()).await, Ok(user.clone()));
+        assert_eq!(store.get_user_by_id(&user.id).await, Ok(user));
+        assert_eq!(store.get_user_by_id(&UserId::default()).await, Err(UserStoreError::UserNotFound));
     }
 
     #[tokio::test]
     async fn test_validate_user() {
-        let mut store = HashmapUserStore::default();
+        let store = HashmapUserStore::default();
         let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
         let password = Password::parse(Secret::new("password123".to_string())).unwrap();
         let user = User::new(email.clone(), password.clone(), true);
         store.add_user(user).await.unwrap();
         assert_eq!(store.validate_user(email.clone(), password.clone()).await, Ok(()));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_disabled_user_is_rejected() {
+        let store = HashmapUserStore::default();
+        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
+        let password = Password::parse(Secret::new("password123".to_string())).unwrap();
+        let wrong_password = Password::parse(Secret::new("password124".to_string())).unwrap();
+        store.add_user(User::new(email.clone(), password.clone(), true)).await.unwrap();
+
+        store.set_disabled(&email, true).await.unwrap();
+        let id = store.get_user(email.clone()).await.unwrap().id;
+        assert_eq!(store.is_disabled(&id).await, Ok(true));
+        assert_eq!(store.validate_user(email.clone(), password.clone()).await, Err(UserStoreError::UserDisabled));
+        assert_eq!(store.validate_user(email.clone(), wrong_password).await, Err(UserStoreError::InvalidCredentials));
+
+        store.set_disabled(&email, false).await.unwrap();
+        assert_eq!(store.is_disabled(&id).await, Ok(false));
+        assert_eq!(store.validate_user(email, password).await, Ok(()));
+
+        let unknown = Email::parse(Secret::new("unknown@example.com".to_owned())).unwrap();
+        assert_eq!(store.set_admin(&unknown, true).await, Err(UserStoreError::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_set_admin() {
+        let store = HashmapUserStore::default();
+        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
+        let password = Password::parse(Secret::new("password123".to_string())).unwrap();
+        let user = User::new(email.clone(), password, false);
+        store.add_user(user.clone()).await.unwrap();
+        assert_eq!(store.is_admin(&user.id).await, Ok(false));
+
+        store.set_admin(&email, true).await.unwrap();
+        assert_eq!(store.is_admin(&user.id).await, Ok(true));
+        assert_eq!(store.is_admin(&UserId::default()).await, Err(UserStoreError::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_add_admin() {
+        let store = HashmapUserStore::default();
+        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
+        let password = Password::parse(Secret::new("password123".to_string())).unwrap();
+        let admin = User::new(email.clone(), password.clone(), false);
+        store.add_admin(admin.clone()).await.unwrap();
+        assert_eq!(store.is_admin(&admin.id).await, Ok(true));
+
+        let user = User::new(email, password, false);
+        assert_eq!(store.add_admin(user).await, Err(UserStoreError::UserAlreadyExists));
+    }
+
+    #[tokio::test]
+    async fn test_set_requires_2fa() {
+        let store = HashmapUserStore::default();
+        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
+        let password = Password::parse(Secret::new("password123".to_string())).unwrap();
+        let user = User::new(email.clone(), password, true);
+        store.add_user(user.clone()).await.unwrap();
+
+        store.set_requires_2fa(&user.id, false).await.unwrap();
+        assert!(!store.get_user(email).await.unwrap().requires_2fa);
+        assert_eq!(store.set_requires_2fa(&UserId::default(), true).await, Err(UserStoreError::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_update_password() {
+        let store = HashmapUserStore::default();
+        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
+        let password = Password::parse(Secret::new("password123".to_string())).unwrap();
+        let user = User::new(email.clone(), password.clone(), false);
+        store.add_user(user.clone()).await.unwrap();
+
+        let new_password = Password::parse(Secret::new("password456".to_string())).unwrap();
+        store.update_password(&user.id, new_password.clone()).await.unwrap();
+        assert_eq!(store.validate_user(email.clone(), password).await, Err(UserStoreError::InvalidCredentials));
+        assert_eq!(store.validate_user(email, new_password.clone()).await, Ok(()));
+        assert_eq!(store.update_password(&UserId::default(), new_password).await, Err(UserStoreError::UserNotFound));
+    }
+
+    #[tokio::test]
+    async fn test_update_profile() {
+        let store = HashmapUserStore::default();
+        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
+        let password = Password::parse(Secret::new("password123".to_string())).unwrap();
+        let user = User::new(email, password, true);
+        store.add_user(user.clone()).await.unwrap();
+        assert_eq!(store.get_profile(&user.id).await, Ok(Profile::default()));
+
+        let update = ProfileUpdate { display_name: Some(Some("Jane".to_owned())), ..Default::default() };
+        let profile = store.update_profile(&user.id, update.clone()).await.unwrap();
+        assert_eq!(profile.display_name.as_deref(), Some("Jane"));
+        assert_eq!(store.get_profile(&user.id).await, Ok(profile));
+
+        assert_eq!(store.update_profile(&UserId::default(), update).await, Err(UserStoreError::UserNotFound));
+    }
+}
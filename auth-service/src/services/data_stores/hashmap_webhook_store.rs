@@ -0,0 +1,247 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::domain::{
+    DeliveryOutcome, DomainEvent, WebhookDelivery, WebhookStore, WebhookStoreError, WebhookSubscription,
+};
+
+// Keeps its own outbox, events are added with `publish` instead of by the user store
+#[derive(Default)]
+pub struct HashmapWebhookStore {
+    // The lock is never held across an await
+    webhooks: Mutex<Webhooks>,
+}
+
+#[derive(Default)]
+struct Webhooks {
+    outbox: Vec<DomainEvent>,
+    subscriptions: Vec<WebhookSubscription>,
+    deliveries: HashMap<Uuid, StoredDelivery>,
+}
+
+pub struct StoredDelivery {
+    pub delivery: WebhookDelivery,
+    pub status: DeliveryStatus,
+    pub next_attempt_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Dead,
+}
+
+impl HashmapWebhookStore {
+    pub fn publish(&self, event: DomainEvent) {
+        self.lock().outbox.push(event);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Webhooks> {
+        self.webhooks.lock().expect("Webhook store lock poisoned")
+    }
+}
+
+#[async_trait::async_trait]
+impl WebhookStore for HashmapWebhookStore {
+    async fn add_subscription(&self, subscription: WebhookSubscription) -> Result<(), WebhookStoreError> {
+        self.lock().subscriptions.push(subscription);
+        Ok(())
+    }
+
+    async fn get_subscriptions(&self) -> Result<Vec<WebhookSubscription>, WebhookStoreError> {
+        Ok(self.lock().subscriptions.clone())
+    }
+
+    async fn remove_subscription(&self, id: &Uuid) -> Result<(), WebhookStoreError> {
+        let webhooks = &mut *self.lock();
+        let count = webhooks.subscriptions.len();
+        webhooks.subscriptions.retain(|subscription| subscription.id != *id);
+        if webhooks.subscriptions.len() == count {
+            return Err(WebhookStoreError::SubscriptionNotFound);
+        }
+        webhooks
+            .deliveries
+            .retain(|_, stored| stored.delivery.subscription_id != *id);
+        Ok(())
+    }
+
+    async fn fan_out_events(&self, limit: usize) -> Result<usize, WebhookStoreError> {
+        let webhooks = &mut *self.lock();
+        let events: Vec<DomainEvent> = webhooks.outbox.drain(..limit.min(webhooks.outbox.len())).collect();
+        let now = Utc::now();
+        for event in events.iter() {
+            for subscription in webhooks.subscriptions.iter().filter(|s| s.wants(event.event_type)) {
+                let delivery = WebhookDelivery {
+                    id: Uuid::new_v4(),
+                    subscription_id: subscription.id,
+                    url: subscription.url.to_string(),
+                    secret: subscription.secret.clone(),
+                    event_id: event.id,
+                    event_type: event.event_type,
+                    payload: event.payload().to_string(),
+                    attempts: 0,
+                    last_error: None,
+                };
+                let stored = StoredDelivery {
+                    delivery,
+                    status: DeliveryStatus::Pending,
+                    next_attempt_at: now,
+                    updated_at: now,
+                };
+                webhooks.deliveries.insert(stored.delivery.id, stored);
+            }
+        }
+        Ok(events.len())
+    }
+
+    async fn claim_deliveries(
+        &self,
+        limit: usize,
+        lease: Duration,
+    ) -> Result<Vec<WebhookDelivery>, WebhookStoreError> {
+        let now = Utc::now();
+        let mut webhooks = self.lock();
+        let mut due: Vec<&mut StoredDelivery> = webhooks
+            .deliveries
+            .values_mut()
+            .filter(|stored| stored.status == DeliveryStatus::Pending && stored.next_attempt_at <= now)
+            .collect();
+        due.sort_by_key(|stored| stored.next_attempt_at);
+
+        Ok(due
+            .into_iter()
+            .take(limit)
+            .map(|stored| {
+                stored.next_attempt_at = now + lease;
+                stored.delivery.clone()
+            })
+            .collect())
+    }
+
+    async fn record_outcome(&self, id: &Uuid, outcome: DeliveryOutcome) -> Result<(), WebhookStoreError> {
+        let mut webhooks = self.lock();
+        let Some(stored) = webhooks.deliveries.get_mut(id) else {
+            return Ok(());
+        };
+        stored.delivery.attempts += 1;
+        stored.updated_at = Utc::now();
+        match outcome {
+            DeliveryOutcome::Delivered => {
+                stored.status = DeliveryStatus::Delivered;
+                stored.delivery.last_error = None;
+            }
+            DeliveryOutcome::Retry { error, at } => {
+                stored.next_attempt_at = at;
+                stored.delivery.last_error = Some(error);
+            }
+            DeliveryOutcome::Dead { error } => {
+                stored.status = DeliveryStatus::Dead;
+                stored.delivery.last_error = Some(error);
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_dead_letters(&self, limit: usize) -> Result<Vec<WebhookDelivery>, WebhookStoreError> {
+        let webhooks = self.lock();
+        let mut dead: Vec<&StoredDelivery> = webhooks
+            .deliveries
+            .values()
+            .filter(|stored| stored.status == DeliveryStatus::Dead)
+            .collect();
+        dead.sort_by_key(|stored| std::cmp::Reverse(stored.updated_at));
+        Ok(dead
+            .into_iter()
+            .take(limit)
+            .map(|stored| stored.delivery.clone())
+            .collect())
+    }
+
+    async fn redeliver(&self, id: &Uuid) -> Result<(), WebhookStoreError> {
+        match self.lock().deliveries.get_mut(id) {
+            Some(stored) if stored.status == DeliveryStatus::Dead => {
+                stored.status = DeliveryStatus::Pending;
+                stored.delivery.attempts = 0;
+                stored.next_attempt_at = Utc::now();
+                stored.updated_at = Utc::now();
+                Ok(())
+            }
+            _ => Err(WebhookStoreError::DeadLetterNotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+
+    use crate::domain::{DomainEventType, Email, UserId};
+
+    use super::*;
+
+    fn subscription(event_types: Vec<DomainEventType>) -> WebhookSubscription {
+        WebhookSubscription::new("https://crm.example.com/hooks", event_types).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_events_reach_matching_subscriptions_once() {
+        let store = HashmapWebhookStore::default();
+        let crm = subscription(vec![DomainEventType::UserCreated]);
+        let billing = subscription(vec![DomainEventType::UserDeleted]);
+        store.add_subscription(crm.clone()).await.unwrap();
+        store.add_subscription(billing).await.unwrap();
+
+        store.publish(DomainEvent::user_login(&UserId::default()));
+        let email = Email::parse(Secret::new("jane@example.com".to_owned())).unwrap();
+        let created = DomainEvent::user_created(&UserId::default(), &email);
+        store.publish(created.clone());
+
+        assert_eq!(store.fan_out_events(10).await.unwrap(), 2);
+        assert_eq!(store.fan_out_events(10).await.unwrap(), 0);
+
+        let claimed = store.claim_deliveries(10, Duration::seconds(60)).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].subscription_id, crm.id);
+        assert_eq!(claimed[0].event_id, created.id);
+
+        // Leased to the first caller
+        assert!(store.claim_deliveries(10, Duration::seconds(60)).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters_can_be_redelivered() {
+        let store = HashmapWebhookStore::default();
+        store
+            .add_subscription(subscription(vec![DomainEventType::UserLogin]))
+            .await
+            .unwrap();
+        store.publish(DomainEvent::user_login(&UserId::default()));
+        store.fan_out_events(10).await.unwrap();
+        let delivery = store.claim_deliveries(10, Duration::zero()).await.unwrap().remove(0);
+
+        assert_eq!(
+            store.redeliver(&delivery.id).await,
+            Err(WebhookStoreError::DeadLetterNotFound)
+        );
+        store
+            .record_outcome(&delivery.id, DeliveryOutcome::Dead { error: "HTTP 500".to_owned() })
+            .await
+            .unwrap();
+        let dead = store.get_dead_letters(10).await.unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].last_error.as_deref(), Some("HTTP 500"));
+        assert!(store.claim_deliveries(10, Duration::zero()).await.unwrap().is_empty());
+
+        store.redeliver(&delivery.id).await.unwrap();
+        let claimed = store.claim_deliveries(10, Duration::zero()).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].attempts, 0);
+    }
+}
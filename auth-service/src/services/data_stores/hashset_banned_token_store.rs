@@ -1,4 +1,7 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::RwLock,
+};
 use secrecy::{ExposeSecret, Secret};
 
 use crate::domain::{BannedTokenStore, BannedTokenStoreError};
@@ -8,21 +11,48 @@ use crate::domain::{BannedTokenStore, BannedTokenStoreError};
 // The concrete type should be a struct called HashsetBannedTokenStore. 
 #[derive(Default)]
 pub struct HashsetBannedTokenStore {
-    pub tokens: HashSet<String>,
+    tokens: RwLock<HashSet<String>>,
+    revoked_before: RwLock<HashMap<String, i64>>,
 }
 
 // Implement the BannedTokenStore trait for HashsetBannedTokenStore.
 #[async_trait::async_trait]
 impl BannedTokenStore for HashsetBannedTokenStore {
-    async fn add_token(&mut self, token: Secret<String>) -> Result<(), BannedTokenStoreError> {
-        //let mut tokens = self.tokens.lock().map_err(|_| BannedTokenStoreError::UnexpectedError)?;
-        self.tokens.insert(token.expose_secret().to_string());
+    async fn add_token(&self, token: Secret<String>) -> Result<(), BannedTokenStoreError> {
+        self.tokens
+            .write()
+            .expect("Banned token store lock poisoned")
+            .insert(token.expose_secret().to_string());
         Ok(())
     }
 
     async fn contains_token(&self, token: &Secret<String>) -> Result<bool, BannedTokenStoreError> {
-        //let tokens = self.tokens.lock().map_err(|_| BannedTokenStoreError::TokenDoNotExist)?;
-        Ok(self.tokens.contains(token.expose_secret()))
+        Ok(self
+            .tokens
+            .read()
+            .expect("Banned token store lock poisoned")
+            .contains(token.expose_secret()))
+    }
+
+    async fn revoke_tokens_issued_before(
+        &self,
+        subject: &str,
+        issued_before: i64,
+    ) -> Result<(), BannedTokenStoreError> {
+        self.revoked_before
+            .write()
+            .expect("Banned token store lock poisoned")
+            .insert(subject.to_owned(), issued_before);
+        Ok(())
+    }
+
+    async fn tokens_revoked_before(&self, subject: &str) -> Result<Option<i64>, BannedTokenStoreError> {
+        Ok(self
+            .revoked_before
+            .read()
+            .expect("Banned token store lock poisoned")
+            .get(subject)
+            .copied())
     }
 }
 
@@ -33,7 +63,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_store_and_check_tokens() {
-        let mut store = HashsetBannedTokenStore::default();
+        let store = HashsetBannedTokenStore::default();
         let token1 = Secret::new("abc123".to_string());
         let token2 = Secret::new("def456".to_string());
         store.add_token(token1.clone()).await.unwrap();
@@ -49,4 +79,13 @@ mod tests {
         assert_eq!(store.contains_token(&token1).await, Ok(false));
         
     }
+
+    #[tokio::test]
+    async fn test_revoke_tokens_of_subject() {
+        let store = HashsetBannedTokenStore::default();
+        assert_eq!(store.tokens_revoked_before("test@example.com").await, Ok(None));
+        store.revoke_tokens_issued_before("test@example.com", 1_700_000_000).await.unwrap();
+        assert_eq!(store.tokens_revoked_before("test@example.com").await, Ok(Some(1_700_000_000)));
+        assert_eq!(store.tokens_revoked_before("other@example.com").await, Ok(None));
+    }
 }
\ No newline at end of file
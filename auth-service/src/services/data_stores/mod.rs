@@ -1,13 +1,33 @@
+pub(crate) mod hashmap_login_history_store;
+pub(crate) mod hashmap_trusted_device_store;
 pub(crate) mod hashmap_user_store;
+pub(crate) mod hashmap_webhook_store;
 pub(crate) mod hashset_banned_token_store;
 pub(crate) mod hashmap_two_fa_code_store;
+pub(crate) mod postgres_audit_log;
+pub(crate) mod postgres_event_outbox;
+pub(crate) mod postgres_login_history_store;
+pub(crate) mod postgres_trusted_device_store;
 pub(crate) mod postgres_user_store;
+pub(crate) mod postgres_webhook_store;
 pub(crate) mod redis_banned_token_store;
 pub(crate) mod redis_two_fa_code_store;
+pub(crate) mod vec_audit_log;
+pub(crate) mod vec_event_outbox;
 
+pub use hashmap_login_history_store::*;
+pub use hashmap_trusted_device_store::*;
 pub use hashmap_user_store::*;
+pub use hashmap_webhook_store::*;
 pub use hashset_banned_token_store::*;
 pub use hashmap_two_fa_code_store::*;
+pub use postgres_audit_log::*;
+pub use postgres_event_outbox::*;
+pub use postgres_login_history_store::*;
+pub use postgres_trusted_device_store::*;
 pub use postgres_user_store::*;
+pub use postgres_webhook_store::*;
 pub use redis_banned_token_store::*;
-pub use redis_two_fa_code_store::*;
\ No newline at end of file
+pub use redis_two_fa_code_store::*;
+pub use vec_audit_log::*;
+pub use vec_event_outbox::*;
\ No newline at end of file
@@ -0,0 +1,117 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::domain::{AuditEvent, AuditLog, AuditLogError, AuditQuery};
+
+pub struct PostgresAuditLog {
+    pool: PgPool,
+}
+
+impl PostgresAuditLog {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl AuditLog for PostgresAuditLog {
+    #[tracing::instrument(name = "Recording audit event in PostgreSQL", skip_all)]
+    async fn record(&self, event: AuditEvent) -> Result<(), AuditLogError> {
+        sqlx::query(
+            "INSERT INTO audit_events \
+             (id, occurred_at, action, outcome, actor, ip, user_agent, request_id, detail) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        )
+        .bind(event.id)
+        .bind(event.occurred_at)
+        .bind(event.action.as_str())
+        .bind(event.outcome.as_str())
+        .bind(event.actor.map(|actor| actor.as_uuid()))
+        .bind(event.ip.map(|ip| ip.to_string()))
+        .bind(event.user_agent)
+        .bind(event.request_id)
+        .bind(event.detail)
+        .execute(&self.pool)
+        .await
+        .map_err(unexpected)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Querying audit events from PostgreSQL", skip_all)]
+    async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>, AuditLogError> {
+        let mut sql = QueryBuilder::<Postgres>::new(
+            "SELECT id, occurred_at, action, outcome, actor, ip, user_agent, request_id, detail \
+             FROM audit_events WHERE true",
+        );
+        if let Some(actor) = query.actor {
+            sql.push(" AND actor = ").push_bind(actor.as_uuid());
+        }
+        if let Some(action) = query.action {
+            sql.push(" AND action = ").push_bind(action.as_str());
+        }
+        if let Some(outcome) = query.outcome {
+            sql.push(" AND outcome = ").push_bind(outcome.as_str());
+        }
+        if let Some(ip) = query.ip {
+            sql.push(" AND ip = ").push_bind(ip.to_string());
+        }
+        if let Some(since) = query.since {
+            sql.push(" AND occurred_at >= ").push_bind(since);
+        }
+        if let Some(until) = query.until {
+            sql.push(" AND occurred_at < ").push_bind(until);
+        }
+        sql.push(" ORDER BY occurred_at DESC LIMIT ").push_bind(query.limit as i64);
+
+        let rows = sql
+            .build_query_as::<AuditEventRow>()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(unexpected)?;
+        rows.into_iter().map(AuditEvent::try_from).collect()
+    }
+}
+
+fn unexpected(e: sqlx::Error) -> AuditLogError {
+    AuditLogError::UnexpectedError(e.into())
+}
+
+#[derive(sqlx::FromRow)]
+struct AuditEventRow {
+    id: Uuid,
+    occurred_at: DateTime<Utc>,
+    action: String,
+    outcome: String,
+    actor: Option<Uuid>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    request_id: Option<Uuid>,
+    detail: Option<String>,
+}
+
+impl TryFrom<AuditEventRow> for AuditEvent {
+    type Error = AuditLogError;
+
+    fn try_from(row: AuditEventRow) -> Result<Self, Self::Error> {
+        let ip = row
+            .ip
+            .map(|ip| ip.parse::<IpAddr>().map_err(|_| eyre!("Invalid IP address in audit log: {}", ip)))
+            .transpose()
+            .map_err(AuditLogError::UnexpectedError)?;
+        Ok(Self {
+            id: row.id,
+            occurred_at: row.occurred_at,
+            action: row.action.parse().map_err(AuditLogError::UnexpectedError)?,
+            outcome: row.outcome.parse().map_err(AuditLogError::UnexpectedError)?,
+            actor: row.actor.map(Into::into),
+            ip,
+            user_agent: row.user_agent,
+            request_id: row.request_id,
+            detail: row.detail,
+        })
+    }
+}
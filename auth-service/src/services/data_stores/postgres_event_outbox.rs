@@ -0,0 +1,90 @@
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::domain::{DomainEvent, DomainEventType, EventOutbox, EventOutboxError};
+
+// Writes the event to the outbox. Stores call it with the transaction of the change
+// the event describes, so that the event exists if and only if the change does.
+pub(crate) async fn insert_outbox_event<'e>(
+    executor: impl PgExecutor<'e>,
+    event: &DomainEvent,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO event_outbox (id, event_type, payload, created_at) VALUES ($1, $2, $3::jsonb, $4)",
+    )
+    .bind(event.id)
+    .bind(event.event_type.as_str())
+    .bind(event.payload().to_string())
+    .bind(event.occurred_at)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+// The webhook dispatcher and the event stream publisher each track their own progress
+// through the outbox, `dispatched_at` and `published_at` respectively
+pub struct PostgresEventOutbox {
+    pool: PgPool,
+}
+
+impl PostgresEventOutbox {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventOutbox for PostgresEventOutbox {
+    #[tracing::instrument(name = "Retrieving unpublished events from PostgreSQL", skip_all)]
+    async fn get_unpublished_events(&self, limit: usize) -> Result<Vec<DomainEvent>, EventOutboxError> {
+        let rows = sqlx::query_as::<_, OutboxRow>(
+            "SELECT id, event_type, (payload -> 'data')::text AS data, created_at FROM event_outbox \
+             WHERE published_at IS NULL ORDER BY created_at LIMIT $1",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(unexpected)?;
+        rows.into_iter().map(DomainEvent::try_from).collect()
+    }
+
+    #[tracing::instrument(name = "Marking events published in PostgreSQL", skip_all)]
+    async fn mark_published(&self, ids: &[Uuid]) -> Result<(), EventOutboxError> {
+        sqlx::query("UPDATE event_outbox SET published_at = now() WHERE id = ANY($1)")
+            .bind(ids)
+            .execute(&self.pool)
+            .await
+            .map_err(unexpected)?;
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct OutboxRow {
+    id: Uuid,
+    event_type: String,
+    // JSON text, the JSON types of sqlx are not enabled
+    data: String,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<OutboxRow> for DomainEvent {
+    type Error = EventOutboxError;
+
+    fn try_from(row: OutboxRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.id,
+            event_type: row
+                .event_type
+                .parse::<DomainEventType>()
+                .map_err(EventOutboxError::UnexpectedError)?,
+            occurred_at: row.created_at,
+            data: serde_json::from_str(&row.data).map_err(|e| EventOutboxError::UnexpectedError(e.into()))?,
+        })
+    }
+}
+
+fn unexpected(e: sqlx::Error) -> EventOutboxError {
+    EventOutboxError::UnexpectedError(e.into())
+}
@@ -0,0 +1,71 @@
+use sqlx::PgPool;
+
+use crate::domain::{DomainEvent, Login, LoginHistoryStore, LoginHistoryStoreError, LoginNovelty};
+
+use super::insert_outbox_event;
+
+pub struct PostgresLoginHistoryStore {
+    pool: PgPool,
+}
+
+impl PostgresLoginHistoryStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl LoginHistoryStore for PostgresLoginHistoryStore {
+    #[tracing::instrument(name = "Recording login in PostgreSQL", skip_all)]
+    async fn record_login(&self, login: Login) -> Result<LoginNovelty, LoginHistoryStoreError> {
+        let (ip, user_agent) = login.device_key();
+        let mut transaction = self.pool.begin().await.map_err(unexpected)?;
+
+        // Logins of the same user are recorded one at a time, so that two logins from
+        // a new device cannot both be the first one from it
+        sqlx::query("SELECT 1 FROM users WHERE id = $1 FOR NO KEY UPDATE")
+            .bind(login.user_id.as_uuid())
+            .execute(&mut *transaction)
+            .await
+            .map_err(unexpected)?;
+
+        let (logins, known): (i64, bool) = sqlx::query_as(
+            "SELECT COUNT(*), COALESCE(BOOL_OR(ip = $2 AND user_agent = $3), false) \
+             FROM login_history WHERE user_id = $1",
+        )
+        .bind(login.user_id.as_uuid())
+        .bind(&ip)
+        .bind(&user_agent)
+        .fetch_one(&mut *transaction)
+        .await
+        .map_err(unexpected)?;
+
+        sqlx::query(
+            "INSERT INTO login_history (user_id, ip, user_agent, first_seen_at, last_seen_at) \
+             VALUES ($1, $2, $3, $4, $4) \
+             ON CONFLICT (user_id, ip, user_agent) DO UPDATE SET last_seen_at = EXCLUDED.last_seen_at",
+        )
+        .bind(login.user_id.as_uuid())
+        .bind(&ip)
+        .bind(&user_agent)
+        .bind(login.logged_in_at)
+        .execute(&mut *transaction)
+        .await
+        .map_err(unexpected)?;
+
+        insert_outbox_event(&mut *transaction, &DomainEvent::user_login(&login.user_id))
+            .await
+            .map_err(unexpected)?;
+        transaction.commit().await.map_err(unexpected)?;
+
+        Ok(match (logins, known) {
+            (0, _) => LoginNovelty::FirstLogin,
+            (_, true) => LoginNovelty::KnownDevice,
+            (_, false) => LoginNovelty::NewDevice,
+        })
+    }
+}
+
+fn unexpected(e: sqlx::Error) -> LoginHistoryStoreError {
+    LoginHistoryStoreError::UnexpectedError(e.into())
+}
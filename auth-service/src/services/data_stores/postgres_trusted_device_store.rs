@@ -0,0 +1,142 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::{
+    TrustedDevice, TrustedDeviceId, TrustedDeviceStore, TrustedDeviceStoreError, UserId,
+};
+
+const COLUMNS: &str = "id, user_id, name, created_at, expires_at, last_used_at";
+
+pub struct PostgresTrustedDeviceStore {
+    pool: PgPool,
+}
+
+impl PostgresTrustedDeviceStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl TrustedDeviceStore for PostgresTrustedDeviceStore {
+    #[tracing::instrument(name = "Adding trusted device to PostgreSQL", skip_all)]
+    async fn add_device(&self, device: TrustedDevice) -> Result<(), TrustedDeviceStoreError> {
+        let mut transaction = self.pool.begin().await.map_err(unexpected)?;
+
+        // Expired devices are never read again, clear the user's ones while here
+        sqlx::query("DELETE FROM trusted_devices WHERE user_id = $1 AND expires_at <= now()")
+            .bind(device.user_id.as_uuid())
+            .execute(&mut *transaction)
+            .await
+            .map_err(unexpected)?;
+
+        sqlx::query(
+            "INSERT INTO trusted_devices (id, user_id, name, created_at, expires_at, last_used_at) \
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(device.id.as_uuid())
+        .bind(device.user_id.as_uuid())
+        .bind(device.name)
+        .bind(device.created_at)
+        .bind(device.expires_at)
+        .bind(device.last_used_at)
+        .execute(&mut *transaction)
+        .await
+        .map_err(unexpected)?;
+
+        transaction.commit().await.map_err(unexpected)
+    }
+
+    #[tracing::instrument(name = "Using trusted device from PostgreSQL", skip_all)]
+    async fn use_device(
+        &self,
+        user_id: &UserId,
+        id: &TrustedDeviceId,
+    ) -> Result<TrustedDevice, TrustedDeviceStoreError> {
+        let sql = format!(
+            "UPDATE trusted_devices SET last_used_at = now() \
+             WHERE id = $1 AND user_id = $2 AND expires_at > now() RETURNING {}",
+            COLUMNS
+        );
+        sqlx::query_as::<_, TrustedDeviceRow>(&sql)
+            .bind(id.as_uuid())
+            .bind(user_id.as_uuid())
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(unexpected)?
+            .map(TrustedDevice::from)
+            .ok_or(TrustedDeviceStoreError::DeviceNotFound)
+    }
+
+    #[tracing::instrument(name = "Listing trusted devices from PostgreSQL", skip_all)]
+    async fn list_devices(&self, user_id: &UserId) -> Result<Vec<TrustedDevice>, TrustedDeviceStoreError> {
+        let sql = format!(
+            "SELECT {} FROM trusted_devices WHERE user_id = $1 AND expires_at > now() \
+             ORDER BY created_at DESC",
+            COLUMNS
+        );
+        let rows = sqlx::query_as::<_, TrustedDeviceRow>(&sql)
+            .bind(user_id.as_uuid())
+            .fetch_all(&self.pool)
+            .await
+            .map_err(unexpected)?;
+        Ok(rows.into_iter().map(TrustedDevice::from).collect())
+    }
+
+    #[tracing::instrument(name = "Removing trusted device from PostgreSQL", skip_all)]
+    async fn remove_device(
+        &self,
+        user_id: &UserId,
+        id: &TrustedDeviceId,
+    ) -> Result<(), TrustedDeviceStoreError> {
+        let result = sqlx::query("DELETE FROM trusted_devices WHERE id = $1 AND user_id = $2")
+            .bind(id.as_uuid())
+            .bind(user_id.as_uuid())
+            .execute(&self.pool)
+            .await
+            .map_err(unexpected)?;
+
+        if result.rows_affected() == 0 {
+            return Err(TrustedDeviceStoreError::DeviceNotFound);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Removing all trusted devices from PostgreSQL", skip_all)]
+    async fn remove_all_devices(&self, user_id: &UserId) -> Result<(), TrustedDeviceStoreError> {
+        sqlx::query("DELETE FROM trusted_devices WHERE user_id = $1")
+            .bind(user_id.as_uuid())
+            .execute(&self.pool)
+            .await
+            .map_err(unexpected)?;
+        Ok(())
+    }
+}
+
+fn unexpected(e: sqlx::Error) -> TrustedDeviceStoreError {
+    TrustedDeviceStoreError::UnexpectedError(e.into())
+}
+
+#[derive(sqlx::FromRow)]
+struct TrustedDeviceRow {
+    id: Uuid,
+    user_id: Uuid,
+    name: Option<String>,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<TrustedDeviceRow> for TrustedDevice {
+    fn from(row: TrustedDeviceRow) -> Self {
+        Self {
+            id: row.id.into(),
+            user_id: row.user_id.into(),
+            name: row.name,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            last_used_at: row.last_used_at,
+        }
+    }
+}
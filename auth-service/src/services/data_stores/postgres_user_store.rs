@@ -1,24 +1,65 @@
+use std::{collections::HashSet, str::FromStr};
+
 use secrecy::{ExposeSecret, Secret}; // New!
 
 use argon2::{
-    password_hash::SaltString, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
+    password_hash::{Ident, SaltString}, Algorithm, Argon2, Params, PasswordHash, PasswordHasher,
     PasswordVerifier, Version,
 };
+use scrypt::Scrypt;
 
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
 
 use crate::{domain::{
-    Email, Password, User, UserStore, UserStoreError
-}, utils::constants::PG_TABLE_NAME};
+    DomainEvent, Email, Password, Profile, ProfileUpdate, User, UserId, UserRecord, UserStore, UserStoreError
+}, utils::constants::{
+    ARGON2_PARAMS, CURRENT_PASSWORD_PEPPER_VERSION, NO_PASSWORD_PEPPER_VERSION, PASSWORD_PEPPERS,
+    PG_TABLE_NAME,
+}};
+
+use super::insert_outbox_event;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 
 use color_eyre::eyre::{eyre, Context, Result};
 
 #[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
 pub struct Users {
+    pub id: Uuid,
     pub email: String,
+    #[sqlx(default)]
+    pub email_canonical: Option<String>,
     pub password_hash: String,
     pub requires_2fa: bool,
+    pub password_pepper_version: i32,
+    #[sqlx(default)]
+    pub disabled: bool,
+}
+
+lazy_static! {
+    // Hash of a throwaway password, created with the same parameters as real hashes.
+    // Verifying against it costs as much as checking a real user's password.
+    static ref DUMMY_PASSWORD_HASH: Secret<String> = Secret::new(
+        hash_password(SaltString::generate(&mut rand::thread_rng()).as_str())
+            .expect("Failed to compute dummy password hash")
+    );
+}
+
+// Matches a user by canonical email, bound as $1. Rows the migration could not give a
+// canonical form, because it collides with another user's or is not ASCII, still match
+// on their exact email, bound as $2, until `auth-service-admin canonicalize-emails`
+// resolves them. New users never share a canonical form with such rows, see `insert_user`.
+const EMAIL_FILTER: &str = "(email_canonical = $1 OR (email_canonical IS NULL AND email = $2))";
+
+// Both forms of a stored email, as read by `auth-service-admin canonicalize-emails`
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct StoredEmail {
+    pub id: Uuid,
+    pub email: String,
+    pub email_canonical: Option<String>,
 }
 
 pub struct PostgresUserStore {
@@ -35,72 +76,510 @@ impl PostgresUserStore {
 impl UserStore for PostgresUserStore {
     // Implement all required methods. Note that you will need to make SQL queries against our PostgreSQL instance inside these methods.
     #[tracing::instrument(name = "Adding user to PostgreSQL", skip_all)]
-    async fn add_user(&mut self, user: User) -> Result<(), UserStoreError> {
-        let password_hash = compute_password_hash(user.password.as_ref().to_owned())
-            .await
-            .map_err(UserStoreError::UnexpectedError)?; // Updated!
-
-        sqlx::query!(
-            r#"
-            INSERT INTO users (email, password_hash, requires_2fa)
-            VALUES ($1, $2, $3)
-            "#,
-            user.email.expose_secret(),
-            &password_hash.expose_secret(), // Updated!
-            user.requires_2fa
-        )
-        .execute(&self.pool)
-        .await
-        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?; // Updated!
+    async fn add_user(&self, user: User) -> Result<(), UserStoreError> {
+        self.insert_user(user, false).await
+    }
 
-        Ok(())
+    #[tracing::instrument(name = "Adding admin to PostgreSQL", skip_all)]
+    async fn add_admin(&self, user: User) -> Result<(), UserStoreError> {
+        self.insert_user(user, true).await
     }
 
     #[tracing::instrument(name = "Retrieving user from PostgreSQL", skip_all)]
     async fn get_user(&self, email: Email) -> Result<User, UserStoreError> {
-        sqlx::query!(
-            r#"
-            SELECT email, password_hash, requires_2fa
-            FROM users
-            WHERE email = $1
-            "#,
-            email.expose_secret()
-        )
-        .fetch_optional(&self.pool)
-        .await
-        .map_err(|e| UserStoreError::UnexpectedError(e.into()))?
-        .map(|row| {
-            Ok(User {
-                email: Email::parse(Secret::new(row.email))
-                    .map_err(|e| UserStoreError::UnexpectedError(eyre!(e)))?, // Updated!
-                password: Password::parse(Secret::new(row.password_hash)) // Updated!
-                    .map_err(UserStoreError::UnexpectedError)?, // Updated!
-                requires_2fa: row.requires_2fa,
-            })
-        })
-        .ok_or(UserStoreError::UserNotFound)?
+        user_from_row(self.find_by_email(&email).await?)
+    }
+
+    #[tracing::instrument(name = "Retrieving user by id from PostgreSQL", skip_all)]
+    async fn get_user_by_id(&self, id: &UserId) -> Result<User, UserStoreError> {
+        let sql = format!("SELECT * FROM {} WHERE id = $1", PG_TABLE_NAME);
+        sqlx::query_as::<_, Users>(&sql)
+            .bind(id.as_uuid())
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?
+            .map(user_from_row)
+            .ok_or(UserStoreError::UserNotFound)?
     }
 
     #[tracing::instrument(name = "Validating user credentials in PostgreSQL", skip_all)] // New!
     async fn validate_user(&self, email: Email, password: Password) -> Result<(), UserStoreError> {
-        let sql = format!("select * from {} where email = $1", PG_TABLE_NAME);
-        let query = sqlx::query_as::<_, Users>(&sql);
-        let data = match query.bind(email.expose_secret()).fetch_one(&self.pool).await {
-            Ok(u) => u,
-            Err(e) => match e {
-                sqlx::Error::RowNotFound => return Err(UserStoreError::UserNotFound),
-                _ => return Err(UserStoreError::UnexpectedError(eyre!(e))),
-            }
-        };
+        let data = self.find_by_email(&email).await?;
 
         let pwd_hash = Secret::new(data.password_hash);
         let pwd = password.as_ref().to_owned();
         
-        verify_password_hash(pwd_hash, pwd).await
+        verify_password_hash(pwd_hash.clone(), pwd.clone(), data.password_pepper_version).await
                 .map_err(|_| UserStoreError::InvalidCredentials)?;
+
+        if data.disabled {
+            return Err(UserStoreError::UserDisabled);
+        }
+
+        // The password is known to be correct here, which is the only time a weak,
+        // legacy or outdated-pepper hash can be replaced. A failed upgrade must not fail the login.
+        if needs_rehash(pwd_hash.expose_secret())
+            || data.password_pepper_version != *CURRENT_PASSWORD_PEPPER_VERSION
+        {
+            if let Err(e) = self.upgrade_password_hash(data.id, &pwd_hash, pwd).await {
+                tracing::warn!("Failed to upgrade password hash: {:?}", e);
+            }
+        }
             
         Ok(())
     }
+
+    #[tracing::instrument(name = "Importing users into PostgreSQL", skip_all)]
+    async fn import_users(&self, users: Vec<UserRecord>) -> Result<Vec<Email>, UserStoreError> {
+        let mut transaction = self.pool.begin().await.map_err(unexpected)?;
+        // Users whose email a legacy row already has are skipped like existing ones
+        let legacy = legacy_canonical_emails(&mut *transaction).await?;
+        let (users, mut existing): (Vec<UserRecord>, Vec<UserRecord>) = users
+            .into_iter()
+            .partition(|user| !legacy.contains(user.email.canonical().expose_secret()));
+
+        let mut ids = Vec::with_capacity(users.len());
+        let mut emails = Vec::with_capacity(users.len());
+        let mut canonical_emails = Vec::with_capacity(users.len());
+        let mut password_hashes = Vec::with_capacity(users.len());
+        let mut requires_2fa = Vec::with_capacity(users.len());
+        let mut pepper_versions = Vec::with_capacity(users.len());
+        for user in users.iter() {
+            ids.push(user.id.as_uuid());
+            emails.push(user.email.expose_secret().to_owned());
+            canonical_emails.push(user.email.canonical().expose_secret().to_owned());
+            password_hashes.push(user.password_hash.expose_secret().to_owned());
+            requires_2fa.push(user.requires_2fa);
+            pepper_versions.push(user.password_pepper_version);
+        }
+
+        // One statement per batch, existing users are skipped instead of failing the batch
+        let sql = format!(
+            "INSERT INTO {} (id, email, email_canonical, password_hash, requires_2fa, password_pepper_version) \
+             SELECT * FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::text[], $5::bool[], $6::int[]) \
+             ON CONFLICT DO NOTHING RETURNING id, email",
+            PG_TABLE_NAME
+        );
+        let inserted: Vec<(Uuid, String)> = sqlx::query_as(&sql)
+            .bind(&ids)
+            .bind(&emails)
+            .bind(&canonical_emails)
+            .bind(&password_hashes)
+            .bind(&requires_2fa)
+            .bind(&pepper_versions)
+            .fetch_all(&mut *transaction)
+            .await
+            .map_err(unexpected)?;
+
+        // Imported users are announced like new signups
+        let (created, skipped): (Vec<UserRecord>, Vec<UserRecord>) = users
+            .into_iter()
+            .partition(|user| inserted.iter().any(|(id, _)| *id == user.id.as_uuid()));
+        existing.extend(skipped);
+        for user in created.iter() {
+            insert_outbox_event(&mut *transaction, &DomainEvent::user_created(&user.id, &user.email))
+                .await
+                .map_err(unexpected)?;
+        }
+        transaction.commit().await.map_err(unexpected)?;
+
+        Ok(existing.into_iter().map(|user| user.email).collect())
+    }
+
+    #[tracing::instrument(name = "Exporting users from PostgreSQL", skip_all)]
+    async fn export_users(
+        &self,
+        after: Option<Email>,
+        limit: usize,
+    ) -> Result<Vec<UserRecord>, UserStoreError> {
+        let sql = format!(
+            "SELECT id, email, password_hash, requires_2fa, password_pepper_version FROM {} \
+             WHERE $1::text IS NULL OR email > $1 ORDER BY email LIMIT $2",
+            PG_TABLE_NAME
+        );
+        let rows = sqlx::query_as::<_, Users>(&sql)
+            .bind(after.as_ref().map(|email| email.expose_secret().to_owned()))
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(UserRecord {
+                    id: row.id.into(),
+                    email: Email::parse(Secret::new(row.email))
+                        .map_err(UserStoreError::UnexpectedError)?,
+                    password_hash: Secret::new(row.password_hash),
+                    requires_2fa: row.requires_2fa,
+                    password_pepper_version: row.password_pepper_version,
+                })
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(name = "Setting admin flag in PostgreSQL", skip_all)]
+    async fn set_admin(&self, email: &Email, is_admin: bool) -> Result<(), UserStoreError> {
+        self.set_flag("is_admin", email, is_admin).await
+    }
+
+    #[tracing::instrument(name = "Checking admin flag in PostgreSQL", skip_all)]
+    async fn is_admin(&self, id: &UserId) -> Result<bool, UserStoreError> {
+        let sql = format!("SELECT is_admin FROM {} WHERE id = $1", PG_TABLE_NAME);
+        sqlx::query_scalar::<_, bool>(&sql)
+            .bind(id.as_uuid())
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?
+            .ok_or(UserStoreError::UserNotFound)
+    }
+
+    #[tracing::instrument(name = "Setting disabled flag in PostgreSQL", skip_all)]
+    async fn set_disabled(&self, email: &Email, disabled: bool) -> Result<(), UserStoreError> {
+        self.set_flag("disabled", email, disabled).await
+    }
+
+    #[tracing::instrument(name = "Checking disabled flag in PostgreSQL", skip_all)]
+    async fn is_disabled(&self, id: &UserId) -> Result<bool, UserStoreError> {
+        let sql = format!("SELECT disabled FROM {} WHERE id = $1", PG_TABLE_NAME);
+        sqlx::query_scalar::<_, bool>(&sql)
+            .bind(id.as_uuid())
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?
+            .ok_or(UserStoreError::UserNotFound)
+    }
+
+    #[tracing::instrument(name = "Setting 2FA requirement in PostgreSQL", skip_all)]
+    async fn set_requires_2fa(&self, id: &UserId, requires_2fa: bool) -> Result<(), UserStoreError> {
+        let sql = format!("UPDATE {} SET requires_2fa = $2 WHERE id = $1", PG_TABLE_NAME);
+        let result = sqlx::query(&sql)
+            .bind(id.as_uuid())
+            .bind(requires_2fa)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserNotFound);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Updating password in PostgreSQL", skip_all)]
+    async fn update_password(&self, id: &UserId, password: Password) -> Result<(), UserStoreError> {
+        let pepper_version = *CURRENT_PASSWORD_PEPPER_VERSION;
+        let password_hash = compute_password_hash(password.as_ref().to_owned(), pepper_version)
+            .await
+            .map_err(UserStoreError::UnexpectedError)?;
+
+        let sql = format!(
+            "UPDATE {} SET password_hash = $2, password_pepper_version = $3 WHERE id = $1",
+            PG_TABLE_NAME
+        );
+        let mut transaction = self.pool.begin().await.map_err(unexpected)?;
+        let result = sqlx::query(&sql)
+            .bind(id.as_uuid())
+            .bind(password_hash.expose_secret())
+            .bind(pepper_version)
+            .execute(&mut *transaction)
+            .await
+            .map_err(unexpected)?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserNotFound);
+        }
+        insert_outbox_event(&mut *transaction, &DomainEvent::user_password_changed(id))
+            .await
+            .map_err(unexpected)?;
+        transaction.commit().await.map_err(unexpected)?;
+        Ok(())
+    }
+
+    // Devices and login history go with the user through `ON DELETE CASCADE`
+    #[tracing::instrument(name = "Deleting user from PostgreSQL", skip_all)]
+    async fn delete_user(&self, id: &UserId) -> Result<(), UserStoreError> {
+        let sql = format!("DELETE FROM {} WHERE id = $1", PG_TABLE_NAME);
+        let mut transaction = self.pool.begin().await.map_err(unexpected)?;
+        let result = sqlx::query(&sql)
+            .bind(id.as_uuid())
+            .execute(&mut *transaction)
+            .await
+            .map_err(unexpected)?;
+
+        if result.rows_affected() == 0 {
+            return Err(UserStoreError::UserNotFound);
+        }
+        insert_outbox_event(&mut *transaction, &DomainEvent::user_deleted(id))
+            .await
+            .map_err(unexpected)?;
+        transaction.commit().await.map_err(unexpected)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving profile from PostgreSQL", skip_all)]
+    async fn get_profile(&self, id: &UserId) -> Result<Profile, UserStoreError> {
+        let sql = format!(
+            "SELECT display_name, locale, timezone, avatar_url FROM {} WHERE id = $1",
+            PG_TABLE_NAME
+        );
+        sqlx::query_as::<_, ProfileRow>(&sql)
+            .bind(id.as_uuid())
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?
+            .map(Profile::from)
+            .ok_or(UserStoreError::UserNotFound)
+    }
+
+    #[tracing::instrument(name = "Updating profile in PostgreSQL", skip_all)]
+    async fn update_profile(
+        &self,
+        id: &UserId,
+        update: ProfileUpdate,
+    ) -> Result<Profile, UserStoreError> {
+        // Each field comes as a flag telling whether to set it, and the new value
+        let sql = format!(
+            "UPDATE {} SET \
+             display_name = CASE WHEN $2 THEN $3 ELSE display_name END, \
+             locale = CASE WHEN $4 THEN $5 ELSE locale END, \
+             timezone = CASE WHEN $6 THEN $7 ELSE timezone END, \
+             avatar_url = CASE WHEN $8 THEN $9 ELSE avatar_url END \
+             WHERE id = $1 RETURNING display_name, locale, timezone, avatar_url",
+            PG_TABLE_NAME
+        );
+        let mut query = sqlx::query_as::<_, ProfileRow>(&sql).bind(id.as_uuid());
+        for field in [update.display_name, update.locale, update.timezone, update.avatar_url] {
+            query = query.bind(field.is_some()).bind(field.flatten());
+        }
+        query
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?
+            .map(Profile::from)
+            .ok_or(UserStoreError::UserNotFound)
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ProfileRow {
+    display_name: Option<String>,
+    locale: Option<String>,
+    timezone: Option<String>,
+    avatar_url: Option<String>,
+}
+
+impl From<ProfileRow> for Profile {
+    fn from(row: ProfileRow) -> Self {
+        Self {
+            display_name: row.display_name,
+            locale: row.locale,
+            timezone: row.timezone,
+            avatar_url: row.avatar_url,
+        }
+    }
+}
+
+impl PostgresUserStore {
+    async fn insert_user(&self, user: User, is_admin: bool) -> Result<(), UserStoreError> {
+        let pepper_version = *CURRENT_PASSWORD_PEPPER_VERSION;
+        let password_hash = compute_password_hash(user.password.as_ref().to_owned(), pepper_version)
+            .await
+            .map_err(UserStoreError::UnexpectedError)?; // Updated!
+
+        let sql = format!(
+            "INSERT INTO {} (id, email, email_canonical, password_hash, requires_2fa, password_pepper_version, is_admin) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            PG_TABLE_NAME
+        );
+        let mut transaction = self.pool.begin().await.map_err(unexpected)?;
+        // The unique constraint only covers rows with a canonical email
+        if legacy_canonical_emails(&mut *transaction)
+            .await?
+            .contains(user.email.canonical().expose_secret())
+        {
+            return Err(UserStoreError::UserAlreadyExists);
+        }
+        sqlx::query(&sql)
+            .bind(user.id.as_uuid())
+            .bind(user.email.expose_secret())
+            .bind(user.email.canonical().expose_secret())
+            .bind(password_hash.expose_secret()) // Updated!
+            .bind(user.requires_2fa)
+            .bind(pepper_version)
+            .bind(is_admin)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|e| match e.as_database_error() {
+                // The email, or another spelling of it, is registered already
+                Some(db_error) if db_error.is_unique_violation() => UserStoreError::UserAlreadyExists,
+                _ => unexpected(e),
+            })?;
+        insert_outbox_event(&mut *transaction, &DomainEvent::user_created(&user.id, &user.email))
+            .await
+            .map_err(unexpected)?;
+        transaction.commit().await.map_err(unexpected)?;
+
+        Ok(())
+    }
+
+    // `column` is never user input
+    async fn set_flag(&self, column: &str, email: &Email, value: bool) -> Result<(), UserStoreError> {
+        let sql = format!("UPDATE {} SET {} = $3 WHERE {}", PG_TABLE_NAME, column, EMAIL_FILTER);
+        let mut transaction = self.pool.begin().await.map_err(unexpected)?;
+        let result = sqlx::query(&sql)
+            .bind(email.canonical().expose_secret())
+            .bind(email.expose_secret())
+            .bind(value)
+            .execute(&mut *transaction)
+            .await
+            .map_err(unexpected)?;
+
+        // Dropping the transaction rolls the update back
+        match result.rows_affected() {
+            0 => Err(UserStoreError::UserNotFound),
+            1 => transaction.commit().await.map_err(unexpected),
+            _ => Err(ambiguous_email()),
+        }
+    }
+
+    // Fails rather than picking one of several users matching the email, which
+    // only legacy rows without a canonical email can cause
+    async fn find_by_email(&self, email: &Email) -> Result<Users, UserStoreError> {
+        let sql = format!("SELECT * FROM {} WHERE {} LIMIT 2", PG_TABLE_NAME, EMAIL_FILTER);
+        let mut rows = sqlx::query_as::<_, Users>(&sql)
+            .bind(email.canonical().expose_secret())
+            .bind(email.expose_secret())
+            .fetch_all(&self.pool)
+            .await
+            .map_err(unexpected)?;
+
+        match rows.len() {
+            0 => Err(UserStoreError::UserNotFound),
+            1 => Ok(rows.remove(0)),
+            _ => Err(ambiguous_email()),
+        }
+    }
+
+    #[tracing::instrument(name = "Listing stored emails from PostgreSQL", skip_all)]
+    pub async fn stored_emails(&self) -> Result<Vec<StoredEmail>, UserStoreError> {
+        let sql = format!("SELECT id, email, email_canonical FROM {} ORDER BY email", PG_TABLE_NAME);
+        sqlx::query_as::<_, StoredEmail>(&sql)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))
+    }
+
+    // Applies every change in one transaction. The affected rows are cleared first so
+    // that the unique constraint only sees the final canonical forms.
+    #[tracing::instrument(name = "Updating canonical emails in PostgreSQL", skip_all)]
+    pub async fn set_canonical_emails(
+        &self,
+        changes: &[(UserId, Option<String>)],
+    ) -> Result<(), UserStoreError> {
+        let ids: Vec<Uuid> = changes.iter().map(|(id, _)| id.as_uuid()).collect();
+        let canonical_emails: Vec<Option<String>> =
+            changes.iter().map(|(_, canonical)| canonical.clone()).collect();
+
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        let sql = format!("UPDATE {} SET email_canonical = NULL WHERE id = ANY($1)", PG_TABLE_NAME);
+        sqlx::query(&sql)
+            .bind(&ids)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        let sql = format!(
+            "UPDATE {table} SET email_canonical = changes.email_canonical \
+             FROM UNNEST($1::uuid[], $2::text[]) AS changes(id, email_canonical) \
+             WHERE {table}.id = changes.id",
+            table = PG_TABLE_NAME
+        );
+        sqlx::query(&sql)
+            .bind(&ids)
+            .bind(&canonical_emails)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))?;
+
+        transaction
+            .commit()
+            .await
+            .map_err(|e| UserStoreError::UnexpectedError(e.into()))
+    }
+
+    #[tracing::instrument(name = "Upgrading password hash in PostgreSQL", skip_all)]
+    async fn upgrade_password_hash(
+        &self,
+        id: Uuid,
+        current_hash: &Secret<String>,
+        password: Secret<String>,
+    ) -> Result<()> {
+        let pepper_version = *CURRENT_PASSWORD_PEPPER_VERSION;
+        let new_hash = compute_password_hash(password, pepper_version).await?;
+
+        // Only replace the hash that was verified, in case the password changed meanwhile
+        let sql = format!(
+            "UPDATE {} SET password_hash = $1, password_pepper_version = $2 WHERE id = $3 AND password_hash = $4",
+            PG_TABLE_NAME
+        );
+        sqlx::query(&sql)
+            .bind(new_hash.expose_secret())
+            .bind(pepper_version)
+            .bind(id)
+            .bind(current_hash.expose_secret())
+            .execute(&self.pool)
+            .await
+            .wrap_err("failed to store upgraded password hash")?;
+
+        Ok(())
+    }
+}
+
+// The password of a stored user holds its hash
+fn unexpected(e: sqlx::Error) -> UserStoreError {
+    UserStoreError::UnexpectedError(e.into())
+}
+
+fn ambiguous_email() -> UserStoreError {
+    UserStoreError::UnexpectedError(eyre!(
+        "more than one user matches the email, run `auth-service-admin canonicalize-emails`"
+    ))
+}
+
+// Canonical forms of the emails the migration left without one. There are few such
+// rows, and the exact rules, IDNA included, can only be applied here.
+async fn legacy_canonical_emails<'e>(
+    executor: impl PgExecutor<'e>,
+) -> Result<HashSet<String>, UserStoreError> {
+    let sql = format!("SELECT email FROM {} WHERE email_canonical IS NULL", PG_TABLE_NAME);
+    let emails: Vec<String> = sqlx::query_scalar(&sql)
+        .fetch_all(executor)
+        .await
+        .map_err(unexpected)?;
+
+    // Rows that no longer parse cannot collide with a valid new email
+    Ok(emails
+        .into_iter()
+        .filter_map(|email| Email::parse(Secret::new(email)).ok())
+        .map(|email| email.canonical().expose_secret().to_owned())
+        .collect())
+}
+
+fn user_from_row(row: Users) -> Result<User, UserStoreError> {
+    Ok(User {
+        id: row.id.into(),
+        email: Email::parse(Secret::new(row.email))
+            .map_err(|e| UserStoreError::UnexpectedError(eyre!(e)))?,
+        password: Password::parse(Secret::new(row.password_hash))
+            .map_err(UserStoreError::UnexpectedError)?,
+        requires_2fa: row.requires_2fa,
+    })
 }
 
 // Helper function to verify if a given password matches an expected hash
@@ -112,18 +591,36 @@ impl UserStore for PostgresUserStore {
 pub async fn verify_password_hash(
     expected_password_hash: Secret<String>, // Updated!
     password_candidate: Secret<String>, // Updated!
+    pepper_version: i32,
 ) -> Result<()> {
     let current_span: tracing::Span = tracing::Span::current();
     let result = tokio::task::spawn_blocking(move || {
         current_span.in_scope(|| {
+            let expected_password_hash = expected_password_hash.expose_secret();
+            let password_candidate = apply_pepper(&password_candidate, pepper_version)?;
+            let password_candidate = password_candidate.expose_secret().as_bytes(); // Updated!
+
+            // bcrypt hashes use their own modular crypt format rather than PHC
+            if is_bcrypt_hash(expected_password_hash) {
+                return match bcrypt::verify(password_candidate, expected_password_hash) {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err(eyre!("invalid password")),
+                    Err(e) => Err(e).wrap_err("failed to verify bcrypt password hash"),
+                };
+            }
+
             let expected_password_hash: PasswordHash<'_> =
-                PasswordHash::new(expected_password_hash.expose_secret())?;
+                PasswordHash::new(expected_password_hash)?;
+
+            // Hashes imported from the legacy system may use scrypt
+            if expected_password_hash.algorithm == SCRYPT_IDENT {
+                return Scrypt
+                    .verify_password(password_candidate, &expected_password_hash)
+                    .wrap_err("failed to verify scrypt password hash");
+            }
 
             Argon2::default()
-                .verify_password(
-                    password_candidate.expose_secret().as_bytes(), // Updated!
-                    &expected_password_hash,
-                )
+                .verify_password(password_candidate, &expected_password_hash)
                 .wrap_err("failed to verify password hash")
         })
     })
@@ -132,27 +629,65 @@ pub async fn verify_password_hash(
     result?
 }
 
+// Whether a stored hash should be replaced by one computed with `ARGON2_PARAMS`,
+// i.e. it is a legacy hash, not argon2id, or uses weaker parameters than the target.
+pub fn needs_rehash(password_hash: &str) -> bool {
+    let Ok(password_hash) = PasswordHash::new(password_hash) else {
+        // bcrypt or unparseable
+        return true;
+    };
+    if password_hash.algorithm != argon2::ARGON2ID_IDENT
+        || password_hash.version != Some(Version::V0x13 as u32)
+    {
+        return true;
+    }
+
+    match Params::try_from(&password_hash) {
+        Ok(params) => {
+            params.m_cost() < ARGON2_PARAMS.m_cost()
+                || params.t_cost() < ARGON2_PARAMS.t_cost()
+                || params.p_cost() < ARGON2_PARAMS.p_cost()
+        }
+        Err(_) => true,
+    }
+}
+
+// Whether `verify_password_hash` can check passwords against this hash
+pub fn is_supported_password_hash(password_hash: &str) -> bool {
+    if is_bcrypt_hash(password_hash) {
+        return bcrypt::HashParts::from_str(password_hash).is_ok();
+    }
+    match PasswordHash::new(password_hash) {
+        Ok(password_hash) => [argon2::ARGON2ID_IDENT, argon2::ARGON2I_IDENT, argon2::ARGON2D_IDENT, SCRYPT_IDENT]
+            .contains(&password_hash.algorithm),
+        Err(_) => false,
+    }
+}
+
+fn is_bcrypt_hash(password_hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| password_hash.starts_with(prefix))
+}
+
+const SCRYPT_IDENT: Ident<'_> = Ident::new_unwrap("scrypt");
+
 // Helper function to hash passwords before persisting them in the database.
 // Hashing is a CPU-intensive operation. To avoid blocking
 // other async tasks, update this function to perform hashing on a
 // separate thread pool using tokio::task::spawn_blocking. Note that you
 // will need to update the input parameters to be String types instead of &str
 #[tracing::instrument(name = "Computing password hash", skip_all)] //New!
-async fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>> { // Updated!
+async fn compute_password_hash(
+    password: Secret<String>,
+    pepper_version: i32,
+) -> Result<Secret<String>> { // Updated!
     let current_span: tracing::Span = tracing::Span::current();
 
     let result = tokio::task::spawn_blocking(move || {
         current_span.in_scope(|| {
-            let salt: SaltString = SaltString::generate(&mut rand::thread_rng());
-            let password_hash = Argon2::new(
-                Algorithm::Argon2id,
-                Version::V0x13,
-                Params::new(15000, 2, 1, None)?,
-            )
-            .hash_password(password.expose_secret().as_bytes(), &salt)? // Updated!
-            .to_string();
-
-            Ok(Secret::new(password_hash)) // Updated!
+            let password = apply_pepper(&password, pepper_version)?;
+            hash_password(password.expose_secret()).map(Secret::new) // Updated!
         })
     })
     .await;
@@ -160,6 +695,143 @@ async fn compute_password_hash(password: Secret<String>) -> Result<Secret<String
     result?
 }
 
+// Keys the password with a secret kept outside the database before it is hashed,
+// so that a database dump alone is not enough to start cracking hashes.
+fn apply_pepper(password: &Secret<String>, pepper_version: i32) -> Result<Secret<String>> {
+    if pepper_version == NO_PASSWORD_PEPPER_VERSION {
+        return Ok(password.clone());
+    }
+
+    let pepper = PASSWORD_PEPPERS
+        .get(&pepper_version)
+        .ok_or_else(|| eyre!("password pepper version {} is not configured", pepper_version))?;
+
+    pepper_password(password, pepper)
+}
+
+fn pepper_password(password: &Secret<String>, pepper: &Secret<String>) -> Result<Secret<String>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(pepper.expose_secret().as_bytes())
+        .wrap_err("failed to create password pepper HMAC")?;
+    mac.update(password.expose_secret().as_bytes());
+    Ok(Secret::new(hex::encode(mac.finalize().into_bytes())))
+}
+
+// Performs a password verification whose outcome is discarded. Used for unknown
+// users so that rejecting them takes as long as rejecting a wrong password.
+#[tracing::instrument(name = "Verify dummy password hash", skip_all)]
+pub async fn verify_dummy_password_hash(password_candidate: Secret<String>) {
+    let _ = verify_password_hash(
+        DUMMY_PASSWORD_HASH.clone(),
+        password_candidate,
+        *CURRENT_PASSWORD_PEPPER_VERSION,
+    )
+    .await;
+}
+
+fn hash_password(password: &str) -> Result<String> {
+    let salt: SaltString = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        ARGON2_PARAMS.clone(),
+    )
+    .hash_password(password.as_bytes(), &salt)?
+    .to_string();
+
+    Ok(password_hash)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use scrypt::password_hash::PasswordHasher as _;
+
+    use super::*;
+
+    fn secret(s: &str) -> Secret<String> {
+        Secret::new(s.to_owned())
+    }
+
+    #[tokio::test]
+    async fn verifies_argon2id_hash() {
+        let hash = compute_password_hash(secret("password123"), NO_PASSWORD_PEPPER_VERSION).await.unwrap();
+        assert!(verify_password_hash(hash.clone(), secret("password123"), NO_PASSWORD_PEPPER_VERSION).await.is_ok());
+        assert!(verify_password_hash(hash.clone(), secret("password124"), NO_PASSWORD_PEPPER_VERSION).await.is_err());
+        assert!(!needs_rehash(hash.expose_secret()));
+    }
+
+    #[tokio::test]
+    async fn verifies_legacy_bcrypt_hash() {
+        let hash = bcrypt::hash("password123", 4).unwrap();
+        assert!(verify_password_hash(secret(&hash), secret("password123"), NO_PASSWORD_PEPPER_VERSION).await.is_ok());
+        assert!(verify_password_hash(secret(&hash), secret("password124"), NO_PASSWORD_PEPPER_VERSION).await.is_err());
+        assert!(needs_rehash(&hash));
+    }
+
+    #[tokio::test]
+    async fn verifies_legacy_scrypt_hash() {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let hash = Scrypt
+            .hash_password_customized(
+                b"password123",
+                None,
+                None,
+                scrypt::Params::new(10, 8, 1, 32).unwrap(),
+                &salt,
+            )
+            .unwrap()
+            .to_string();
+        assert!(verify_password_hash(secret(&hash), secret("password123"), NO_PASSWORD_PEPPER_VERSION).await.is_ok());
+        assert!(verify_password_hash(secret(&hash), secret("password124"), NO_PASSWORD_PEPPER_VERSION).await.is_err());
+        assert!(needs_rehash(&hash));
+    }
+
+    #[test]
+    fn peppers_change_the_hashed_input() {
+        let password = secret("password123");
+        let first = pepper_password(&password, &secret("first-pepper-at-least-32-bytes-long")).unwrap();
+        let second = pepper_password(&password, &secret("second-pepper-at-least-32-bytes-long")).unwrap();
+        assert_ne!(first.expose_secret(), password.expose_secret());
+        assert_ne!(first.expose_secret(), second.expose_secret());
+        assert_eq!(
+            apply_pepper(&password, NO_PASSWORD_PEPPER_VERSION).unwrap().expose_secret(),
+            password.expose_secret()
+        );
+    }
+
+    #[test]
+    fn unknown_pepper_version_is_rejected() {
+        assert!(apply_pepper(&secret("password123"), i32::MAX).is_err());
+    }
+
+    #[test]
+    fn weaker_argon2_params_need_rehash() {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let weak_params = Params::new(ARGON2_PARAMS.m_cost() / 2, 1, 1, None).unwrap();
+        let hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, weak_params)
+            .hash_password(b"password123", &salt)
+            .unwrap()
+            .to_string();
+        assert!(needs_rehash(&hash));
+
+        let argon2i_hash = Argon2::new(Algorithm::Argon2i, Version::V0x13, ARGON2_PARAMS.clone())
+            .hash_password(b"password123", &salt)
+            .unwrap()
+            .to_string();
+        assert!(needs_rehash(&argon2i_hash));
+    }
+
+    #[test]
+    fn recognizes_supported_password_hashes() {
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let argon2_hash = Argon2::default().hash_password(b"password123", &salt).unwrap().to_string();
+        assert!(is_supported_password_hash(&argon2_hash));
+        assert!(is_supported_password_hash(&bcrypt::hash("password123", 4).unwrap()));
+        assert!(!is_supported_password_hash("$pbkdf2-sha256$i=1000$c2FsdA$aGFzaA"));
+        assert!(!is_supported_password_hash("$2b$04$tooshort"));
+        assert!(!is_supported_password_hash("password123"));
+    }
+}
 
 /*mod tests {
     use super::*;
@@ -0,0 +1,244 @@
+use chrono::{DateTime, Duration, Utc};
+use color_eyre::eyre::eyre;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use url::Url;
+use uuid::Uuid;
+
+use crate::domain::{
+    DeliveryOutcome, DomainEventType, WebhookDelivery, WebhookStore, WebhookStoreError,
+    WebhookSubscription,
+};
+
+pub struct PostgresWebhookStore {
+    pool: PgPool,
+}
+
+impl PostgresWebhookStore {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+const DELIVERY_COLUMNS: &str = "d.id, d.subscription_id, s.url, s.secret, d.event_id, e.event_type, \
+     e.payload::text AS payload, d.attempts, d.last_error";
+
+#[async_trait::async_trait]
+impl WebhookStore for PostgresWebhookStore {
+    #[tracing::instrument(name = "Adding webhook subscription to PostgreSQL", skip_all)]
+    async fn add_subscription(&self, subscription: WebhookSubscription) -> Result<(), WebhookStoreError> {
+        let event_types: Vec<&str> = subscription.event_types.iter().map(|t| t.as_str()).collect();
+        sqlx::query(
+            "INSERT INTO webhook_subscriptions (id, url, secret, event_types, created_at) \
+             VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(subscription.id)
+        .bind(subscription.url.as_str())
+        .bind(subscription.secret.expose_secret())
+        .bind(&event_types)
+        .bind(subscription.created_at)
+        .execute(&self.pool)
+        .await
+        .map_err(unexpected)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving webhook subscriptions from PostgreSQL", skip_all)]
+    async fn get_subscriptions(&self) -> Result<Vec<WebhookSubscription>, WebhookStoreError> {
+        let rows = sqlx::query_as::<_, SubscriptionRow>(
+            "SELECT id, url, secret, event_types, created_at FROM webhook_subscriptions ORDER BY created_at",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(unexpected)?;
+        rows.into_iter().map(WebhookSubscription::try_from).collect()
+    }
+
+    #[tracing::instrument(name = "Removing webhook subscription from PostgreSQL", skip_all)]
+    async fn remove_subscription(&self, id: &Uuid) -> Result<(), WebhookStoreError> {
+        let result = sqlx::query("DELETE FROM webhook_subscriptions WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map_err(unexpected)?;
+
+        if result.rows_affected() == 0 {
+            return Err(WebhookStoreError::SubscriptionNotFound);
+        }
+        Ok(())
+    }
+
+    // One statement, so that events are marked dispatched only along with their deliveries.
+    // Locked events are skipped, another instance is fanning them out.
+    #[tracing::instrument(name = "Fanning out outbox events in PostgreSQL", skip_all)]
+    async fn fan_out_events(&self, limit: usize) -> Result<usize, WebhookStoreError> {
+        let dispatched = sqlx::query(
+            "WITH events AS ( \
+                 SELECT id, event_type FROM event_outbox WHERE dispatched_at IS NULL \
+                 ORDER BY created_at LIMIT $1 FOR UPDATE SKIP LOCKED \
+             ), deliveries AS ( \
+                 INSERT INTO webhook_deliveries (id, subscription_id, event_id, next_attempt_at, updated_at) \
+                 SELECT gen_random_uuid(), s.id, e.id, now(), now() \
+                 FROM events e JOIN webhook_subscriptions s ON e.event_type = ANY(s.event_types) \
+                 ON CONFLICT (subscription_id, event_id) DO NOTHING \
+             ) \
+             UPDATE event_outbox SET dispatched_at = now() WHERE id IN (SELECT id FROM events)",
+        )
+        .bind(limit as i64)
+        .execute(&self.pool)
+        .await
+        .map_err(unexpected)?
+        .rows_affected();
+        Ok(dispatched as usize)
+    }
+
+    #[tracing::instrument(name = "Claiming webhook deliveries in PostgreSQL", skip_all)]
+    async fn claim_deliveries(
+        &self,
+        limit: usize,
+        lease: Duration,
+    ) -> Result<Vec<WebhookDelivery>, WebhookStoreError> {
+        let sql = format!(
+            "WITH claimed AS ( \
+                 UPDATE webhook_deliveries SET next_attempt_at = now() + make_interval(secs => $2) \
+                 WHERE id IN ( \
+                     SELECT id FROM webhook_deliveries WHERE status = 'pending' AND next_attempt_at <= now() \
+                     ORDER BY next_attempt_at LIMIT $1 FOR UPDATE SKIP LOCKED \
+                 ) RETURNING * \
+             ) \
+             SELECT {} FROM claimed d \
+             JOIN webhook_subscriptions s ON s.id = d.subscription_id \
+             JOIN event_outbox e ON e.id = d.event_id",
+            DELIVERY_COLUMNS
+        );
+        let rows = sqlx::query_as::<_, DeliveryRow>(&sql)
+            .bind(limit as i64)
+            .bind(lease.num_milliseconds() as f64 / 1000.0)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(unexpected)?;
+        rows.into_iter().map(WebhookDelivery::try_from).collect()
+    }
+
+    #[tracing::instrument(name = "Recording webhook delivery outcome in PostgreSQL", skip_all)]
+    async fn record_outcome(&self, id: &Uuid, outcome: DeliveryOutcome) -> Result<(), WebhookStoreError> {
+        let (status, error, next_attempt_at): (&str, Option<String>, Option<DateTime<Utc>>) = match outcome {
+            DeliveryOutcome::Delivered => ("delivered", None, None),
+            DeliveryOutcome::Retry { error, at } => ("pending", Some(error), Some(at)),
+            DeliveryOutcome::Dead { error } => ("dead", Some(error), None),
+        };
+        sqlx::query(
+            "UPDATE webhook_deliveries SET status = $2, last_error = $3, \
+             next_attempt_at = COALESCE($4, next_attempt_at), attempts = attempts + 1, updated_at = now() \
+             WHERE id = $1",
+        )
+        .bind(id)
+        .bind(status)
+        .bind(error)
+        .bind(next_attempt_at)
+        .execute(&self.pool)
+        .await
+        .map_err(unexpected)?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Retrieving dead letters from PostgreSQL", skip_all)]
+    async fn get_dead_letters(&self, limit: usize) -> Result<Vec<WebhookDelivery>, WebhookStoreError> {
+        let sql = format!(
+            "SELECT {} FROM webhook_deliveries d \
+             JOIN webhook_subscriptions s ON s.id = d.subscription_id \
+             JOIN event_outbox e ON e.id = d.event_id \
+             WHERE d.status = 'dead' ORDER BY d.updated_at DESC LIMIT $1",
+            DELIVERY_COLUMNS
+        );
+        let rows = sqlx::query_as::<_, DeliveryRow>(&sql)
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(unexpected)?;
+        rows.into_iter().map(WebhookDelivery::try_from).collect()
+    }
+
+    #[tracing::instrument(name = "Redelivering dead letter in PostgreSQL", skip_all)]
+    async fn redeliver(&self, id: &Uuid) -> Result<(), WebhookStoreError> {
+        let result = sqlx::query(
+            "UPDATE webhook_deliveries SET status = 'pending', attempts = 0, next_attempt_at = now(), \
+             updated_at = now() WHERE id = $1 AND status = 'dead'",
+        )
+        .bind(id)
+        .execute(&self.pool)
+        .await
+        .map_err(unexpected)?;
+
+        if result.rows_affected() == 0 {
+            return Err(WebhookStoreError::DeadLetterNotFound);
+        }
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct SubscriptionRow {
+    id: Uuid,
+    url: String,
+    secret: String,
+    event_types: Vec<String>,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<SubscriptionRow> for WebhookSubscription {
+    type Error = WebhookStoreError;
+
+    fn try_from(row: SubscriptionRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.id,
+            url: Url::parse(&row.url).map_err(|e| WebhookStoreError::UnexpectedError(eyre!(e)))?,
+            secret: Secret::new(row.secret),
+            event_types: row
+                .event_types
+                .iter()
+                .map(|event_type| event_type.parse())
+                .collect::<Result<_, _>>()
+                .map_err(WebhookStoreError::UnexpectedError)?,
+            created_at: row.created_at,
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct DeliveryRow {
+    id: Uuid,
+    subscription_id: Uuid,
+    url: String,
+    secret: String,
+    event_id: Uuid,
+    event_type: String,
+    payload: String,
+    attempts: i32,
+    last_error: Option<String>,
+}
+
+impl TryFrom<DeliveryRow> for WebhookDelivery {
+    type Error = WebhookStoreError;
+
+    fn try_from(row: DeliveryRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: row.id,
+            subscription_id: row.subscription_id,
+            url: row.url,
+            secret: Secret::new(row.secret),
+            event_id: row.event_id,
+            event_type: row
+                .event_type
+                .parse::<DomainEventType>()
+                .map_err(WebhookStoreError::UnexpectedError)?,
+            payload: row.payload,
+            attempts: row.attempts.max(0) as u32,
+            last_error: row.last_error,
+        })
+    }
+}
+
+fn unexpected(e: sqlx::Error) -> WebhookStoreError {
+    WebhookStoreError::UnexpectedError(e.into())
+}
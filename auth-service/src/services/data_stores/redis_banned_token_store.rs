@@ -1,21 +1,18 @@
-use std::sync::Arc;
-
 use color_eyre::eyre::Context;
-use redis::{Commands, Connection};
+use redis::{aio::ConnectionManager, AsyncCommands};
 use secrecy::{ExposeSecret, Secret};
-use tokio::sync::RwLock;
 
 use crate::{
     domain::{BannedTokenStore, BannedTokenStoreError},
-    utils::auth::TOKEN_TTL_SECONDS,
+    utils::auth::{LONGEST_TOKEN_TTL_SECONDS, TOKEN_TTL_SECONDS},
 };
 
 pub struct RedisBannedTokenStore {
-    conn: Arc<RwLock<Connection>>,
+    conn: ConnectionManager,
 }
 
 impl RedisBannedTokenStore {
-    pub fn new(conn: Arc<RwLock<Connection>>) -> Self {
+    pub fn new(conn: ConnectionManager) -> Self {
         Self { conn }
     }
 }
@@ -23,7 +20,7 @@ impl RedisBannedTokenStore {
 #[async_trait::async_trait]
 impl BannedTokenStore for RedisBannedTokenStore {
     #[tracing::instrument(name = "Banned Store Add Token", skip_all)]
-    async fn add_token(&mut self, token: Secret<String>) -> Result<(), BannedTokenStoreError> {
+    async fn add_token(&self, token: Secret<String>) -> Result<(), BannedTokenStoreError> {
         let token_key = get_key(token.expose_secret());
 
         let value = true;
@@ -35,9 +32,9 @@ impl BannedTokenStore for RedisBannedTokenStore {
 
         let _: () = self
             .conn
-            .write()
-            .await
+            .clone()
             .set_ex(&token_key, value, ttl)
+            .await
             .wrap_err("failed to set banned token in Redis") // New!
             .map_err(BannedTokenStoreError::UnexpectedError)?; // Updated!
 
@@ -46,18 +43,52 @@ impl BannedTokenStore for RedisBannedTokenStore {
 
     #[tracing::instrument(name = "Banned Store Contains Token", skip_all)]
     async fn contains_token(&self, token: &Secret<String>) -> Result<bool, BannedTokenStoreError> {
-        let token_key = get_key(&token.expose_secret());
+        let token_key = get_key(token.expose_secret());
 
         let is_banned: bool = self
             .conn
-            .write()
-            .await
+            .clone()
             .exists(&token_key)
+            .await
             .wrap_err("failed to check if token exists in Redis") // New!
             .map_err(BannedTokenStoreError::UnexpectedError)?; // Updated!
 
         Ok(is_banned)
     }
+
+    #[tracing::instrument(name = "Banned Store Revoke Tokens", skip_all)]
+    async fn revoke_tokens_issued_before(
+        &self,
+        subject: &str,
+        issued_before: i64,
+    ) -> Result<(), BannedTokenStoreError> {
+        // Every token issued before the cutoff, login report links included, has
+        // expired once LONGEST_TOKEN_TTL_SECONDS have passed
+        let ttl: u64 = LONGEST_TOKEN_TTL_SECONDS
+            .try_into()
+            .wrap_err("failed to cast LONGEST_TOKEN_TTL_SECONDS to u64")
+            .map_err(BannedTokenStoreError::UnexpectedError)?;
+
+        let _: () = self
+            .conn
+            .clone()
+            .set_ex(get_revocation_key(subject), issued_before, ttl)
+            .await
+            .wrap_err("failed to set token revocation in Redis")
+            .map_err(BannedTokenStoreError::UnexpectedError)?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Banned Store Tokens Revoked Before", skip_all)]
+    async fn tokens_revoked_before(&self, subject: &str) -> Result<Option<i64>, BannedTokenStoreError> {
+        self.conn
+            .clone()
+            .get(get_revocation_key(subject))
+            .await
+            .wrap_err("failed to get token revocation from Redis")
+            .map_err(BannedTokenStoreError::UnexpectedError)
+    }
 }
 
 // We are using a key prefix to prevent collisions and organize data!
@@ -65,4 +96,10 @@ const BANNED_TOKEN_KEY_PREFIX: &str = "banned_token:";
 #[tracing::instrument(name = "Banned Store Get Key", skip_all)]
 fn get_key(token: &str) -> String {
     format!("{}{}", BANNED_TOKEN_KEY_PREFIX, token)
-}
\ No newline at end of file
+}
+
+const REVOKED_TOKENS_KEY_PREFIX: &str = "tokens_revoked_before:";
+#[tracing::instrument(name = "Banned Store Get Revocation Key", skip_all)]
+fn get_revocation_key(subject: &str) -> String {
+    format!("{}{}", REVOKED_TOKENS_KEY_PREFIX, subject)
+}
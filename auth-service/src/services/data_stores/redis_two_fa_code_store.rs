@@ -1,122 +1,222 @@
-use std::sync::Arc;
-
-use redis::{Commands, Connection};
+use chrono::Utc;
+use redis::{aio::ConnectionManager, AsyncCommands, Script};
 use secrecy::{ExposeSecret, Secret};
-use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
-use color_eyre::eyre::Context;
+use color_eyre::eyre::{eyre, Context};
 
 
-use crate::domain::{LoginAttemptId, TwoFACode, TwoFACodeStore, TwoFACodeStoreError,Email};
+use crate::{
+    domain::{LoginAttemptId, TwoFACodeHash, TwoFACodeStore, TwoFACodeStoreError, UserId},
+    utils::constants::{MAX_PENDING_TWO_FA_ATTEMPTS, MAX_TWO_FA_RESENDS, TWO_FA_RESEND_COOLDOWN_SECONDS},
+};
 
 pub struct RedisTwoFACodeStore {
-    conn: Arc<RwLock<Connection>>,
+    conn: ConnectionManager,
 }
 
 impl RedisTwoFACodeStore {
-    pub fn new(conn: Arc<RwLock<Connection>>) -> Self {
+    pub fn new(conn: ConnectionManager) -> Self {
         Self { conn }
     }
+
+    // Returns the id of the user a login attempt belongs to
+    async fn get_user_id(
+        &self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<String, TwoFACodeStoreError> {
+        let key = get_key(login_attempt_id.as_ref().expose_secret());
+
+        let user_id: Option<String> = self
+            .conn
+            .clone()
+            .hget(&key, "user_id")
+            .await
+            .wrap_err("failed to get 2FA login attempt from Redis")
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
+
+        user_id.ok_or(TwoFACodeStoreError::LoginAttemptIdNotFound)
+    }
 }
 
 #[async_trait::async_trait]
 impl TwoFACodeStore for RedisTwoFACodeStore {
     #[tracing::instrument(name = "2FA Store Add Code", skip_all)]
     async fn add_code(
-        &mut self,
-        email: Email,
+        &self,
+        user_id: UserId,
         login_attempt_id: LoginAttemptId,
-        code: TwoFACode,
+        code_hash: TwoFACodeHash,
     ) -> Result<(), TwoFACodeStoreError> {
-        // 1. Create a new key using the get_key helper function.
-        // 2. Create a TwoFATuple instance.
-        // 3. Use serde_json::to_string to serialize the TwoFATuple instance into a JSON string. 
-        // Return TwoFACodeStoreError::UnexpectedError if serialization fails.
-        // 4. Call the set_ex command on the Redis connection to set a new key/value pair with an expiration time (TTL). 
-        // The value should be the serialized 2FA tuple.
-        // The expiration time should be set to TEN_MINUTES_IN_SECONDS.
-        // Return TwoFACodeStoreError::UnexpectedError if casting fails or the call to set_ex fails.
-
-        let key = get_key(&email);
-
-        let data = TwoFATuple(
-            login_attempt_id.as_ref().expose_secret().to_owned(),
-            code.as_ref().expose_secret().to_owned(),
-        );
-        let serialized_data = serde_json::to_string(&data)
-            .wrap_err("failed to serialize 2FA tuple") // New!
-            .map_err(TwoFACodeStoreError::UnexpectedError)?; // Updated!
-
-        let _: () = self
-            .conn
-            .write()
+        // Evicting and adding happen in one script, so that parallel logins
+        // cannot each make room and then all add their attempt
+        let _: () = Script::new(ADD_CODE_SCRIPT)
+            .key(get_index_key(user_id))
+            .key(get_key(login_attempt_id.as_ref().expose_secret()))
+            .arg(login_attempt_id.as_ref().expose_secret())
+            .arg(user_id.to_string())
+            .arg(code_hash.as_ref().expose_secret())
+            .arg(Utc::now().timestamp_millis())
+            .arg(MAX_PENDING_TWO_FA_ATTEMPTS)
+            .arg(TEN_MINUTES_IN_SECONDS)
+            .arg(TWO_FA_CODE_PREFIX)
+            .invoke_async(&mut self.conn.clone())
             .await
-            .set_ex(&key, serialized_data, TEN_MINUTES_IN_SECONDS)
-            .wrap_err("failed to set 2FA code in Redis") // New! 
-            .map_err(TwoFACodeStoreError::UnexpectedError)?; // Updated!
+            .wrap_err("failed to add 2FA login attempt to Redis")
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
 
         Ok(())
     }
 
     #[tracing::instrument(name = "2FA Store Remove Code", skip_all)]
-    async fn remove_code(&mut self, email: &Email) -> Result<(), TwoFACodeStoreError> {
-        // 1. Create a new key using the get_key helper function.
-        // 2. Call the del command on the Redis connection to delete the 2FA code entry. 
-        // Return TwoFACodeStoreError::UnexpectedError if the operation fails.
+    async fn remove_code(
+        &self,
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(), TwoFACodeStoreError> {
+        let user_id = match self.get_user_id(login_attempt_id).await {
+            Ok(user_id) => user_id,
+            Err(TwoFACodeStoreError::LoginAttemptIdNotFound) => return Ok(()),
+            Err(e) => return Err(e),
+        };
 
-        let key = get_key(email);
+        let login_attempt_id = login_attempt_id.as_ref().expose_secret();
+        let mut conn = self.conn.clone();
 
-        let _: () = self
-            .conn
-            .write()
+        let _: () = conn
+            .del(get_key(login_attempt_id))
             .await
-            .del(&key)
             .wrap_err("failed to delete 2FA code from Redis") // New!
             .map_err(TwoFACodeStoreError::UnexpectedError)?; // Updated!
 
+        let _: () = conn
+            .zrem(get_index_key(user_id), login_attempt_id)
+            .await
+            .wrap_err("failed to remove 2FA login attempt from Redis index")
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
+
         Ok(())
     }
 
     #[tracing::instrument(name = "2FA Store Get Code", skip_all)]
     async fn get_code(
         &self,
-        email: &Email,
-    ) -> Result<(LoginAttemptId, TwoFACode), TwoFACodeStoreError> {
-        // 1. Create a new key using the get_key helper function.
-        // 2. Call the get command on the Redis connection to get the value stored for the key. 
-        // Return TwoFACodeStoreError::LoginAttemptIdNotFound if the operation fails.
-        // If the operation succeeds, call serde_json::from_str to parse the JSON string into a TwoFATuple. 
-        // Then, parse the login attempt ID string and 2FA code string into a LoginAttemptId and TwoFACode type respectively.
-        // Return TwoFACodeStoreError::UnexpectedError if parsing fails.
-
-        let key = get_key(email);
-
-        match self.conn.write().await.get::<_, String>(&key) {
-            Ok(value) => {
-                let data: TwoFATuple = serde_json::from_str(&value)
-                    .wrap_err("failed to deserialize 2FA tuple") // New!
-                    .map_err(TwoFACodeStoreError::UnexpectedError)?; // Updated!
-
-                let login_attempt_id =
-                    LoginAttemptId::parse(Secret::new(data.0)).map_err(TwoFACodeStoreError::UnexpectedError)?; // Updated!
-
-                let email_code =
-                    TwoFACode::parse(Secret::new(data.1)).map_err(TwoFACodeStoreError::UnexpectedError)?; // Updated!
-
-                Ok((login_attempt_id, email_code))
-            }
-            Err(_) => Err(TwoFACodeStoreError::LoginAttemptIdNotFound),
+        login_attempt_id: &LoginAttemptId,
+    ) -> Result<(UserId, TwoFACodeHash), TwoFACodeStoreError> {
+        let key = get_key(login_attempt_id.as_ref().expose_secret());
+
+        let (user_id, code_hash): (Option<String>, Option<String>) = self
+            .conn
+            .clone()
+            .hget(&key, &["user_id", "code_hash"])
+            .await
+            .wrap_err("failed to get 2FA code from Redis")
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
+
+        let (Some(user_id), Some(code_hash)) = (user_id, code_hash) else {
+            return Err(TwoFACodeStoreError::LoginAttemptIdNotFound);
+        };
+
+        let user_id = UserId::parse(&user_id).map_err(TwoFACodeStoreError::UnexpectedError)?;
+
+        let code_hash = TwoFACodeHash::parse(Secret::new(code_hash))
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
+
+        Ok((user_id, code_hash))
+    }
+
+    #[tracing::instrument(name = "2FA Store Resend Code", skip_all)]
+    async fn resend_code(
+        &self,
+        user_id: &UserId,
+        login_attempt_id: &LoginAttemptId,
+        code_hash: TwoFACodeHash,
+    ) -> Result<(), TwoFACodeStoreError> {
+        // Checked and updated in one script, so that parallel resends cannot all
+        // pass the cooldown and limit checks before any of them is recorded
+        let outcome: String = Script::new(RESEND_CODE_SCRIPT)
+            .key(get_key(login_attempt_id.as_ref().expose_secret()))
+            .arg(user_id.to_string())
+            .arg(code_hash.as_ref().expose_secret())
+            .arg(Utc::now().timestamp())
+            .arg(TWO_FA_RESEND_COOLDOWN_SECONDS)
+            .arg(MAX_TWO_FA_RESENDS)
+            .invoke_async(&mut self.conn.clone())
+            .await
+            .wrap_err("failed to replace 2FA code in Redis")
+            .map_err(TwoFACodeStoreError::UnexpectedError)?;
+
+        match outcome.as_str() {
+            "resent" => Ok(()),
+            "not_found" => Err(TwoFACodeStoreError::LoginAttemptIdNotFound),
+            "too_soon" => Err(TwoFACodeStoreError::ResendTooSoon),
+            "limit_reached" => Err(TwoFACodeStoreError::ResendLimitReached),
+            other => Err(TwoFACodeStoreError::UnexpectedError(eyre!(
+                "unexpected 2FA resend outcome: {}",
+                other
+            ))),
         }
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct TwoFATuple(pub String, pub String);
+// Adds a login attempt to the user's index, after dropping expired attempts and
+// evicting the oldest pending ones beyond the limit. The index is scored by
+// creation time in milliseconds, so that attempts made within the same second
+// keep their order. Only the keyed hash of the code is kept, so Redis read
+// access is not enough to complete a login.
+// The evicted attempts' keys are built from the prefix rather than declared in
+// KEYS, as they are only known once the index is read. This store therefore
+// needs a single Redis node: Redis Cluster would reject the script, and the
+// attempts could not share a hash slot with the index anyway, since they are
+// looked up by login attempt id alone.
+// KEYS[1]: user's index of pending attempts, KEYS[2]: new login attempt
+// ARGV: login attempt id, user id, code hash, now in milliseconds,
+// maximum pending attempts, expiry in seconds, login attempt key prefix
+const ADD_CODE_SCRIPT: &str = r#"
+local now = tonumber(ARGV[4])
+local ttl = tonumber(ARGV[6])
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - ttl * 1000)
+local excess = redis.call('ZCARD', KEYS[1]) + 1 - tonumber(ARGV[5])
+if excess > 0 then
+    for _, evicted in ipairs(redis.call('ZRANGE', KEYS[1], 0, excess - 1)) do
+        redis.call('DEL', ARGV[7] .. evicted)
+    end
+    redis.call('ZREMRANGEBYRANK', KEYS[1], 0, excess - 1)
+end
+redis.call('HSET', KEYS[2], 'user_id', ARGV[2], 'code_hash', ARGV[3], 'resend_count', 0, 'last_sent_at', math.floor(now / 1000))
+redis.call('EXPIRE', KEYS[2], ttl)
+redis.call('ZADD', KEYS[1], now, ARGV[1])
+redis.call('EXPIRE', KEYS[1], ttl)
+"#;
+
+// Replaces the code of a login attempt, following the same rules as
+// `TwoFAResendState::next`. The hash keeps its expiry, so resending never
+// extends the login attempt.
+// KEYS[1]: login attempt
+// ARGV: user id, new code hash, now, cooldown in seconds, maximum resends
+const RESEND_CODE_SCRIPT: &str = r#"
+local entry = redis.call('HMGET', KEYS[1], 'user_id', 'resend_count', 'last_sent_at')
+if not entry[1] or entry[1] ~= ARGV[1] then
+    return 'not_found'
+end
+if tonumber(entry[2]) >= tonumber(ARGV[5]) then
+    return 'limit_reached'
+end
+if tonumber(ARGV[3]) - tonumber(entry[3]) < tonumber(ARGV[4]) then
+    return 'too_soon'
+end
+redis.call('HSET', KEYS[1], 'code_hash', ARGV[2], 'resend_count', entry[2] + 1, 'last_sent_at', ARGV[3])
+return 'resent'
+"#;
 
 const TEN_MINUTES_IN_SECONDS: u64 = 600;
-const TWO_FA_CODE_PREFIX: &str = "two_fa_code:";
+// Each pending login attempt is a hash of user_id, code_hash, resend_count and last_sent_at
+const TWO_FA_CODE_PREFIX: &str = "two_fa_login_attempt:";
+const TWO_FA_ATTEMPTS_PREFIX: &str = "two_fa_attempts:";
 
 #[tracing::instrument(name = "2FA Store Get Key", skip_all)]
-fn get_key(email: &Email) -> String {
-    format!("{}{}", TWO_FA_CODE_PREFIX, email.expose_secret())
-}
\ No newline at end of file
+fn get_key(login_attempt_id: &str) -> String {
+    format!("{}{}", TWO_FA_CODE_PREFIX, login_attempt_id)
+}
+
+#[tracing::instrument(name = "2FA Store Get Index Key", skip_all)]
+fn get_index_key(user_id: impl std::fmt::Display) -> String {
+    format!("{}{}", TWO_FA_ATTEMPTS_PREFIX, user_id)
+}
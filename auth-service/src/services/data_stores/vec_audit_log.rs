@@ -0,0 +1,85 @@
+use std::sync::RwLock;
+
+use crate::domain::{AuditEvent, AuditLog, AuditLogError, AuditQuery};
+
+#[derive(Default)]
+pub struct VecAuditLog {
+    // In the order they were recorded
+    events: RwLock<Vec<AuditEvent>>,
+}
+
+#[async_trait::async_trait]
+impl AuditLog for VecAuditLog {
+    async fn record(&self, event: AuditEvent) -> Result<(), AuditLogError> {
+        self.events.write().expect("Audit log lock poisoned").push(event);
+        Ok(())
+    }
+
+    async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>, AuditLogError> {
+        let mut events: Vec<AuditEvent> = self
+            .events
+            .read()
+            .expect("Audit log lock poisoned")
+            .iter()
+            .filter(|event| query.matches(event))
+            .cloned()
+            .collect();
+        events.sort_by_key(|event| std::cmp::Reverse(event.occurred_at));
+        events.truncate(query.limit);
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use uuid::Uuid;
+
+    use crate::domain::{AuditAction, AuditOutcome, UserId};
+
+    use super::*;
+
+    fn event(action: AuditAction, outcome: AuditOutcome, actor: Option<UserId>, minutes_ago: i64) -> AuditEvent {
+        AuditEvent {
+            id: Uuid::new_v4(),
+            occurred_at: Utc::now() - Duration::minutes(minutes_ago),
+            action,
+            outcome,
+            actor,
+            ip: Some("10.0.0.1".parse().unwrap()),
+            user_agent: None,
+            request_id: None,
+            detail: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_filters_and_orders_events() {
+        let log = VecAuditLog::default();
+        let user_id = UserId::default();
+        let first = event(AuditAction::Login, AuditOutcome::Failure, Some(user_id), 3);
+        let second = event(AuditAction::Login, AuditOutcome::Success, Some(user_id), 2);
+        let third = event(AuditAction::Logout, AuditOutcome::Success, Some(user_id), 1);
+        for event in [&first, &second, &third] {
+            log.record(event.clone()).await.unwrap();
+        }
+        log.record(event(AuditAction::Login, AuditOutcome::Failure, None, 0)).await.unwrap();
+
+        let query = AuditQuery { actor: Some(user_id), ..Default::default() };
+        assert_eq!(log.query(&query).await.unwrap(), vec![third.clone(), second.clone(), first.clone()]);
+
+        let query = AuditQuery {
+            actor: Some(user_id),
+            action: Some(AuditAction::Login),
+            outcome: Some(AuditOutcome::Failure),
+            ..Default::default()
+        };
+        assert_eq!(log.query(&query).await.unwrap(), vec![first.clone()]);
+
+        // Pages continue before the last event seen
+        let query = AuditQuery { actor: Some(user_id), limit: 2, ..Default::default() };
+        assert_eq!(log.query(&query).await.unwrap(), vec![third, second.clone()]);
+        let query = AuditQuery { actor: Some(user_id), until: Some(second.occurred_at), ..query };
+        assert_eq!(log.query(&query).await.unwrap(), vec![first]);
+    }
+}
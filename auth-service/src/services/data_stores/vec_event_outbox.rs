@@ -0,0 +1,38 @@
+use std::sync::{Mutex, MutexGuard};
+
+use uuid::Uuid;
+
+use crate::domain::{DomainEvent, EventOutbox, EventOutboxError};
+
+// Events are set up front, only their publication changes once the outbox is shared
+#[derive(Default)]
+pub struct VecEventOutbox {
+    pub events: Vec<DomainEvent>,
+    // The lock is never held across an await
+    published: Mutex<Vec<Uuid>>,
+}
+
+impl VecEventOutbox {
+    fn published(&self) -> MutexGuard<'_, Vec<Uuid>> {
+        self.published.lock().expect("Event outbox lock poisoned")
+    }
+}
+
+#[async_trait::async_trait]
+impl EventOutbox for VecEventOutbox {
+    async fn get_unpublished_events(&self, limit: usize) -> Result<Vec<DomainEvent>, EventOutboxError> {
+        let published = self.published();
+        Ok(self
+            .events
+            .iter()
+            .filter(|event| !published.contains(&event.id))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn mark_published(&self, ids: &[Uuid]) -> Result<(), EventOutboxError> {
+        self.published().extend_from_slice(ids);
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+use std::{sync::Arc, time::Duration};
+
+use color_eyre::eyre::Result;
+
+use crate::domain::{EventOutbox, EventStream};
+
+pub type EventOutboxType = Arc<dyn EventOutbox + Send + Sync>;
+pub type EventStreamType = Arc<dyn EventStream + Send + Sync>;
+
+// Events published per round
+const BATCH_SIZE: usize = 100;
+
+// Copies outbox events to the event stream. Events are marked published only once
+// the stream has them, so a failure leads to them being published again: consumers
+// see every event at least once and skip ids they have handled already.
+pub struct EventStreamPublisher {
+    outbox: EventOutboxType,
+    stream: EventStreamType,
+}
+
+impl EventStreamPublisher {
+    pub fn new(outbox: EventOutboxType, stream: EventStreamType) -> Self {
+        Self { outbox, stream }
+    }
+
+    // Publishes every pending event, returns how many
+    #[tracing::instrument(name = "Publishing outbox events", skip_all)]
+    pub async fn publish(&self) -> Result<usize> {
+        let mut published = 0;
+        loop {
+            let events = self.outbox.get_unpublished_events(BATCH_SIZE).await?;
+            if events.is_empty() {
+                return Ok(published);
+            }
+            self.stream.publish(&events).await?;
+
+            let ids: Vec<_> = events.iter().map(|event| event.id).collect();
+            self.outbox.mark_published(&ids).await?;
+            published += events.len();
+        }
+    }
+
+    // Publishes every `interval` for as long as the service runs
+    pub fn spawn(self, interval: Duration) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.publish().await {
+                    tracing::error!("Failed to publish events to the event stream: {:?}", e);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        domain::{mock_event_stream::MockEventStream, DomainEvent, UserId},
+        services::data_stores::VecEventOutbox,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn publishes_each_event_once_in_order() {
+        let mut outbox = VecEventOutbox::default();
+        let events: Vec<DomainEvent> = (0..BATCH_SIZE + 1)
+            .map(|_| DomainEvent::user_login(&UserId::default()))
+            .collect();
+        outbox.events = events.clone();
+        let stream = Arc::new(MockEventStream::default());
+        let publisher = EventStreamPublisher::new(Arc::new(outbox), stream.clone());
+
+        assert_eq!(publisher.publish().await.unwrap(), BATCH_SIZE + 1);
+        assert_eq!(publisher.publish().await.unwrap(), 0);
+        assert_eq!(*stream.events.lock().unwrap(), events);
+    }
+}
@@ -1,3 +1,5 @@
 pub mod data_stores;
+pub mod event_stream_publisher;
 pub mod postmark_email_client;
-
+pub mod redis_event_stream;
+pub mod webhook_dispatcher;
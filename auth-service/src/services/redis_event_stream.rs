@@ -0,0 +1,85 @@
+use std::{collections::HashMap, time::Duration};
+
+use color_eyre::eyre::{Context, Result};
+use redis::{
+    aio::{ConnectionLike, ConnectionManager},
+    from_redis_value,
+    streams::{StreamMaxlen, StreamReadOptions, StreamReadReply},
+    AsyncCommands, RedisResult,
+};
+
+use crate::domain::{event_stream_fields, DomainEvent, EventStream, StreamEvent};
+
+// Appends events to a Redis Stream, trimmed to about `max_len` entries
+pub struct RedisEventStream {
+    conn: ConnectionManager,
+    stream_name: String,
+    max_len: usize,
+}
+
+impl RedisEventStream {
+    pub fn new(conn: ConnectionManager, stream_name: String, max_len: usize) -> Self {
+        Self {
+            conn,
+            stream_name,
+            max_len,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EventStream for RedisEventStream {
+    #[tracing::instrument(name = "Publishing events to Redis", skip_all)]
+    async fn publish(&self, events: &[DomainEvent]) -> Result<()> {
+        let mut pipeline = redis::pipe();
+        for event in events {
+            // Approximate trimming lets Redis drop whole nodes, which is much cheaper
+            pipeline.xadd_maxlen(
+                &self.stream_name,
+                StreamMaxlen::Approx(self.max_len),
+                "*",
+                &event_stream_fields(event),
+            );
+        }
+
+        let _: () = pipeline
+            .query_async(&mut self.conn.clone())
+            .await
+            .wrap_err("failed to add events to the Redis stream")?;
+        Ok(())
+    }
+}
+
+// What a consumer of the stream does: reads up to `count` events after the entry
+// `after`, "0" for the start of the stream, waiting up to `block` for one to arrive.
+// Consumers keep the `entry_id` of the last event they handled to resume from. A
+// blocking read holds up the connection, so give consumers one of their own.
+pub async fn read_stream_events(
+    conn: &mut (impl ConnectionLike + Send),
+    stream_name: &str,
+    after: &str,
+    count: usize,
+    block: Option<Duration>,
+) -> Result<Vec<StreamEvent>> {
+    let mut options = StreamReadOptions::default().count(count);
+    if let Some(block) = block {
+        options = options.block(block.as_millis() as usize);
+    }
+    // Nothing arrived while blocking
+    let reply: Option<StreamReadReply> = conn
+        .xread_options(&[stream_name], &[after], &options)
+        .await
+        .wrap_err("failed to read from the Redis stream")?;
+
+    let mut events = Vec::new();
+    for entry in reply.into_iter().flat_map(|reply| reply.keys).flat_map(|key| key.ids) {
+        let fields = entry
+            .map
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), from_redis_value(value)?)))
+            .collect::<RedisResult<HashMap<String, String>>>()
+            .wrap_err("invalid entry in the Redis stream")?;
+        events.push(StreamEvent::parse(entry.id, fields)?);
+    }
+    Ok(events)
+}
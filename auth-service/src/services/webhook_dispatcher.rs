@@ -0,0 +1,197 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use reqwest::{header::CONTENT_TYPE, Client};
+use tokio::task::JoinSet;
+
+use crate::{
+    domain::{
+        sign_webhook_payload, DeliveryOutcome, RetryPolicy, WebhookDelivery, WebhookStore,
+        WebhookStoreError, WEBHOOK_EVENT_HEADER, WEBHOOK_ID_HEADER, WEBHOOK_SIGNATURE_HEADER,
+    },
+    utils::constants::WEBHOOK_DELIVERY_LEASE,
+};
+
+pub type WebhookStoreType = Arc<dyn WebhookStore + Send + Sync>;
+
+// Events and deliveries handled per round
+const BATCH_SIZE: usize = 50;
+
+// Sends outbox events to the subscribed webhooks. Every instance of the service runs
+// one, the store makes sure each delivery is only attempted by one of them at a time.
+pub struct WebhookDispatcher {
+    store: WebhookStoreType,
+    http_client: Client,
+    retry_policy: RetryPolicy,
+}
+
+impl WebhookDispatcher {
+    pub fn new(store: WebhookStoreType, http_client: Client, retry_policy: RetryPolicy) -> Self {
+        Self {
+            store,
+            http_client,
+            retry_policy,
+        }
+    }
+
+    // Queues deliveries for new outbox events, then attempts the due deliveries at
+    // once. Returns the number of attempts made.
+    #[tracing::instrument(name = "Dispatching webhooks", skip_all)]
+    pub async fn dispatch(&self) -> Result<usize, WebhookStoreError> {
+        while self.store.fan_out_events(BATCH_SIZE).await? == BATCH_SIZE {}
+
+        let lease = chrono::Duration::from_std(WEBHOOK_DELIVERY_LEASE)
+            .map_err(|e| WebhookStoreError::UnexpectedError(e.into()))?;
+        let deliveries = self.store.claim_deliveries(BATCH_SIZE, lease).await?;
+        let attempts = deliveries.len();
+
+        let mut sends = JoinSet::new();
+        for delivery in deliveries {
+            let http_client = self.http_client.clone();
+            sends.spawn(async move {
+                let result = send(&http_client, &delivery).await;
+                (delivery, result)
+            });
+        }
+
+        while let Some(joined) = sends.join_next().await {
+            let (delivery, result) = joined.map_err(|e| WebhookStoreError::UnexpectedError(e.into()))?;
+            let outcome = match result {
+                Ok(()) => DeliveryOutcome::Delivered,
+                Err(error) => self.retry_policy.after_failure(delivery.attempts + 1, error),
+            };
+            match &outcome {
+                DeliveryOutcome::Delivered => {}
+                DeliveryOutcome::Retry { error, at } => tracing::warn!(
+                    "Webhook delivery {} to {} failed, retrying at {}: {}",
+                    delivery.id, delivery.url, at, error
+                ),
+                DeliveryOutcome::Dead { error } => tracing::error!(
+                    "Webhook delivery {} to {} dead-lettered after {} attempts: {}",
+                    delivery.id, delivery.url, delivery.attempts + 1, error
+                ),
+            }
+            self.store.record_outcome(&delivery.id, outcome).await?;
+        }
+        Ok(attempts)
+    }
+
+    // Dispatches every `interval` for as long as the service runs
+    pub fn spawn(self, interval: Duration) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.dispatch().await {
+                    tracing::error!("Failed to dispatch webhooks: {:?}", e);
+                }
+            }
+        });
+    }
+}
+
+// Any 2xx answer counts as delivered. The error describes the failure for the dead letter.
+async fn send(http_client: &Client, delivery: &WebhookDelivery) -> Result<(), String> {
+    let signature = sign_webhook_payload(&delivery.secret, Utc::now().timestamp(), &delivery.payload);
+    let response = http_client
+        .post(&delivery.url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(WEBHOOK_ID_HEADER, delivery.event_id.to_string())
+        .header(WEBHOOK_EVENT_HEADER, delivery.event_type.as_str())
+        .header(WEBHOOK_SIGNATURE_HEADER, signature)
+        .body(delivery.payload.clone())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !response.status().is_success() {
+        return Err(format!("Receiver answered {}", response.status()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+    use wiremock::{
+        matchers::{header, header_exists, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::{
+        domain::{verify_webhook_signature, DomainEvent, DomainEventType, UserId, WebhookSubscription},
+        services::data_stores::HashmapWebhookStore,
+    };
+
+    use super::*;
+
+    fn retry_policy(max_attempts: u32) -> RetryPolicy {
+        // Retries are due right away, so that each `dispatch` makes an attempt
+        RetryPolicy {
+            max_attempts,
+            base_delay: chrono::Duration::zero(),
+            max_delay: chrono::Duration::zero(),
+        }
+    }
+
+    async fn dispatcher(server: &MockServer, max_attempts: u32) -> (WebhookDispatcher, Secret<String>) {
+        let store = HashmapWebhookStore::default();
+        let subscription = WebhookSubscription::new(
+            &format!("{}/hooks", server.uri()),
+            vec![DomainEventType::UserLogin],
+        )
+        .unwrap();
+        let secret = subscription.secret.clone();
+        store.add_subscription(subscription).await.unwrap();
+        store.publish(DomainEvent::user_login(&UserId::default()));
+
+        let store = Arc::new(store);
+        (WebhookDispatcher::new(store, Client::new(), retry_policy(max_attempts)), secret)
+    }
+
+    #[tokio::test]
+    async fn sends_signed_payloads() {
+        let server = MockServer::start().await;
+        Mock::given(path("/hooks"))
+            .and(method("POST"))
+            .and(header(WEBHOOK_EVENT_HEADER, "user.login"))
+            .and(header_exists(WEBHOOK_ID_HEADER))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let (dispatcher, secret) = dispatcher(&server, 3).await;
+
+        assert_eq!(dispatcher.dispatch().await.unwrap(), 1);
+        assert_eq!(dispatcher.dispatch().await.unwrap(), 0);
+
+        let request = &server.received_requests().await.unwrap()[0];
+        let signature = request.headers.get(WEBHOOK_SIGNATURE_HEADER).unwrap().to_str().unwrap();
+        let body = String::from_utf8(request.body.clone()).unwrap();
+        assert!(verify_webhook_signature(&secret, signature, &body, chrono::Duration::minutes(5)));
+    }
+
+    #[tokio::test]
+    async fn retries_then_dead_letters_failed_deliveries() {
+        let server = MockServer::start().await;
+        Mock::given(path("/hooks"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&server)
+            .await;
+        let (dispatcher, _) = dispatcher(&server, 3).await;
+
+        for _ in 0..3 {
+            assert_eq!(dispatcher.dispatch().await.unwrap(), 1);
+        }
+        assert_eq!(dispatcher.dispatch().await.unwrap(), 0);
+
+        let dead_letters = dispatcher.store.get_dead_letters(10).await.unwrap();
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].attempts, 3);
+        assert_eq!(
+            dead_letters[0].last_error.as_deref(),
+            Some("Receiver answered 500 Internal Server Error")
+        );
+    }
+}
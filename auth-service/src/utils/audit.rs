@@ -0,0 +1,174 @@
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, MatchedPath, Request, State},
+    http::{request::Parts, Method},
+    middleware::Next,
+    response::Response,
+};
+use axum_extra::extract::CookieJar;
+use chrono::Utc;
+use secrecy::Secret;
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    domain::{AuditAction, AuditEvent, AuditOutcome, UserId},
+};
+
+use super::{auth::token_subject, constants::JWT_COOKIE_NAME, request_metadata::RequestMetadata};
+
+// What a handler knows about the request that the audit layer cannot tell, noted
+// while it runs. Handlers outside the audit layer get a note nobody reads.
+#[derive(Clone, Debug, Default)]
+pub struct Audit(Arc<Mutex<AuditNote>>);
+
+#[derive(Debug, Default)]
+struct AuditNote {
+    actor: Option<UserId>,
+    detail: Option<String>,
+}
+
+impl Audit {
+    // Needed when the request does not carry a session, e.g. on login
+    pub fn actor(&self, actor: UserId) {
+        self.note().actor = Some(actor);
+    }
+
+    // How a successful request went, e.g. "2fa_required"
+    pub fn detail(&self, detail: &str) {
+        self.note().detail = Some(detail.to_owned());
+    }
+
+    fn note(&self) -> std::sync::MutexGuard<'_, AuditNote> {
+        // Notes stay usable even if a handler panicked while holding the lock
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Audit {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts.extensions.get::<Audit>().cloned().unwrap_or_default())
+    }
+}
+
+// Error message of a failed request, added to the response by `AuthAPIError`
+#[derive(Clone, Copy, Debug)]
+pub struct AuditFailure(pub &'static str);
+
+// Records an audit event for every request to a route of the service. Added with
+// `route_layer`, the matched route tells the action.
+pub async fn record_audit_event(
+    State(state): State<AppState>,
+    metadata: RequestMetadata,
+    jar: CookieJar,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let action = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|path| audit_action(request.method(), path.as_str()));
+    let Some(action) = action else {
+        return next.run(request).await;
+    };
+
+    let audit = Audit::default();
+    request.extensions_mut().insert(audit.clone());
+    let response = next.run(request).await;
+
+    let note = std::mem::take(&mut *audit.note());
+    let status = response.status();
+    let (outcome, detail) = if status.is_client_error() || status.is_server_error() {
+        let error = match response.extensions().get::<AuditFailure>() {
+            Some(AuditFailure(message)) => message.to_string(),
+            // Rejected before reaching the handler, e.g. a malformed body
+            None => status.canonical_reason().unwrap_or("Failed").to_owned(),
+        };
+        (AuditOutcome::Failure, Some(error))
+    } else {
+        (AuditOutcome::Success, note.detail)
+    };
+
+    let event = AuditEvent {
+        id: Uuid::new_v4(),
+        occurred_at: Utc::now(),
+        action,
+        outcome,
+        // Otherwise whoever the session belongs to, even if it is no longer valid
+        actor: note.actor.or_else(|| {
+            jar.get(JWT_COOKIE_NAME)
+                .and_then(|cookie| token_subject(&Secret::new(cookie.value().to_owned())))
+        }),
+        ip: metadata.ip,
+        user_agent: metadata.user_agent,
+        request_id: metadata.request_id,
+        detail,
+    };
+    // The request has been handled, a missing event must not fail it
+    if let Err(e) = state.audit_log.record(event).await {
+        tracing::error!("Failed to record audit event: {:?}", e);
+    }
+
+    response
+}
+
+fn audit_action(method: &Method, path: &str) -> Option<AuditAction> {
+    let action = match (method.as_str(), path) {
+        ("POST", "/signup") => AuditAction::Signup,
+        ("POST", "/login") => AuditAction::Login,
+        ("POST", "/logout") => AuditAction::Logout,
+        ("POST", "/verify-2fa") => AuditAction::Verify2FA,
+        ("POST", "/resend-2fa") => AuditAction::Resend2FA,
+        ("POST", "/verify-token") => AuditAction::VerifyToken,
+        ("POST", "/step-up") => AuditAction::StepUp,
+        ("POST", "/report-login") => AuditAction::ReportLogin,
+        ("GET", "/me") => AuditAction::ViewProfile,
+        ("PATCH", "/me") => AuditAction::UpdateProfile,
+        ("POST", "/me/2fa/enable") => AuditAction::Enable2FA,
+        ("POST", "/me/2fa/disable") => AuditAction::Disable2FA,
+        ("GET", "/me/devices") => AuditAction::ListTrustedDevices,
+        ("DELETE", "/me/devices/:id") => AuditAction::RevokeTrustedDevice,
+        ("GET", "/me/activity") => AuditAction::ViewActivity,
+        ("GET", "/admin/audit-events") => AuditAction::QueryAuditLog,
+        // Static assets
+        _ => return None,
+    };
+    Some(action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routes_map_to_actions() {
+        assert_eq!(audit_action(&Method::POST, "/login"), Some(AuditAction::Login));
+        assert_eq!(
+            audit_action(&Method::DELETE, "/me/devices/:id"),
+            Some(AuditAction::RevokeTrustedDevice)
+        );
+        assert_eq!(audit_action(&Method::GET, "/login"), None);
+        assert_eq!(audit_action(&Method::GET, "/app.js"), None);
+    }
+
+    #[test]
+    fn test_latest_note_wins() {
+        let audit = Audit::default();
+        let actor = UserId::default();
+        audit.actor(UserId::default());
+        audit.actor(actor);
+        audit.detail("2fa_required");
+
+        let note = audit.note();
+        assert_eq!(note.actor, Some(actor));
+        assert_eq!(note.detail.as_deref(), Some("2fa_required"));
+    }
+}
@@ -5,18 +5,25 @@ use thiserror::Error;
 use axum_extra::extract::cookie::{Cookie, SameSite};
 use chrono::Utc;
 
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Validation};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
 
-use crate::{app_state::BannedTokenStoreType, domain::Email};
+use crate::{app_state::BannedTokenStoreType, domain::{LoginAttemptId, Profile, ProfileField, TrustedDevice, UserId}};
 
-use super::constants::{JWT_COOKIE_NAME, JWT_SECRET};
+use super::constants::{
+    JWT_COOKIE_NAME, JWT_SECRET, LOGIN_ATTEMPT_COOKIE_NAME, LOGIN_ATTEMPT_COOKIE_TTL_SECONDS,
+    TRUSTED_DEVICE_COOKIE_NAME,
+};
 
 
 // Create cookie with a new JWT auth token
 #[tracing::instrument(name = "Generate Auth Cookie", skip_all)]
-pub fn generate_auth_cookie(email: &Email) -> Result<Cookie<'static>> {
-    let token = generate_auth_token(email)?;
+pub fn generate_auth_cookie(
+    user_id: &UserId,
+    authentication: AuthenticationClaims,
+    profile: ProfileClaims,
+) -> Result<Cookie<'static>> {
+    let token = generate_auth_token_with_claims(user_id, authentication, profile)?;
     Ok(create_auth_cookie(token))
 }
 
@@ -32,6 +39,34 @@ fn create_auth_cookie(token: Secret<String>) -> Cookie<'static> {
     cookie
 }
 
+// Create the cookie binding a pending 2FA login attempt to the browser that started it.
+// It is meant to be added to a `SignedCookieJar` so that it cannot be forged.
+#[tracing::instrument(name = "Create Login Attempt Cookie", skip_all)]
+pub fn create_login_attempt_cookie(login_attempt_id: &LoginAttemptId) -> Cookie<'static> {
+    Cookie::build((
+        LOGIN_ATTEMPT_COOKIE_NAME,
+        login_attempt_id.expose_secret().to_owned(),
+    ))
+    .path("/")
+    .http_only(true)
+    .same_site(SameSite::Lax)
+    .max_age(time::Duration::seconds(LOGIN_ATTEMPT_COOKIE_TTL_SECONDS))
+    .build()
+}
+
+// Create the cookie letting this browser skip 2FA until the device expires. Like the
+// login attempt cookie, it is meant to be added to a `SignedCookieJar`.
+#[tracing::instrument(name = "Create Trusted Device Cookie", skip_all)]
+pub fn create_trusted_device_cookie(device: &TrustedDevice) -> Cookie<'static> {
+    let max_age = (device.expires_at - device.created_at).num_seconds();
+    Cookie::build((TRUSTED_DEVICE_COOKIE_NAME, device.id.to_string()))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::seconds(max_age))
+        .build()
+}
+
 #[derive(Debug, Error)]
 pub enum GenerateTokenError {
     #[error("Json webtoken decoding error")]
@@ -43,16 +78,69 @@ pub enum GenerateTokenError {
 
 // This value determines how long the JWT auth token is valid for
 pub const TOKEN_TTL_SECONDS: i64 = 600; // 10 minutes
-
-// Create JWT auth token
+// Elevated tokens from `/step-up` prove a recent login, so they expire sooner
+pub const STEP_UP_TOKEN_TTL_SECONDS: i64 = 300; // 5 minutes
+// Login alerts may be read days later
+pub const LOGIN_REPORT_TOKEN_TTL_SECONDS: i64 = 7 * 24 * 60 * 60; // 7 days
+// Revoking a user's tokens has to last until every token it covers has expired
+pub const LONGEST_TOKEN_TTL_SECONDS: i64 = if LOGIN_REPORT_TOKEN_TTL_SECONDS > TOKEN_TTL_SECONDS {
+    LOGIN_REPORT_TOKEN_TTL_SECONDS
+} else {
+    TOKEN_TTL_SECONDS
+};
+const LOGIN_REPORT_AUDIENCE: &str = "login-report";
+
+// Create JWT auth token, without authentication claims as the user did not log in
 #[tracing::instrument(name = "Generate Auth Token", skip_all)]
-pub fn generate_auth_token(email: &Email) -> Result<Secret<String>> {
-    let delta = chrono::Duration::try_seconds(TOKEN_TTL_SECONDS)
-        .wrap_err("failed to create 10 minute time delta")?;
+pub fn generate_auth_token(user_id: &UserId) -> Result<Secret<String>> {
+    generate_auth_token_with_claims(user_id, AuthenticationClaims::default(), ProfileClaims::default())
+}
+
+// Create JWT auth token telling how the user logged in, and carrying some of their profile
+#[tracing::instrument(name = "Generate Auth Token With Claims", skip_all)]
+pub fn generate_auth_token_with_claims(
+    user_id: &UserId,
+    authentication: AuthenticationClaims,
+    profile: ProfileClaims,
+) -> Result<Secret<String>> {
+    generate_token(user_id, authentication, profile, TOKEN_TTL_SECONDS)
+}
+
+// Create short-lived JWT auth token for a user who has just authenticated again
+#[tracing::instrument(name = "Generate Step-up Token", skip_all)]
+pub fn generate_step_up_token(
+    user_id: &UserId,
+    authentication: AuthenticationClaims,
+    profile: ProfileClaims,
+) -> Result<Secret<String>> {
+    generate_token(user_id, authentication, profile, STEP_UP_TOKEN_TTL_SECONDS)
+}
+
+fn generate_token(
+    user_id: &UserId,
+    authentication: AuthenticationClaims,
+    profile: ProfileClaims,
+    ttl_seconds: i64,
+) -> Result<Secret<String>> {
+    let (iat, exp) = issued_and_expiry(ttl_seconds)?;
 
-    let exp = Utc::now()
+    // The subject is the stable user id, so tokens survive an email change
+    let sub = user_id.to_string();
+
+    let claims = Claims { sub, exp, iat, authentication, profile };
+
+    create_token(&claims)
+}
+
+// `iat` and `exp` claims of a token valid for `ttl_seconds` from now
+fn issued_and_expiry(ttl_seconds: i64) -> Result<(usize, usize)> {
+    let delta = chrono::Duration::try_seconds(ttl_seconds)
+        .wrap_err_with(|| format!("failed to create {} second time delta", ttl_seconds))?;
+
+    let now = Utc::now();
+    let exp = now
         .checked_add_signed(delta)
-        .ok_or(eyre!("failed to add 10 minutes to current time"))?
+        .ok_or(eyre!("failed to add {} seconds to current time", ttl_seconds))?
         .timestamp();
 
     let exp: usize = exp.try_into().wrap_err(format!(
@@ -60,20 +148,58 @@ pub fn generate_auth_token(email: &Email) -> Result<Secret<String>> {
         exp
     ))?;
 
-    let sub = email.as_ref().expose_secret().to_owned();
+    let iat: usize = now
+        .timestamp()
+        .try_into()
+        .wrap_err("failed to cast iat time to usize")?;
 
-    let claims = Claims { sub, exp };
+    Ok((iat, exp))
+}
 
+// Create the token of the "this wasn't me" link sent with login alerts. Its audience
+// keeps it from being accepted as an auth token, and the other way around.
+#[tracing::instrument(name = "Generate Login Report Token", skip_all)]
+pub fn generate_login_report_token(user_id: &UserId) -> Result<Secret<String>> {
+    let (iat, exp) = issued_and_expiry(LOGIN_REPORT_TOKEN_TTL_SECONDS)?;
+    let claims = LoginReportClaims {
+        sub: user_id.to_string(),
+        exp,
+        iat,
+        aud: LOGIN_REPORT_AUDIENCE.to_owned(),
+    };
     create_token(&claims)
 }
 
+// Check a login report token and return the user it was sent to. Reporting a login
+// revokes the user's tokens, which makes the link single use.
+#[tracing::instrument(name = "Validate Login Report Token", skip_all)]
+pub async fn validate_login_report_token(
+    token: &Secret<String>,
+    banned_token_store: BannedTokenStoreType,
+) -> Result<UserId> {
+    let mut validation = token_validation();
+    validation.set_audience(&[LOGIN_REPORT_AUDIENCE]);
+    let claims = decode::<LoginReportClaims>(token.expose_secret(), &decoding_key(), &validation)
+        .map(|data| data.claims)
+        .wrap_err("failed to decode login report token")?;
+
+    let revoked_before = banned_token_store
+        .tokens_revoked_before(&claims.sub)
+        .await?;
+    if revoked_before.is_some_and(|revoked_before| (claims.iat as i64) < revoked_before) {
+        return Err(eyre!("login report token is revoked"));
+    }
+
+    UserId::parse(&claims.sub)
+}
+
 // Check if JWT auth token is valid by decoding it using the JWT secret
 #[tracing::instrument(name = "Validate Token", skip_all)]
 pub async fn validate_token(
     token: &Secret<String>,
     banned_token_store: BannedTokenStoreType,
 ) -> Result<Claims> {
-    match banned_token_store.read().await.contains_token(token).await {
+    match banned_token_store.contains_token(token).await {
         Ok(value) => {
             if value {
                 return Err(eyre!("token is banned"));
@@ -82,18 +208,124 @@ pub async fn validate_token(
         Err(e) => return Err(e.into()),
     }
 
-    decode::<Claims>(
-        token.expose_secret(),
-        &DecodingKey::from_secret(JWT_SECRET.expose_secret().as_bytes()),
-        &Validation::default(),
-    )
-    .map(|data| data.claims)
-    .wrap_err("failed to decode token")
+    let claims = decode::<Claims>(token.expose_secret(), &decoding_key(), &token_validation())
+        .map(|data| data.claims)
+        .wrap_err("failed to decode token")?;
+
+    // Tokens of the user may have been revoked all at once, e.g. from the admin CLI
+    let revoked_before = banned_token_store
+        .tokens_revoked_before(&claims.sub)
+        .await?;
+    if is_revoked(&claims, revoked_before) {
+        return Err(eyre!("token is revoked"));
+    }
+
+    Ok(claims)
+}
+
+// Outcome of each check `validate_token` performs, to explain why a token is rejected
+#[derive(Debug, Default)]
+pub struct TokenInspection {
+    // Set when the token cannot be decoded at all, the other checks are then skipped
+    pub decode_error: Option<String>,
+    // Decoded without verifying the signature
+    pub header: Option<Header>,
+    pub claims: Option<Claims>,
+    pub signature_valid: bool,
+    // Set for tokens meant for something else, e.g. login report links
+    pub audience: Option<String>,
+    // Seconds until `exp`, negative once it has passed
+    pub expires_in: Option<i64>,
+    // Past `exp` by more than the allowed leeway
+    pub expired: bool,
+    pub banned: bool,
+    pub revoked_before: Option<i64>,
+    pub revoked: bool,
+}
+
+impl TokenInspection {
+    pub fn is_valid(&self) -> bool {
+        self.decode_error.is_none()
+            && self.signature_valid
+            && self.audience.is_none()
+            && !self.expired
+            && !self.banned
+            && !self.revoked
+    }
+}
+
+// Runs every check of `validate_token` instead of stopping at the first failure
+#[tracing::instrument(name = "Inspect Token", skip_all)]
+pub async fn inspect_token(
+    token: &Secret<String>,
+    banned_token_store: BannedTokenStoreType,
+) -> Result<TokenInspection> {
+    let mut inspection = TokenInspection {
+        banned: banned_token_store.contains_token(token).await?,
+        ..Default::default()
+    };
+
+    // The audience is reported as a check of its own rather than failing the decode
+    let mut unverified = token_validation();
+    unverified.insecure_disable_signature_validation();
+    unverified.validate_exp = false;
+    unverified.validate_aud = false;
+    let data = match decode::<Claims>(token.expose_secret(), &DecodingKey::from_secret(&[]), &unverified) {
+        Ok(data) => data,
+        Err(e) => {
+            inspection.decode_error = Some(e.to_string());
+            return Ok(inspection);
+        }
+    };
+    inspection.audience =
+        decode::<AudienceClaim>(token.expose_secret(), &DecodingKey::from_secret(&[]), &unverified)
+            .ok()
+            .and_then(|data| data.claims.aud);
+
+    let mut signature_only = token_validation();
+    signature_only.validate_exp = false;
+    signature_only.validate_aud = false;
+    inspection.signature_valid =
+        decode::<Claims>(token.expose_secret(), &decoding_key(), &signature_only).is_ok();
+
+    let expires_in = data.claims.exp as i64 - Utc::now().timestamp();
+    inspection.expires_in = Some(expires_in);
+    inspection.expired = expires_in + (token_validation().leeway as i64) < 0;
+
+    inspection.revoked_before = banned_token_store
+        .tokens_revoked_before(&data.claims.sub)
+        .await?;
+    inspection.revoked = is_revoked(&data.claims, inspection.revoked_before);
+
+    inspection.header = Some(data.header);
+    inspection.claims = Some(data.claims);
+    Ok(inspection)
+}
+
+// The user an auth token was issued to, checking its signature only. Tells who made a
+// request, e.g. for the audit log, but use `validate_token` before trusting them.
+pub fn token_subject(token: &Secret<String>) -> Option<UserId> {
+    let mut validation = token_validation();
+    validation.validate_exp = false;
+    let claims = decode::<Claims>(token.expose_secret(), &decoding_key(), &validation).ok()?.claims;
+    UserId::parse(&claims.sub).ok()
+}
+
+fn decoding_key() -> DecodingKey {
+    DecodingKey::from_secret(JWT_SECRET.expose_secret().as_bytes())
+}
+
+fn token_validation() -> Validation {
+    Validation::default()
+}
+
+fn is_revoked(claims: &Claims, revoked_before: Option<i64>) -> bool {
+    revoked_before.is_some_and(|revoked_before| (claims.iat as i64) < revoked_before)
 }
 
 // Create JWT auth token by encoding claims using the JWT secret
 #[tracing::instrument(name = "Create Token", skip_all)]
-fn create_token(claims: &Claims) -> Result<Secret<String>> {
+fn create_token<C: Serialize>(claims: &C) -> Result<Secret<String>> {
     encode(
         &jsonwebtoken::Header::default(),
         &claims,
@@ -103,16 +335,116 @@ fn create_token(claims: &Claims) -> Result<Secret<String>> {
     .wrap_err("failed to create token")
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
+    // Issued at. Tokens created before this claim was added count as issued at the epoch.
+    #[serde(default)]
+    pub iat: usize,
+    #[serde(flatten)]
+    pub authentication: AuthenticationClaims,
+    #[serde(flatten)]
+    pub profile: ProfileClaims,
+}
+
+// Auth tokens have no audience, see `inspect_token`
+#[derive(Deserialize)]
+struct AudienceClaim {
+    #[serde(default)]
+    aud: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LoginReportClaims {
+    sub: String,
+    exp: usize,
+    iat: usize,
+    aud: String,
+}
+
+// How and when the user authenticated, under their OpenID Connect names. Empty for
+// tokens issued without a login, e.g. from the admin CLI, and for older tokens.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuthenticationClaims {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_time: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub amr: Vec<AuthMethod>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acr: Option<Acr>,
+}
+
+impl AuthenticationClaims {
+    // The user has just authenticated with these methods
+    pub fn now(amr: Vec<AuthMethod>) -> Self {
+        Self {
+            auth_time: Some(Utc::now().timestamp() as usize),
+            acr: Some(Acr::from_methods(&amr)),
+            amr,
+        }
+    }
+}
+
+// Authentication methods, as in RFC 8176 where it defines them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMethod {
+    Pwd,
+    Otp,
+    // Code sent by email
+    Email,
+    Webauthn,
+}
+
+// Authentication assurance level of the login
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Acr {
+    // A single factor
+    Aal1,
+    // Two different factors
+    Aal2,
+}
+
+impl Acr {
+    fn from_methods(methods: &[AuthMethod]) -> Self {
+        if methods.len() >= 2 {
+            Self::Aal2
+        } else {
+            Self::Aal1
+        }
+    }
+}
+
+// Profile fields selected by TOKEN_PROFILE_CLAIMS, under their OpenID Connect names
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProfileClaims {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zoneinfo: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub picture: Option<String>,
+}
+
+impl ProfileClaims {
+    pub fn new(profile: Profile, fields: &[ProfileField]) -> Self {
+        let pick = |field, value: Option<String>| value.filter(|_| fields.contains(&field));
+        Self {
+            name: pick(ProfileField::Name, profile.display_name),
+            locale: pick(ProfileField::Locale, profile.locale),
+            zoneinfo: pick(ProfileField::Zoneinfo, profile.timezone),
+            picture: pick(ProfileField::Picture, profile.avatar_url),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
-    use tokio::sync::RwLock;
 
     use crate::{domain::BannedTokenStore, services::data_stores::HashsetBannedTokenStore};
 
@@ -120,8 +452,13 @@ mod tests {
 
     #[tokio::test]
     async fn test_generate_auth_cookie() {
-        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
-        let cookie = generate_auth_cookie(&email).unwrap();
+        let user_id = UserId::default();
+        let cookie = generate_auth_cookie(
+            &user_id,
+            AuthenticationClaims::now(vec![AuthMethod::Pwd]),
+            ProfileClaims::default(),
+        )
+        .unwrap();
         assert_eq!(cookie.name(), JWT_COOKIE_NAME);
         assert_eq!(cookie.value().split('.').count(), 3);
         assert_eq!(cookie.path(), Some("/"));
@@ -140,20 +477,44 @@ mod tests {
         assert_eq!(cookie.same_site(), Some(SameSite::Lax));
     }
 
+    #[tokio::test]
+    async fn test_create_login_attempt_cookie() {
+        let login_attempt_id = LoginAttemptId::default();
+        let cookie = create_login_attempt_cookie(&login_attempt_id);
+        assert_eq!(cookie.name(), LOGIN_ATTEMPT_COOKIE_NAME);
+        assert_eq!(cookie.value(), login_attempt_id.expose_secret());
+        assert_eq!(cookie.path(), Some("/"));
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(
+            cookie.max_age(),
+            Some(time::Duration::seconds(LOGIN_ATTEMPT_COOKIE_TTL_SECONDS))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_trusted_device_cookie() {
+        let device = TrustedDevice::new(UserId::default(), None, chrono::Duration::days(30));
+        let cookie = create_trusted_device_cookie(&device);
+        assert_eq!(cookie.name(), TRUSTED_DEVICE_COOKIE_NAME);
+        assert_eq!(cookie.value(), device.id.to_string());
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.max_age(), Some(time::Duration::days(30)));
+    }
+
     #[tokio::test]
     async fn test_generate_auth_token() {
-        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
-        let result = generate_auth_token(&email).unwrap();
+        let user_id = UserId::default();
+        let result = generate_auth_token(&user_id).unwrap();
         assert_eq!(result.expose_secret().split('.').count(), 3);
     }
 
     #[tokio::test]
     async fn test_validate_token_with_valid_token() {
-        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
-        let token = generate_auth_token(&email).unwrap();
-        let banned_token_store = Arc::new(RwLock::new(HashsetBannedTokenStore::default()));
+        let user_id = UserId::default();
+        let token = generate_auth_token(&user_id).unwrap();
+        let banned_token_store = Arc::new(HashsetBannedTokenStore::default());
         let result = validate_token(&token, banned_token_store).await.unwrap();
-        assert_eq!(result.sub, "test@example.com");
+        assert_eq!(result.sub, user_id.to_string());
 
         let exp = Utc::now()
             .checked_add_signed(chrono::Duration::try_minutes(9).expect("valid duration"))
@@ -163,21 +524,171 @@ mod tests {
         assert!(result.exp > exp as usize);
     }
 
+    #[tokio::test]
+    async fn test_profile_claims_are_included_when_selected() {
+        let user_id = UserId::default();
+        let profile = Profile {
+            display_name: Some("Jane".to_owned()),
+            locale: Some("fr-FR".to_owned()),
+            timezone: Some("Europe/Paris".to_owned()),
+            avatar_url: None,
+        };
+        let claims = ProfileClaims::new(profile, &[ProfileField::Name, ProfileField::Picture]);
+        assert_eq!(claims, ProfileClaims { name: Some("Jane".to_owned()), ..Default::default() });
+
+        let token =
+            generate_auth_token_with_claims(&user_id, AuthenticationClaims::default(), claims.clone())
+                .unwrap();
+        let banned_token_store = Arc::new(HashsetBannedTokenStore::default());
+        let result = validate_token(&token, banned_token_store).await.unwrap();
+        assert_eq!(result.profile, claims);
+    }
+
+    #[tokio::test]
+    async fn test_authentication_claims_round_trip() {
+        let user_id = UserId::default();
+        let authentication = AuthenticationClaims::now(vec![AuthMethod::Pwd, AuthMethod::Email]);
+        assert_eq!(authentication.acr, Some(Acr::Aal2));
+
+        let token = generate_step_up_token(&user_id, authentication.clone(), ProfileClaims::default())
+            .unwrap();
+        let banned_token_store = Arc::new(HashsetBannedTokenStore::default());
+        let claims = validate_token(&token, banned_token_store.clone()).await.unwrap();
+        assert_eq!(claims.authentication, authentication);
+        assert!(claims.exp <= claims.iat + STEP_UP_TOKEN_TTL_SECONDS as usize);
+
+        // Tokens issued without a login carry none of these claims
+        let token = generate_auth_token(&user_id).unwrap();
+        let claims = validate_token(&token, banned_token_store).await.unwrap();
+        assert_eq!(claims.authentication, AuthenticationClaims::default());
+    }
+
+    #[test]
+    fn test_authentication_claims_use_openid_connect_names() {
+        let claims = serde_json::to_value(AuthenticationClaims::now(vec![AuthMethod::Pwd])).unwrap();
+        assert_eq!(claims["amr"], serde_json::json!(["pwd"]));
+        assert_eq!(claims["acr"], "aal1");
+        assert!(claims["auth_time"].is_u64());
+    }
+
     #[tokio::test]
     async fn test_validate_token_with_invalid_token() {
         let token = Secret::new("invalid_token".to_owned());
-        let banned_token_store = Arc::new(RwLock::new(HashsetBannedTokenStore::default()));
+        let banned_token_store = Arc::new(HashsetBannedTokenStore::default());
         let result = validate_token(&token, banned_token_store).await;
         assert!(result.is_err());
     }
 
+    #[tokio::test]
+    async fn test_login_report_tokens_are_not_auth_tokens() {
+        let user_id = UserId::default();
+        let banned_token_store = Arc::new(HashsetBannedTokenStore::default());
+
+        let report_token = generate_login_report_token(&user_id).unwrap();
+        assert_eq!(
+            validate_login_report_token(&report_token, banned_token_store.clone()).await.unwrap(),
+            user_id
+        );
+        assert!(validate_token(&report_token, banned_token_store.clone()).await.is_err());
+
+        let auth_token = generate_auth_token(&user_id).unwrap();
+        assert!(validate_login_report_token(&auth_token, banned_token_store.clone()).await.is_err());
+
+        // Revoking the user's tokens uses up the link
+        banned_token_store
+            .revoke_tokens_issued_before(&user_id.to_string(), Utc::now().timestamp() + 1)
+            .await
+            .unwrap();
+        assert!(validate_login_report_token(&report_token, banned_token_store).await.is_err());
+    }
+
+    #[test]
+    fn test_token_subject_requires_a_valid_signature() {
+        let user_id = UserId::default();
+        let token = generate_auth_token(&user_id).unwrap();
+        assert_eq!(token_subject(&token), Some(user_id));
+
+        let forged = format!("{}x", token.expose_secret());
+        assert_eq!(token_subject(&Secret::new(forged)), None);
+        // Login report tokens are meant for another audience
+        assert_eq!(token_subject(&generate_login_report_token(&user_id).unwrap()), None);
+    }
+
+    #[tokio::test]
+    async fn test_validate_token_with_revoked_tokens() {
+        let user_id = UserId::default();
+        let token = generate_auth_token(&user_id).unwrap();
+        let hs = HashsetBannedTokenStore::default();
+        hs.revoke_tokens_issued_before(&user_id.to_string(), Utc::now().timestamp() + 1)
+            .await
+            .unwrap();
+        let banned_token_store = Arc::new(hs);
+        assert!(validate_token(&token, banned_token_store.clone()).await.is_err());
+
+        // Tokens issued after the revocation are accepted again
+        banned_token_store
+            .revoke_tokens_issued_before(&user_id.to_string(), Utc::now().timestamp() - 1)
+            .await
+            .unwrap();
+        assert!(validate_token(&token, banned_token_store).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_inspect_token_reports_each_check() {
+        let user_id = UserId::default();
+        let banned_token_store = Arc::new(HashsetBannedTokenStore::default());
+
+        let token = generate_auth_token(&user_id).unwrap();
+        let inspection = inspect_token(&token, banned_token_store.clone()).await.unwrap();
+        assert!(inspection.is_valid());
+        assert_eq!(inspection.claims.unwrap().sub, user_id.to_string());
+
+        // Signed with another secret and expired beyond the leeway
+        let now = Utc::now().timestamp() as usize;
+        let claims = Claims {
+            sub: user_id.to_string(),
+            exp: now - 120,
+            iat: now - 720,
+            authentication: AuthenticationClaims::default(),
+            profile: ProfileClaims::default(),
+        };
+        let forged = encode(
+            &jsonwebtoken::Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"another-secret"),
+        )
+        .map(Secret::new)
+        .unwrap();
+        banned_token_store.add_token(forged.clone()).await.unwrap();
+        let inspection = inspect_token(&forged, banned_token_store.clone()).await.unwrap();
+        assert!(!inspection.signature_valid);
+        assert!(inspection.expired);
+        assert!(inspection.banned);
+        assert!(!inspection.revoked);
+        assert!(!inspection.is_valid());
+
+        // Well formed and correctly signed, but not an auth token
+        let report_token = generate_login_report_token(&user_id).unwrap();
+        let inspection = inspect_token(&report_token, banned_token_store.clone()).await.unwrap();
+        assert!(inspection.decode_error.is_none());
+        assert!(inspection.signature_valid);
+        assert_eq!(inspection.audience.as_deref(), Some("login-report"));
+        assert!(!inspection.is_valid());
+
+        let inspection = inspect_token(&Secret::new("invalid_token".to_owned()), banned_token_store)
+            .await
+            .unwrap();
+        assert!(inspection.decode_error.is_some());
+        assert!(!inspection.is_valid());
+    }
+
     #[tokio::test]
     async fn test_validate_token_with_banned_token() {
-        let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
-        let token = generate_auth_token(&email).unwrap();
-        let mut hs = HashsetBannedTokenStore::default();
+        let user_id = UserId::default();
+        let token = generate_auth_token(&user_id).unwrap();
+        let hs = HashsetBannedTokenStore::default();
         hs.add_token(token.clone()).await.unwrap();
-        let banned_token_store = Arc::new(RwLock::new(hs));
+        let banned_token_store = Arc::new(hs);
         let result = validate_token(&token, banned_token_store).await;
         assert!(result.is_err());
     }
@@ -1,7 +1,13 @@
+use argon2::Params;
 use dotenvy::dotenv;
 use lazy_static::lazy_static;
 use secrecy::Secret;
-use std::env as std_env;
+use std::{collections::{HashMap, HashSet}, env as std_env, str::FromStr, sync::Arc, time::Duration};
+
+use crate::domain::{
+    parse_domain, BreachedPasswords, DisposableDomains, PasswordPolicy, ProfileField, RetryPolicy,
+    SignupPolicy,
+};
 
 // Define a lazily evaluated static. lazy_static is needed because std_env::var is not a const function.
 lazy_static! {
@@ -9,6 +15,24 @@ lazy_static! {
     pub static ref DATABASE_URL: Secret<String> = set_database_url();
     pub static ref REDIS_HOST_NAME: String = set_redis_host(); // New!
     pub static ref POSTMARK_AUTH_TOKEN: Secret<String> = set_postmark_auth_token(); 
+    pub static ref COOKIE_SECRET: Secret<String> = set_cookie_secret();
+    pub static ref TWO_FA_CODE_SECRET: Secret<String> = set_two_fa_code_secret();
+    pub static ref ENUMERATION_PROTECTION: bool = set_enumeration_protection();
+    pub static ref EMAIL_FOLD_LOCAL_PART_CASE: bool = set_email_fold_local_part_case();
+    pub static ref PASSWORD_POLICY: PasswordPolicy = set_password_policy();
+    pub static ref SIGNUP_POLICY: SignupPolicy = set_signup_policy();
+    pub static ref TOKEN_PROFILE_CLAIMS: Vec<ProfileField> = set_token_profile_claims();
+    pub static ref TRUSTED_DEVICE_TTL_DAYS: i64 = set_trusted_device_ttl_days();
+    pub static ref TRUST_FORWARDED_FOR: bool = set_trust_forwarded_for();
+    pub static ref PUBLIC_URL: String = set_public_url();
+    pub static ref WEBHOOK_RETRY_POLICY: RetryPolicy = set_webhook_retry_policy();
+    pub static ref EVENT_STREAM_NAME: String = set_event_stream_name();
+    pub static ref EVENT_STREAM_MAX_LEN: usize = set_event_stream_max_len();
+    pub static ref ARGON2_PARAMS: Params = set_argon2_params();
+    pub static ref PASSWORD_PEPPERS: HashMap<i32, Secret<String>> = set_password_peppers();
+    // Highest configured pepper version, used for new hashes. 0 when no pepper is configured.
+    pub static ref CURRENT_PASSWORD_PEPPER_VERSION: i32 =
+        PASSWORD_PEPPERS.keys().copied().max().unwrap_or(NO_PASSWORD_PEPPER_VERSION);
 }
 
 
@@ -43,16 +67,300 @@ fn set_postmark_auth_token() -> Secret<String> {
     )
 }
 
+fn set_cookie_secret() -> Secret<String> {
+    dotenv().ok();
+    let secret = std_env::var(env::COOKIE_SECRET_ENV_VAR).expect("COOKIE_SECRET must be set.");
+    // The signing key is derived from this secret, which requires at least 256 bits
+    if secret.len() < 32 {
+        panic!("COOKIE_SECRET must be at least 32 bytes long.");
+    }
+    Secret::new(secret)
+}
+
+fn set_two_fa_code_secret() -> Secret<String> {
+    dotenv().ok();
+    let secret =
+        std_env::var(env::TWO_FA_CODE_SECRET_ENV_VAR).expect("TWO_FA_CODE_SECRET must be set.");
+    // 2FA codes are short, the key is what keeps their hashes from being brute-forced
+    if secret.len() < 32 {
+        panic!("TWO_FA_CODE_SECRET must be at least 32 bytes long.");
+    }
+    Secret::new(secret)
+}
+
+// Off by default. When enabled, login and signup responses no longer reveal
+// whether an email address is registered.
+fn set_enumeration_protection() -> bool {
+    dotenv().ok();
+    std_env::var(env::ENUMERATION_PROTECTION_ENV_VAR)
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true"))
+        .unwrap_or(false)
+}
+
+// On by default, `Bob@example.com` and `bob@example.com` are the same account. Disable
+// it for mail systems with case-sensitive local parts, then run
+// `auth-service-admin canonicalize-emails` so stored addresses follow the new rule.
+fn set_email_fold_local_part_case() -> bool {
+    dotenv().ok();
+    std_env::var(env::EMAIL_FOLD_LOCAL_PART_CASE_ENV_VAR)
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true"))
+        .unwrap_or(true)
+}
+
+// Every rule can be overridden through the environment, see `PasswordPolicy::default`
+// for the defaults. The breached password file is loaded once, at startup.
+fn set_password_policy() -> PasswordPolicy {
+    dotenv().ok();
+    let default = PasswordPolicy::default();
+
+    let breached_passwords_file = std_env::var(env::BREACHED_PASSWORDS_FILE_ENV_VAR)
+        .unwrap_or(DEFAULT_BREACHED_PASSWORDS_FILE.to_owned());
+    let breached_passwords = BreachedPasswords::from_file(&breached_passwords_file)
+        .expect("Failed to load breached password file.");
+
+    PasswordPolicy {
+        min_length: env_or(env::PASSWORD_MIN_LENGTH_ENV_VAR, default.min_length),
+        max_length: env_or(env::PASSWORD_MAX_LENGTH_ENV_VAR, default.max_length),
+        require_lowercase: env_or(env::PASSWORD_REQUIRE_LOWERCASE_ENV_VAR, default.require_lowercase),
+        require_uppercase: env_or(env::PASSWORD_REQUIRE_UPPERCASE_ENV_VAR, default.require_uppercase),
+        require_digit: env_or(env::PASSWORD_REQUIRE_DIGIT_ENV_VAR, default.require_digit),
+        require_symbol: env_or(env::PASSWORD_REQUIRE_SYMBOL_ENV_VAR, default.require_symbol),
+        reject_email_local_part: env_or(
+            env::PASSWORD_REJECT_EMAIL_ENV_VAR,
+            default.reject_email_local_part,
+        ),
+        min_strength: env_or(env::PASSWORD_MIN_STRENGTH_ENV_VAR, default.min_strength),
+        breached_passwords: Arc::new(breached_passwords),
+    }
+}
+
+// Domain lists are comma separated. Disposable addresses are rejected by default, the
+// list file is reloaded every DISPOSABLE_DOMAINS_REFRESH_INTERVAL when it changes.
+fn set_signup_policy() -> SignupPolicy {
+    dotenv().ok();
+    let disposable_domains_file = std_env::var(env::DISPOSABLE_DOMAINS_FILE_ENV_VAR)
+        .unwrap_or(DEFAULT_DISPOSABLE_DOMAINS_FILE.to_owned());
+    let disposable_domains = DisposableDomains::from_file(&disposable_domains_file)
+        .expect("Failed to load disposable domain file.");
+
+    SignupPolicy {
+        allowed_domains: domains_from_env(env::SIGNUP_ALLOWED_DOMAINS_ENV_VAR),
+        blocked_domains: domains_from_env(env::SIGNUP_BLOCKED_DOMAINS_ENV_VAR),
+        reject_disposable: env_or(env::SIGNUP_REJECT_DISPOSABLE_ENV_VAR, true),
+        disposable_domains: Arc::new(disposable_domains),
+    }
+}
+
+fn domains_from_env(name: &str) -> HashSet<String> {
+    std_env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .filter(|domain| !domain.trim().is_empty())
+        .map(|domain| parse_domain(domain).unwrap_or_else(|_| panic!("{} has an invalid domain.", name)))
+        .collect()
+}
+
+// Comma separated claim names (name, locale, zoneinfo, picture) of the profile fields
+// copied into auth tokens. None by default, tokens only identify the user.
+fn set_token_profile_claims() -> Vec<ProfileField> {
+    dotenv().ok();
+    std_env::var(env::TOKEN_PROFILE_CLAIMS_ENV_VAR)
+        .unwrap_or_default()
+        .split(',')
+        .filter(|claim| !claim.trim().is_empty())
+        .map(|claim| claim.parse().expect("TOKEN_PROFILE_CLAIMS has an unknown claim."))
+        .collect()
+}
+
+// How long a browser trusted at 2FA verification can skip 2FA, 30 days by default
+fn set_trusted_device_ttl_days() -> i64 {
+    dotenv().ok();
+    let days = env_or(env::TRUSTED_DEVICE_TTL_DAYS_ENV_VAR, DEFAULT_TRUSTED_DEVICE_TTL_DAYS);
+    if days <= 0 {
+        panic!("TRUSTED_DEVICE_TTL_DAYS must be positive.");
+    }
+    days
+}
+
+// Only behind a proxy that sets X-Forwarded-For, clients could otherwise pick their IP
+fn set_trust_forwarded_for() -> bool {
+    dotenv().ok();
+    std_env::var(env::TRUST_FORWARDED_FOR_ENV_VAR)
+        .map(|value| matches!(value.to_lowercase().as_str(), "1" | "true"))
+        .unwrap_or(false)
+}
+
+// Where users reach the service, for links in emails
+fn set_public_url() -> String {
+    dotenv().ok();
+    std_env::var(env::PUBLIC_URL_ENV_VAR)
+        .unwrap_or(DEFAULT_PUBLIC_URL.to_owned())
+        .trim_end_matches('/')
+        .to_owned()
+}
+
+// Failed webhook deliveries are retried after 30s, 1m, 2m... up to 6h apart, and
+// dead-lettered after the last attempt
+fn set_webhook_retry_policy() -> RetryPolicy {
+    dotenv().ok();
+    let policy = RetryPolicy {
+        max_attempts: env_or(env::WEBHOOK_MAX_ATTEMPTS_ENV_VAR, DEFAULT_WEBHOOK_MAX_ATTEMPTS),
+        base_delay: chrono::Duration::seconds(env_or(
+            env::WEBHOOK_RETRY_BASE_SECONDS_ENV_VAR,
+            DEFAULT_WEBHOOK_RETRY_BASE_SECONDS,
+        )),
+        max_delay: chrono::Duration::seconds(env_or(
+            env::WEBHOOK_RETRY_MAX_SECONDS_ENV_VAR,
+            DEFAULT_WEBHOOK_RETRY_MAX_SECONDS,
+        )),
+    };
+    if policy.max_attempts == 0 || policy.base_delay <= chrono::Duration::zero() {
+        panic!("WEBHOOK_MAX_ATTEMPTS and WEBHOOK_RETRY_BASE_SECONDS must be positive.");
+    }
+    policy
+}
+
+fn set_event_stream_name() -> String {
+    dotenv().ok();
+    std_env::var(env::EVENT_STREAM_NAME_ENV_VAR).unwrap_or(DEFAULT_EVENT_STREAM_NAME.to_owned())
+}
+
+// Older entries are trimmed once the stream is about this long
+fn set_event_stream_max_len() -> usize {
+    dotenv().ok();
+    let max_len = env_or(env::EVENT_STREAM_MAX_LEN_ENV_VAR, DEFAULT_EVENT_STREAM_MAX_LEN);
+    if max_len == 0 {
+        panic!("EVENT_STREAM_MAX_LEN must be positive.");
+    }
+    max_len
+}
+
+// Target parameters for new password hashes. Stored hashes with weaker
+// parameters are upgraded on the user's next successful login.
+fn set_argon2_params() -> Params {
+    dotenv().ok();
+    Params::new(
+        env_or(env::ARGON2_M_COST_ENV_VAR, DEFAULT_ARGON2_M_COST),
+        env_or(env::ARGON2_T_COST_ENV_VAR, DEFAULT_ARGON2_T_COST),
+        env_or(env::ARGON2_P_COST_ENV_VAR, DEFAULT_ARGON2_P_COST),
+        None,
+    )
+    .expect("Invalid argon2 parameters.")
+}
+
+// Comma separated `version:secret` pairs, e.g. "1:old-secret,2:new-secret". To rotate,
+// add a pepper with a higher version and keep the old ones until every user has logged
+// in again, hashes are moved to the newest pepper on login.
+// Unset means no pepper, which the service warns about at startup: the admin CLI has
+// to run without one to generate the first.
+fn set_password_peppers() -> HashMap<i32, Secret<String>> {
+    dotenv().ok();
+    let Ok(peppers) = std_env::var(env::PASSWORD_PEPPERS_ENV_VAR) else {
+        return HashMap::new();
+    };
+
+    let mut versions = HashMap::new();
+    for pepper in peppers.split(',').filter(|pepper| !pepper.trim().is_empty()) {
+        let (version, secret) = pepper
+            .trim()
+            .split_once(':')
+            .expect("PASSWORD_PEPPERS entries must look like `version:secret`.");
+        let version: i32 = version.parse().expect("Pepper versions must be integers.");
+        if version <= NO_PASSWORD_PEPPER_VERSION {
+            panic!("Pepper versions must be greater than {}.", NO_PASSWORD_PEPPER_VERSION);
+        }
+        if secret.len() < 32 {
+            panic!("Peppers must be at least 32 bytes long.");
+        }
+        if versions.insert(version, Secret::new(secret.to_owned())).is_some() {
+            panic!("Pepper version {} is configured more than once.", version);
+        }
+    }
+    versions
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match std_env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} has an invalid value.", name)),
+        Err(_) => default,
+    }
+}
+
 pub mod env {
     pub const JWT_SECRET_ENV_VAR: &str = "JWT_SECRET";
     pub const DATABASE_URL_ENV_VAR: &str = "DATABASE_URL";
     pub const REDIS_HOST_NAME_ENV_VAR: &str = "REDIS_HOST_NAME"; // New!
     pub const POSTMARK_AUTH_TOKEN_ENV_VAR: &str = "POSTMARK_AUTH_TOKEN"; // New!
+    pub const COOKIE_SECRET_ENV_VAR: &str = "COOKIE_SECRET";
+    pub const TWO_FA_CODE_SECRET_ENV_VAR: &str = "TWO_FA_CODE_SECRET";
+    pub const ENUMERATION_PROTECTION_ENV_VAR: &str = "ENUMERATION_PROTECTION";
+    pub const EMAIL_FOLD_LOCAL_PART_CASE_ENV_VAR: &str = "EMAIL_FOLD_LOCAL_PART_CASE";
+    pub const PASSWORD_MIN_LENGTH_ENV_VAR: &str = "PASSWORD_MIN_LENGTH";
+    pub const PASSWORD_MAX_LENGTH_ENV_VAR: &str = "PASSWORD_MAX_LENGTH";
+    pub const PASSWORD_REQUIRE_LOWERCASE_ENV_VAR: &str = "PASSWORD_REQUIRE_LOWERCASE";
+    pub const PASSWORD_REQUIRE_UPPERCASE_ENV_VAR: &str = "PASSWORD_REQUIRE_UPPERCASE";
+    pub const PASSWORD_REQUIRE_DIGIT_ENV_VAR: &str = "PASSWORD_REQUIRE_DIGIT";
+    pub const PASSWORD_REQUIRE_SYMBOL_ENV_VAR: &str = "PASSWORD_REQUIRE_SYMBOL";
+    pub const PASSWORD_REJECT_EMAIL_ENV_VAR: &str = "PASSWORD_REJECT_EMAIL";
+    pub const PASSWORD_MIN_STRENGTH_ENV_VAR: &str = "PASSWORD_MIN_STRENGTH";
+    pub const BREACHED_PASSWORDS_FILE_ENV_VAR: &str = "BREACHED_PASSWORDS_FILE";
+    pub const ARGON2_M_COST_ENV_VAR: &str = "ARGON2_M_COST";
+    pub const ARGON2_T_COST_ENV_VAR: &str = "ARGON2_T_COST";
+    pub const ARGON2_P_COST_ENV_VAR: &str = "ARGON2_P_COST";
+    pub const PASSWORD_PEPPERS_ENV_VAR: &str = "PASSWORD_PEPPERS";
+    pub const SIGNUP_ALLOWED_DOMAINS_ENV_VAR: &str = "SIGNUP_ALLOWED_DOMAINS";
+    pub const SIGNUP_BLOCKED_DOMAINS_ENV_VAR: &str = "SIGNUP_BLOCKED_DOMAINS";
+    pub const SIGNUP_REJECT_DISPOSABLE_ENV_VAR: &str = "SIGNUP_REJECT_DISPOSABLE";
+    pub const DISPOSABLE_DOMAINS_FILE_ENV_VAR: &str = "DISPOSABLE_DOMAINS_FILE";
+    pub const TOKEN_PROFILE_CLAIMS_ENV_VAR: &str = "TOKEN_PROFILE_CLAIMS";
+    pub const TRUSTED_DEVICE_TTL_DAYS_ENV_VAR: &str = "TRUSTED_DEVICE_TTL_DAYS";
+    pub const TRUST_FORWARDED_FOR_ENV_VAR: &str = "TRUST_FORWARDED_FOR";
+    pub const PUBLIC_URL_ENV_VAR: &str = "PUBLIC_URL";
+    pub const WEBHOOK_MAX_ATTEMPTS_ENV_VAR: &str = "WEBHOOK_MAX_ATTEMPTS";
+    pub const WEBHOOK_RETRY_BASE_SECONDS_ENV_VAR: &str = "WEBHOOK_RETRY_BASE_SECONDS";
+    pub const WEBHOOK_RETRY_MAX_SECONDS_ENV_VAR: &str = "WEBHOOK_RETRY_MAX_SECONDS";
+    pub const EVENT_STREAM_NAME_ENV_VAR: &str = "EVENT_STREAM_NAME";
+    pub const EVENT_STREAM_MAX_LEN_ENV_VAR: &str = "EVENT_STREAM_MAX_LEN";
 }
 
 pub const JWT_COOKIE_NAME: &str = "jwt";
+pub const LOGIN_ATTEMPT_COOKIE_NAME: &str = "login_attempt_id";
+// Matches the lifetime of a pending 2FA code
+pub const LOGIN_ATTEMPT_COOKIE_TTL_SECONDS: i64 = 600;
+pub const TRUSTED_DEVICE_COOKIE_NAME: &str = "trusted_device";
+pub const DEFAULT_TRUSTED_DEVICE_TTL_DAYS: i64 = 30;
+pub const DEFAULT_PUBLIC_URL: &str = "http://localhost:3000";
 pub const PG_TABLE_NAME: &str = "users";
 pub const DEFAULT_REDIS_HOSTNAME: &str = "127.0.0.1"; // New!
+pub const DEFAULT_BREACHED_PASSWORDS_FILE: &str = "data/breached_passwords.txt";
+pub const DEFAULT_DISPOSABLE_DOMAINS_FILE: &str = "data/disposable_domains.txt";
+// How often the disposable domain file is checked for changes
+pub const DISPOSABLE_DOMAINS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+// Memory (KiB), iterations and lanes used for argon2id password hashes
+pub const DEFAULT_ARGON2_M_COST: u32 = 15000;
+pub const DEFAULT_ARGON2_T_COST: u32 = 2;
+pub const DEFAULT_ARGON2_P_COST: u32 = 1;
+// Pepper version of hashes stored without a pepper, e.g. created before peppers existed
+pub const NO_PASSWORD_PEPPER_VERSION: i32 = 0;
+// Minimum delay between two 2FA code emails for the same login attempt
+pub const TWO_FA_RESEND_COOLDOWN_SECONDS: i64 = 30;
+// How many times the 2FA code of a login attempt can be re-sent
+pub const MAX_TWO_FA_RESENDS: u32 = 3;
+// How many 2FA logins a single user can have pending at the same time
+pub const MAX_PENDING_TWO_FA_ATTEMPTS: usize = 3;
+pub const DEFAULT_WEBHOOK_MAX_ATTEMPTS: u32 = 10;
+pub const DEFAULT_WEBHOOK_RETRY_BASE_SECONDS: i64 = 30;
+pub const DEFAULT_WEBHOOK_RETRY_MAX_SECONDS: i64 = 6 * 60 * 60;
+// How often the webhook dispatcher looks for new events and due deliveries
+pub const WEBHOOK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// How long a delivery being sent is hidden from other dispatchers, longer than a request can take
+pub const WEBHOOK_DELIVERY_LEASE: Duration = Duration::from_secs(60);
+pub const DEFAULT_EVENT_STREAM_NAME: &str = "auth-events";
+pub const DEFAULT_EVENT_STREAM_MAX_LEN: usize = 100_000;
+// How often new outbox events are published to the event stream
+pub const EVENT_STREAM_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 pub mod prod {
     pub const APP_ADDRESS: &str = "0.0.0.0:3000";
@@ -64,6 +372,12 @@ pub mod prod {
         pub const SENDER: &str = "bogdan@codeiron.io";
         pub const TIMEOUT: Duration = std::time::Duration::from_secs(10);
     }
+    pub mod webhooks {
+        use std::time::Duration;
+
+        // Receivers should answer quickly and process the event afterwards
+        pub const TIMEOUT: Duration = std::time::Duration::from_secs(10);
+    }
 }
 
 pub mod test {
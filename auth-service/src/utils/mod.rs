@@ -1,3 +1,5 @@
 pub mod constants;
+pub mod audit;
 pub mod auth;
+pub mod request_metadata;
 pub mod tracing;
\ No newline at end of file
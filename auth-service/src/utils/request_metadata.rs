@@ -0,0 +1,102 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts, HeaderMap},
+};
+
+use uuid::Uuid;
+
+use super::{constants::TRUST_FORWARDED_FOR, tracing::RequestId};
+
+const FORWARDED_FOR: &str = "x-forwarded-for";
+// User agents are only shown to users, long ones are cut
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+// Where a request comes from, as far as the service can tell. Never fails, fields
+// are missing when unknown.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RequestMetadata {
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    // Set by the `assign_request_id` layer
+    pub request_id: Option<Uuid>,
+}
+
+impl RequestMetadata {
+    fn new(headers: &HeaderMap, peer: Option<IpAddr>, trust_forwarded_for: bool) -> Self {
+        let forwarded_for = headers
+            .get(FORWARDED_FOR)
+            .and_then(|value| value.to_str().ok())
+            // The first address is the client, proxies append theirs
+            .and_then(|value| value.split(',').next())
+            .and_then(|ip| ip.trim().parse().ok())
+            .filter(|_| trust_forwarded_for);
+
+        Self {
+            ip: forwarded_for.or(peer),
+            user_agent: headers
+                .get(USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.chars().take(MAX_USER_AGENT_LENGTH).collect()),
+            request_id: None,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for RequestMetadata {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // Only set when the server is started with `into_make_service_with_connect_info`
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip());
+        Ok(Self {
+            request_id: parts.extensions.get::<RequestId>().map(|RequestId(id)| *id),
+            ..Self::new(&parts.headers, peer, *TRUST_FORWARDED_FOR)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(forwarded_for: &str, user_agent: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(FORWARDED_FOR, HeaderValue::from_str(forwarded_for).unwrap());
+        headers.insert(USER_AGENT, HeaderValue::from_str(user_agent).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_forwarded_for_is_only_used_when_trusted() {
+        let headers = headers("203.0.113.7, 10.0.0.2", "Firefox");
+        let peer = Some("10.0.0.1".parse().unwrap());
+
+        let metadata = RequestMetadata::new(&headers, peer, false);
+        assert_eq!(metadata.ip, peer);
+        assert_eq!(metadata.user_agent.as_deref(), Some("Firefox"));
+
+        let metadata = RequestMetadata::new(&headers, peer, true);
+        assert_eq!(metadata.ip, Some("203.0.113.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_missing_or_invalid_values_are_unknown() {
+        let metadata = RequestMetadata::new(&headers("not an ip", &"a".repeat(1000)), None, true);
+        assert_eq!(metadata.ip, None);
+        assert_eq!(metadata.user_agent.map(|user_agent| user_agent.len()), Some(MAX_USER_AGENT_LENGTH));
+
+        assert_eq!(RequestMetadata::new(&HeaderMap::new(), None, true), RequestMetadata::default());
+    }
+}
@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use axum::{body::Body, extract::Request, response::Response};
+use axum::{body::Body, extract::Request, middleware::Next, response::Response};
 use color_eyre::eyre::Result;
 use tracing::{Level, Span};
 use tracing_error::ErrorLayer;
@@ -29,10 +29,25 @@ pub fn init_tracing() -> Result<()> {
     Ok(())
 }
 
+// Unique id of a request, shown in its tracing span and kept in the audit log
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestId(pub uuid::Uuid);
+
+// Gives each request its id. Added as the outermost layer, so that the tracing span,
+// the other layers and the handler all see the same one.
+pub async fn assign_request_id(mut request: Request, next: Next) -> Response {
+    request.extensions_mut().insert(RequestId(uuid::Uuid::new_v4()));
+    next.run(request).await
+}
+
 // Creates a new tracing span with a unique request ID for each incoming request.
 // This helps in tracking and correlating logs for individual requests.
 pub fn make_span_with_request_id(request: &Request<Body>) -> Span {
-    let request_id = uuid::Uuid::new_v4();
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .map(|RequestId(id)| *id)
+        .unwrap_or_else(uuid::Uuid::new_v4);
     tracing::span!(
         Level::INFO,
         "[REQUEST]",
@@ -0,0 +1,128 @@
+use auth_service::{
+    domain::{Email, UserStore},
+    routes::AuditEventsResponse,
+    services::data_stores::PostgresUserStore,
+};
+use secrecy::Secret;
+
+use crate::helpers::{get_random_email, TestApp};
+
+const PASSWORD: &str = "Sup3r-Secret-Pass!";
+
+async fn signup(app: &TestApp, email: &str) {
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": PASSWORD,
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+async fn login(app: &TestApp, email: &str, password: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({ "email": email, "password": password }))
+        .await
+}
+
+#[tokio::test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let mut app = TestApp::new().await;
+
+    assert_eq!(app.get_activity().await.status().as_u16(), 400);
+    assert_eq!(app.get_audit_events(&[]).await.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_list_own_activity_including_failed_logins() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    // From another browser, which the events are tied to
+    let browser = app.new_device_with_user_agent("Audit Test Browser");
+    let response = browser
+        .post(format!("{}/login", &app.address))
+        .json(&serde_json::json!({ "email": random_email, "password": "Wr0ng-Password!" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(login(&app, &random_email, PASSWORD).await.status().as_u16(), 200);
+
+    let response = app.get_activity().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let events = response.json::<AuditEventsResponse>().await.unwrap().events;
+
+    let actions: Vec<(&str, &str)> = events
+        .iter()
+        .map(|event| (event.action.as_str(), event.outcome.as_str()))
+        .collect();
+    assert_eq!(
+        actions,
+        vec![("login", "success"), ("login", "failure"), ("signup", "success")]
+    );
+
+    let failed_login = &events[1];
+    assert_eq!(failed_login.ip.as_deref(), Some("127.0.0.1"));
+    assert_eq!(failed_login.user_agent.as_deref(), Some("Audit Test Browser"));
+    assert_eq!(failed_login.detail.as_deref(), Some("Incorrect credentials"));
+    assert!(failed_login.request_id.is_some());
+    assert_ne!(failed_login.request_id, events[0].request_id);
+
+    // Viewing the activity is itself recorded, for the next page of activity
+    let events = app.get_activity().await.json::<AuditEventsResponse>().await.unwrap().events;
+    assert_eq!(events[0].action, "view_activity");
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_only_let_admins_query_all_events() {
+    let mut app = TestApp::new().await;
+    let other_email = get_random_email();
+    signup(&app, &other_email).await;
+    assert_eq!(login(&app, &other_email, "Wr0ng-Password!").await.status().as_u16(), 401);
+
+    let admin_email = get_random_email();
+    signup(&app, &admin_email).await;
+    assert_eq!(login(&app, &admin_email, PASSWORD).await.status().as_u16(), 200);
+
+    let response = app.get_audit_events(&[]).await;
+    assert_eq!(response.status().as_u16(), 403);
+
+    PostgresUserStore::new(app.pg_pool.clone())
+        .set_admin(&Email::parse(Secret::new(admin_email)).unwrap(), true)
+        .await
+        .unwrap();
+
+    let response = app
+        .get_audit_events(&[("action", "login"), ("outcome", "failure")])
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let events = response.json::<AuditEventsResponse>().await.unwrap().events;
+    assert_eq!(events.len(), 1);
+    let other_user = events[0].actor.clone().expect("Failed login has no actor");
+
+    let response = app.get_audit_events(&[("actor", other_user.as_str())]).await;
+    let events = response.json::<AuditEventsResponse>().await.unwrap().events;
+    let actions: Vec<&str> = events.iter().map(|event| event.action.as_str()).collect();
+    assert_eq!(actions, vec!["login", "signup"]);
+
+    // Refused queries are recorded too
+    let response = app.get_audit_events(&[("action", "fly")]).await;
+    assert_eq!(response.status().as_u16(), 400);
+    let response = app
+        .get_audit_events(&[("action", "query_audit_log"), ("outcome", "failure")])
+        .await;
+    let events = response.json::<AuditEventsResponse>().await.unwrap().events;
+    let details: Vec<Option<&str>> = events.iter().map(|event| event.detail.as_deref()).collect();
+    assert_eq!(
+        details,
+        vec![Some("Invalid audit log query"), Some("Admin rights required")]
+    );
+
+    app.clean_up().await;
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use auth_service::{
+    domain::{DomainEvent, EventStream, UserId},
+    get_redis_connection,
+    services::{
+        data_stores::PostgresEventOutbox,
+        event_stream_publisher::EventStreamPublisher,
+        redis_event_stream::{read_stream_events, RedisEventStream},
+    },
+    utils::constants::REDIS_HOST_NAME,
+};
+use redis::aio::ConnectionManager;
+use uuid::Uuid;
+
+use crate::helpers::{get_random_email, TestApp};
+
+const PASSWORD: &str = "Sup3r-Secret-Pass!";
+
+async fn redis_connection() -> ConnectionManager {
+    get_redis_connection(REDIS_HOST_NAME.to_owned())
+        .await
+        .expect("Failed to get Redis connection")
+}
+
+// Publishes to a stream of its own, so that tests don't read each other's events
+async fn redis_stream(max_len: usize) -> (RedisEventStream, String) {
+    let stream_name = format!("auth-events-test-{}", Uuid::new_v4());
+    let conn = redis_connection().await;
+    (RedisEventStream::new(conn, stream_name.clone(), max_len), stream_name)
+}
+
+async fn delete_stream(conn: &mut ConnectionManager, stream_name: &str) {
+    let _: () = redis::cmd("DEL").arg(stream_name).query_async(conn).await.unwrap();
+}
+
+#[tokio::test]
+async fn should_publish_user_events_to_the_stream_once() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": random_email,
+            "password": PASSWORD,
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let response = app
+        .post_login(&serde_json::json!({ "email": random_email, "password": PASSWORD }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let (stream, stream_name) = redis_stream(1000).await;
+    let outbox = Arc::new(PostgresEventOutbox::new(app.pg_pool.clone()));
+    let publisher = EventStreamPublisher::new(outbox, Arc::new(stream));
+    assert_eq!(publisher.publish().await.unwrap(), 2);
+    assert_eq!(publisher.publish().await.unwrap(), 0);
+
+    let mut conn = redis_connection().await;
+    let events = read_stream_events(&mut conn, &stream_name, "0", 10, None).await.unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].event.event_type.as_str(), "user.created");
+    assert_eq!(events[0].event.data["email"], random_email.as_str());
+    assert_eq!(events[1].event.event_type.as_str(), "user.login");
+    assert_eq!(events[1].event.data["userId"], events[0].event.data["userId"]);
+
+    // Reading resumes after the last entry handled
+    let events = read_stream_events(&mut conn, &stream_name, &events[1].entry_id, 10, None).await.unwrap();
+    assert!(events.is_empty());
+
+    delete_stream(&mut conn, &stream_name).await;
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_trim_the_stream_to_about_its_max_length() {
+    let (stream, stream_name) = redis_stream(10).await;
+    let events: Vec<DomainEvent> = (0..250).map(|_| DomainEvent::user_login(&UserId::default())).collect();
+    for batch in events.chunks(50) {
+        stream.publish(batch).await.unwrap();
+    }
+
+    // Redis trims whole nodes of entries, so more than 10 can be left
+    let mut conn = redis_connection().await;
+    let len: usize = redis::cmd("XLEN").arg(&stream_name).query_async(&mut conn).await.unwrap();
+    assert!(len >= 10 && len < events.len());
+
+    let newest = read_stream_events(&mut conn, &stream_name, "0", events.len(), None).await.unwrap();
+    assert_eq!(newest.last().unwrap().event, *events.last().unwrap());
+
+    delete_stream(&mut conn, &stream_name).await;
+}
@@ -1,16 +1,16 @@
 use auth_service::{
-    app_state::{BannedTokenStoreType, EmailClientType, TwoFACodeStoreType}, 
-    domain::{mock_email_client::MockEmailClient, Email}, get_postgres_pool, get_redis_client, 
-    services::{data_stores::{PostgresUserStore, RedisBannedTokenStore, RedisTwoFACodeStore}, postmark_email_client::PostmarkEmailClient}, 
-    utils::constants::{prod, test, DATABASE_URL, POSTMARK_AUTH_TOKEN, REDIS_HOST_NAME}, Application
+    app_state::{BannedTokenStoreType, TwoFACodeStoreType}, 
+    domain::{Email, ProfileField, SignupPolicy}, get_postgres_pool, get_redis_connection, 
+    services::{data_stores::{PostgresAuditLog, PostgresLoginHistoryStore, PostgresTrustedDeviceStore, PostgresUserStore, RedisBannedTokenStore, RedisTwoFACodeStore}, postmark_email_client::PostmarkEmailClient}, 
+    utils::constants::{test, DATABASE_URL, REDIS_HOST_NAME}, Application
 };
 use secrecy::{ExposeSecret, Secret};
 use sqlx::{postgres::{PgConnectOptions, PgPoolOptions}, Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
 use auth_service::app_state::AppState;
-use tokio::sync::RwLock;
 use wiremock::MockServer;
 use std::{str::FromStr, sync::Arc};
+use redis::aio::ConnectionManager;
 use reqwest::{cookie::Jar, Client};
 
 pub struct TestApp {
@@ -20,6 +20,7 @@ pub struct TestApp {
     pub two_fa_code_store: TwoFACodeStoreType,
     pub http_client: reqwest::Client,
     pub email_server: MockServer, // New!
+    pub pg_pool: PgPool,
     db_name: String,
     clean_up_called: bool,
     
@@ -35,14 +36,35 @@ impl Drop for TestApp {
 
 impl TestApp {
     pub async fn new() -> Self {
+        Self::build(|_| {}).await
+    }
+
+    // App that hides whether an email is registered, see `AppState::enumeration_protection`
+    pub async fn new_with_enumeration_protection() -> Self {
+        Self::build(|app_state| app_state.enumeration_protection = true).await
+    }
+
+    pub async fn new_with_signup_policy(signup_policy: SignupPolicy) -> Self {
+        Self::build(|app_state| app_state.signup_policy = Arc::new(signup_policy)).await
+    }
+
+    pub async fn new_with_token_profile_claims(fields: Vec<ProfileField>) -> Self {
+        Self::build(|app_state| app_state.token_profile_claims = fields).await
+    }
+
+    // App with any other settings changed by `configure`
+    pub async fn build(configure: impl FnOnce(&mut AppState)) -> Self {
 
         let db_name = Uuid::new_v4().to_string();
         let pg_pool = configure_postgresql(&db_name).await;
-        let user_store = Arc::new(RwLock::new(PostgresUserStore::new(pg_pool)));
+        let user_store = Arc::new(PostgresUserStore::new(pg_pool.clone()));
+        let trusted_device_store = Arc::new(PostgresTrustedDeviceStore::new(pg_pool.clone()));
+        let login_history_store = Arc::new(PostgresLoginHistoryStore::new(pg_pool.clone()));
+        let audit_log = Arc::new(PostgresAuditLog::new(pg_pool.clone()));
 
-        let redis_client = Arc::new(RwLock::new(configure_redis()));
-        let banned_token_store = Arc::new(RwLock::new(RedisBannedTokenStore::new(redis_client.clone())));
-        let two_fa_code_store: TwoFACodeStoreType  = Arc::new(RwLock::new(RedisTwoFACodeStore::new(redis_client))); 
+        let redis_conn = configure_redis().await;
+        let banned_token_store = Arc::new(RedisBannedTokenStore::new(redis_conn.clone()));
+        let two_fa_code_store: TwoFACodeStoreType  = Arc::new(RedisTwoFACodeStore::new(redis_conn)); 
 
         
         // Set up a mock email server
@@ -50,12 +72,16 @@ impl TestApp {
         let base_url = email_server.uri(); // New!
         let email_client = Arc::new(configure_postmark_email_client(base_url)); // Updated!
 
-        let app_state = AppState::new(
+        let mut app_state = AppState::new(
                     user_store,
                     banned_token_store.clone(),
                     two_fa_code_store.clone(),
+                    trusted_device_store,
+                    login_history_store,
+                    audit_log,
                     email_client.clone()
         );
+        configure(&mut app_state);
 
         let app = Application::build(app_state, test::APP_ADDRESS)
             .await
@@ -70,10 +96,7 @@ impl TestApp {
 
         let cookie_jar = Arc::new(Jar::default());
         //let http_client = todo!(); // Create a Reqwest http client instance
-        let http_client = reqwest::Client::builder()
-            .cookie_provider(cookie_jar.clone())
-            .build()
-            .unwrap();
+        let http_client = build_http_client(cookie_jar.clone());
 
         Self {
             address,
@@ -82,6 +105,7 @@ impl TestApp {
             two_fa_code_store,
             http_client,
             email_server, // New!
+            pg_pool,
             db_name,
             clean_up_called: false,
         }
@@ -89,7 +113,7 @@ impl TestApp {
 
     pub async fn get_root(&self) -> reqwest::Response {
         self.http_client
-            .get(&format!("{}/", &self.address))
+            .get(format!("{}/", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -101,7 +125,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/signup", &self.address))
+            .post(format!("{}/signup", &self.address))
             .json(body)
             .send()
             .await
@@ -113,8 +137,16 @@ impl TestApp {
     where
         Body: serde::Serialize,
     {
-        self.http_client
-            .post(&format!("{}/login", &self.address))
+        self.post_login_from(&self.http_client, body).await
+    }
+
+    // Sends the request from another client, e.g. one returned by `new_device`
+    pub async fn post_login_from<Body>(&self, client: &Client, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        client
+            .post(format!("{}/login", &self.address))
             .json(body)
             .send()
             .await
@@ -123,7 +155,7 @@ impl TestApp {
 
     pub async fn post_logout(&self) -> reqwest::Response {
         self.http_client
-           .post(&format!("{}/logout", &self.address))
+           .post(format!("{}/logout", &self.address))
            .send()
            .await
            .expect("Failed to execute request.")
@@ -133,7 +165,14 @@ impl TestApp {
     where
         Body: serde::Serialize,
     {
-        self.http_client
+        self.post_verify_2fa_from(&self.http_client, body).await
+    }
+
+    pub async fn post_verify_2fa_from<Body>(&self, client: &Client, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        client
             .post(format!("{}/verify-2fa", &self.address))
             .json(body)
             .send()
@@ -141,6 +180,18 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn post_resend_2fa<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/resend-2fa", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_verify_token<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
@@ -153,12 +204,151 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn get_me(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/me", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn patch_me<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .patch(format!("{}/me", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_enable_2fa<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/me/2fa/enable", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_disable_2fa<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/me/2fa/disable", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_step_up<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/step-up", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_trusted_devices(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/me/devices", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_activity(&self) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/me/activity", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_audit_events(&self, query: &[(&str, &str)]) -> reqwest::Response {
+        self.http_client
+            .get(format!("{}/admin/audit-events", &self.address))
+            .query(query)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_trusted_device(&self, id: &str) -> reqwest::Response {
+        self.http_client
+            .delete(format!("{}/me/devices/{}", &self.address, id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_report_login<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.http_client
+            .post(format!("{}/report-login", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    // Stores only keep a hash of the 2FA code, so tests read it from the
+    // last email captured by the mock email server instead
+    pub async fn get_last_2fa_code(&self) -> String {
+        let requests = self
+            .email_server
+            .received_requests()
+            .await
+            .expect("Request recording is disabled");
+        let last_email = requests.last().expect("No email was sent");
+        let body: serde_json::Value =
+            serde_json::from_slice(&last_email.body).expect("Email body is not JSON");
+        body["TextBody"]
+            .as_str()
+            .expect("Email has no text body")
+            .to_owned()
+    }
+
+    // A client with its own cookie store, acting as a second browser
+    pub fn new_device(&self) -> Client {
+        build_http_client(Arc::new(Jar::default()))
+    }
+
+    // Another browser, which tells the service its user agent
+    pub fn new_device_with_user_agent(&self, user_agent: &str) -> Client {
+        Client::builder()
+            .cookie_provider(Arc::new(Jar::default()))
+            .user_agent(user_agent)
+            .build()
+            .unwrap()
+    }
+
     pub async fn clean_up(&mut self) {
         delete_database(&self.db_name).await;
         self.clean_up_called = true;
     }
 }
 
+fn build_http_client(cookie_jar: Arc<Jar>) -> Client {
+    Client::builder()
+        .cookie_provider(cookie_jar)
+        .build()
+        .unwrap()
+}
+
 async fn configure_postgresql(db_name: &str) -> PgPool {
     let postgresql_conn_url = DATABASE_URL.to_owned();
 
@@ -166,7 +356,7 @@ async fn configure_postgresql(db_name: &str) -> PgPool {
     //let db_name = Uuid::new_v4().to_string();
     //println!("Creating database: {}", db_name);
 
-    configure_database(&postgresql_conn_url.expose_secret(), &db_name).await;
+    configure_database(postgresql_conn_url.expose_secret(), db_name).await;
 
     let postgresql_conn_url_with_db = format!("{}/{}", postgresql_conn_url.expose_secret(), db_name);
 
@@ -245,10 +435,9 @@ async fn delete_database(db_name: &str) {
         .expect("Failed to drop the database.");
 }
 
-fn configure_redis() -> redis::Connection {
-    get_redis_client(REDIS_HOST_NAME.to_owned())
-        .expect("Failed to get Redis client")
-        .get_connection()
+async fn configure_redis() -> ConnectionManager {
+    get_redis_connection(REDIS_HOST_NAME.to_owned())
+        .await
         .expect("Failed to get Redis connection")
 }
 
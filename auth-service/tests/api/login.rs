@@ -1,5 +1,5 @@
-use auth_service::{domain::Email, routes::TwoFactorAuthResponse, utils::constants::JWT_COOKIE_NAME};
-use secrecy::{Secret, ExposeSecret};
+use auth_service::{domain::{LoginAttemptId, TwoFACode, UserId}, routes::TwoFactorAuthResponse, utils::constants::{CURRENT_PASSWORD_PEPPER_VERSION, JWT_COOKIE_NAME}, ErrorResponse};
+use secrecy::{ExposeSecret, Secret};
 use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
 
 use crate::helpers::{get_random_email, TestApp};
@@ -12,7 +12,7 @@ async fn should_return_206_if_valid_credentials_and_2fa_enabled() {
 
     let signup_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "Sup3r-Secret-Pass!",
         "requires2FA": true
     });
 
@@ -30,7 +30,7 @@ async fn should_return_206_if_valid_credentials_and_2fa_enabled() {
 
     let login_body = serde_json::json!({
         "email": random_email.clone(),
-        "password": "password123",
+        "password": "Sup3r-Secret-Pass!",
         "requires2FA": true
     });
     let response = app.post_login(&login_body).await;
@@ -45,14 +45,23 @@ async fn should_return_206_if_valid_credentials_and_2fa_enabled() {
 
     // Tassert that `json_body.login_attempt_id` is stored inside `app.two_fa_code_store`
     
-    let login_attempt_id = app.two_fa_code_store.
-                read().
+    let login_attempt_id = LoginAttemptId::parse(Secret::new(json_body.login_attempt_id)).unwrap();
+    let (stored_user_id, stored_code_hash) = app.two_fa_code_store.
+                get_code(&login_attempt_id).
                 await.
-                get_code(&Email::parse(Secret::new(random_email)).unwrap()).
-                await.
-                unwrap().0.as_ref().expose_secret().to_owned();
+                unwrap();
+
+    let user_id: uuid::Uuid = sqlx::query_scalar("SELECT id FROM users WHERE email = $1")
+        .bind(&random_email)
+        .fetch_one(&app.pg_pool)
+        .await
+        .unwrap();
+    assert_eq!(stored_user_id, UserId::from(user_id));
 
-    assert_eq!(login_attempt_id, json_body.login_attempt_id);
+    // Only a hash of the emailed code is stored
+    let code = TwoFACode::parse(Secret::new(app.get_last_2fa_code().await)).unwrap();
+    assert_ne!(stored_code_hash.as_ref().expose_secret(), code.as_ref().expose_secret());
+    assert!(stored_code_hash.verify(&login_attempt_id, &code));
                                     
     app.clean_up().await;
     
@@ -68,7 +77,7 @@ async fn should_return_200_if_valid_credentials_and_2fa_disabled() {
 
     let signup_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "Sup3r-Secret-Pass!",
         "requires2FA": false
     });
 
@@ -78,7 +87,7 @@ async fn should_return_200_if_valid_credentials_and_2fa_disabled() {
 
     let login_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "Sup3r-Secret-Pass!",
     });
 
     let response = app.post_login(&login_body).await;
@@ -151,7 +160,7 @@ async fn should_return_400_if_invalid_input() {
     let input = [
         serde_json::json!({
             "email": "",
-            "password": "password123",
+            "password": "Sup3r-Secret-Pass!",
         }),
         serde_json::json!({
             "email": random_email,
@@ -193,4 +202,139 @@ async fn should_return_401_if_incorrect_credentials() {
     );
 
     
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn should_return_same_401_for_unknown_user_and_wrong_password_with_enumeration_protection() {
+    let mut app = TestApp::new_with_enumeration_protection().await;
+
+    let random_email = get_random_email();
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let wrong_password_response = app
+        .post_login(&serde_json::json!({
+            "email": random_email,
+            "password": "wrong-password123",
+        }))
+        .await;
+    assert_eq!(wrong_password_response.status().as_u16(), 401);
+
+    let unknown_user_response = app
+        .post_login(&serde_json::json!({
+            "email": get_random_email(),
+            "password": "Sup3r-Secret-Pass!",
+        }))
+        .await;
+    assert_eq!(unknown_user_response.status().as_u16(), 401);
+
+    assert_eq!(
+        wrong_password_response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        unknown_user_response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error
+    );
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_migrate_legacy_bcrypt_hash_to_argon2id_on_login() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    // Users imported from the legacy system keep their bcrypt hash
+    let legacy_hash = bcrypt::hash("Sup3r-Secret-Pass!", 4).unwrap();
+    sqlx::query("INSERT INTO users (email, password_hash, requires_2fa) VALUES ($1, $2, false)")
+        .bind(&random_email)
+        .bind(&legacy_hash)
+        .execute(&app.pg_pool)
+        .await
+        .unwrap();
+
+    let login_body = serde_json::json!({
+        "email": random_email,
+        "password": "Sup3r-Secret-Pass!",
+    });
+
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let (stored_hash, pepper_version): (String, i32) =
+        sqlx::query_as("SELECT password_hash, password_pepper_version FROM users WHERE email = $1")
+            .bind(&random_email)
+            .fetch_one(&app.pg_pool)
+            .await
+            .unwrap();
+    assert!(stored_hash.starts_with("$argon2id$"));
+    assert_eq!(pepper_version, *CURRENT_PASSWORD_PEPPER_VERSION);
+
+    // The upgraded hash keeps accepting the same password
+    let response = app.post_login(&login_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_403_if_user_is_disabled() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    sqlx::query("UPDATE users SET disabled = TRUE WHERE email = $1")
+        .bind(&random_email)
+        .execute(&app.pg_pool)
+        .await
+        .unwrap();
+
+    // A wrong password must not reveal that the account is disabled
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": random_email,
+            "password": "Wr0ng-Secret-Pass!",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(response.cookies().all(|cookie| cookie.name() != JWT_COOKIE_NAME));
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "User account is disabled".to_owned()
+    );
+
+    app.clean_up().await;
+}
@@ -0,0 +1,188 @@
+use auth_service::{
+    get_redis_connection,
+    routes::LOGIN_ALERT_SUBJECT,
+    utils::{
+        auth::TOKEN_TTL_SECONDS,
+        constants::{JWT_COOKIE_NAME, REDIS_HOST_NAME},
+    },
+};
+use uuid::Uuid;
+use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
+
+use crate::helpers::{get_random_email, TestApp};
+
+const PASSWORD: &str = "Sup3r-Secret-Pass!";
+const NEW_PASSWORD: &str = "An0ther-Secret-Pass!";
+
+async fn signup(app: &TestApp, email: &str) {
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": PASSWORD,
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+async fn login_from(app: &TestApp, client: &reqwest::Client, email: &str, password: &str) -> reqwest::Response {
+    app.post_login_from(client, &serde_json::json!({ "email": email, "password": password }))
+        .await
+}
+
+async fn mock_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+// Text bodies of the login alerts sent so far
+async fn login_alerts(app: &TestApp) -> Vec<String> {
+    let requests = app.email_server.received_requests().await.unwrap();
+    requests
+        .iter()
+        .map(|request| serde_json::from_slice::<serde_json::Value>(&request.body).unwrap())
+        .filter(|body| body["Subject"] == LOGIN_ALERT_SUBJECT)
+        .map(|body| body["TextBody"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+fn report_token(alert: &str) -> String {
+    alert
+        .split("report_login=")
+        .nth(1)
+        .expect("No report link in login alert")
+        .trim()
+        .to_owned()
+}
+
+#[tokio::test]
+async fn should_alert_only_on_logins_from_new_devices() {
+    let mut app = TestApp::new().await;
+    mock_email_server(&app).await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    // The first login has nothing to compare with
+    let firefox = app.new_device_with_user_agent("Firefox");
+    assert_eq!(login_from(&app, &firefox, &random_email, PASSWORD).await.status().as_u16(), 200);
+    assert_eq!(login_from(&app, &firefox, &random_email, PASSWORD).await.status().as_u16(), 200);
+    assert!(login_alerts(&app).await.is_empty());
+
+    let chrome = app.new_device_with_user_agent("Chrome");
+    assert_eq!(login_from(&app, &chrome, &random_email, PASSWORD).await.status().as_u16(), 200);
+    let alerts = login_alerts(&app).await;
+    assert_eq!(alerts.len(), 1);
+    assert!(alerts[0].contains("Browser: Chrome"));
+    assert!(alerts[0].contains("IP address: 127.0.0.1"));
+
+    // Failed logins are not recorded
+    let safari = app.new_device_with_user_agent("Safari");
+    assert_eq!(login_from(&app, &safari, &random_email, "Wr0ng-Password!").await.status().as_u16(), 401);
+    assert_eq!(login_from(&app, &chrome, &random_email, PASSWORD).await.status().as_u16(), 200);
+    assert_eq!(login_alerts(&app).await.len(), 1);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_secure_account_when_login_is_reported() {
+    let mut app = TestApp::new().await;
+    mock_email_server(&app).await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    let firefox = app.new_device_with_user_agent("Firefox");
+    login_from(&app, &firefox, &random_email, PASSWORD).await;
+    let intruder = app.new_device_with_user_agent("Intruder");
+    let response = login_from(&app, &intruder, &random_email, PASSWORD).await;
+    let intruder_token = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+    let token = report_token(&login_alerts(&app).await[0]);
+
+    let response = app
+        .post_report_login(&serde_json::json!({ "token": "invalid", "password": NEW_PASSWORD }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    // The new password must follow the policy
+    let response = app
+        .post_report_login(&serde_json::json!({ "token": token, "password": "short" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app
+        .post_report_login(&serde_json::json!({ "token": token, "password": NEW_PASSWORD }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // The intruder is signed out and the old password no longer works
+    let response = app
+        .post_verify_token(&serde_json::json!({ "token": intruder_token }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(login_from(&app, &intruder, &random_email, PASSWORD).await.status().as_u16(), 401);
+    assert_eq!(login_from(&app, &firefox, &random_email, NEW_PASSWORD).await.status().as_u16(), 200);
+
+    // The link only works once
+    let response = app
+        .post_report_login(&serde_json::json!({ "token": token, "password": "Y3t-Another-Pass!" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_not_accept_a_used_report_link_once_auth_tokens_expire() {
+    let mut app = TestApp::new().await;
+    mock_email_server(&app).await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    let firefox = app.new_device_with_user_agent("Firefox");
+    login_from(&app, &firefox, &random_email, PASSWORD).await;
+    let intruder = app.new_device_with_user_agent("Intruder");
+    login_from(&app, &intruder, &random_email, PASSWORD).await;
+    let token = report_token(&login_alerts(&app).await[0]);
+
+    let response = app
+        .post_report_login(&serde_json::json!({ "token": token, "password": NEW_PASSWORD }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Fast-forward the revocation in Redis past the lifetime of an auth token
+    let user_id: Uuid = sqlx::query_scalar("SELECT id FROM users")
+        .fetch_one(&app.pg_pool)
+        .await
+        .unwrap();
+    let revocation_key = format!("tokens_revoked_before:{}", user_id);
+    let mut conn = get_redis_connection(REDIS_HOST_NAME.to_owned())
+        .await
+        .expect("Failed to get Redis connection");
+    let ttl: i64 = redis::cmd("TTL")
+        .arg(&revocation_key)
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+    let _: () = redis::cmd("EXPIRE")
+        .arg(&revocation_key)
+        .arg(ttl - TOKEN_TTL_SECONDS)
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+
+    // The link is still valid for days, it must stay used up
+    let response = app
+        .post_report_login(&serde_json::json!({ "token": token, "password": "Y3t-Another-Pass!" }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
@@ -12,7 +12,7 @@ async fn should_return_200_if_valid_jwt_cookie() {
 
     let signup_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "Sup3r-Secret-Pass!",
         "requires2FA": false
     });
 
@@ -22,7 +22,7 @@ async fn should_return_200_if_valid_jwt_cookie() {
 
     let login_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "Sup3r-Secret-Pass!",
     });
 
     let response = app.post_login(&login_body).await;
@@ -40,8 +40,6 @@ async fn should_return_200_if_valid_jwt_cookie() {
     assert_eq!(logout_response.status().as_u16(), 200);
 
     let is_banned = app.banned_token_store
-            .read()
-            .await
             .contains_token(&Secret::new(auth_cookie.value().to_string()))
             .await
             .unwrap();
@@ -59,7 +57,7 @@ async fn should_return_400_if_logout_called_twice_in_a_row() {
 
     let signup_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "Sup3r-Secret-Pass!",
         "requires2FA": false
     });
 
@@ -69,7 +67,7 @@ async fn should_return_400_if_logout_called_twice_in_a_row() {
 
     let login_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "Sup3r-Secret-Pass!",
     });
 
     let response = app.post_login(&login_body).await;
@@ -1,7 +1,16 @@
+mod audit;
+mod event_stream;
 mod helpers;
 mod login;
+mod login_alerts;
 mod logout;
+mod me;
+mod resend_2fa;
 mod root;
 mod signup;
+mod step_up;
+mod trusted_devices;
+mod two_fa_settings;
 mod verify_2fa;
-mod verify_token;
\ No newline at end of file
+mod verify_token;
+mod webhooks;
\ No newline at end of file
@@ -0,0 +1,160 @@
+use auth_service::{
+    domain::ProfileField,
+    routes::MeResponse,
+    utils::{auth::validate_token, constants::JWT_COOKIE_NAME},
+    ErrorResponse,
+};
+use secrecy::Secret;
+
+use crate::helpers::{get_random_email, TestApp};
+
+async fn signup_and_login(app: &TestApp, email: &str) -> reqwest::Response {
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": email,
+            "password": "Sup3r-Secret-Pass!"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    response
+}
+
+#[tokio::test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let mut app = TestApp::new().await;
+
+    let response = app.get_me().await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app.patch_me(&serde_json::json!({ "displayName": "Jane" })).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_200_with_the_current_user() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    signup_and_login(&app, &random_email).await;
+
+    let response = app.get_me().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body = response
+        .json::<MeResponse>()
+        .await
+        .expect("Could not deserialize response body to MeResponse");
+    assert_eq!(body.email, random_email);
+    assert!(!body.requires_2fa);
+    assert_eq!(body.profile, Default::default());
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_update_only_the_given_fields() {
+    let mut app = TestApp::new().await;
+    signup_and_login(&app, &get_random_email()).await;
+
+    let response = app
+        .patch_me(&serde_json::json!({
+            "displayName": " Jane Doe ",
+            "locale": "en-GB",
+            "timezone": "Europe/London"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.patch_me(&serde_json::json!({ "locale": null })).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body = app.get_me().await.json::<MeResponse>().await.unwrap();
+    assert_eq!(body.profile.display_name.as_deref(), Some("Jane Doe"));
+    assert_eq!(body.profile.locale, None);
+    assert_eq!(body.profile.timezone.as_deref(), Some("Europe/London"));
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_400_with_reasons_if_profile_is_invalid() {
+    let mut app = TestApp::new().await;
+    signup_and_login(&app, &get_random_email()).await;
+
+    let response = app
+        .patch_me(&serde_json::json!({
+            "timezone": "Europe/Atlantis",
+            "avatarUrl": "javascript:alert(1)"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let body = response
+        .json::<ErrorResponse>()
+        .await
+        .expect("Could not deserialize response body to ErrorResponse");
+    assert_eq!(body.error, "Invalid profile".to_owned());
+    let codes: Vec<&str> = body.reasons.iter().map(|reason| reason.code.as_str()).collect();
+    assert_eq!(codes, vec!["invalid_timezone", "invalid_avatar_url"]);
+
+    // Nothing was saved
+    let body = app.get_me().await.json::<MeResponse>().await.unwrap();
+    assert_eq!(body.profile, Default::default());
+
+    let response = app.patch_me(&serde_json::json!({ "email": "other@example.com" })).await;
+    assert_eq!(response.status().as_u16(), 422);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_include_selected_profile_claims_in_tokens() {
+    let mut app =
+        TestApp::new_with_token_profile_claims(vec![ProfileField::Name, ProfileField::Zoneinfo])
+            .await;
+    let random_email = get_random_email();
+    signup_and_login(&app, &random_email).await;
+
+    let response = app
+        .patch_me(&serde_json::json!({
+            "displayName": "Jane Doe",
+            "locale": "fr",
+            "timezone": "Europe/Paris"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Claims are copied when a token is issued
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let token = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+
+    let claims = validate_token(&Secret::new(token), app.banned_token_store.clone())
+        .await
+        .unwrap();
+    assert_eq!(claims.profile.name.as_deref(), Some("Jane Doe"));
+    assert_eq!(claims.profile.zoneinfo.as_deref(), Some("Europe/Paris"));
+    assert_eq!(claims.profile.locale, None);
+
+    app.clean_up().await;
+}
@@ -0,0 +1,235 @@
+use auth_service::{
+    domain::LoginAttemptId, get_redis_connection, routes::TwoFactorAuthResponse,
+    utils::constants::REDIS_HOST_NAME, ErrorResponse,
+};
+use chrono::Utc;
+use secrecy::Secret;
+use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
+
+use crate::helpers::{get_random_email, TestApp};
+
+#[tokio::test]
+async fn should_return_422_if_malformed_input() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let test_cases = [
+        serde_json::json!({
+            "loginAttemptId": "550e8400-e29b-41d4-a716-446655440000",
+        }),
+        serde_json::json!({
+            "email": random_email,
+        }),
+    ];
+
+    for test_case in test_cases.iter() {
+        let response = app.post_resend_2fa(test_case).await;
+        assert_eq!(
+            response.status().as_u16(),
+            422,
+            "Failed for input: {:?}",
+            test_case
+        );
+    }
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_400_if_invalid_input() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let test_cases = [
+        // wrong email format
+        serde_json::json!({
+            "email": "invalid-email",
+            "loginAttemptId": "550e8400-e29b-41d4-a716-446655440000",
+        }),
+        // wrong loginAttemptId format
+        serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": "550e8400-e29b-",
+        }),
+    ];
+
+    for test_case in test_cases.iter() {
+        let response = app.post_resend_2fa(test_case).await;
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "Failed for input: {:?}",
+            test_case
+        );
+    }
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_unknown_login_attempt() {
+    let mut app = TestApp::new().await;
+
+    let response = app
+        .post_resend_2fa(&serde_json::json!({
+            "email": get_random_email(),
+            "loginAttemptId": "550e8400-e29b-41d4-a716-446655440000",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_429_if_resent_during_cooldown() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    // Only the login should send an email, the resend is rejected
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+
+    let code_tuple = app
+        .two_fa_code_store
+        .get_code(&LoginAttemptId::parse(Secret::new(login_attempt_id.clone())).unwrap())
+        .await
+        .unwrap();
+
+    let response = app
+        .post_resend_2fa(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": login_attempt_id,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 429);
+
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .error,
+        "Please wait before requesting a new 2FA code".to_owned()
+    );
+
+    // The pending code must not have been replaced
+    let new_code_tuple = app
+        .two_fa_code_store
+        .get_code(&LoginAttemptId::parse(Secret::new(login_attempt_id)).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(code_tuple, new_code_tuple);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_resend_once_when_resends_race() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    // One email for the login and one for the only resend that gets through
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+
+    // Pretend the code was sent a minute ago, so that the cooldown has elapsed
+    let mut conn = get_redis_connection(REDIS_HOST_NAME.to_owned())
+        .await
+        .expect("Failed to get Redis connection");
+    let _: () = redis::cmd("HSET")
+        .arg(format!("two_fa_login_attempt:{}", login_attempt_id))
+        .arg("last_sent_at")
+        .arg(Utc::now().timestamp() - 60)
+        .query_async(&mut conn)
+        .await
+        .unwrap();
+
+    let mut resends = tokio::task::JoinSet::new();
+    for _ in 0..10 {
+        let client = reqwest::Client::new();
+        let url = format!("{}/resend-2fa", &app.address);
+        let body = serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": login_attempt_id,
+        });
+        resends.spawn(async move {
+            client
+                .post(url)
+                .json(&body)
+                .send()
+                .await
+                .expect("Failed to execute request.")
+                .status()
+                .as_u16()
+        });
+    }
+
+    let mut statuses = Vec::new();
+    while let Some(status) = resends.join_next().await {
+        statuses.push(status.unwrap());
+    }
+    statuses.sort();
+    assert_eq!(statuses, [200, 429, 429, 429, 429, 429, 429, 429, 429, 429]);
+
+    app.clean_up().await;
+}
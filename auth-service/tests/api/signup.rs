@@ -1,5 +1,8 @@
+use std::{sync::Arc, time::Duration};
+
 use crate::helpers::{get_random_email, TestApp};
-use auth_service::{routes::SignupResponse, ErrorResponse};
+use auth_service::{domain::{parse_domain, Email, EmailClient, SignupPolicy}, routes::SignupResponse, ErrorReason, ErrorResponse};
+use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
 
 #[tokio::test]
 async fn should_return_422_if_malformed_input() {
@@ -10,7 +13,7 @@ async fn should_return_422_if_malformed_input() {
     // add more malformed input test cases
     let test_cases = [
         serde_json::json!({
-            "password": "password123",
+            "password": "Sup3r-Secret-Pass!",
             "requires2FA": true
         }),
         serde_json::json!({
@@ -41,7 +44,7 @@ async fn should_return_201_if_valid_input() {
     let test_case = 
         serde_json::json!({
             "email": random_email,
-            "password": "password123",
+            "password": "Sup3r-Secret-Pass!",
             "requires2FA": true
         })
     ;
@@ -81,7 +84,7 @@ async fn should_return_400_if_invalid_input() {
     let input = [
         serde_json::json!({
             "email": "",
-            "password": "password123",
+            "password": "Sup3r-Secret-Pass!",
             "requires2FA": true
         }),
         serde_json::json!({
@@ -114,7 +117,7 @@ async fn should_return_409_if_email_already_exists() {
     let test_case = 
         serde_json::json!({
             "email": random_email,
-            "password": "password123",
+            "password": "Sup3r-Secret-Pass!",
             "requires2FA": true
         })
     ;
@@ -135,4 +138,334 @@ async fn should_return_409_if_email_already_exists() {
 
     app.clean_up().await;
 
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn should_return_409_if_email_differs_only_by_case_or_whitespace() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": random_email.to_uppercase(),
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": format!(" {} ", random_email),
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    // Both spellings log into the same account, which keeps the address as typed at signup
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let stored: (String, String) =
+        sqlx::query_as("SELECT email, email_canonical FROM users")
+            .fetch_one(&app.pg_pool)
+            .await
+            .unwrap();
+    assert_eq!(stored, (random_email.to_uppercase(), random_email));
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_create_one_user_when_signups_for_an_email_race() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    let mut signups = tokio::task::JoinSet::new();
+    for i in 0..10 {
+        let client = reqwest::Client::new();
+        let url = format!("{}/signup", &app.address);
+        // Spellings of the same address race as well
+        let email = if i % 2 == 0 { random_email.clone() } else { random_email.to_uppercase() };
+        let body = serde_json::json!({
+            "email": email,
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": false
+        });
+        signups.spawn(async move {
+            client
+                .post(url)
+                .json(&body)
+                .send()
+                .await
+                .expect("Failed to execute request.")
+                .status()
+                .as_u16()
+        });
+    }
+
+    let mut statuses = Vec::new();
+    while let Some(status) = signups.join_next().await {
+        statuses.push(status.unwrap());
+    }
+    statuses.sort();
+    assert_eq!(statuses, [201, 409, 409, 409, 409, 409, 409, 409, 409, 409]);
+
+    let users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+        .fetch_one(&app.pg_pool)
+        .await
+        .unwrap();
+    assert_eq!(users, 1);
+
+    app.clean_up().await;
+}
+
+// Copies the only user into a row the canonical email migration left NULL, as it
+// does for emails that collide once canonicalized and for non-ASCII ones
+async fn add_legacy_row(app: &TestApp, email: &str, email_canonical: Option<&str>) {
+    sqlx::query(
+        "INSERT INTO users (email, email_canonical, password_hash, requires_2fa, password_pepper_version) \
+         SELECT $1, $2, password_hash, requires_2fa, password_pepper_version FROM users LIMIT 1",
+    )
+    .bind(email)
+    .bind(email_canonical)
+    .execute(&app.pg_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn should_return_409_if_email_collides_with_legacy_rows() {
+    let mut app = TestApp::new().await;
+
+    let signup = |email: &str| {
+        serde_json::json!({
+            "email": email,
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": false
+        })
+    };
+    let login = serde_json::json!({
+        "email": "bob@example.com",
+        "password": "Sup3r-Secret-Pass!",
+    });
+
+    // Two spellings the old email primary key allowed, both left without a canonical email
+    let response = app.post_signup(&signup("bob@example.com")).await;
+    assert_eq!(response.status().as_u16(), 201);
+    sqlx::query("UPDATE users SET email_canonical = NULL")
+        .execute(&app.pg_pool)
+        .await
+        .unwrap();
+    add_legacy_row(&app, "Bob@example.com", None).await;
+
+    let response = app.post_signup(&signup("BOB@example.com")).await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    // Each legacy row still matches its exact spelling only
+    let response = app.post_login(&login).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // A non-ASCII legacy row collides with the punycode spelling of its domain
+    add_legacy_row(&app, "dave@bücher.example", None).await;
+    let response = app.post_signup(&signup("dave@xn--bcher-kva.example")).await;
+    assert_eq!(response.status().as_u16(), 409);
+
+    let users: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+        .fetch_one(&app.pg_pool)
+        .await
+        .unwrap();
+    assert_eq!(users, 3);
+
+    // Lookups fail rather than pick one of several matching users, such as one that
+    // signed up before legacy rows were checked
+    add_legacy_row(&app, "BOB@example.com", Some("bob@example.com")).await;
+    let response = app.post_login(&login).await;
+    assert_eq!(response.status().as_u16(), 500);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_201_and_notify_owner_if_email_exists_with_enumeration_protection() {
+    let mut app = TestApp::new_with_enumeration_protection().await;
+
+    let random_email = get_random_email();
+
+    let test_case = serde_json::json!({
+        "email": random_email,
+        "password": "Sup3r-Secret-Pass!",
+        "requires2FA": true
+    });
+
+    let first_response = app.post_signup(&test_case).await;
+    assert_eq!(first_response.status().as_u16(), 201);
+
+    // Only the second signup emails the owner of the address
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let second_response = app.post_signup(&test_case).await;
+    assert_eq!(second_response.status().as_u16(), 201);
+
+    assert_eq!(
+        second_response
+            .json::<SignupResponse>()
+            .await
+            .expect("Could not deserialize response body to SignupResponse"),
+        first_response
+            .json::<SignupResponse>()
+            .await
+            .expect("Could not deserialize response body to SignupResponse")
+    );
+
+    app.clean_up().await;
+}
+
+// An email provider that takes an hour to answer
+struct SlowEmailClient;
+
+#[async_trait::async_trait]
+impl EmailClient for SlowEmailClient {
+    async fn send_email(&self, _recipient: &Email, _subject: &str, _content: &str) -> color_eyre::eyre::Result<()> {
+        tokio::time::sleep(Duration::from_secs(3600)).await;
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn should_not_wait_for_the_email_client_if_email_exists_with_enumeration_protection() {
+    let mut app = TestApp::build(|app_state| {
+        app_state.enumeration_protection = true;
+        app_state.email_client = Arc::new(SlowEmailClient);
+    })
+    .await;
+
+    let test_case = serde_json::json!({
+        "email": get_random_email(),
+        "password": "Sup3r-Secret-Pass!",
+        "requires2FA": true
+    });
+
+    // The first signup creates the account, the second one notifies its owner
+    for _ in 0..2 {
+        let response = tokio::time::timeout(Duration::from_secs(30), app.post_signup(&test_case))
+            .await
+            .expect("Signup waited for the email client");
+        assert_eq!(response.status().as_u16(), 201);
+    }
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_400_with_reasons_if_password_violates_policy() {
+    let mut app = TestApp::new().await;
+
+    let test_cases = [
+        ("password123", vec!["breached"]),
+        ("aaaaaaaaaaaa", vec!["too_weak"]),
+        ("short", vec!["too_short", "too_weak"]),
+    ];
+
+    for (password, expected_codes) in test_cases {
+        let response = app
+            .post_signup(&serde_json::json!({
+                "email": get_random_email(),
+                "password": password,
+                "requires2FA": false
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 400, "Failed for password: {}", password);
+
+        let body = response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse");
+        assert_eq!(body.error, "Password does not meet the requirements".to_owned());
+
+        let codes: Vec<&str> = body.reasons.iter().map(|reason| reason.code.as_str()).collect();
+        assert_eq!(codes, expected_codes, "Failed for password: {}", password);
+        assert!(body.reasons.iter().all(|reason| !reason.message.is_empty()));
+    }
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_400_if_email_is_disposable() {
+    let mut app = TestApp::new().await;
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": "someone@Mailinator.com",
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let body = response
+        .json::<ErrorResponse>()
+        .await
+        .expect("Could not deserialize response body to ErrorResponse");
+    assert_eq!(body.error, "Email address is not allowed to sign up".to_owned());
+    assert_eq!(
+        body.reasons,
+        vec![ErrorReason {
+            code: "disposable_domain".to_owned(),
+            message: "Disposable email addresses are not allowed".to_owned(),
+        }]
+    );
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_only_accept_allowed_domains_when_configured() {
+    let mut app = TestApp::new_with_signup_policy(SignupPolicy {
+        allowed_domains: [parse_domain("corp.example").unwrap()].into(),
+        ..Default::default()
+    })
+    .await;
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": get_random_email(),
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+    assert_eq!(
+        response
+            .json::<ErrorResponse>()
+            .await
+            .expect("Could not deserialize response body to ErrorResponse")
+            .reasons[0]
+            .code,
+        "domain_not_allowed".to_owned()
+    );
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": "jane@eu.corp.example",
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    app.clean_up().await;
+}
@@ -0,0 +1,115 @@
+use auth_service::{
+    routes::{StepUpResponse, TwoFactorAuthResponse, VerifyTokenResponse},
+    utils::auth::{Acr, AuthMethod, STEP_UP_TOKEN_TTL_SECONDS},
+};
+use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
+
+use crate::helpers::{get_random_email, TestApp};
+
+const PASSWORD: &str = "Sup3r-Secret-Pass!";
+
+async fn signup_and_login(app: &TestApp, email: &str, requires_2fa: bool) {
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": PASSWORD,
+            "requires2FA": requires_2fa
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = app
+        .post_login(&serde_json::json!({ "email": email, "password": PASSWORD }))
+        .await;
+    if !requires_2fa {
+        assert_eq!(response.status().as_u16(), 200);
+        return;
+    }
+    assert_eq!(response.status().as_u16(), 206);
+    let login_attempt_id = response.json::<TwoFactorAuthResponse>().await.unwrap().login_attempt_id;
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": app.get_last_2fa_code().await
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+async fn verify(app: &TestApp, token: &str) -> VerifyTokenResponse {
+    let response = app.post_verify_token(&serde_json::json!({ "token": token })).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json::<VerifyTokenResponse>().await.unwrap()
+}
+
+#[tokio::test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let mut app = TestApp::new().await;
+
+    let response = app.post_step_up(&serde_json::json!({ "password": PASSWORD })).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_password_is_incorrect() {
+    let mut app = TestApp::new().await;
+    signup_and_login(&app, &get_random_email(), false).await;
+
+    let response = app.post_step_up(&serde_json::json!({ "password": "Wr0ng-Password!" })).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_issue_short_lived_token_after_password() {
+    let mut app = TestApp::new().await;
+    signup_and_login(&app, &get_random_email(), false).await;
+
+    let response = app.post_step_up(&serde_json::json!({ "password": PASSWORD })).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.json::<StepUpResponse>().await.unwrap();
+    assert_eq!(body.expires_in, STEP_UP_TOKEN_TTL_SECONDS);
+
+    let claims = verify(&app, &body.token).await;
+    assert_eq!(claims.amr, vec![AuthMethod::Pwd]);
+    assert_eq!(claims.acr, Some(Acr::Aal1));
+    let auth_time = claims.auth_time.expect("No auth_time claim");
+    assert!(claims.exp <= auth_time + STEP_UP_TOKEN_TTL_SECONDS as usize + 1);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_require_a_fresh_2fa_code_if_2fa_enabled() {
+    let mut app = TestApp::new().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    signup_and_login(&app, &get_random_email(), true).await;
+
+    let response = app.post_step_up(&serde_json::json!({ "password": PASSWORD })).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let login_attempt_id = response.json::<TwoFactorAuthResponse>().await.unwrap().login_attempt_id;
+
+    let response = app
+        .post_step_up(&serde_json::json!({
+            "password": PASSWORD,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": app.get_last_2fa_code().await
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.json::<StepUpResponse>().await.unwrap();
+
+    let claims = verify(&app, &body.token).await;
+    assert_eq!(claims.amr, vec![AuthMethod::Pwd, AuthMethod::Email]);
+    assert_eq!(claims.acr, Some(Acr::Aal2));
+
+    app.clean_up().await;
+}
@@ -0,0 +1,113 @@
+use auth_service::routes::{TrustedDevicesResponse, TwoFactorAuthResponse};
+use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
+
+use crate::helpers::{get_random_email, TestApp};
+
+const PASSWORD: &str = "Sup3r-Secret-Pass!";
+
+async fn signup(app: &TestApp, email: &str) {
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": PASSWORD,
+            "requires2FA": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+async fn login(app: &TestApp, email: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({ "email": email, "password": PASSWORD }))
+        .await
+}
+
+async fn login_with_2fa(app: &TestApp, email: &str, trust_device: bool) {
+    let response = login(app, email).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let login_attempt_id = response.json::<TwoFactorAuthResponse>().await.unwrap().login_attempt_id;
+
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": app.get_last_2fa_code().await,
+            "trustDevice": trust_device
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+async fn mock_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let mut app = TestApp::new().await;
+
+    assert_eq!(app.get_trusted_devices().await.status().as_u16(), 400);
+    let response = app
+        .delete_trusted_device("1c6a0e2e-8f5e-4a4f-9d3e-1b0f2c3d4e5f")
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_skip_2fa_on_trusted_device_only() {
+    let mut app = TestApp::new().await;
+    mock_email_server(&app).await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+
+    // Not trusting the device keeps asking for a code
+    login_with_2fa(&app, &random_email, false).await;
+    assert_eq!(login(&app, &random_email).await.status().as_u16(), 206);
+
+    login_with_2fa(&app, &random_email, true).await;
+    assert_eq!(login(&app, &random_email).await.status().as_u16(), 200);
+
+    // Another browser has no trusted device cookie
+    let response = app
+        .post_login_from(
+            &app.new_device(),
+            &serde_json::json!({ "email": random_email, "password": PASSWORD }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_list_and_revoke_trusted_devices() {
+    let mut app = TestApp::new().await;
+    mock_email_server(&app).await;
+    let random_email = get_random_email();
+    signup(&app, &random_email).await;
+    login_with_2fa(&app, &random_email, true).await;
+
+    let response = app.get_trusted_devices().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let devices = response.json::<TrustedDevicesResponse>().await.unwrap().devices;
+    assert_eq!(devices.len(), 1);
+    assert!(devices[0].current);
+    assert!(devices[0].last_used_at.is_none());
+
+    assert_eq!(app.delete_trusted_device("not-a-device").await.status().as_u16(), 404);
+
+    let response = app.delete_trusted_device(&devices[0].id).await;
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(app.delete_trusted_device(&devices[0].id).await.status().as_u16(), 404);
+
+    let response = app.get_trusted_devices().await;
+    assert!(response.json::<TrustedDevicesResponse>().await.unwrap().devices.is_empty());
+    assert_eq!(login(&app, &random_email).await.status().as_u16(), 206);
+
+    app.clean_up().await;
+}
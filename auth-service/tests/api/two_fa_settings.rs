@@ -0,0 +1,150 @@
+use auth_service::routes::{TwoFASettingResponse, TwoFactorAuthResponse};
+use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
+
+use crate::helpers::{get_random_email, TestApp};
+
+const PASSWORD: &str = "Sup3r-Secret-Pass!";
+
+async fn signup(app: &TestApp, email: &str, requires_2fa: bool) {
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": email,
+            "password": PASSWORD,
+            "requires2FA": requires_2fa
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+}
+
+async fn login(app: &TestApp, email: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({ "email": email, "password": PASSWORD }))
+        .await
+}
+
+// Logs in with the emailed code, the user must require 2FA
+async fn login_with_2fa(app: &TestApp, email: &str) {
+    let response = login(app, email).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let login_attempt_id = response.json::<TwoFactorAuthResponse>().await.unwrap().login_attempt_id;
+
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": app.get_last_2fa_code().await
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+async fn last_email_subject(app: &TestApp) -> String {
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    body["Subject"].as_str().unwrap().to_owned()
+}
+
+async fn mock_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn should_return_400_if_jwt_cookie_missing() {
+    let mut app = TestApp::new().await;
+
+    let body = serde_json::json!({ "password": PASSWORD });
+    assert_eq!(app.post_enable_2fa(&body).await.status().as_u16(), 400);
+    assert_eq!(app.post_disable_2fa(&body).await.status().as_u16(), 400);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_enable_2fa_after_reauthentication() {
+    let mut app = TestApp::new().await;
+    mock_email_server(&app).await;
+    let random_email = get_random_email();
+    signup(&app, &random_email, false).await;
+    assert_eq!(login(&app, &random_email).await.status().as_u16(), 200);
+
+    let response = app.post_enable_2fa(&serde_json::json!({ "password": "Wr0ng-Password!" })).await;
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(app.email_server.received_requests().await.unwrap().is_empty());
+
+    let response = app.post_enable_2fa(&serde_json::json!({ "password": PASSWORD })).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.json::<TwoFASettingResponse>().await.unwrap(),
+        TwoFASettingResponse { requires_2fa: true }
+    );
+    assert_eq!(last_email_subject(&app).await, "Two-factor authentication enabled");
+
+    // Logging in now asks for a code
+    assert_eq!(login(&app, &random_email).await.status().as_u16(), 206);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_disable_2fa_with_password_and_current_code() {
+    let mut app = TestApp::new().await;
+    mock_email_server(&app).await;
+    let random_email = get_random_email();
+    signup(&app, &random_email, true).await;
+    login_with_2fa(&app, &random_email).await;
+
+    // The password alone only starts the change
+    let response = app.post_disable_2fa(&serde_json::json!({ "password": PASSWORD })).await;
+    assert_eq!(response.status().as_u16(), 206);
+    let login_attempt_id = response.json::<TwoFactorAuthResponse>().await.unwrap().login_attempt_id;
+    let code = app.get_last_2fa_code().await;
+
+    let wrong_code = if code == "111111" { "222222" } else { "111111" };
+    let response = app
+        .post_disable_2fa(&serde_json::json!({
+            "password": PASSWORD,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": wrong_code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_disable_2fa(&serde_json::json!({
+            "password": "Wr0ng-Password!",
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_disable_2fa(&serde_json::json!({
+            "password": PASSWORD,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.json::<TwoFASettingResponse>().await.unwrap(),
+        TwoFASettingResponse { requires_2fa: false }
+    );
+    assert_eq!(last_email_subject(&app).await, "Two-factor authentication disabled");
+
+    // Disabling again changes nothing, and logging in no longer asks for a code
+    let response = app
+        .post_disable_2fa(&serde_json::json!({
+            "password": PASSWORD,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(login(&app, &random_email).await.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
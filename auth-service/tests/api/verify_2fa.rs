@@ -1,5 +1,6 @@
-use auth_service::{domain::Email, routes::TwoFactorAuthResponse, utils::constants::JWT_COOKIE_NAME};
-use secrecy::{Secret, ExposeSecret};
+use auth_service::{domain::LoginAttemptId, routes::TwoFactorAuthResponse, utils::constants::{JWT_COOKIE_NAME, LOGIN_ATTEMPT_COOKIE_NAME, MAX_PENDING_TWO_FA_ATTEMPTS}};
+use reqwest::Url;
+use secrecy::Secret;
 use wiremock::{matchers::{method, path}, Mock, ResponseTemplate};
 
 use crate::helpers::{get_random_email, TestApp};
@@ -178,15 +179,7 @@ async fn should_return_401_if_old_code() {
     let login_attempt_id = response.login_attempt_id;
     println!("Login attempt ID: {}", login_attempt_id);
     
-    let code_tuple = app
-        .two_fa_code_store
-        .read()
-        .await
-        .get_code(&Email::parse(Secret::new(random_email.clone())).unwrap())
-        .await
-        .unwrap();
-
-    let first_token = code_tuple.1.as_ref();
+    let first_token = app.get_last_2fa_code().await;
 
     // Login with the created user again
     let response = app
@@ -209,7 +202,7 @@ async fn should_return_401_if_old_code() {
      .post_verify_2fa(&serde_json::json!({
             "email": random_email,
             "loginAttemptId": second_login_attempt_id,
-            "2FACode": first_token.expose_secret()
+            "2FACode": first_token
         }))
         .await;
     assert_eq!(response.status().as_u16(), 401);
@@ -259,20 +252,12 @@ async fn should_return_200_if_correct_code() {
 
     let login_attempt_id = response_body.login_attempt_id;
 
-    let code_tuple = app
-        .two_fa_code_store
-        .read()
-        .await
-        .get_code(&Email::parse(Secret::new(random_email.clone())).unwrap())
-        .await
-        .unwrap();
-
-    let code = code_tuple.1.as_ref();
+    let code = app.get_last_2fa_code().await;
 
     let request_body = serde_json::json!({
         "email": random_email,
         "loginAttemptId": login_attempt_id,
-        "2FACode": code.expose_secret()
+        "2FACode": code
     });
 
     let response = app.post_verify_2fa(&request_body).await;
@@ -285,6 +270,66 @@ async fn should_return_200_if_correct_code() {
 
     assert!(!auth_cookie.value().is_empty());
 
+    // The login attempt cookie is cleared once the attempt is complete
+    let login_attempt_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == LOGIN_ATTEMPT_COOKIE_NAME)
+        .expect("No login attempt cookie found");
+    assert!(login_attempt_cookie.value().is_empty());
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_403_if_user_disabled_while_login_pending() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": random_email,
+            "password": "StrongPassword199$123",
+            "requires2FA": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": random_email,
+            "password": "StrongPassword199$123"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+    let code = app.get_last_2fa_code().await;
+
+    // An admin disables the user before the code is entered
+    sqlx::query("UPDATE users SET disabled = TRUE")
+        .execute(&app.pg_pool)
+        .await
+        .unwrap();
+
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(response.cookies().all(|cookie| cookie.name() != JWT_COOKIE_NAME));
 
     app.clean_up().await;
 }
@@ -297,7 +342,7 @@ async fn should_return_401_if_same_code_twice() {
 
     let signup_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "Sup3r-Secret-Pass!",
         "requires2FA": true
     });
 
@@ -313,7 +358,7 @@ async fn should_return_401_if_same_code_twice() {
 
     let login_body = serde_json::json!({
         "email": random_email,
-        "password": "password123"
+        "password": "Sup3r-Secret-Pass!"
     });
 
     let response = app.post_login(&login_body).await;
@@ -329,20 +374,12 @@ async fn should_return_401_if_same_code_twice() {
 
     let login_attempt_id = response_body.login_attempt_id;
 
-    let code_tuple = app
-        .two_fa_code_store
-        .read()
-        .await
-        .get_code(&Email::parse(Secret::new(random_email.clone())).unwrap())
-        .await
-        .unwrap();
-
-    let code = code_tuple.1.as_ref();
+    let code = app.get_last_2fa_code().await;
 
     let request_body = serde_json::json!({
         "email": random_email,
         "loginAttemptId": login_attempt_id,
-        "2FACode": code.expose_secret()
+        "2FACode": code
     });
 
     let response = app.post_verify_2fa(&request_body).await;
@@ -359,4 +396,301 @@ async fn should_return_401_if_same_code_twice() {
     assert_eq!(response.status().as_u16(), 401);
 
     app.clean_up().await;
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn should_return_200_for_concurrent_login_attempts() {
+    // Logging in from a second device must not invalidate the first device's pending attempt
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let devices = [app.http_client.clone(), app.new_device()];
+
+    let mut login_attempts = Vec::new();
+    for device in devices.iter() {
+        let response = app
+            .post_login_from(
+                device,
+                &serde_json::json!({
+                    "email": random_email,
+                    "password": "Sup3r-Secret-Pass!"
+                }),
+            )
+            .await;
+        assert_eq!(response.status().as_u16(), 206);
+
+        let response_body = response
+            .json::<TwoFactorAuthResponse>()
+            .await
+            .expect("Could not deserialize response body to TwoFactorAuthResponse");
+        login_attempts.push((response_body.login_attempt_id, app.get_last_2fa_code().await));
+    }
+
+    for (device, (login_attempt_id, code)) in devices.iter().zip(login_attempts) {
+        let response = app
+            .post_verify_2fa_from(
+                device,
+                &serde_json::json!({
+                    "email": random_email,
+                    "loginAttemptId": login_attempt_id,
+                    "2FACode": code
+                }),
+            )
+            .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_attempt_evicted_by_newer_logins() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(MAX_PENDING_TWO_FA_ATTEMPTS as u64 + 1)
+        .mount(&app.email_server)
+        .await;
+
+    let mut login_attempt_ids = Vec::new();
+    for _ in 0..=MAX_PENDING_TWO_FA_ATTEMPTS {
+        let response = app
+            .post_login(&serde_json::json!({
+                "email": random_email,
+                "password": "Sup3r-Secret-Pass!"
+            }))
+            .await;
+        assert_eq!(response.status().as_u16(), 206);
+
+        let response_body = response
+            .json::<TwoFactorAuthResponse>()
+            .await
+            .expect("Could not deserialize response body to TwoFactorAuthResponse");
+        login_attempt_ids.push(response_body.login_attempt_id);
+    }
+
+    // The oldest attempt was evicted, the remaining ones are still pending
+    let first_attempt = app
+        .two_fa_code_store
+        .get_code(&LoginAttemptId::parse(Secret::new(login_attempt_ids[0].clone())).unwrap())
+        .await;
+    assert!(first_attempt.is_err());
+
+    for login_attempt_id in login_attempt_ids[1..].iter() {
+        let pending_attempt = app
+            .two_fa_code_store
+            .get_code(&LoginAttemptId::parse(Secret::new(login_attempt_id.clone())).unwrap())
+            .await;
+        assert!(pending_attempt.is_ok());
+    }
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_keep_at_most_max_pending_attempts_when_logins_race() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let mut logins = tokio::task::JoinSet::new();
+    for _ in 0..10 {
+        let client = app.new_device();
+        let url = format!("{}/login", &app.address);
+        let body = serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!"
+        });
+        logins.spawn(async move {
+            let response = client
+                .post(url)
+                .json(&body)
+                .send()
+                .await
+                .expect("Failed to execute request.");
+            assert_eq!(response.status().as_u16(), 206);
+            response
+                .json::<TwoFactorAuthResponse>()
+                .await
+                .expect("Could not deserialize response body to TwoFactorAuthResponse")
+                .login_attempt_id
+        });
+    }
+
+    let mut login_attempt_ids = Vec::new();
+    while let Some(login_attempt_id) = logins.join_next().await {
+        login_attempt_ids.push(login_attempt_id.unwrap());
+    }
+
+    let mut pending_attempts = 0;
+    for login_attempt_id in login_attempt_ids {
+        let login_attempt_id = LoginAttemptId::parse(Secret::new(login_attempt_id)).unwrap();
+        if app.two_fa_code_store.get_code(&login_attempt_id).await.is_ok() {
+            pending_attempts += 1;
+        }
+    }
+    assert_eq!(pending_attempts, MAX_PENDING_TWO_FA_ATTEMPTS);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_verified_from_another_device() {
+    // A stolen login attempt ID and code must not be usable from a different browser
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+
+    let login_attempt_cookie = response
+        .cookies()
+        .find(|cookie| cookie.name() == LOGIN_ATTEMPT_COOKIE_NAME)
+        .expect("No login attempt cookie found");
+    assert!(login_attempt_cookie.http_only());
+
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+
+    let code = app.get_last_2fa_code().await;
+
+    let request_body = serde_json::json!({
+        "email": random_email,
+        "loginAttemptId": login_attempt_id,
+        "2FACode": code
+    });
+
+    let response = app
+        .post_verify_2fa_from(&app.new_device(), &request_body)
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    // The attempt is still usable from the browser that started it
+    let response = app.post_verify_2fa(&request_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_return_401_if_login_attempt_cookie_is_tampered() {
+    let mut app = TestApp::new().await;
+    let random_email = get_random_email();
+
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!",
+            "requires2FA": true
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_login(&serde_json::json!({
+            "email": random_email,
+            "password": "Sup3r-Secret-Pass!"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 206);
+
+    let login_attempt_id = response
+        .json::<TwoFactorAuthResponse>()
+        .await
+        .expect("Could not deserialize response body to TwoFactorAuthResponse")
+        .login_attempt_id;
+
+    let code = app.get_last_2fa_code().await;
+
+    // Replace the signed cookie with an unsigned one carrying the same ID
+    app.cookie_jar.add_cookie_str(
+        &format!(
+            "{}={}; HttpOnly; SameSite=Lax; Path=/",
+            LOGIN_ATTEMPT_COOKIE_NAME, login_attempt_id
+        ),
+        &Url::parse("http://127.0.0.1").expect("Failed to parse URL"),
+    );
+
+    let response = app
+        .post_verify_2fa(&serde_json::json!({
+            "email": random_email,
+            "loginAttemptId": login_attempt_id,
+            "2FACode": code
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
@@ -1,6 +1,7 @@
-use auth_service::utils::{auth::generate_auth_token, constants::JWT_COOKIE_NAME};
-use auth_service::domain::Email;
-use secrecy::{ExposeSecret, Secret};
+use auth_service::routes::VerifyTokenResponse;
+use auth_service::utils::{auth::{generate_auth_token, Acr, AuthMethod}, constants::JWT_COOKIE_NAME};
+use auth_service::domain::UserId;
+use secrecy::ExposeSecret;
 use crate::helpers::{get_random_email, TestApp};
 
 
@@ -17,14 +18,14 @@ async fn should_return_200_valid_token() {
 
     let signup_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "Sup3r-Secret-Pass!",
         "requires2FA": false
     });
     let response = app.post_signup(&signup_body).await;
     assert_eq!(response.status().as_u16(), 201);
     let login_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "Sup3r-Secret-Pass!",
         "requires2FA": false
     });
     let response = app.post_login(&login_body).await;
@@ -40,6 +41,15 @@ async fn should_return_200_valid_token() {
     let response = app.post_verify_token(&verify_body).await;
     assert_eq!(response.status().as_u16(), 200);
 
+    // The response tells how and when the user logged in
+    let body = response
+        .json::<VerifyTokenResponse>()
+        .await
+        .expect("Could not deserialize response body to VerifyTokenResponse");
+    assert_eq!(body.amr, vec![AuthMethod::Pwd]);
+    assert_eq!(body.acr, Some(Acr::Aal1));
+    assert!(body.auth_time.is_some());
+
     app.clean_up().await;
     
 }
@@ -52,14 +62,14 @@ async fn should_return_401_if_banned_token() {
     let random_email = get_random_email();
     let signup_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "Sup3r-Secret-Pass!",
         "requires2FA": false
     });
     let response = app.post_signup(&signup_body).await;
     assert_eq!(response.status().as_u16(), 201);
     let login_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "Sup3r-Secret-Pass!",
         "requires2FA": false
     });
 
@@ -101,14 +111,14 @@ async fn should_return_401_if_invalid_token() {
 
     let signup_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "Sup3r-Secret-Pass!",
         "requires2FA": false
     });
     let response = app.post_signup(&signup_body).await;
     assert_eq!(response.status().as_u16(), 201);
     let login_body = serde_json::json!({
         "email": random_email,
-        "password": "password123",
+        "password": "Sup3r-Secret-Pass!",
         "requires2FA": false
     });
     let response = app.post_login(&login_body).await;
@@ -133,8 +143,7 @@ async fn should_return_422_if_malformed_input() {
 
     let mut app = TestApp::new().await;
 
-    let email = Email::parse(Secret::new("test@example.com".to_owned())).unwrap();
-    let result = generate_auth_token(&email).unwrap();
+    let result = generate_auth_token(&UserId::default()).unwrap();
 
     let verify_body = serde_json::json!({
         "token": result.expose_secret()
@@ -144,4 +153,73 @@ async fn should_return_422_if_malformed_input() {
     assert_eq!(response.status().as_u16(), 422);
 
     app.clean_up().await;
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn should_return_401_if_tokens_of_user_were_revoked() {
+    let mut app = TestApp::new().await;
+
+    let user_id = UserId::default();
+    let token = generate_auth_token(&user_id).unwrap();
+
+    let verify_body = serde_json::json!({
+        "token": token.expose_secret()
+    });
+    let response = app.post_verify_token(&verify_body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Revoke everything issued up to now, as `auth-service-admin revoke-tokens` does
+    app.banned_token_store
+        .revoke_tokens_issued_before(&user_id.to_string(), chrono::Utc::now().timestamp() + 1)
+        .await
+        .unwrap();
+
+    let response = app.post_verify_token(&verify_body).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_verify_concurrent_requests_against_the_shared_redis_connection() {
+    let mut app = TestApp::new().await;
+
+    let random_email = get_random_email();
+    let body = serde_json::json!({
+        "email": random_email,
+        "password": "Sup3r-Secret-Pass!",
+        "requires2FA": false
+    });
+    let response = app.post_signup(&body).await;
+    assert_eq!(response.status().as_u16(), 201);
+    let response = app.post_login(&body).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let token = response
+        .cookies()
+        .find(|cookie| cookie.name() == JWT_COOKIE_NAME)
+        .expect("No auth cookie found")
+        .value()
+        .to_owned();
+
+    // Each check looks the token up in Redis
+    let mut requests = tokio::task::JoinSet::new();
+    for _ in 0..50 {
+        let client = reqwest::Client::new();
+        let url = format!("{}/verify-token", &app.address);
+        let verify_body = serde_json::json!({ "token": token });
+        requests.spawn(async move {
+            client
+                .post(url)
+                .json(&verify_body)
+                .send()
+                .await
+                .expect("Failed to execute request.")
+                .status()
+                .as_u16()
+        });
+    }
+    while let Some(status) = requests.join_next().await {
+        assert_eq!(status.unwrap(), 200);
+    }
+
+    app.clean_up().await;
+}
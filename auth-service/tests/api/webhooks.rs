@@ -0,0 +1,184 @@
+use std::sync::Arc;
+
+use auth_service::{
+    domain::{
+        verify_webhook_signature, DomainEventType, Email, Password, RetryPolicy, User, UserId,
+        UserStore, UserStoreError, WebhookStore, WebhookSubscription, WEBHOOK_SIGNATURE_HEADER,
+    },
+    services::{
+        data_stores::{PostgresUserStore, PostgresWebhookStore},
+        webhook_dispatcher::WebhookDispatcher,
+    },
+};
+use secrecy::Secret;
+use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+use crate::helpers::{get_random_email, TestApp};
+
+const PASSWORD: &str = "Sup3r-Secret-Pass!";
+
+// Retries are due right away, so that each dispatch makes an attempt
+fn dispatcher(app: &TestApp, max_attempts: u32) -> WebhookDispatcher {
+    let retry_policy = RetryPolicy {
+        max_attempts,
+        base_delay: chrono::Duration::zero(),
+        max_delay: chrono::Duration::zero(),
+    };
+    let store = Arc::new(PostgresWebhookStore::new(app.pg_pool.clone()));
+    WebhookDispatcher::new(store, reqwest::Client::new(), retry_policy)
+}
+
+async fn subscribe(app: &TestApp, receiver: &MockServer, event_types: Vec<DomainEventType>) -> WebhookSubscription {
+    let subscription =
+        WebhookSubscription::new(&format!("{}/hooks", receiver.uri()), event_types).unwrap();
+    PostgresWebhookStore::new(app.pg_pool.clone())
+        .add_subscription(subscription.clone())
+        .await
+        .unwrap();
+    subscription
+}
+
+async fn received_payloads(receiver: &MockServer) -> Vec<serde_json::Value> {
+    receiver
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect()
+}
+
+async fn count_outbox_events(app: &TestApp, event_type: DomainEventType) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM event_outbox WHERE event_type = $1")
+        .bind(event_type.as_str())
+        .fetch_one(&app.pg_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn should_send_signed_events_for_signups_and_logins() {
+    let mut app = TestApp::new().await;
+    let receiver = MockServer::start().await;
+    Mock::given(path("/hooks"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&receiver)
+        .await;
+    let subscription = subscribe(
+        &app,
+        &receiver,
+        vec![DomainEventType::UserCreated, DomainEventType::UserLogin],
+    )
+    .await;
+
+    let random_email = get_random_email();
+    let response = app
+        .post_signup(&serde_json::json!({
+            "email": random_email,
+            "password": PASSWORD,
+            "requires2FA": false
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let response = app
+        .post_login(&serde_json::json!({ "email": random_email, "password": PASSWORD }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let dispatcher = dispatcher(&app, 3);
+    assert_eq!(dispatcher.dispatch().await.unwrap(), 2);
+    assert_eq!(dispatcher.dispatch().await.unwrap(), 0);
+
+    let requests = receiver.received_requests().await.unwrap();
+    for request in requests.iter() {
+        let signature = request.headers.get(WEBHOOK_SIGNATURE_HEADER).unwrap().to_str().unwrap();
+        let body = String::from_utf8(request.body.clone()).unwrap();
+        assert!(verify_webhook_signature(
+            &subscription.secret,
+            signature,
+            &body,
+            chrono::Duration::minutes(5)
+        ));
+    }
+
+    let mut payloads = received_payloads(&receiver).await;
+    payloads.sort_by_key(|payload| payload["occurredAt"].as_str().unwrap().to_owned());
+    assert_eq!(payloads[0]["type"], "user.created");
+    assert_eq!(payloads[0]["data"]["email"], random_email.as_str());
+    assert_eq!(payloads[1]["type"], "user.login");
+    assert_eq!(payloads[1]["data"]["userId"], payloads[0]["data"]["userId"]);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_not_emit_events_for_failed_changes() {
+    let mut app = TestApp::new().await;
+    let user_store = PostgresUserStore::new(app.pg_pool.clone());
+    let email = Email::parse(Secret::new(get_random_email())).unwrap();
+    let password = Password::parse(Secret::new(PASSWORD.to_owned())).unwrap();
+
+    user_store
+        .add_user(User::new(email.clone(), password.clone(), false))
+        .await
+        .unwrap();
+    // Same email, the insert fails and takes its event with it
+    assert!(user_store
+        .add_user(User::new(email, password.clone(), false))
+        .await
+        .is_err());
+    assert_eq!(count_outbox_events(&app, DomainEventType::UserCreated).await, 1);
+
+    assert_eq!(
+        user_store.update_password(&UserId::default(), password).await,
+        Err(UserStoreError::UserNotFound)
+    );
+    assert_eq!(count_outbox_events(&app, DomainEventType::UserPasswordChanged).await, 0);
+
+    app.clean_up().await;
+}
+
+#[tokio::test]
+async fn should_retry_then_dead_letter_failed_deliveries() {
+    let mut app = TestApp::new().await;
+    let receiver = MockServer::start().await;
+    Mock::given(path("/hooks"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(3)
+        .mount(&receiver)
+        .await;
+    subscribe(&app, &receiver, vec![DomainEventType::UserDeleted]).await;
+
+    let user_store = PostgresUserStore::new(app.pg_pool.clone());
+    let email = Email::parse(Secret::new(get_random_email())).unwrap();
+    let user = User::new(email, Password::parse(Secret::new(PASSWORD.to_owned())).unwrap(), false);
+    user_store.add_user(user.clone()).await.unwrap();
+    user_store.delete_user(&user.id).await.unwrap();
+
+    let dispatcher = dispatcher(&app, 2);
+    assert_eq!(dispatcher.dispatch().await.unwrap(), 1);
+    assert_eq!(dispatcher.dispatch().await.unwrap(), 1);
+    assert_eq!(dispatcher.dispatch().await.unwrap(), 0);
+
+    let webhook_store = PostgresWebhookStore::new(app.pg_pool.clone());
+    let dead_letters = webhook_store.get_dead_letters(10).await.unwrap();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].attempts, 2);
+    assert_eq!(dead_letters[0].event_type, DomainEventType::UserDeleted);
+
+    // Once redelivered it gets a fresh set of attempts
+    webhook_store.redeliver(&dead_letters[0].id).await.unwrap();
+    Mock::given(path("/hooks"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&receiver)
+        .await;
+    assert_eq!(dispatcher.dispatch().await.unwrap(), 1);
+    assert_eq!(dispatcher.dispatch().await.unwrap(), 1);
+    assert!(webhook_store.get_dead_letters(10).await.unwrap().is_empty());
+
+    let payloads = received_payloads(&receiver).await;
+    assert_eq!(payloads.len(), 4);
+    assert!(payloads.iter().all(|payload| payload["data"]["userId"] == user.id.to_string()));
+
+    app.clean_up().await;
+}